@@ -0,0 +1,154 @@
+//! Who used moderator commands and when, for sorting out disputes later.
+
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// How many entries are kept in memory for `!audit`, the file keeps all of them.
+const KEPT: usize = 100;
+
+/// A single use of a privileged command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub channel: String,
+    pub user: String,
+    /// The whole message, e.g. `!remove alice`.
+    pub command: String,
+}
+
+/// The latest entries, oldest first.
+#[derive(Default)]
+pub struct AuditLog {
+    entries: VecDeque<Entry>,
+    // every entry is appended to it as a line of JSON, if set
+    path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    /// Restores the latest entries saved at `path`, if there are any, and
+    /// appends every new one to it. Lines that can't be read, e.g. one cut off
+    /// by a crash, are skipped.
+    pub fn persistent(path: PathBuf) -> io::Result<AuditLog> {
+        let mut entries = VecDeque::new();
+        match fs::read_to_string(&path) {
+            Ok(saved) => {
+                let lines = saved.lines().enumerate();
+                for (number, line) in lines.filter(|(_, line)| !line.trim().is_empty()) {
+                    match serde_json::from_str(line) {
+                        Ok(entry) => entries.push_back(entry),
+                        Err(e) => {
+                            warn!("Skipping line {} of {}: {}", number + 1, path.display(), e);
+                            continue;
+                        }
+                    }
+                    if entries.len() > KEPT {
+                        entries.pop_front();
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(AuditLog {
+            entries,
+            path: Some(path),
+        })
+    }
+
+    pub fn record(&mut self, entry: Entry) {
+        self.append(&entry);
+        self.entries.push_back(entry);
+        if self.entries.len() > KEPT {
+            self.entries.pop_front();
+        }
+    }
+
+    /// The last `count` entries in `channel`, oldest first.
+    pub fn latest(&self, channel: &str, count: usize) -> Vec<&Entry> {
+        let mut latest: Vec<&Entry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.channel == channel)
+            .take(count)
+            .collect();
+        latest.reverse();
+        latest
+    }
+
+    fn append(&self, entry: &Entry) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let line = serde_json::to_string(entry).unwrap();
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = appended {
+            error!("Failed to save audit log to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str) -> Entry {
+        entry_in("channel", command)
+    }
+
+    fn entry_in(channel: &str, command: &str) -> Entry {
+        Entry {
+            time: Utc::now(),
+            channel: channel.to_owned(),
+            user: "moderator".to_owned(),
+            command: command.to_owned(),
+        }
+    }
+
+    #[test]
+    fn latest_entries() {
+        let mut log = AuditLog::default();
+        for i in 0..=KEPT {
+            log.record(entry(&format!("!remove user{}", i)));
+        }
+        log.record(entry_in("other", "!next"));
+        let latest: Vec<&str> = log
+            .latest("channel", 2)
+            .into_iter()
+            .map(|e| e.command.as_str())
+            .collect();
+        assert_eq!(latest, ["!remove user99", "!remove user100"]);
+        assert_eq!(log.latest("channel", usize::MAX).len(), KEPT - 1);
+    }
+
+    #[test]
+    fn persistence() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let mut log = AuditLog::persistent(path.clone()).unwrap();
+        log.record(entry("!next"));
+        log.record(entry("!disable !dave"));
+
+        let mut restored = AuditLog::persistent(path.clone()).unwrap();
+        assert_eq!(restored.latest("channel", 10), log.latest("channel", 10));
+        restored.record(entry("!enable !dave"));
+        let reloaded = AuditLog::persistent(path.clone()).unwrap();
+        assert_eq!(reloaded.latest("channel", 10).len(), 3);
+
+        // a line cut off by a crash doesn't lose the rest
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "{{\"time\":").unwrap();
+        let restored = AuditLog::persistent(path.clone()).unwrap();
+        assert_eq!(restored.latest("channel", 10).len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Phrases moderators don't want in chat, added with `!addbanned`.
+
+use log::{error, warn};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::{fs, io};
+use twitch_queue_bot::json_store::JsonStore;
+
+/// The banned phrases, lowercase so they match regardless of case.
+#[derive(Default)]
+pub struct BannedPhrases {
+    phrases: BTreeSet<String>,
+    // saved to after every change and reloaded when edited, if set
+    store: Option<JsonStore<BTreeSet<String>>>,
+    // of the file when it was last loaded or saved
+    modified: Option<SystemTime>,
+}
+
+impl BannedPhrases {
+    /// Restores the phrases saved at `path`, if there are any, and saves every
+    /// change back to it.
+    pub fn persistent(path: PathBuf) -> io::Result<BannedPhrases> {
+        let store = JsonStore::new(path);
+        let (phrases, modified) = match load(&store) {
+            Ok(loaded) => loaded,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (BTreeSet::new(), None),
+            Err(e) => return Err(e),
+        };
+        Ok(BannedPhrases {
+            phrases,
+            store: Some(store),
+            modified,
+        })
+    }
+
+    /// Returns whether the phrase wasn't banned yet.
+    pub fn add(&mut self, phrase: &str) -> bool {
+        let added = self.phrases.insert(phrase.to_lowercase());
+        if added {
+            self.save();
+        }
+        added
+    }
+
+    /// Returns whether the phrase was banned.
+    pub fn remove(&mut self, phrase: &str) -> bool {
+        let removed = self.phrases.remove(&phrase.to_lowercase());
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    /// The first banned phrase in `text`, ignoring case.
+    pub fn find(&self, text: &str) -> Option<&str> {
+        let text = text.to_lowercase();
+        self.phrases
+            .iter()
+            .find(|phrase| text.contains(phrase.as_str()))
+            .map(String::as_str)
+    }
+
+    /// Loads the file again if it was edited since it was last loaded or saved,
+    /// returns whether it was. The current phrases are kept if the file is
+    /// invalid.
+    pub fn reload_if_changed(&mut self) -> io::Result<bool> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(false),
+        };
+        let modified = match fs::metadata(store.path()) {
+            Ok(metadata) => metadata.modified()?,
+            // nothing was banned yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        // not retried until the file changes again
+        self.modified = Some(modified);
+        self.phrases = load(store)?.0;
+        Ok(true)
+    }
+
+    fn save(&mut self) {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return,
+        };
+        match store.write(&self.phrases) {
+            // so the bot's own changes aren't loaded again
+            Ok(()) => self.modified = fs::metadata(store.path()).and_then(|m| m.modified()).ok(),
+            Err(e) => error!("Failed to save {}: {}", store.path().display(), e),
+        }
+    }
+}
+
+fn load(store: &JsonStore<BTreeSet<String>>) -> io::Result<(BTreeSet<String>, Option<SystemTime>)> {
+    let modified = fs::metadata(store.path())?.modified()?;
+    // edited by hand, so not necessarily trimmed or lowercase
+    let phrases = store.load()?.unwrap_or_default();
+    let mut loaded = BTreeSet::new();
+    for phrase in phrases {
+        let phrase = phrase.trim();
+        if phrase.is_empty() {
+            // would match every message
+            warn!("Leaving out an empty phrase in {}", store.path().display());
+            continue;
+        }
+        loaded.insert(phrase.to_lowercase());
+    }
+    Ok((loaded, Some(modified)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn finding_phrases() {
+        let mut banned = BannedPhrases::default();
+        assert!(banned.add("Buy Followers"));
+        assert!(!banned.add("BUY followers"));
+        assert_eq!(
+            banned.find("cheap BUY FOLLOWERS here"),
+            Some("buy followers")
+        );
+        assert_eq!(banned.find("buy some followers"), None);
+
+        assert!(banned.remove("BUY FOLLOWERS"));
+        assert!(!banned.remove("buy followers"));
+        assert_eq!(banned.find("buy followers"), None);
+    }
+
+    #[test]
+    fn persistence_and_reloading() {
+        let path = std::env::temp_dir().join(format!("banned-phrases-{}.json", std::process::id()));
+        let mut banned = BannedPhrases::persistent(path.clone()).unwrap();
+        assert!(!banned.reload_if_changed().unwrap());
+        banned.add("spam");
+        // its own changes aren't reloaded
+        assert!(!banned.reload_if_changed().unwrap());
+
+        let restored = BannedPhrases::persistent(path.clone()).unwrap();
+        assert_eq!(restored.find("no SPAM please"), Some("spam"));
+
+        fs::write(&path, r#"["Scam ", " "]"#).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(banned.reload_if_changed().unwrap());
+        assert_eq!(banned.find("spam"), None);
+        assert_eq!(banned.find("a scam"), Some("scam"));
+        assert_eq!(banned.find("no phrase in here"), None);
+
+        // broken edits keep the previous phrases
+        fs::write(&path, "[").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(20))
+            .unwrap();
+        assert!(banned.reload_if_changed().is_err());
+        assert_eq!(banned.find("a scam"), Some("scam"));
+        fs::remove_file(path).unwrap();
+    }
+}
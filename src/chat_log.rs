@@ -0,0 +1,217 @@
+//! Everything said in the joined channels and the timeouts, bans and deleted
+//! messages there, written to one file per channel and day (in UTC) so
+//! moderators can look back at it.
+
+use crate::format_duration;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use twitch_irc::message::{ClearChatAction, ClearChatMessage, ClearMsgMessage, PrivmsgMessage};
+
+pub struct ChatLog {
+    dir: PathBuf,
+    // logs older than this are deleted, they're kept forever if unset
+    retention: Option<Duration>,
+    // to the thread writing the lines, so chat isn't held up by the disk
+    lines: mpsc::Sender<Line>,
+}
+
+struct Line {
+    channel: String,
+    at: DateTime<Utc>,
+    text: String,
+}
+
+impl ChatLog {
+    pub fn new(dir: PathBuf, retention: Option<Duration>) -> ChatLog {
+        let (lines, received) = mpsc::channel::<Line>();
+        let mut files = Files::new(dir.clone());
+        thread::spawn(move || {
+            for line in received {
+                files.write(&line.channel, line.at, &line.text);
+            }
+        });
+        ChatLog {
+            dir,
+            retention,
+            lines,
+        }
+    }
+
+    /// Adds `line` to the log of `channel` for the day of `at`.
+    pub fn write(&self, channel: &str, at: DateTime<Utc>, line: String) {
+        let line = Line {
+            channel: channel.to_owned(),
+            at,
+            text: line,
+        };
+        if self.lines.send(line).is_err() {
+            error!("The chat log of {} can't be written anymore", channel);
+        }
+    }
+
+    /// Deletes the logs of days that are past the retention on `today`. A log
+    /// that can't be deleted is left for the next time.
+    pub fn prune(&self, today: NaiveDate) -> io::Result<()> {
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return Ok(()),
+        };
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            // nothing was logged yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    error!("Failed to read {}: {}", self.dir.display(), e);
+                    continue;
+                }
+            };
+            let day = path
+                .file_name()
+                .and_then(|name| day_of(&name.to_string_lossy()));
+            if day.is_some_and(|day| day < today - retention) {
+                info!("Deleting the old chat log {}", path.display());
+                if let Err(e) = fs::remove_file(&path) {
+                    error!("Failed to delete the chat log {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The log of every channel for the day it was last written to, kept open
+/// until a line for the next day comes in.
+struct Files {
+    dir: PathBuf,
+    // keyed by channel login
+    open: HashMap<String, (NaiveDate, File)>,
+}
+
+impl Files {
+    fn new(dir: PathBuf) -> Files {
+        Files {
+            dir,
+            open: HashMap::new(),
+        }
+    }
+
+    fn write(&mut self, channel: &str, at: DateTime<Utc>, line: &str) {
+        let day = at.date_naive();
+        let path = self.dir.join(file_name(channel, day));
+        let open_day = self.open.get(channel).map(|(open_day, _)| *open_day);
+        if open_day != Some(day) {
+            let opened = fs::create_dir_all(&self.dir)
+                .and_then(|()| OpenOptions::new().create(true).append(true).open(&path));
+            match opened {
+                Ok(file) => self.open.insert(channel.to_owned(), (day, file)),
+                Err(e) => return error!("Failed to open the chat log {}: {}", path.display(), e),
+            };
+        }
+        let (_, file) = self.open.get_mut(channel).unwrap();
+        if let Err(e) = writeln!(file, "[{}] {}", at.format("%H:%M:%S"), line) {
+            error!("Failed to write to the chat log {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn file_name(channel: &str, day: NaiveDate) -> String {
+    format!("{}-{}.log", channel, day.format("%Y-%m-%d"))
+}
+
+/// The day a log with the file name `name` is for, `None` for other files.
+fn day_of(name: &str) -> Option<NaiveDate> {
+    let name = name.strip_suffix(".log")?;
+    let day = name.get(name.len().checked_sub(10)?..)?;
+    NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()
+}
+
+/// E.g. "<Ferris> hello" or "* Ferris waves" for `/me`.
+pub fn message_line(msg: &PrivmsgMessage) -> String {
+    if msg.is_action {
+        format!("* {} {}", msg.sender.name, msg.message_text)
+    } else {
+        format!("<{}> {}", msg.sender.name, msg.message_text)
+    }
+}
+
+/// E.g. "-- ferris was timed out for 10m 0s".
+pub fn clear_chat_line(clear: &ClearChatMessage) -> String {
+    match &clear.action {
+        ClearChatAction::ChatCleared => "-- Chat was cleared".to_owned(),
+        ClearChatAction::UserBanned { user_login, .. } => format!("-- {} was banned", user_login),
+        ClearChatAction::UserTimedOut {
+            user_login,
+            timeout_length,
+            ..
+        } => {
+            let length = Duration::from_std(*timeout_length).unwrap_or_else(|_| Duration::zero());
+            format!(
+                "-- {} was timed out for {}",
+                user_login,
+                format_duration(length)
+            )
+        }
+    }
+}
+
+/// E.g. "-- A message by ferris was deleted: spam".
+pub fn clear_msg_line(clear: &ClearMsgMessage) -> String {
+    format!(
+        "-- A message by {} was deleted: {}",
+        clear.sender_login, clear.message_text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn daily_files_and_retention() {
+        let dir = std::env::temp_dir().join(format!("chat-log-{}", std::process::id()));
+        let mut files = Files::new(dir.clone());
+        let at = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap();
+        files.write("channel", at, "<Ferris> hello");
+        files.write("channel", at, "-- ferris was banned");
+        files.write("channel", at + Duration::days(1), "<Ferris> again");
+        files.write("other", at, "<Corro> hi");
+        assert_eq!(
+            fs::read_to_string(dir.join("channel-2024-03-01.log")).unwrap(),
+            "[12:30:05] <Ferris> hello\n[12:30:05] -- ferris was banned\n"
+        );
+        fs::write(dir.join("notes.txt"), "kept").unwrap();
+        // can't be deleted as a file, the others still are
+        fs::create_dir(dir.join("stuck-2024-02-01.log")).unwrap();
+
+        let log = ChatLog::new(dir.clone(), Some(Duration::days(7)));
+        log.prune(at.date_naive() + Duration::days(7)).unwrap();
+        assert!(dir.join("channel-2024-03-01.log").exists());
+        log.prune(at.date_naive() + Duration::days(8)).unwrap();
+        assert!(!dir.join("channel-2024-03-01.log").exists());
+        assert!(!dir.join("other-2024-03-01.log").exists());
+        assert!(dir.join("channel-2024-03-02.log").exists());
+        assert!(dir.join("notes.txt").exists());
+        assert!(dir.join("stuck-2024-02-01.log").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn days_of_files() {
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert_eq!(day_of(&file_name("some-channel", day)), Some(day));
+        assert_eq!(day_of("notes.log"), None);
+        assert_eq!(day_of("x.txt"), None);
+    }
+}
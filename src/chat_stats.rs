@@ -0,0 +1,126 @@
+//! How lively chat was lately, for `!chatstats` and the end of stream summary.
+
+use crate::format_duration;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// How many commands the summary lists.
+const TOP_COMMANDS: usize = 3;
+
+struct Message {
+    channel: String,
+    user: String,
+    command: Option<String>,
+    at: DateTime<Utc>,
+}
+
+/// The chat messages in every channel within the last `window`.
+pub struct ChatStats {
+    window: Duration,
+    // oldest first
+    messages: VecDeque<Message>,
+    // when the bot started counting, the window can't reach further back
+    since: DateTime<Utc>,
+}
+
+impl ChatStats {
+    pub fn new(window: Duration, since: DateTime<Utc>) -> ChatStats {
+        ChatStats {
+            window,
+            messages: VecDeque::new(),
+            since,
+        }
+    }
+
+    /// Counts a message by `user` in `channel`, with the `command` it used if
+    /// any, and forgets the ones that fell out of the window.
+    pub fn record(&mut self, channel: &str, user: &str, command: Option<&str>, at: DateTime<Utc>) {
+        self.messages.push_back(Message {
+            channel: channel.to_owned(),
+            user: user.to_owned(),
+            command: command.map(str::to_owned),
+            at,
+        });
+        while let Some(oldest) = self.messages.front() {
+            if oldest.at > at - self.window {
+                break;
+            }
+            self.messages.pop_front();
+        }
+    }
+
+    /// E.g. "120 messages in the last 1h 0m (2.0 per minute) from 15
+    /// chatter(s), most used commands: !join (8), !queue (3)".
+    pub fn summary(&self, channel: &str, now: DateTime<Utc>) -> String {
+        let span = self.window.min(now - self.since);
+        let messages: Vec<&Message> = self
+            .messages
+            .iter()
+            .filter(|message| message.channel == channel && message.at > now - span)
+            .collect();
+        if messages.is_empty() {
+            return format!("No messages in the last {}", format_duration(span));
+        }
+        let chatters: HashSet<&str> = messages.iter().map(|m| m.user.as_str()).collect();
+        let per_minute = messages.len() as f64 / (span.num_seconds().max(60) as f64 / 60.0);
+        let mut summary = format!(
+            "{} messages in the last {} ({:.1} per minute) from {} chatter(s)",
+            messages.len(),
+            format_duration(span),
+            per_minute,
+            chatters.len()
+        );
+
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for command in messages.iter().filter_map(|m| m.command.as_deref()) {
+            *counts.entry(command).or_default() += 1;
+        }
+        let mut top: Vec<(&str, u64)> = counts.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let top: Vec<String> = top
+            .iter()
+            .take(TOP_COMMANDS)
+            .map(|(command, count)| format!("{} ({})", command, count))
+            .collect();
+        if !top.is_empty() {
+            summary.push_str(&format!(", most used commands: {}", top.join(", ")));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_window() {
+        let start = Utc::now();
+        let mut stats = ChatStats::new(Duration::minutes(10), start);
+        let at = |minutes| start + Duration::minutes(minutes);
+        assert_eq!(
+            stats.summary("channel", at(5)),
+            "No messages in the last 5m 0s"
+        );
+
+        stats.record("channel", "alice", None, at(1));
+        stats.record("channel", "alice", Some("!join"), at(8));
+        stats.record("channel", "bob", Some("!join"), at(9));
+        stats.record("channel", "bob", Some("!queue"), at(10));
+        stats.record("other", "carol", None, at(10));
+        assert_eq!(
+            stats.summary("channel", at(10)),
+            "4 messages in the last 10m 0s (0.4 per minute) from 2 chatter(s), \
+             most used commands: !join (2), !queue (1)"
+        );
+
+        // the first message fell out of the window
+        stats.record("channel", "carol", None, at(12));
+        assert_eq!(
+            stats.summary("channel", at(12)),
+            "4 messages in the last 10m 0s (0.4 per minute) from 3 chatter(s), \
+             most used commands: !join (2), !queue (1)"
+        );
+        assert_eq!(stats.messages.len(), 5);
+    }
+}
@@ -0,0 +1,141 @@
+//! Who has chatted before, so first-time chatters can be welcomed.
+
+use log::{error, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// The logins of everyone who chatted, per channel.
+#[derive(Default)]
+pub struct Chatters {
+    // keyed by channel login
+    seen: BTreeMap<String, BTreeSet<String>>,
+    // every new chatter is appended to it as a line of JSON, if set
+    path: Option<PathBuf>,
+}
+
+impl Chatters {
+    /// Restores the chatters saved at `path`, if there are any, and appends
+    /// every new one to it. Lines that can't be read, e.g. one cut off by a
+    /// crash, are skipped.
+    pub fn persistent(path: PathBuf) -> io::Result<Chatters> {
+        let mut seen: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        match fs::read_to_string(&path) {
+            Ok(saved) => {
+                let lines = saved.lines().enumerate();
+                for (number, line) in lines.filter(|(_, line)| !line.trim().is_empty()) {
+                    match serde_json::from_str::<(String, String)>(line) {
+                        Ok((channel, user)) => {
+                            seen.entry(channel).or_default().insert(user);
+                        }
+                        Err(e) => {
+                            warn!("Skipping line {} of {}: {}", number + 1, path.display(), e)
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Chatters {
+            seen,
+            path: Some(path),
+        })
+    }
+
+    /// Remembers that `user` chatted in `channel`, returns whether it was
+    /// their first time.
+    pub fn first_time(&mut self, channel: &str, user: &str) -> bool {
+        let first = self
+            .seen
+            .entry(channel.to_owned())
+            .or_default()
+            .insert(user.to_owned());
+        if first {
+            self.append(channel, user);
+        }
+        first
+    }
+
+    /// Forgets everyone who chatted in `channel`, e.g. when a new stream
+    /// starts. The file is written anew, without them.
+    pub fn forget(&mut self, channel: &str) {
+        if self.seen.remove(channel).is_some() {
+            self.rewrite();
+        }
+    }
+
+    fn append(&self, channel: &str, user: &str) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(&(channel, user))?));
+        if let Err(e) = appended {
+            error!("Failed to save {}: {}", path.display(), e);
+        }
+    }
+
+    fn rewrite(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut lines = String::new();
+        for (channel, users) in &self.seen {
+            for user in users {
+                // serializing strings can't fail
+                lines.push_str(&serde_json::to_string(&(channel, user)).unwrap());
+                lines.push('\n');
+            }
+        }
+        if let Err(e) = fs::write(path, lines) {
+            error!("Failed to save {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_times() {
+        let mut chatters = Chatters::default();
+        assert!(chatters.first_time("channel", "alice"));
+        assert!(!chatters.first_time("channel", "alice"));
+        assert!(chatters.first_time("other", "alice"));
+
+        chatters.forget("channel");
+        assert!(chatters.first_time("channel", "alice"));
+        assert!(!chatters.first_time("other", "alice"));
+    }
+
+    #[test]
+    fn appending_chatters() {
+        let path = std::env::temp_dir().join(format!("chatters-{}.json", std::process::id()));
+        let mut chatters = Chatters::persistent(path.clone()).unwrap();
+        chatters.first_time("channel", "alice");
+        chatters.first_time("channel", "alice");
+        chatters.first_time("other", "bob");
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        chatters.forget("other");
+        // cut off by a crash
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"[\"other\", \"ca\n")
+            .unwrap();
+        let mut restored = Chatters::persistent(path.clone()).unwrap();
+        assert!(!restored.first_time("channel", "alice"));
+        assert!(restored.first_time("other", "bob"));
+        fs::remove_file(path).unwrap();
+    }
+}
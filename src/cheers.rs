@@ -0,0 +1,81 @@
+//! How many bits everyone has cheered, for `!topcheers`.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use twitch_queue_bot::json_store::JsonStore;
+
+/// Bits cheered per user, per channel.
+#[derive(Default)]
+pub struct Cheers {
+    // keyed by channel login, then by user login
+    totals: BTreeMap<String, BTreeMap<String, u64>>,
+    // saved to after every cheer, if set
+    store: Option<JsonStore<BTreeMap<String, BTreeMap<String, u64>>>>,
+}
+
+impl Cheers {
+    /// Restores the totals saved at `path`, if there are any, and saves every
+    /// cheer back to it.
+    pub fn persistent(path: PathBuf) -> io::Result<Cheers> {
+        let store = JsonStore::new(path);
+        Ok(Cheers {
+            totals: store.load_or_default()?,
+            store: Some(store),
+        })
+    }
+
+    /// Adds `bits` cheered by `user` in `channel`, returns their new total.
+    pub fn add(&mut self, channel: &str, user: &str, bits: u64) -> u64 {
+        let total = self
+            .totals
+            .entry(channel.to_owned())
+            .or_default()
+            .entry(user.to_owned())
+            .or_default();
+        *total += bits;
+        let total = *total;
+        self.save();
+        total
+    }
+
+    /// The `n` users who cheered the most in `channel` with their totals, ties
+    /// ordered by name.
+    pub fn top(&self, channel: &str, n: usize) -> Vec<(&str, u64)> {
+        let mut top: Vec<(&str, u64)> = match self.totals.get(channel) {
+            Some(totals) => totals
+                .iter()
+                .map(|(user, &bits)| (user.as_str(), bits))
+                .collect(),
+            None => return Vec::new(),
+        };
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+
+    fn save(&self) {
+        if let Some(store) = &self.store {
+            store.save(&self.totals);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals() {
+        let mut cheers = Cheers::default();
+        assert_eq!(cheers.add("channel", "alice", 100), 100);
+        assert_eq!(cheers.add("channel", "bob", 500), 500);
+        assert_eq!(cheers.add("channel", "carol", 100), 100);
+        assert_eq!(cheers.add("channel", "alice", 50), 150);
+        cheers.add("other", "dave", 1000);
+
+        assert_eq!(cheers.top("channel", 2), [("bob", 500), ("alice", 150)]);
+        assert_eq!(cheers.top("channel", 10).len(), 3);
+        assert!(cheers.top("unknown", 10).is_empty());
+    }
+}
@@ -0,0 +1,1062 @@
+//! Chat commands: every command is a `CommandHandler` registered under its
+//! trigger, so commands can live in their own modules and carry their own
+//! state. Commands belong to a `BotModule`, one per feature of the bot, which
+//! can be turned off in the config.
+
+mod args;
+mod chat_modes;
+mod counters;
+mod events;
+mod filters;
+mod help;
+mod memes;
+mod middleware;
+mod moderation;
+mod polls;
+mod queue;
+mod redemptions;
+#[cfg(feature = "scripting")]
+mod scripts;
+mod snippets;
+mod stream;
+mod suggestions;
+mod timers;
+mod toggles;
+mod triggers;
+
+use crate::config::FerrisBotConfig;
+use crate::disabled_commands::DisabledCommands;
+use crate::eventsub::Event;
+use crate::helix::User;
+use crate::permissions::Permission;
+use crate::responses::Response;
+use crate::templates::{self, Variables};
+use crate::{Context, Priority};
+use args::{Args, Param};
+use async_trait::async_trait;
+use counters::Counter;
+use log::{error, info};
+use middleware::Middleware;
+use serde::Deserialize;
+use serenity::model::id::ChannelId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use twitch_irc::message::PrivmsgMessage;
+
+pub use events::chat_event;
+
+/// Replied when Twitch can't be asked, the error itself is logged.
+const UNAVAILABLE: &str = "Couldn't reach Twitch, try again later";
+
+/// Starts the steps of chains that post in Discord.
+const DISCORD_STEP: &str = "discord:";
+
+#[async_trait]
+pub trait CommandHandler: Send + Sync {
+    /// Who may use the command unless configured otherwise.
+    fn permission(&self) -> Permission {
+        Permission::Everyone
+    }
+
+    /// What the command does, for `!help`.
+    fn description(&self) -> &'static str {
+        ""
+    }
+
+    /// The arguments the command expects, checked before it's handled.
+    fn params(&self) -> Vec<Param> {
+        Vec::new()
+    }
+
+    /// E.g. "Usage: !move <user> <position> [queue]" for the `command` this
+    /// handler is registered as.
+    fn usage(&self, command: &str) -> String {
+        args::usage(command, &self.params())
+    }
+
+    /// Where replies to the command go unless configured otherwise, where
+    /// all replies go by default if `None`.
+    fn reply_target(&self) -> Option<ReplyTarget> {
+        None
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context);
+}
+
+/// Where the replies to a command go.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyTarget {
+    /// To the whole chat, addressed to whoever used the command.
+    #[default]
+    Chat,
+    /// Whispered to whoever used the command.
+    Whisper,
+    /// As a Twitch reply to the message with the command.
+    Thread,
+}
+
+/// A feature of the bot, e.g. the queue, that can be turned off in `[modules]`.
+#[async_trait]
+pub trait BotModule: Send + Sync {
+    /// The module's key in `[modules]`.
+    fn name(&self) -> &'static str;
+
+    /// Registers the module's commands.
+    fn register(&self, registry: &mut Registry, config: &FerrisBotConfig);
+
+    /// Sees every chat message before it's handled as a command, returns
+    /// whether the module took care of it so it isn't handled any further.
+    async fn on_message(&self, _msg: &PrivmsgMessage, _ctx: &Context) -> bool {
+        false
+    }
+
+    /// Whether the messages the module takes care of are removed from chat,
+    /// like the ones caught by the moderation filters.
+    fn removes_messages(&self) -> bool {
+        false
+    }
+    /// Reacts to `event` in `channel_login`, e.g. a follow or the stream
+    /// going live.
+    async fn on_event(&self, _event: &Event, _channel_login: &str, _ctx: &Context) {}
+}
+
+/// A chat message calling a command.
+pub struct Invocation {
+    pub msg: PrivmsgMessage,
+    /// The command with aliases resolved, e.g. `!queue` for `!q 2`.
+    pub command: String,
+    /// Everything after the command as typed, e.g. the note given with `!join`.
+    pub rest: String,
+    /// `rest` parsed according to the handler's `params`.
+    pub args: Args,
+    /// Where replies go, `None` for where replies go by default.
+    pub reply_target: Option<ReplyTarget>,
+}
+
+impl Invocation {
+    /// `None` unless `msg` starts with `prefix` and a command. Commands match
+    /// case-insensitively and are named with a `!` whatever the prefix is.
+    fn parse(
+        msg: PrivmsgMessage,
+        prefix: &str,
+        aliases: &HashMap<String, String>,
+    ) -> Option<Invocation> {
+        let (cmd, rest) = split_command(&msg.message_text, prefix)?;
+        let command = resolve_alias(aliases, cmd);
+        let rest = rest.trim().to_owned();
+        Some(Invocation {
+            command,
+            rest,
+            args: Args::default(),
+            msg,
+            reply_target: None,
+        })
+    }
+
+    /// The words after the command, see `tokenize`.
+    pub fn words(&self) -> Vec<&str> {
+        tokenize(&self.rest)
+    }
+}
+
+/// Maps command triggers to their handlers.
+pub struct Registry {
+    handlers: HashMap<String, Box<dyn CommandHandler>>,
+    // run in order before every handler
+    middleware: Vec<Box<dyn Middleware>>,
+    // shared with `!disable` and `!enable`
+    disabled: Arc<Mutex<DisabledCommands>>,
+    modules: Vec<Box<dyn BotModule>>,
+    // whether response commands, custom commands and counters are handled
+    responses: bool,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let disabled = Arc::default();
+        Registry {
+            handlers: HashMap::new(),
+            middleware: middleware::builtin(&disabled),
+            disabled,
+            modules: Vec::new(),
+            responses: false,
+        }
+    }
+}
+
+impl Registry {
+    /// Every built-in command, commands that are disabled in `config` are left out.
+    pub fn builtin(config: &FerrisBotConfig) -> Registry {
+        #[allow(unused_mut)]
+        let mut modules: Vec<Box<dyn BotModule>> = vec![
+            // first, so filtered messages aren't seen by any other module
+            Box::new(moderation::Module::new(config)),
+            Box::new(queue::Module),
+            Box::new(snippets::Module),
+            Box::new(memes::Module::new(config)),
+            Box::new(polls::Module),
+            Box::new(timers::Module),
+            Box::new(stream::Module),
+            Box::new(events::Module::default()),
+            Box::new(redemptions::Module),
+        ];
+        #[cfg(feature = "scripting")]
+        modules.push(Box::new(scripts::Module));
+        #[cfg(not(feature = "scripting"))]
+        if !config.scripts.commands.is_empty() {
+            log::warn!(
+                "Scripts are configured but the bot was built without the scripting feature"
+            );
+        }
+        let mut registry = Registry::default();
+        for module in modules {
+            if config.modules.is_enabled(module.name()) {
+                module.register(&mut registry, config);
+                registry.modules.push(module);
+            } else {
+                info!("The {} module is turned off", module.name());
+            }
+        }
+        // last, to know about every other command
+        help::register(&mut registry);
+        registry
+    }
+
+    /// Registers `handler` for `trigger`, replacing the handler registered
+    /// before. Triggers match case-insensitively with or without the `!`.
+    pub fn register(&mut self, trigger: &str, handler: impl CommandHandler + 'static) {
+        self.handlers
+            .insert(command_name(trigger), Box::new(handler));
+    }
+
+    /// Shows `msg` to the modules and then runs the command in it, if any.
+    /// Built-in commands take precedence over chains, counters, then the
+    /// response commands with the same name and then custom commands.
+    /// Returns whether the message stayed in chat.
+    pub async fn dispatch(&self, msg: PrivmsgMessage, ctx: &Context) -> bool {
+        for module in &self.modules {
+            if module.on_message(&msg, ctx).await {
+                return !module.removes_messages();
+            }
+        }
+        self.dispatch_command(msg, ctx).await;
+        true
+    }
+
+    /// Shows `event` in `channel_login` to the modules.
+    pub async fn react(&self, event: &Event, channel_login: &str, ctx: &Context) {
+        info!("Event in {}: {:?}", channel_login, event);
+        for module in &self.modules {
+            module.on_event(event, channel_login, ctx).await;
+        }
+    }
+
+    /// Runs the command in `msg`, if any, without showing it to the modules
+    /// first. Whispers go here, they aren't chat for triggers and filters.
+    pub async fn dispatch_command(&self, msg: PrivmsgMessage, ctx: &Context) {
+        let prefix = ctx.config.commands.prefix(&msg.channel_login);
+        let invocation = match Invocation::parse(msg, prefix, &ctx.config.commands.aliases) {
+            Some(invocation) => invocation,
+            None => return,
+        };
+        if !self.handlers.contains_key(&invocation.command) {
+            if let Some(steps) = chain(&invocation.command, &ctx.config) {
+                return self.run_chain(invocation, steps, prefix, ctx).await;
+            }
+        }
+        self.run(invocation, prefix, true, ctx).await;
+    }
+
+    /// Runs each step of a chain as if its caller had typed it, every step
+    /// still needs its own permission. Chains can't run other chains. Steps
+    /// like `discord: text` post the text in Discord instead, which only
+    /// moderators can do unless the chain's permission says otherwise.
+    async fn run_chain(
+        &self,
+        invocation: Invocation,
+        steps: &[String],
+        prefix: &str,
+        ctx: &Context,
+    ) {
+        let default = if steps.iter().any(|step| step.starts_with(DISCORD_STEP)) {
+            Permission::Moderator
+        } else {
+            Permission::Everyone
+        };
+        if !self.allowed(&invocation, default, ctx) {
+            return;
+        }
+        record_usage(&invocation, ctx);
+        for step in steps {
+            if let Some(text) = step.strip_prefix(DISCORD_STEP) {
+                let text = render(text.trim(), &invocation, ctx);
+                let discord_channel = ChannelId(ctx.config.discord.channel_id);
+                if let Err(e) = discord_channel.say(&ctx.discord_http, text).await {
+                    error!("Failed to post {} in Discord: {}", invocation.command, e);
+                }
+                continue;
+            }
+            let mut msg = invocation.msg.clone();
+            msg.message_text = step.clone();
+            if let Some(step) = Invocation::parse(msg, prefix, &ctx.config.commands.aliases) {
+                self.run(step, prefix, false, ctx).await;
+            }
+        }
+        self.handled(&invocation, default, ctx);
+    }
+
+    /// Runs a built-in command, counter or response command. If there's no
+    /// such command, `suggest` has the bot guess which one was meant.
+    async fn run(&self, mut invocation: Invocation, prefix: &str, suggest: bool, ctx: &Context) {
+        let counter;
+        let handler = match self.handlers.get(&invocation.command) {
+            Some(handler) => Some(handler.as_ref()),
+            None if !self.responses => None,
+            None => {
+                counter = Counter::find(&invocation.command, ctx);
+                counter
+                    .as_ref()
+                    .map(|counter| counter as &dyn CommandHandler)
+            }
+        };
+        let response = match handler {
+            None if self.responses => {
+                response(&invocation.command, &invocation.msg.channel_login, ctx)
+            }
+            _ => None,
+        };
+        if handler.is_none() && response.is_none() {
+            if suggest && !ctx.config.commands.silence_suggestions {
+                self.suggest(&invocation, prefix, ctx);
+            }
+            return;
+        }
+        let default = handler.map_or(Permission::Everyone, |handler| handler.permission());
+        if !self.allowed(&invocation, default, ctx) {
+            return;
+        }
+
+        let target = configured(&ctx.config.commands.reply_targets, &invocation.command)
+            .copied()
+            .or_else(|| handler.and_then(|handler| handler.reply_target()));
+        invocation.reply_target = target;
+        if Registry::respond(&mut invocation, handler, response, prefix, ctx).await {
+            self.handled(&invocation, default, ctx);
+        }
+    }
+
+    /// Replies with the command closest to an unknown one, if any is close.
+    fn suggest(&self, invocation: &Invocation, prefix: &str, ctx: &Context) {
+        let level = ctx.permission(&invocation.msg);
+        let mut names: Vec<String> = self
+            .handlers
+            .iter()
+            .filter(|(name, handler)| {
+                required_permission(name, handler.permission(), &ctx.config) <= level
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.extend(
+            ctx.config
+                .commands
+                .chains
+                .keys()
+                .map(|name| command_name(name)),
+        );
+        if self.responses {
+            let commands = &ctx.config.commands;
+            if let Some(channel) = commands.channels.get(&invocation.msg.channel_login) {
+                names.extend(channel.responses.keys().map(|name| command_name(name)));
+            }
+            names.extend(ctx.responses.lock().unwrap().names().map(str::to_owned));
+            names.extend(
+                ctx.custom_commands
+                    .lock()
+                    .unwrap()
+                    .names()
+                    .map(str::to_owned),
+            );
+            let counters = ctx.counters.lock().unwrap();
+            names.extend(counters.iter().map(|(name, _)| command_name(name)));
+        }
+
+        let closest = suggestions::closest(&invocation.command, names.iter().map(String::as_str));
+        if let Some(closest) = closest {
+            // as the commands would be typed in this channel
+            let typed = |command: &str| format!("{}{}", prefix, &command[1..]);
+            let reply = format!(
+                "Unknown command {}, did you mean {}?",
+                typed(&invocation.command),
+                typed(closest)
+            );
+            ctx.reply(invocation, &reply);
+        }
+    }
+
+    /// Runs the handler of an allowed command, or answers with its response.
+    /// Returns whether it did, rather than explaining how to use the command.
+    async fn respond(
+        invocation: &mut Invocation,
+        handler: Option<&dyn CommandHandler>,
+        response: Option<Response>,
+        prefix: &str,
+        ctx: &Context,
+    ) -> bool {
+        if let Some(handler) = handler {
+            let params = handler.params();
+            match Args::parse(&params, &invocation.rest) {
+                Some(args) => invocation.args = args,
+                None => {
+                    // as the command would be typed in this channel
+                    let typed = format!("{}{}", prefix, &invocation.command[1..]);
+                    let usage = handler.usage(&typed);
+                    ctx.reply(invocation, &usage);
+                    return false;
+                }
+            }
+        }
+
+        record_usage(invocation, ctx);
+        let msg = &invocation.msg;
+        match (handler, response) {
+            (Some(handler), _) => handler.handle(invocation, ctx).await,
+            (None, Some(response)) => {
+                let text = response.pick(&mut rand::thread_rng());
+                let text = render(text, invocation, ctx);
+                if response.reply {
+                    ctx.reply(invocation, &text);
+                } else {
+                    ctx.say_with_priority(msg.channel_login.clone(), text, Priority::Low);
+                }
+            }
+            (None, None) => {}
+        }
+        true
+    }
+
+    /// Whether every middleware lets the command through.
+    fn allowed(&self, invocation: &Invocation, permission: Permission, ctx: &Context) -> bool {
+        let allowed = self
+            .middleware
+            .iter()
+            .all(|middleware| middleware.allow(invocation, permission, ctx));
+        if allowed {
+            for middleware in &self.middleware {
+                middleware.passed(invocation, permission, ctx);
+            }
+        }
+        allowed
+    }
+
+    /// Lets every middleware know the command was handled.
+    fn handled(&self, invocation: &Invocation, permission: Permission, ctx: &Context) {
+        for middleware in &self.middleware {
+            middleware.handled(invocation, permission, ctx);
+        }
+    }
+}
+
+/// The steps of `command` if it's a chain.
+fn chain<'a>(command: &str, config: &'a FerrisBotConfig) -> Option<&'a [String]> {
+    configured(&config.commands.chains, command).map(Vec::as_slice)
+}
+
+/// The setting for `command` in a map keyed by command names as configured.
+fn configured<'a, T>(settings: &'a HashMap<String, T>, command: &str) -> Option<&'a T> {
+    settings
+        .iter()
+        .find(|(name, _)| command_name(name) == command)
+        .map(|(_, setting)| setting)
+}
+
+fn record_usage(invocation: &Invocation, ctx: &Context) {
+    ctx.usage
+        .lock()
+        .unwrap()
+        .record(&invocation.command, &invocation.msg.sender.login);
+}
+
+/// The answer to a `command` configured for `channel`, defined in the
+/// responses file, or else one added with `!addcmd`.
+fn response(command: &str, channel: &str, ctx: &Context) -> Option<Response> {
+    let configured = ctx
+        .config
+        .commands
+        .channels
+        .get(channel)
+        .and_then(|config| configured(&config.responses, command));
+    if let Some(text) = configured {
+        return Some(Response::new(text.clone(), false));
+    }
+    if let Some(response) = ctx.responses.lock().unwrap().get(command) {
+        return Some(response.clone());
+    }
+    let custom_commands = ctx.custom_commands.lock().unwrap();
+    custom_commands
+        .get(command)
+        .map(|text| Response::new(text.to_owned(), false))
+}
+
+/// Fills in the variables of a response.
+fn render(text: &str, invocation: &Invocation, ctx: &Context) -> String {
+    let variables = Variables {
+        user: &invocation.msg.sender.name,
+        channel: &invocation.msg.channel_login,
+        args: &invocation.rest,
+        count: ctx.usage.lock().unwrap().count(&invocation.command),
+        months: None,
+    };
+    templates::render(text, &variables, &mut rand::thread_rng())
+}
+
+/// The Twitch user `login`, `None` after replying to `invocation` why it
+/// couldn't be found.
+async fn twitch_user(login: &str, invocation: &Invocation, ctx: &Context) -> Option<User> {
+    match ctx.helix.users(&[login]).await {
+        Ok(users) => {
+            let user = users.into_iter().next();
+            if user.is_none() {
+                ctx.reply(invocation, &format!("There is no user {}", login));
+            }
+            user
+        }
+        Err(e) => {
+            error!("Failed to look up {}: {}", login, e);
+            ctx.reply(invocation, UNAVAILABLE);
+            None
+        }
+    }
+}
+
+/// The level needed for `command` as configured, or `default`.
+fn required_permission(command: &str, default: Permission, config: &FerrisBotConfig) -> Permission {
+    config
+        .commands
+        .permissions
+        .iter()
+        .find(|(configured, _)| command_name(configured) == command)
+        .map_or(default, |(_, &permission)| permission)
+}
+
+/// The command `msg` is trying to use, if any, whether it exists or not.
+pub fn invoked_command(msg: &PrivmsgMessage, ctx: &Context) -> Option<String> {
+    let prefix = ctx.config.commands.prefix(&msg.channel_login);
+    let (cmd, _) = split_command(&msg.message_text, prefix)?;
+    Some(resolve_alias(&ctx.config.commands.aliases, cmd))
+}
+
+/// The command at the start of `text` and what comes after it, `None` unless
+/// `text` starts with `prefix` and a command.
+fn split_command<'a>(text: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    let text = text.strip_prefix(prefix)?;
+    let (cmd, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    if cmd.is_empty() {
+        return None;
+    }
+    Some((cmd, rest))
+}
+
+/// The command `cmd` stands for, itself if it's not an alias. Aliases can be
+/// configured with or without the `!` and match case-insensitively.
+fn resolve_alias(aliases: &HashMap<String, String>, cmd: &str) -> String {
+    let cmd = command_name(cmd);
+    aliases
+        .iter()
+        .find(|(alias, _)| command_name(alias) == cmd)
+        .map_or(cmd, |(_, target)| command_name(target))
+}
+
+/// Normalizes a command name, `Queue` and `!queue` both name `!queue`.
+pub fn command_name(name: &str) -> String {
+    format!("!{}", name.trim_start_matches('!').to_lowercase())
+}
+
+/// Splits chat input on whitespace, keeping double-quoted sections together as
+/// a single argument (without the quotes).
+fn tokenize(input: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let (token, remainder) = match rest.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], &rest[end..]),
+                None => (rest, ""),
+            },
+        };
+        tokens.push(token);
+        rest = remainder.trim_start();
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChannelCommandsConfig;
+    use crate::cooldowns::Cooldown;
+    use crate::tests::{
+        drain, test_broadcaster_msg, test_config, test_context, test_context_with, test_msg,
+    };
+    use proptest::prelude::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl CommandHandler for Echo {
+        async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+            ctx.reply(invocation, &invocation.words().join("|"));
+        }
+    }
+
+    fn parse(text: &str) -> Option<Invocation> {
+        Invocation::parse(test_msg(text), "!", &HashMap::new())
+    }
+
+    #[test]
+    fn parsing_invocations() {
+        assert!(parse("regular message text").is_none());
+
+        let invocation = parse("!JOIN Review  help with lifetimes ").unwrap();
+        assert_eq!(invocation.command, "!join");
+        assert_eq!(invocation.rest, "Review  help with lifetimes");
+        assert_eq!(invocation.words(), ["Review", "help", "with", "lifetimes"]);
+
+        let invocation = parse(r#"!poll start "Best editor?" vim "vs code""#).unwrap();
+        assert_eq!(
+            invocation.words(),
+            ["start", "Best editor?", "vim", "vs code"]
+        );
+    }
+
+    #[test]
+    fn resolving_aliases() {
+        let aliases = HashMap::from([
+            ("!q".to_owned(), "!queue".to_owned()),
+            ("j".to_owned(), "Join".to_owned()),
+        ]);
+
+        assert_eq!(resolve_alias(&aliases, "!Q"), "!queue");
+        assert_eq!(resolve_alias(&aliases, "!j"), "!join");
+        assert_eq!(resolve_alias(&aliases, "!queue"), "!queue");
+
+        // the alias is cut off the arguments just like the command would be
+        let invocation = Invocation::parse(test_msg("!j review lifetimes"), "!", &aliases).unwrap();
+        assert_eq!(invocation.command, "!join");
+        assert_eq!(invocation.rest, "review lifetimes");
+    }
+
+    #[tokio::test]
+    async fn dispatching_to_handlers() {
+        let (ctx, mut sent) = test_context();
+        let mut registry = Registry::default();
+        registry.register("Echo", Echo);
+
+        registry.dispatch(test_msg("!echo a \"b c\""), &ctx).await;
+        registry.dispatch(test_msg("!unknown"), &ctx).await;
+        registry.dispatch(test_msg("echo"), &ctx).await;
+        assert_eq!(drain(&mut sent), ["@login: a|b c"]);
+    }
+
+    #[tokio::test]
+    async fn dispatching_responses() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        // from the shipped responses.toml, commands are case-insensitive
+        registry.dispatch(test_msg("!sToNk"), &ctx).await;
+        registry.dispatch(test_msg("!dc"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: yOu shOULd Buy AMC sTOnKS",
+                "https://discord.gg/UyrsFX7N"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatching_custom_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        {
+            let mut custom_commands = ctx.custom_commands.lock().unwrap();
+            custom_commands.add("!repo", "https://github.com/silen-z/ferris-bot".to_owned());
+            custom_commands.add("!stonk", "shadowed".to_owned());
+        }
+
+        registry.dispatch(test_msg("!Repo please"), &ctx).await;
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "https://github.com/silen-z/ferris-bot",
+                "@login: yOu shOULd Buy AMC sTOnKS"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rendering_responses() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        ctx.custom_commands.lock().unwrap().add(
+            "!bonk",
+            "$(user) bonked $(args) in $(channel), $(count) bonks so far".to_owned(),
+        );
+
+        registry.dispatch(test_msg("!bonk"), &ctx).await;
+        registry.dispatch(test_msg("!bonk @ferris"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "name bonked  in channel_login, 1 bonks so far",
+                "name bonked @ferris in channel_login, 2 bonks so far",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatching_chains() {
+        let mut config = test_config();
+        config.commands.chains = HashMap::from([
+            (
+                "dnd".to_owned(),
+                vec!["!closequeue".to_owned(), "!stonk".to_owned()],
+            ),
+            (
+                "!again".to_owned(),
+                vec!["!dnd".to_owned(), "!dc".to_owned()],
+            ),
+        ]);
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_broadcaster_msg("!DND"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "the queue is now closed",
+                "@broadcaster: yOu shOULd Buy AMC sTOnKS"
+            ]
+        );
+
+        // every step needs its own permission, chains don't run chains
+        registry.dispatch(test_msg("!dnd"), &ctx).await;
+        registry.dispatch(test_msg("!again"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: yOu shOULd Buy AMC sTOnKS",
+                "https://discord.gg/UyrsFX7N"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn chains_with_custom_prefixes_and_discord_steps() {
+        let mut config = test_config();
+        config.commands.prefix = Some("?".to_owned());
+        config.commands.chains = HashMap::from([(
+            "brb".to_owned(),
+            vec!["discord: $(user) is away".to_owned(), "?stonk".to_owned()],
+        )]);
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        // posting in Discord is for moderators
+        registry.dispatch(test_msg("?brb"), &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+        // the Discord post fails without a token, the other steps still run
+        registry.dispatch(test_broadcaster_msg("?brb"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            ["@broadcaster: yOu shOULd Buy AMC sTOnKS"]
+        );
+    }
+
+    #[tokio::test]
+    async fn routing_replies() {
+        let mut config = test_config();
+        config
+            .commands
+            .reply_targets
+            .insert("stonk".to_owned(), ReplyTarget::Whisper);
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        drain(&mut sent);
+
+        for text in ["!stonk", "!position", "!leave"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        let whisper = sent.try_recv().unwrap();
+        assert_eq!(whisper.whisper_to.as_deref(), Some("login"));
+        assert_eq!(whisper.text, "yOu shOULd Buy AMC sTOnKS");
+        let thread = sent.try_recv().unwrap();
+        assert_eq!(thread.reply_to, Some(test_msg("").message_id));
+        assert!(!thread.text.starts_with("@login"));
+        // back to chat for commands that don't say otherwise
+        assert_eq!(drain(&mut sent), ["@login: You left the queue"]);
+    }
+
+    #[tokio::test]
+    async fn threading_replies_by_default() {
+        let mut config = test_config();
+        config.commands.reply_target = ReplyTarget::Thread;
+        config
+            .commands
+            .reply_targets
+            .insert("!leave".to_owned(), ReplyTarget::Chat);
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in ["!join", "!leave"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        let thread = sent.try_recv().unwrap();
+        assert_eq!(thread.reply_to, Some(test_msg("").message_id));
+        assert_eq!(thread.text, "Join requested. You are next in the queue");
+        assert_eq!(drain(&mut sent), ["@login: You left the queue"]);
+    }
+
+    #[tokio::test]
+    async fn suggesting_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        // moderator commands are only suggested to moderators
+        for text in ["!queeu", "!STONKS", "!nexxt", "!nonsense"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        registry
+            .dispatch(test_broadcaster_msg("!nexxt"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: Unknown command !queeu, did you mean !queue?",
+                "@login: Unknown command !stonks, did you mean !stonk?",
+                "@broadcaster: Unknown command !nexxt, did you mean !next?",
+            ]
+        );
+
+        let mut config = test_config();
+        config.commands.silence_suggestions = true;
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+        registry.dispatch(test_msg("!queeu"), &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+    }
+
+    #[tokio::test]
+    async fn turning_modules_off() {
+        let mut config = test_config();
+        config.modules.memes = false;
+        config.modules.polls = false;
+        config.modules.events = false;
+        config.events.follow = Some("Thanks for the follow, $(user)!".to_owned());
+        let (ctx, mut sent) = test_context_with(config);
+        ctx.custom_commands
+            .lock()
+            .unwrap()
+            .add("!repo", "https://github.com/silen-z/ferris-bot".to_owned());
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in ["!stonk", "!repo", "segfault", "!addcmd !a b", "!poll end"] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        let follow = Event::Follow {
+            user: "Ferris".to_owned(),
+        };
+        registry.react(&follow, "channel_login", &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        assert_eq!(drain(&mut sent).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dispatching_redemptions() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        drain(&mut sent);
+
+        // rewards are never handled as commands, this one isn't configured
+        let mut msg = test_msg("!join but actually a reward");
+        msg.source.tags.0.insert(
+            "custom-reward-id".to_owned(),
+            Some("f0b5c1c2-reward".to_owned()),
+        );
+        registry.dispatch(msg, &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+    }
+
+    #[tokio::test]
+    async fn configured_permissions() {
+        let (ctx, mut sent) = test_context();
+        let mut registry = Registry::default();
+        registry.register("!code", Echo);
+
+        // !code is limited to subscribers in the sample config
+        registry
+            .dispatch(test_msg("!code fn main() {}"), &ctx)
+            .await;
+        assert!(drain(&mut sent).is_empty());
+        registry
+            .dispatch(test_broadcaster_msg("!code fn main() {}"), &ctx)
+            .await;
+        assert_eq!(drain(&mut sent).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn commands_on_cooldown() {
+        let (ctx, mut sent) = test_context();
+        let mut registry = Registry::default();
+        registry.register("!dave", Echo);
+        registry.register("!zoya", Echo);
+
+        registry.dispatch(test_msg("!dave"), &ctx).await;
+        registry.dispatch(test_msg("!DAVE"), &ctx).await;
+        registry.dispatch(test_msg("!zoya"), &ctx).await;
+        registry.dispatch(test_broadcaster_msg("!dave"), &ctx).await;
+        assert_eq!(drain(&mut sent).len(), 3);
+    }
+
+    #[test]
+    fn tokenizing_arguments() {
+        assert_eq!(tokenize("  a  b c "), vec!["a", "b", "c"]);
+        assert_eq!(
+            tokenize(r#"start "a question" x"#),
+            vec!["start", "a question", "x"]
+        );
+        // an unterminated quote swallows the rest of the input
+        assert_eq!(tokenize(r#"a "b c"#), vec!["a", "b c"]);
+        assert!(tokenize("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn dispatching_edge_cases() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        for text in ["!", "! ", "!\"", "!\"\"", "! !join", "!ż", "!vote ٣"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+            assert!(drain(&mut sent).is_empty(), "{:?}", text);
+        }
+        assert_eq!(tokenize("\"ż\"ó ł"), vec!["ż", "ó", "ł"]);
+        assert_eq!(tokenize("\u{3000}a\u{3000}"), vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn configured_prefixes() {
+        let mut config = test_config();
+        config.commands.prefix = Some("~".to_owned());
+        config.commands.channels.insert(
+            "other_channel".to_owned(),
+            ChannelCommandsConfig {
+                prefix: Some("?".to_owned()),
+                ..Default::default()
+            },
+        );
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        let mut other = test_msg("?STONK");
+        other.channel_login = "other_channel".to_owned();
+        for msg in [
+            test_msg("!stonk"),
+            test_msg("~Stonk"),
+            test_msg("~queue main nope"),
+            other,
+        ] {
+            registry.dispatch(msg, &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: yOu shOULd Buy AMC sTOnKS",
+                "@login: Usage: ~queue [queue] [page]",
+                "@login: yOu shOULd Buy AMC sTOnKS",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn per_channel_commands() {
+        let mut config = test_config();
+        config.commands.channels.insert(
+            "other_channel".to_owned(),
+            ChannelCommandsConfig {
+                disabled: vec!["stonk".to_owned()],
+                cooldowns: HashMap::from([(
+                    "!rules".to_owned(),
+                    Cooldown {
+                        global: Some(60),
+                        user: None,
+                    },
+                )]),
+                responses: HashMap::from([("!Rules".to_owned(), "Be nice".to_owned())]),
+                ..Default::default()
+            },
+        );
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        let in_other = |text| {
+            let mut msg = test_msg(text);
+            msg.channel_login = "other_channel".to_owned();
+            msg
+        };
+        for msg in [
+            in_other("!stonk"),
+            in_other("!rules"),
+            in_other("!rules"),
+            test_msg("!rules"),
+            test_msg("!stonk"),
+        ] {
+            registry.dispatch(msg, &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            ["Be nice", "@login: yOu shOULd Buy AMC sTOnKS"]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_never_panics(text in "\\PC*") {
+            parse(&text);
+        }
+
+        #[test]
+        fn parsing_requires_prefix(text in "[^!]\\PC*") {
+            prop_assert!(parse(&text).is_none());
+        }
+
+        #[test]
+        fn prefixed_commands_never_panic(cmd in "!\\PC{0,10}", rest in "\\PC{0,50}") {
+            if let Some(invocation) = parse(&format!("{} {}", cmd, rest)) {
+                invocation.words();
+            }
+        }
+
+        #[test]
+        fn tokens_are_trimmed_and_non_empty_without_quotes(text in "[^\"]*") {
+            let tokens = tokenize(&text);
+            prop_assert_eq!(tokens, text.split_whitespace().collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn tokens_are_substrings(text in "\\PC*") {
+            for token in tokenize(&text) {
+                prop_assert!(text.contains(token));
+            }
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Typed command arguments. Handlers declare the arguments they expect and
+//! the dispatcher checks them before the handler runs, replying with the
+//! command's usage if they don't match.
+
+use super::tokenize;
+use chrono::Duration;
+use std::num::NonZeroUsize;
+use twitch_queue_bot::queue_manager::DEFAULT_QUEUE;
+
+/// A type arguments can be parsed as.
+pub trait Arg: Sized {
+    fn parse(token: &str) -> Option<Self>;
+}
+
+/// A user name typed in chat, `@Ferris` -> `ferris`.
+#[derive(Debug, PartialEq)]
+pub struct UserName(pub String);
+
+impl Arg for UserName {
+    fn parse(token: &str) -> Option<UserName> {
+        let name = token.trim_start_matches('@');
+        (!name.is_empty()).then(|| UserName(name.to_lowercase()))
+    }
+}
+
+/// A queue name, the default queue if left out. Numbers are never queue names
+/// so they can be told apart from counts and pages.
+#[derive(Debug, PartialEq)]
+pub struct QueueName(pub String);
+
+impl Default for QueueName {
+    fn default() -> QueueName {
+        QueueName(DEFAULT_QUEUE.to_owned())
+    }
+}
+
+impl Arg for QueueName {
+    fn parse(token: &str) -> Option<QueueName> {
+        let is_number = token.chars().all(|c| c.is_ascii_digit());
+        (!is_number).then(|| QueueName(token.to_lowercase()))
+    }
+}
+
+/// `on` or `off`.
+#[derive(Debug, PartialEq)]
+pub struct Toggle(pub bool);
+
+impl Arg for Toggle {
+    fn parse(token: &str) -> Option<Toggle> {
+        match token.to_lowercase().as_str() {
+            "on" => Some(Toggle(true)),
+            "off" => Some(Toggle(false)),
+            _ => None,
+        }
+    }
+}
+
+/// `off`, or a value to turn something on with.
+#[derive(Debug, PartialEq)]
+pub enum Switch<T> {
+    Off,
+    On(T),
+}
+
+impl<T: Arg> Arg for Switch<T> {
+    fn parse(token: &str) -> Option<Switch<T>> {
+        if token.eq_ignore_ascii_case("off") {
+            return Some(Switch::Off);
+        }
+        T::parse(token).map(Switch::On)
+    }
+}
+
+impl Arg for String {
+    fn parse(token: &str) -> Option<String> {
+        Some(token.to_owned())
+    }
+}
+
+impl Arg for usize {
+    fn parse(token: &str) -> Option<usize> {
+        token.parse().ok()
+    }
+}
+
+impl Arg for NonZeroUsize {
+    fn parse(token: &str) -> Option<NonZeroUsize> {
+        token.parse().ok()
+    }
+}
+
+/// Seconds, or a combination of hours, minutes and seconds like `1h30m` or `90s`.
+impl Arg for Duration {
+    fn parse(token: &str) -> Option<Duration> {
+        if let Ok(seconds) = token.parse::<u32>() {
+            return Some(Duration::seconds(seconds.into()));
+        }
+        if token.is_empty() {
+            return None;
+        }
+        // in seconds, checked so absurdly long ones are turned down
+        let mut total: i64 = 0;
+        let mut rest = token;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+            let amount: i64 = rest[..digits].parse().ok()?;
+            let mut unit = rest[digits..].chars();
+            let seconds = match unit.next()?.to_ascii_lowercase() {
+                'h' => amount.checked_mul(3600)?,
+                'm' => amount.checked_mul(60)?,
+                's' => amount,
+                _ => return None,
+            };
+            total = total.checked_add(seconds)?;
+            rest = unit.as_str();
+        }
+        Duration::try_seconds(total)
+    }
+}
+
+/// One argument a command expects.
+pub struct Param {
+    name: &'static str,
+    required: bool,
+    // takes the rest of the message as typed
+    text: bool,
+    accepts: fn(&str) -> bool,
+}
+
+impl Param {
+    pub fn required<T: Arg>(name: &'static str) -> Param {
+        Param {
+            name,
+            required: true,
+            text: false,
+            accepts: accepts::<T>,
+        }
+    }
+
+    pub fn optional<T: Arg>(name: &'static str) -> Param {
+        Param {
+            name,
+            required: false,
+            ..Param::required::<T>(name)
+        }
+    }
+
+    /// Everything left of the message, spaces included.
+    pub fn text(name: &'static str) -> Param {
+        Param {
+            text: true,
+            ..Param::required::<String>(name)
+        }
+    }
+
+    /// Everything left of the message if there's anything left.
+    pub fn optional_text(name: &'static str) -> Param {
+        Param {
+            required: false,
+            ..Param::text(name)
+        }
+    }
+}
+
+fn accepts<T: Arg>(token: &str) -> bool {
+    T::parse(token).is_some()
+}
+
+/// The arguments of an invocation, checked against the command's `Param`s.
+#[derive(Debug, Default)]
+pub struct Args {
+    // one for every param, `None` for optional ones that were left out
+    values: Vec<Option<String>>,
+}
+
+impl Args {
+    /// `None` if `input` doesn't fit `params`. Optional arguments can be left
+    /// out in the middle, `!next 3` is `!next [queue] [count]` without the
+    /// queue. Anything after the last argument is ignored.
+    pub fn parse(params: &[Param], input: &str) -> Option<Args> {
+        let tokens = tokenize(input);
+        let mut values = Vec::with_capacity(params.len());
+        let mut next = 0;
+        // whether the next token was already turned down by an optional param
+        let mut rejected = false;
+
+        for param in params {
+            let token = tokens.get(next);
+            if param.text {
+                let text = token.map_or("", |token| {
+                    let start = token.as_ptr() as usize - input.as_ptr() as usize;
+                    input[start..].trim()
+                });
+                match text {
+                    "" if param.required => return None,
+                    "" => values.push(None),
+                    text => values.push(Some(text.to_owned())),
+                }
+                next = tokens.len();
+                rejected = false;
+                continue;
+            }
+            match token {
+                Some(token) if (param.accepts)(token) => {
+                    values.push(Some(token.to_string()));
+                    next += 1;
+                    rejected = false;
+                }
+                Some(_) if !param.required => {
+                    values.push(None);
+                    rejected = true;
+                }
+                None if !param.required => values.push(None),
+                _ => return None,
+            }
+        }
+
+        (!rejected).then_some(Args { values })
+    }
+
+    /// The argument for the param at `index`, `None` if it was left out.
+    pub fn get<T: Arg>(&self, index: usize) -> Option<T> {
+        self.values.get(index)?.as_deref().and_then(T::parse)
+    }
+
+    /// The argument for the required param at `index`.
+    pub fn value<T: Arg>(&self, index: usize) -> T {
+        self.get(index)
+            .expect("required arguments are checked before the handler runs")
+    }
+}
+
+/// E.g. "Usage: !move <user> <position> [queue]".
+pub fn usage(command: &str, params: &[Param]) -> String {
+    let mut usage = format!("Usage: {}", command);
+    for param in params {
+        if param.required {
+            usage.push_str(&format!(" <{}>", param.name));
+        } else {
+            usage.push_str(&format!(" [{}]", param.name));
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next_params() -> Vec<Param> {
+        vec![
+            Param::optional::<QueueName>("queue"),
+            Param::optional::<NonZeroUsize>("count"),
+        ]
+    }
+
+    #[test]
+    fn parsing_optional_args() {
+        let next = |input| {
+            let args = Args::parse(&next_params(), input)?;
+            Some((
+                args.get::<QueueName>(0).unwrap_or_default().0,
+                args.get::<NonZeroUsize>(1).map(NonZeroUsize::get),
+            ))
+        };
+        assert_eq!(next(""), Some((DEFAULT_QUEUE.into(), None)));
+        assert_eq!(next("4"), Some((DEFAULT_QUEUE.into(), Some(4))));
+        assert_eq!(next("Review"), Some(("review".into(), None)));
+        assert_eq!(next("review 3"), Some(("review".into(), Some(3))));
+        assert_eq!(next("review 3 and more"), Some(("review".into(), Some(3))));
+        // turned down by every param it could have been
+        assert_eq!(next("0"), None);
+        assert_eq!(next("review x"), None);
+    }
+
+    #[test]
+    fn parsing_required_args() {
+        let params = [
+            Param::required::<UserName>("user"),
+            Param::required::<NonZeroUsize>("position"),
+            Param::optional::<QueueName>("queue"),
+        ];
+        let args = Args::parse(&params, "@Alice 2").unwrap();
+        assert_eq!(args.value::<UserName>(0), UserName("alice".into()));
+        assert_eq!(args.value::<NonZeroUsize>(1).get(), 2);
+        assert_eq!(args.get::<QueueName>(2), None);
+
+        assert!(Args::parse(&params, "alice").is_none());
+        assert!(Args::parse(&params, "alice 0").is_none());
+        assert!(Args::parse(&params, "@ 2").is_none());
+    }
+
+    #[test]
+    fn parsing_text() {
+        let params = [Param::required::<String>("name"), Param::text("text")];
+        let args = Args::parse(&params, "!repo  see  https://github.com ").unwrap();
+        assert_eq!(args.value::<String>(0), "!repo");
+        assert_eq!(args.value::<String>(1), "see  https://github.com");
+
+        assert!(Args::parse(&params, "!repo").is_none());
+
+        let params = [
+            Param::required::<UserName>("user"),
+            Param::optional_text("reason"),
+        ];
+        let args = Args::parse(&params, "@Spammer way too many links").unwrap();
+        assert_eq!(args.get::<String>(1).unwrap(), "way too many links");
+        let args = Args::parse(&params, "@Spammer").unwrap();
+        assert_eq!(args.get::<String>(1), None);
+    }
+
+    #[test]
+    fn parsing_durations() {
+        let duration = |token| Duration::parse(token).map(|d| d.num_seconds());
+        assert_eq!(duration("90"), Some(90));
+        assert_eq!(duration("90s"), Some(90));
+        assert_eq!(duration("5m"), Some(300));
+        assert_eq!(duration("1H30m"), Some(5400));
+        assert_eq!(duration("1h30"), None);
+        assert_eq!(duration("m"), None);
+        assert_eq!(duration("1d"), None);
+        assert_eq!(duration(""), None);
+        // too long for a duration, not a reason to crash
+        assert_eq!(duration("9999999999999999h"), None);
+        assert_eq!(duration("9999999999999999s"), None);
+        assert_eq!(duration("99999999999999999999s"), None);
+        assert_eq!(duration("9223372036854775807s1s"), None);
+    }
+
+    #[test]
+    fn toggles() {
+        assert_eq!(Toggle::parse("ON"), Some(Toggle(true)));
+        assert_eq!(Toggle::parse("off"), Some(Toggle(false)));
+        assert_eq!(Toggle::parse("maybe"), None);
+    }
+
+    #[test]
+    fn switches() {
+        assert_eq!(Switch::<Duration>::parse("OFF"), Some(Switch::Off));
+        assert_eq!(
+            Switch::parse("1m30s"),
+            Some(Switch::On(Duration::seconds(90)))
+        );
+        assert_eq!(Switch::<Duration>::parse("soon"), None);
+    }
+
+    #[test]
+    fn describing_usage() {
+        assert_eq!(
+            usage("!next", &next_params()),
+            "Usage: !next [queue] [count]"
+        );
+        assert_eq!(
+            usage(
+                "!addcmd",
+                &[Param::required::<String>("name"), Param::text("text")]
+            ),
+            "Usage: !addcmd <name> <text>"
+        );
+    }
+}
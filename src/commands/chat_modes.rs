@@ -0,0 +1,248 @@
+//! `!slow`, `!emoteonly` and `!followersonly`, switching chat modes through
+//! Helix. Turning a mode off brings back what it was before the bot changed it.
+
+use super::args::{Param, Switch, Toggle};
+use super::{CommandHandler, Invocation, Registry, UNAVAILABLE};
+use crate::helix::ChatSettings;
+use crate::permissions::Permission;
+use crate::{format_duration, Context};
+use async_trait::async_trait;
+use chrono::Duration;
+use log::error;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Seconds slow mode waits unless told otherwise.
+const DEFAULT_SLOW_MODE: u64 = 30;
+
+/// What Twitch allows.
+const MIN_SLOW_MODE: u64 = 3;
+const MAX_SLOW_MODE: u64 = 120;
+const MAX_FOLLOWER_MINUTES: u64 = 129_600;
+
+pub fn register(registry: &mut Registry) {
+    registry.register("!slow", ChatMode::new(Mode::Slow));
+    registry.register("!emoteonly", ChatMode::new(Mode::EmoteOnly));
+    registry.register("!followersonly", ChatMode::new(Mode::FollowersOnly));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Slow,
+    EmoteOnly,
+    FollowersOnly,
+}
+
+impl Mode {
+    /// The mode's setting in `settings`, seconds of slow mode or minutes of
+    /// following, `None` while it's off.
+    fn get(self, settings: &ChatSettings) -> Option<u64> {
+        match self {
+            Mode::Slow => settings.slow_mode_wait_time.filter(|_| settings.slow_mode),
+            Mode::EmoteOnly => settings.emote_mode.then_some(0),
+            Mode::FollowersOnly => settings
+                .follower_mode_duration
+                .filter(|_| settings.follower_mode),
+        }
+    }
+
+    /// The changes to make for the mode to have `setting`.
+    fn changes(self, setting: Option<u64>) -> Value {
+        match (self, setting) {
+            (Mode::Slow, Some(seconds)) => {
+                json!({ "slow_mode": true, "slow_mode_wait_time": seconds })
+            }
+            (Mode::Slow, None) => json!({ "slow_mode": false }),
+            (Mode::EmoteOnly, setting) => json!({ "emote_mode": setting.is_some() }),
+            (Mode::FollowersOnly, Some(minutes)) => {
+                json!({ "follower_mode": true, "follower_mode_duration": minutes })
+            }
+            (Mode::FollowersOnly, None) => json!({ "follower_mode": false }),
+        }
+    }
+
+    fn describe(self, setting: Option<u64>) -> String {
+        match (self, setting) {
+            (Mode::Slow, Some(seconds)) => format!(
+                "Slow mode is on, chatters wait {} between messages",
+                format_duration(Duration::seconds(seconds as i64))
+            ),
+            (Mode::Slow, None) => "Slow mode is off".to_owned(),
+            (Mode::EmoteOnly, Some(_)) => "Emote-only mode is on".to_owned(),
+            (Mode::EmoteOnly, None) => "Emote-only mode is off".to_owned(),
+            (Mode::FollowersOnly, Some(0)) => "Followers-only mode is on".to_owned(),
+            (Mode::FollowersOnly, Some(minutes)) => format!(
+                "Followers-only mode is on, for followers of at least {}",
+                format_duration(Duration::minutes(minutes as i64))
+            ),
+            (Mode::FollowersOnly, None) => "Followers-only mode is off".to_owned(),
+        }
+    }
+}
+
+/// Switches one chat mode, `!slow [duration|off]`, `!emoteonly [on|off]` or
+/// `!followersonly [duration|off]`.
+struct ChatMode {
+    mode: Mode,
+    // the setting before the bot first changed it, keyed by channel login
+    previous: Mutex<HashMap<String, Option<u64>>>,
+}
+
+impl ChatMode {
+    fn new(mode: Mode) -> ChatMode {
+        ChatMode {
+            mode,
+            previous: Mutex::default(),
+        }
+    }
+
+    /// The setting `invocation` asks for, `None` to turn the mode off, or why
+    /// it can't be.
+    fn requested(&self, invocation: &Invocation) -> Result<Option<u64>, &'static str> {
+        if self.mode == Mode::EmoteOnly {
+            let Toggle(on) = invocation.args.get(0).unwrap_or(Toggle(true));
+            return Ok(on.then_some(0));
+        }
+        let duration = match invocation.args.get(0) {
+            None if self.mode == Mode::Slow => return Ok(Some(DEFAULT_SLOW_MODE)),
+            None => Duration::zero(),
+            Some(Switch::Off) => return Ok(None),
+            Some(Switch::On(duration)) => duration,
+        };
+        if self.mode == Mode::Slow {
+            let seconds = duration.num_seconds() as u64;
+            if !(MIN_SLOW_MODE..=MAX_SLOW_MODE).contains(&seconds) {
+                return Err("Slow mode waits between 3 seconds and 2 minutes");
+            }
+            Ok(Some(seconds))
+        } else {
+            let minutes = duration.num_minutes() as u64;
+            if minutes > MAX_FOLLOWER_MINUTES {
+                return Err("Followers-only mode asks for 90 days of following at most");
+            }
+            Ok(Some(minutes))
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for ChatMode {
+    fn description(&self) -> &'static str {
+        match self.mode {
+            Mode::Slow => "Turns slow mode on or back off",
+            Mode::EmoteOnly => "Turns emote-only mode on or back off",
+            Mode::FollowersOnly => "Turns followers-only mode on or back off",
+        }
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        match self.mode {
+            Mode::EmoteOnly => vec![Param::optional::<Toggle>("on|off")],
+            _ => vec![Param::optional::<Switch<Duration>>("duration|off")],
+        }
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let requested = match self.requested(invocation) {
+            Ok(requested) => requested,
+            Err(reason) => return ctx.reply(invocation, reason),
+        };
+        let setting = match requested {
+            Some(setting) => {
+                let current = match ctx.helix.chat_settings(&msg.channel_id).await {
+                    Ok(settings) => self.mode.get(&settings),
+                    Err(e) => {
+                        error!("Failed to look up the chat settings: {}", e);
+                        return ctx.reply(invocation, UNAVAILABLE);
+                    }
+                };
+                let mut previous = self.previous.lock().unwrap();
+                previous.entry(msg.channel_login.clone()).or_insert(current);
+                Some(setting)
+            }
+            // back to how it was
+            None => (self.previous.lock().unwrap())
+                .remove(&msg.channel_login)
+                .flatten(),
+        };
+        let changes = self.mode.changes(setting);
+        let reply = match ctx
+            .helix
+            .update_chat_settings(&msg.channel_id, &changes)
+            .await
+        {
+            Ok(settings) => self.mode.describe(self.mode.get(&settings)),
+            Err(e) => {
+                error!("Failed to change the chat settings: {}", e);
+                UNAVAILABLE.to_owned()
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drain, test_broadcaster_msg, test_context};
+
+    #[test]
+    fn describing_modes() {
+        let settings = ChatSettings {
+            slow_mode: true,
+            slow_mode_wait_time: Some(30),
+            follower_mode: false,
+            follower_mode_duration: None,
+            emote_mode: true,
+        };
+        assert_eq!(Mode::Slow.get(&settings), Some(30));
+        assert_eq!(Mode::EmoteOnly.get(&settings), Some(0));
+        assert_eq!(Mode::FollowersOnly.get(&settings), None);
+        assert_eq!(
+            Mode::Slow.describe(Some(90)),
+            "Slow mode is on, chatters wait 1m 30s between messages"
+        );
+        assert_eq!(
+            Mode::FollowersOnly.describe(Some(600)),
+            "Followers-only mode is on, for followers of at least 10h 0m"
+        );
+        assert_eq!(
+            Mode::FollowersOnly.changes(Some(10)),
+            json!({ "follower_mode": true, "follower_mode_duration": 10 })
+        );
+        assert_eq!(Mode::Slow.changes(None), json!({ "slow_mode": false }));
+    }
+
+    #[tokio::test]
+    async fn switching_modes() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!slow 1s",
+            "!slow 5m",
+            "!followersonly 100d",
+            "!emoteonly maybe",
+            "!slow 10",
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Slow mode waits between 3 seconds and 2 minutes",
+                "@broadcaster: Slow mode waits between 3 seconds and 2 minutes",
+                "@broadcaster: Usage: !followersonly [duration|off]",
+                "@broadcaster: Usage: !emoteonly [on|off]",
+                // the test Helix has no token
+                &format!("@broadcaster: {}", UNAVAILABLE),
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! `!addcounter <name>`, and the commands of every counter: `!<name>` to show
+//! it and `!<name>+` to count one more.
+
+use super::args::Param;
+use super::{CommandHandler, Invocation, Registry};
+use crate::permissions::Permission;
+use crate::Context;
+use async_trait::async_trait;
+
+pub fn register(registry: &mut Registry) {
+    registry.register("!addcounter", AddCounter);
+}
+
+/// `!addcounter <name>`
+struct AddCounter;
+
+#[async_trait]
+impl CommandHandler for AddCounter {
+    fn description(&self) -> &'static str {
+        "Adds a counter, shown with !<name> and counted with !<name>+"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<String>("name")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let name = invocation.args.value::<String>(0);
+        let name = name.trim_start_matches('!').to_lowercase();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return ctx.reply(
+                invocation,
+                "Counter names can only use letters, digits and _",
+            );
+        }
+        let reply = if ctx.counters.lock().unwrap().add(&name) {
+            format!(
+                "Added the counter, use !{0} to show it and !{0}+ to count",
+                name
+            )
+        } else {
+            format!("There already is a counter !{}", name)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!<name>` or `!<name>+` for an existing counter.
+pub struct Counter {
+    name: String,
+    increment: bool,
+}
+
+impl Counter {
+    /// The counter `command` uses, if there is one.
+    pub fn find(command: &str, ctx: &Context) -> Option<Counter> {
+        let name = command.trim_start_matches('!');
+        let (name, increment) = match name.strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        ctx.counters.lock().unwrap().get(name)?;
+        Some(Counter {
+            name: name.to_owned(),
+            increment,
+        })
+    }
+}
+
+#[async_trait]
+impl CommandHandler for Counter {
+    fn permission(&self) -> Permission {
+        if self.increment {
+            Permission::Moderator
+        } else {
+            Permission::Everyone
+        }
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let value = {
+            let mut counters = ctx.counters.lock().unwrap();
+            if self.increment {
+                counters.increment(&self.name)
+            } else {
+                counters.get(&self.name)
+            }
+        };
+        if let Some(value) = value {
+            ctx.say(
+                invocation.msg.channel_login.clone(),
+                format!("{}: {}", self.name, value),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drain, test_broadcaster_msg, test_context, test_msg};
+
+    #[tokio::test]
+    async fn counting() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!deaths",
+            "!addcounter !Deaths",
+            "!addcounter deaths",
+            "!addcounter dea+ths",
+            "!addcounter !",
+            "!deaths+",
+            "!DEATHS+",
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        // only moderators can count
+        registry.dispatch(test_msg("!deaths+"), &ctx).await;
+        registry.dispatch(test_msg("!deaths"), &ctx).await;
+        registry.dispatch(test_msg("!addcounter bonks"), &ctx).await;
+
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Added the counter, use !deaths to show it and !deaths+ to count",
+                "@broadcaster: There already is a counter !deaths",
+                "@broadcaster: Counter names can only use letters, digits and _",
+                "@broadcaster: Counter names can only use letters, digits and _",
+                "deaths: 1",
+                "deaths: 2",
+                "deaths: 2",
+            ]
+        );
+        assert_eq!(ctx.counters.lock().unwrap().get("bonks"), None);
+    }
+}
@@ -0,0 +1,410 @@
+//! Thanks for follows, subscriptions, raids and cheers, and the hype train
+//! announcements, for events from EventSub as well as from chat.
+
+use super::{BotModule, Registry};
+use crate::config::{EventsConfig, FerrisBotConfig};
+use crate::eventsub::{Event, HypeTrainStage};
+use crate::templates::{self, Variables};
+use crate::Context;
+use async_trait::async_trait;
+use log::error;
+use serenity::model::id::ChannelId;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use twitch_irc::message::{UserNoticeEvent, UserNoticeMessage};
+
+#[derive(Default)]
+pub struct Module {
+    // the hype train level last announced, see `hype_train_text`
+    hype_train_level: AtomicU64,
+}
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "events"
+    }
+
+    fn register(&self, _registry: &mut Registry, _config: &FerrisBotConfig) {}
+
+    /// Thanks whoever followed, subscribed, raided or cheered, in Discord as
+    /// well if enabled and it was the main channel.
+    async fn on_event(&self, event: &Event, channel_login: &str, ctx: &Context) {
+        let config = &ctx.config.events;
+        let text = match event {
+            Event::HypeTrain { stage, level } => hype_train_text(
+                *stage,
+                *level,
+                channel_login,
+                &self.hype_train_level,
+                config,
+            ),
+            event => event_text(event, channel_login, config),
+        };
+        let text = match text {
+            Some(text) => text,
+            None => return,
+        };
+        ctx.say(channel_login.to_owned(), text.clone());
+        if config.discord && channel_login == ctx.config.twitch.channel_name {
+            let discord_channel = ChannelId(ctx.config.discord.channel_id);
+            if let Err(e) = discord_channel.say(&ctx.discord_http, text).await {
+                error!("Failed to announce event in Discord: {}", e);
+            }
+        }
+    }
+}
+
+/// How many community gifts are remembered to skip the single gifts they're
+/// made of, which follow right after.
+const COMMUNITY_GIFTS_KEPT: usize = 20;
+
+/// The event a `USERNOTICE` in chat is about, if the bot cares about it.
+/// `community_gifts` are the origin ids of the latest community gifts, the
+/// single gifts sharing one are part of it and left out.
+pub fn chat_event(
+    notice: &UserNoticeMessage,
+    community_gifts: &mut VecDeque<String>,
+) -> Option<Event> {
+    let origin_id = match notice.source.tags.0.get("msg-param-origin-id") {
+        Some(Some(id)) => Some(id.clone()),
+        _ => None,
+    };
+    match &notice.event {
+        UserNoticeEvent::Raid { viewer_count, .. } => Some(Event::Raid {
+            user: notice.sender.name.clone(),
+            viewers: *viewer_count,
+        }),
+        UserNoticeEvent::SubOrResub {
+            cumulative_months, ..
+        } => Some(Event::Subscribe {
+            user: notice.sender.name.clone(),
+            gift: false,
+            months: *cumulative_months,
+        }),
+        UserNoticeEvent::SubMysteryGift {
+            mass_gift_count, ..
+        }
+        | UserNoticeEvent::AnonSubMysteryGift {
+            mass_gift_count, ..
+        } => {
+            if let Some(origin_id) = origin_id {
+                community_gifts.push_back(origin_id);
+                if community_gifts.len() > COMMUNITY_GIFTS_KEPT {
+                    community_gifts.pop_front();
+                }
+            }
+            let user = match &notice.event {
+                UserNoticeEvent::AnonSubMysteryGift { .. } => "An anonymous gifter".to_owned(),
+                _ => notice.sender.name.clone(),
+            };
+            Some(Event::CommunityGift {
+                user,
+                count: *mass_gift_count,
+            })
+        }
+        UserNoticeEvent::SubGift { .. }
+            if origin_id.is_some_and(|id| community_gifts.contains(&id)) =>
+        {
+            None
+        }
+        UserNoticeEvent::SubGift {
+            recipient,
+            cumulative_months,
+            ..
+        } => Some(Event::Subscribe {
+            user: recipient.name.clone(),
+            gift: true,
+            months: *cumulative_months,
+        }),
+        _ => None,
+    }
+}
+
+/// The configured message for `event`, if there is one.
+fn event_text(event: &Event, channel_login: &str, config: &EventsConfig) -> Option<String> {
+    let (template, user, count, months) = match event {
+        Event::Follow { user } => (&config.follow, user, 0, None),
+        Event::Subscribe {
+            user,
+            gift: true,
+            months,
+        } if config.gift_subscribe.is_some() => (&config.gift_subscribe, user, 0, Some(*months)),
+        Event::Subscribe { user, months, .. } => (&config.subscribe, user, 0, Some(*months)),
+        Event::CommunityGift { user, count } => (&config.community_gift, user, *count, None),
+        Event::Raid { user, viewers } => (&config.raid, user, *viewers, None),
+        Event::Cheer { user, bits } if *bits >= config.min_cheer => {
+            (&config.cheer, user, *bits, None)
+        }
+        Event::Cheer { .. } => return None,
+        Event::Redemption { .. }
+        | Event::HypeTrain { .. }
+        | Event::Online { .. }
+        | Event::AutoModHold { .. } => return None,
+    };
+    let variables = Variables {
+        user,
+        channel: channel_login,
+        args: "",
+        count,
+        months,
+    };
+    let template = template.as_ref()?;
+    Some(templates::render(
+        template,
+        &variables,
+        &mut rand::thread_rng(),
+    ))
+}
+
+/// The configured message for a hype train reaching `stage`, progress is only
+/// announced once it reaches a level higher than `announced_level`.
+fn hype_train_text(
+    stage: HypeTrainStage,
+    level: u64,
+    channel_login: &str,
+    announced_level: &AtomicU64,
+    config: &EventsConfig,
+) -> Option<String> {
+    let config = config.hype_train.as_ref()?;
+    let template = match stage {
+        HypeTrainStage::Begin => {
+            announced_level.store(level, Ordering::Relaxed);
+            config.begin.as_ref()
+        }
+        HypeTrainStage::Progress => {
+            if announced_level.fetch_max(level, Ordering::Relaxed) >= level {
+                return None;
+            }
+            (config.levels.get(&level.to_string())).or(config.level.as_ref())
+        }
+        HypeTrainStage::End => {
+            announced_level.store(0, Ordering::Relaxed);
+            config.end.as_ref()
+        }
+    };
+    let variables = Variables {
+        user: "",
+        channel: channel_login,
+        args: "",
+        count: level,
+        months: None,
+    };
+    Some(templates::render(
+        template?,
+        &variables,
+        &mut rand::thread_rng(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HypeTrainConfig;
+    use crate::tests::{drain, test_config, test_context_with};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use twitch_irc::message::IRCMessage;
+
+    #[test]
+    fn hyping_hype_trains() {
+        let mut levels = HashMap::new();
+        levels.insert("3".to_owned(), "Level 3, halfway there!".to_owned());
+        let config = EventsConfig {
+            hype_train: Some(HypeTrainConfig {
+                begin: Some("All aboard the hype train!".to_owned()),
+                level: Some("Hype train level $(count)!".to_owned()),
+                levels,
+                end: Some("The hype train made it to level $(count)!".to_owned()),
+            }),
+            ..EventsConfig::default()
+        };
+        let level = AtomicU64::new(0);
+        let texts: Vec<Option<String>> = [
+            (HypeTrainStage::Begin, 1),
+            (HypeTrainStage::Progress, 1),
+            (HypeTrainStage::Progress, 2),
+            (HypeTrainStage::Progress, 2),
+            (HypeTrainStage::Progress, 3),
+            (HypeTrainStage::End, 3),
+        ]
+        .iter()
+        .map(|&(stage, count)| hype_train_text(stage, count, "channel", &level, &config))
+        .collect();
+        assert_eq!(
+            texts,
+            [
+                Some("All aboard the hype train!".to_owned()),
+                None,
+                Some("Hype train level 2!".to_owned()),
+                None,
+                Some("Level 3, halfway there!".to_owned()),
+                Some("The hype train made it to level 3!".to_owned()),
+            ]
+        );
+        assert_eq!(
+            hype_train_text(
+                HypeTrainStage::Begin,
+                1,
+                "channel",
+                &level,
+                &EventsConfig::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn describing_events() {
+        let config = EventsConfig {
+            follow: Some("Thanks for the follow, $(user)!".to_owned()),
+            subscribe: Some("$(user) just subbed for $(months) months!".to_owned()),
+            gift_subscribe: None,
+            community_gift: None,
+            raid: Some("$(user) is raiding with $(count) viewers".to_owned()),
+            cheer: Some("Thanks for the $(count) bits, $(user)!".to_owned()),
+            min_cheer: 100,
+            discord: false,
+            hype_train: None,
+        };
+        let user = || "Ferris".to_owned();
+        let text = |event| event_text(&event, "channel", &config);
+
+        assert_eq!(
+            text(Event::Follow { user: user() }).unwrap(),
+            "Thanks for the follow, Ferris!"
+        );
+        assert_eq!(
+            text(Event::Subscribe {
+                user: user(),
+                gift: true,
+                months: 3
+            })
+            .unwrap(),
+            "Ferris just subbed for 3 months!"
+        );
+        assert_eq!(
+            text(Event::Raid {
+                user: user(),
+                viewers: 42
+            })
+            .unwrap(),
+            "Ferris is raiding with 42 viewers"
+        );
+        assert_eq!(
+            text(Event::Cheer {
+                user: user(),
+                bits: 100
+            })
+            .unwrap(),
+            "Thanks for the 100 bits, Ferris!"
+        );
+        assert_eq!(
+            text(Event::Cheer {
+                user: user(),
+                bits: 99
+            }),
+            None
+        );
+
+        let config = EventsConfig {
+            follow: None,
+            ..config
+        };
+        assert_eq!(
+            event_text(&Event::Follow { user: user() }, "channel", &config),
+            None
+        );
+        assert!(config.kinds().is_empty());
+    }
+
+    #[tokio::test]
+    async fn welcoming_raids() {
+        let mut config = test_config();
+        config.events.raid = Some("Welcome $(user) and the $(count) raiders!".to_owned());
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        let raid = "@badge-info=;badges=;color=#FF69B4;display-name=Ferris;emotes=;flags=;\
+                    id=bb99dda7-3736-4583-9114-52aa11b23d17;login=ferris;mod=0;msg-id=raid;\
+                    msg-param-displayName=Ferris;msg-param-login=ferris;\
+                    msg-param-profileImageURL=https://example.com/ferris.png;\
+                    msg-param-viewerCount=42;room-id=71092938;subscriber=0;\
+                    system-msg=42\\sraiders\\sfrom\\sFerris\\shave\\sjoined!;\
+                    tmi-sent-ts=1594517796120;user-id=155874595;user-type= \
+                    :tmi.twitch.tv USERNOTICE #channel_login";
+        let notice = UserNoticeMessage::try_from(IRCMessage::parse(raid).unwrap()).unwrap();
+        let event = chat_event(&notice, &mut VecDeque::new()).unwrap();
+        assert_eq!(
+            event,
+            Event::Raid {
+                user: "Ferris".to_owned(),
+                viewers: 42
+            }
+        );
+        registry.react(&event, &notice.channel_login, &ctx).await;
+        assert_eq!(drain(&mut sent), ["Welcome Ferris and the 42 raiders!"]);
+    }
+
+    #[test]
+    fn subscriptions_in_chat() {
+        let notice = |tags: &str| {
+            let source = format!(
+                "@badge-info=;badges=;color=;display-name=Ferris;emotes=;flags=;\
+                 id=e21409b1-d25d-4a1a-b5cf-ef27d8b7030e;login=ferris;mod=0;{};\
+                 room-id=71092938;subscriber=1;system-msg=;tmi-sent-ts=1594583782376;\
+                 user-id=211711554;user-type= :tmi.twitch.tv USERNOTICE #channel_login",
+                tags
+            );
+            UserNoticeMessage::try_from(IRCMessage::parse(&source).unwrap()).unwrap()
+        };
+        let resub = notice(
+            "msg-id=resub;msg-param-cumulative-months=7;msg-param-should-share-streak=0;\
+             msg-param-sub-plan-name=Ferris;msg-param-sub-plan=1000",
+        );
+        let mut community_gifts = VecDeque::new();
+        assert_eq!(
+            chat_event(&resub, &mut community_gifts),
+            Some(Event::Subscribe {
+                user: "Ferris".to_owned(),
+                gift: false,
+                months: 7
+            })
+        );
+        let gift = notice(
+            "msg-id=subgift;msg-param-gift-months=1;msg-param-months=2;\
+             msg-param-recipient-display-name=Corro;msg-param-recipient-id=236653628;\
+             msg-param-recipient-user-name=corro;msg-param-sub-plan-name=Ferris;\
+             msg-param-sub-plan=1000",
+        );
+        assert_eq!(
+            chat_event(&gift, &mut community_gifts),
+            Some(Event::Subscribe {
+                user: "Corro".to_owned(),
+                gift: true,
+                months: 2
+            })
+        );
+
+        // the single gifts of a community gift are only thanked for once
+        let community_gift = notice(
+            "msg-id=submysterygift;msg-param-mass-gift-count=5;msg-param-sender-count=5;\
+             msg-param-origin-id=abc;msg-param-sub-plan=1000",
+        );
+        assert_eq!(
+            chat_event(&community_gift, &mut community_gifts),
+            Some(Event::CommunityGift {
+                user: "Ferris".to_owned(),
+                count: 5
+            })
+        );
+        let part_of_it = notice(
+            "msg-id=subgift;msg-param-gift-months=1;msg-param-months=1;\
+             msg-param-origin-id=abc;msg-param-recipient-display-name=Corro;\
+             msg-param-recipient-id=236653628;msg-param-recipient-user-name=corro;\
+             msg-param-sub-plan-name=Ferris;msg-param-sub-plan=1000",
+        );
+        assert_eq!(chat_event(&part_of_it, &mut community_gifts), None);
+        assert!(chat_event(&gift, &mut community_gifts).is_some());
+    }
+}
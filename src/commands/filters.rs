@@ -0,0 +1,318 @@
+//! Chat filters removing messages moderators don't want in chat, run by the
+//! moderation module before anything else sees a message.
+
+use crate::config::{BannedPhrasesConfig, LinkFilterConfig, SpamFilterConfig};
+use crate::permissions::Permission;
+use crate::Context;
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use regex::Regex;
+use std::collections::HashMap;
+use twitch_irc::message::PrivmsgMessage;
+
+/// Web addresses with a scheme or `www.`, or a domain with a common top-level
+/// domain, e.g. `ferris.rs/crab`.
+const LINK_PATTERN: &str =
+    r"(?i)\b(https?://|www\.)\S+|\b[a-z0-9-]+\.(com|net|org|io|tv|gg|rs|dev|me|co|ly|xyz)\b";
+
+/// Messages with links, from users below the exempt role.
+pub struct LinkFilter {
+    patterns: Vec<Regex>,
+    exempt: Permission,
+    /// How long a `!permit` lasts.
+    pub permit: Duration,
+    timeout: Option<Duration>,
+}
+
+impl LinkFilter {
+    pub fn new(config: &LinkFilterConfig) -> Result<LinkFilter, regex::Error> {
+        let patterns = match config.patterns.as_slice() {
+            [] => vec![Regex::new(LINK_PATTERN)?],
+            patterns => patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(LinkFilter {
+            patterns,
+            exempt: config.exempt,
+            permit: Duration::seconds(config.permit_seconds),
+            timeout: config.timeout.map(Duration::seconds),
+        })
+    }
+
+    /// Whether `msg` has a link its sender may not post.
+    pub fn catches(&self, msg: &PrivmsgMessage, ctx: &Context) -> bool {
+        ctx.permission(msg) < self.exempt
+            && self
+                .patterns
+                .iter()
+                .any(|pattern| pattern.is_match(&msg.message_text))
+    }
+
+    /// Removes `msg` and tells its sender why.
+    pub async fn punish(&self, msg: &PrivmsgMessage, ctx: &Context) {
+        let warning = "Please ask a moderator for a !permit before posting links";
+        punish(msg, self.timeout, warning, ctx).await;
+    }
+}
+
+/// Messages shouting, repeating characters or flooding emotes.
+pub struct SpamFilter {
+    max_caps_percent: Option<usize>,
+    caps_min_letters: usize,
+    max_repeated: Option<usize>,
+    max_emotes: Option<usize>,
+    exempt: Permission,
+    timeout: Option<Duration>,
+}
+
+impl SpamFilter {
+    pub fn new(config: &SpamFilterConfig) -> SpamFilter {
+        SpamFilter {
+            max_caps_percent: config.max_caps_percent.map(|percent| percent as usize),
+            caps_min_letters: config.caps_min_letters,
+            max_repeated: config.max_repeated,
+            max_emotes: config.max_emotes,
+            exempt: config.exempt,
+            timeout: config.timeout.map(Duration::seconds),
+        }
+    }
+
+    /// The warning for the sender of `msg` if it breaks a rule.
+    pub fn check(&self, msg: &PrivmsgMessage, ctx: &Context) -> Option<&'static str> {
+        if ctx.permission(msg) >= self.exempt {
+            return None;
+        }
+        let exceeds = |limit: Option<usize>, value: usize| limit.is_some_and(|max| value > max);
+        if exceeds(self.max_emotes, msg.emotes.len()) {
+            return Some("Please don't spam emotes");
+        }
+        if exceeds(self.max_repeated, longest_repeat(&msg.message_text)) {
+            return Some("Please don't spam characters");
+        }
+        let (letters, caps) = letters_and_caps(msg);
+        if letters >= self.caps_min_letters
+            && exceeds(self.max_caps_percent, caps * 100 / letters.max(1))
+        {
+            return Some("Please don't shout");
+        }
+        None
+    }
+
+    /// Removes `msg` and gives its sender `warning`.
+    pub async fn punish(&self, msg: &PrivmsgMessage, warning: &str, ctx: &Context) {
+        punish(msg, self.timeout, warning, ctx).await;
+    }
+}
+
+/// Messages with a phrase banned with `!addbanned`, from users below the
+/// exempt role.
+pub struct BannedPhraseFilter {
+    exempt: Permission,
+    timeout: Option<Duration>,
+}
+
+impl BannedPhraseFilter {
+    pub fn new(config: &BannedPhrasesConfig) -> BannedPhraseFilter {
+        BannedPhraseFilter {
+            exempt: config.exempt,
+            timeout: config.timeout.map(Duration::seconds),
+        }
+    }
+
+    /// Whether `msg` has a banned phrase its sender may not use.
+    pub fn catches(&self, msg: &PrivmsgMessage, ctx: &Context) -> bool {
+        ctx.permission(msg) < self.exempt
+            && ctx
+                .banned_phrases
+                .lock()
+                .unwrap()
+                .find(&msg.message_text)
+                .is_some()
+    }
+
+    /// Removes `msg` and tells its sender why, without repeating the phrase.
+    pub async fn punish(&self, msg: &PrivmsgMessage, ctx: &Context) {
+        let warning = "That message had a banned phrase in it";
+        punish(msg, self.timeout, warning, ctx).await;
+    }
+}
+
+/// The most times a character other than a space is repeated in a row.
+fn longest_repeat(text: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut last = None;
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        current = if last == Some(c) { current + 1 } else { 1 };
+        longest = longest.max(current);
+        last = Some(c);
+    }
+    longest
+}
+
+/// How many letters `msg` has outside of emotes, and how many of them are
+/// capitals.
+fn letters_and_caps(msg: &PrivmsgMessage) -> (usize, usize) {
+    let in_emote = |index: usize| {
+        msg.emotes
+            .iter()
+            .any(|emote| emote.char_range.contains(&index))
+    };
+    let letters: Vec<char> = msg
+        .message_text
+        .chars()
+        .enumerate()
+        .filter(|&(index, c)| c.is_alphabetic() && !in_emote(index))
+        .map(|(_, c)| c)
+        .collect();
+    let caps = letters.iter().filter(|c| c.is_uppercase()).count();
+    (letters.len(), caps)
+}
+
+/// Users allowed to post links for a while, keyed by channel and login.
+#[derive(Default)]
+pub struct Permits {
+    until: HashMap<(String, String), DateTime<Utc>>,
+}
+
+impl Permits {
+    pub fn grant(&mut self, channel: &str, user: &str, until: DateTime<Utc>) {
+        self.until
+            .insert((channel.to_owned(), user.to_owned()), until);
+    }
+
+    pub fn allows(&mut self, channel: &str, user: &str, now: DateTime<Utc>) -> bool {
+        self.until.retain(|_, until| *until > now);
+        self.until
+            .contains_key(&(channel.to_owned(), user.to_owned()))
+    }
+}
+
+/// Deletes `msg`, or times its sender out for `timeout` if set, which removes
+/// their messages as well, and tells them why with `warning`.
+async fn punish(msg: &PrivmsgMessage, timeout: Option<Duration>, warning: &str, ctx: &Context) {
+    info!(
+        "Removing a message by {} in {}: {}",
+        msg.sender.login, msg.channel_login, msg.message_text
+    );
+    ctx.reply_to(msg, None, warning);
+    let removed = match timeout {
+        Some(timeout) => {
+            ctx.helix
+                .ban(&msg.channel_id, &msg.sender.id, Some(timeout), warning)
+                .await
+        }
+        None => {
+            ctx.helix
+                .delete_message(&msg.channel_id, &msg.message_id)
+                .await
+        }
+    };
+    if let Err(e) = removed {
+        error!("Failed to remove a message by {}: {}", msg.sender.login, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_broadcaster_msg, test_context, test_msg};
+    use twitch_irc::message::Emote;
+
+    fn filter(patterns: &[&str]) -> LinkFilter {
+        LinkFilter::new(&LinkFilterConfig {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            exempt: Permission::Vip,
+            permit_seconds: 60,
+            timeout: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn catching_links() {
+        let (ctx, _) = test_context();
+        let links = filter(&[]);
+        for text in [
+            "see https://example.com/page",
+            "www.example.org",
+            "go to ferris.rs now",
+            "discord.gg/abc",
+        ] {
+            assert!(links.catches(&test_msg(text), &ctx), "{}", text);
+        }
+        for text in ["no links here", "version 1.2.3", "e.g. this", "!join"] {
+            assert!(!links.catches(&test_msg(text), &ctx), "{}", text);
+        }
+        assert!(!links.catches(&test_broadcaster_msg("https://example.com"), &ctx));
+
+        let custom = filter(&[r"bit\.ly"]);
+        assert!(custom.catches(&test_msg("bit.ly/x"), &ctx));
+        assert!(!custom.catches(&test_msg("https://example.com"), &ctx));
+    }
+
+    #[test]
+    fn catching_spam() {
+        let spam = SpamFilter::new(&SpamFilterConfig {
+            max_caps_percent: Some(70),
+            caps_min_letters: 10,
+            max_repeated: Some(5),
+            max_emotes: Some(2),
+            exempt: Permission::Vip,
+            timeout: None,
+        });
+        let (ctx, _) = test_context();
+        let check = |text| spam.check(&test_msg(text), &ctx);
+        assert_eq!(check("THIS IS SO COOL"), Some("Please don't shout"));
+        assert_eq!(check("SHORT YAY"), None);
+        assert_eq!(check("This Is Mostly Fine"), None);
+        assert_eq!(check("nooooooo"), Some("Please don't spam characters"));
+        assert_eq!(check("nooooo"), None);
+        assert_eq!(check("!!!!!! ???"), Some("Please don't spam characters"));
+        assert_eq!(
+            spam.check(&test_broadcaster_msg("NOOOOOOOOOOOOO"), &ctx),
+            None
+        );
+
+        let mut msg = test_msg("Kappa Kappa Kappa");
+        msg.emotes = (0..3)
+            .map(|i| Emote {
+                id: "25".to_owned(),
+                char_range: i * 6..i * 6 + 5,
+                code: "Kappa".to_owned(),
+            })
+            .collect();
+        assert_eq!(spam.check(&msg, &ctx), Some("Please don't spam emotes"));
+        msg.emotes.pop();
+        assert_eq!(spam.check(&msg, &ctx), None);
+
+        // emotes aren't shouting
+        let mut msg = test_msg("LUL LUL LUL LUL wow");
+        msg.emotes = (0..4)
+            .map(|i| Emote {
+                id: "425618".to_owned(),
+                char_range: i * 4..i * 4 + 3,
+                code: "LUL".to_owned(),
+            })
+            .collect();
+        let spam = SpamFilter {
+            max_emotes: None,
+            caps_min_letters: 1,
+            ..spam
+        };
+        assert_eq!(spam.check(&msg, &ctx), None);
+    }
+
+    #[test]
+    fn expiring_permits() {
+        let mut permits = Permits::default();
+        let now = Utc::now();
+        permits.grant("channel", "alice", now + Duration::seconds(60));
+        assert!(permits.allows("channel", "alice", now));
+        assert!(!permits.allows("other", "alice", now));
+        assert!(!permits.allows("channel", "bob", now));
+        assert!(!permits.allows("channel", "alice", now + Duration::seconds(60)));
+    }
+}
@@ -0,0 +1,187 @@
+//! `!help`, listing the commands the caller may use, and `!help <command>`
+//! describing one of them.
+
+use super::args::Param;
+use super::{
+    chain, command_name, required_permission, resolve_alias, response, CommandHandler, Invocation,
+    Registry,
+};
+use crate::permissions::Permission;
+use crate::Context;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub fn register(registry: &mut Registry) {
+    let mut entries: Vec<Entry> = registry
+        .handlers
+        .iter()
+        .map(|(command, handler)| Entry::of(command, handler.as_ref()))
+        .collect();
+    let help = Help::default();
+    entries.push(Entry::of("!help", &help));
+    entries.push(Entry::of("!commands", &help));
+    entries.sort_by(|a, b| a.command.cmp(&b.command));
+
+    let help = Help {
+        entries: Arc::new(entries),
+    };
+    registry.register("!help", help.clone());
+    registry.register("!commands", help);
+}
+
+/// What `!help` knows about a built-in command.
+struct Entry {
+    command: String,
+    permission: Permission,
+    usage: String,
+    description: &'static str,
+}
+
+impl Entry {
+    fn of(command: &str, handler: &dyn CommandHandler) -> Entry {
+        Entry {
+            command: command.to_owned(),
+            permission: handler.permission(),
+            usage: handler.usage(command),
+            description: handler.description(),
+        }
+    }
+}
+
+/// `!help [command]`, also registered as `!commands`.
+#[derive(Clone, Default)]
+struct Help {
+    entries: Arc<Vec<Entry>>,
+}
+
+impl Help {
+    /// Every command the sender of `invocation` may use, sorted.
+    fn available(&self, invocation: &Invocation, ctx: &Context) -> Vec<String> {
+        let level = ctx.permission(&invocation.msg);
+        let allowed =
+            |command: &str, default| required_permission(command, default, &ctx.config) <= level;
+
+        let mut commands: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|entry| allowed(&entry.command, entry.permission))
+            .map(|entry| entry.command.clone())
+            .collect();
+        let responses = ctx.responses.lock().unwrap();
+        let custom_commands = ctx.custom_commands.lock().unwrap();
+        let commands_config = &ctx.config.commands;
+        let chains = commands_config.chains.keys().map(String::as_str);
+        let channel_responses = commands_config
+            .channels
+            .get(&invocation.msg.channel_login)
+            .into_iter()
+            .flat_map(|config| config.responses.keys().map(String::as_str));
+        for command in chains
+            .chain(channel_responses)
+            .chain(responses.names())
+            .chain(custom_commands.names())
+        {
+            let command = &command_name(command);
+            if allowed(command, Permission::Everyone) && !commands.iter().any(|c| c == command) {
+                commands.push(command.to_owned());
+            }
+        }
+        commands.sort();
+        commands
+    }
+
+    /// What `!help <command>` answers with in `channel`.
+    fn describe(&self, command: &str, channel: &str, ctx: &Context) -> String {
+        let command = resolve_alias(&ctx.config.commands.aliases, &command_name(command));
+        if let Some(entry) = self.entries.iter().find(|entry| entry.command == command) {
+            return match entry.description {
+                "" => entry.usage.clone(),
+                description => format!("{} - {}", entry.usage, description),
+            };
+        }
+        if let Some(steps) = chain(&command, &ctx.config) {
+            return format!("{} runs {}", command, steps.join(", "));
+        }
+        if response(&command, channel, ctx).is_some() {
+            format!("{} answers with a fixed text", command)
+        } else {
+            format!("There is no command {}", command)
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for Help {
+    fn description(&self) -> &'static str {
+        "Lists the commands you can use, or describes one"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<String>("command")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let reply = match invocation.args.get::<String>(0) {
+            Some(command) => self.describe(&command, &invocation.msg.channel_login, ctx),
+            None => format!(
+                "Commands: {}. Use !help <command> for details",
+                self.available(invocation, ctx).join(", ")
+            ),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drain, test_broadcaster_msg, test_context, test_msg};
+
+    #[tokio::test]
+    async fn listing_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!commands"), &ctx).await;
+        let everyone = drain(&mut sent).concat();
+        registry.dispatch(test_broadcaster_msg("!help"), &ctx).await;
+        let broadcaster = drain(&mut sent).concat();
+
+        for listed in ["!join", "!help", "!stonk", "!here", "!dnd"] {
+            assert!(everyone.contains(listed), "{}", listed);
+        }
+        // moderator commands and ones limited in the config are left out
+        for hidden in ["!next", "!mute", "!code"] {
+            assert!(!everyone.contains(hidden), "{}", hidden);
+            assert!(broadcaster.contains(hidden), "{}", hidden);
+        }
+    }
+
+    #[tokio::test]
+    async fn describing_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!help move",
+            "!help !Q",
+            "!help !stonk",
+            "!help !nope",
+            "!help join",
+            "!help dnd",
+        ] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: Usage: !move <user> <position> [queue] - Moves a user to a position, starting at 1",
+                "@login: Usage: !queue [queue] [page] - Lists who is waiting",
+                "@login: !stonk answers with a fixed text",
+                "@login: There is no command !nope",
+                "@login: Usage: !join [queue] [note] - Joins a queue, optionally with a note",
+                "@login: !dnd runs !closequeue, !discord",
+            ]
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Custom commands, counters, the response commands file, keyword triggers
+//! and lurking: the bot's sense of humour.
+
+use super::args::Param;
+use super::triggers::Trigger;
+use super::{command_name, counters, BotModule, CommandHandler, Invocation, Registry};
+use crate::config::FerrisBotConfig;
+use crate::cooldowns::Cooldowns;
+use crate::permissions::Permission;
+use crate::{Context, Priority, MAX_MESSAGE_LENGTH};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{debug, info};
+use rand::seq::SliceRandom;
+use std::sync::Mutex;
+use twitch_irc::message::PrivmsgMessage;
+
+pub struct Module {
+    triggers: Vec<Trigger>,
+    // of the triggers, commands have theirs in the registry
+    cooldowns: Mutex<Cooldowns>,
+}
+
+impl Module {
+    /// Panics if a trigger in `config` is invalid.
+    pub fn new(config: &FerrisBotConfig) -> Module {
+        let triggers = config
+            .commands
+            .triggers
+            .iter()
+            .map(|trigger| {
+                Trigger::new(trigger).unwrap_or_else(|e| panic!("Invalid trigger: {}", e))
+            })
+            .collect();
+        Module {
+            triggers,
+            cooldowns: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "memes"
+    }
+
+    fn register(&self, registry: &mut Registry, _config: &FerrisBotConfig) {
+        registry.responses = true;
+        registry.register("!addcmd", AddCommand);
+        registry.register("!editcmd", EditCommand);
+        registry.register("!delcmd", RemoveCommand);
+        registry.register("!lurk", Lurk);
+        registry.register("!unlurk", Unlurk);
+        counters::register(registry);
+    }
+
+    /// Answers messages that aren't commands if they match a trigger that
+    /// isn't on cooldown.
+    async fn on_message(&self, msg: &PrivmsgMessage, ctx: &Context) -> bool {
+        let prefix = ctx.config.commands.prefix(&msg.channel_login);
+        if msg.message_text.starts_with(prefix) {
+            return false;
+        }
+        let trigger = match self.triggers.iter().find(|t| t.matches(&msg.message_text)) {
+            Some(trigger) => trigger,
+            None => return false,
+        };
+        let allowed = ctx.permission(msg) >= Permission::Moderator
+            || self.cooldowns.lock().unwrap().try_use(
+                trigger.key(),
+                &msg.sender.login,
+                &trigger.cooldown,
+                Utc::now(),
+            );
+        if !allowed {
+            debug!("Trigger {} is on cooldown", trigger.key());
+        } else if trigger.reply {
+            ctx.reply_to(msg, None, &trigger.text);
+        } else {
+            ctx.say_with_priority(
+                msg.channel_login.clone(),
+                trigger.text.clone(),
+                Priority::Low,
+            );
+        }
+        true
+    }
+}
+
+/// `!addcmd !name <text>`
+struct AddCommand;
+
+#[async_trait]
+impl CommandHandler for AddCommand {
+    fn description(&self) -> &'static str {
+        "Adds or replaces a custom command"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<String>("name"), Param::text("text")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let name = command_name(&invocation.args.value::<String>(0));
+        let text: String = invocation.args.value(1);
+        if let Some(error) = length_error(&text) {
+            return ctx.reply(invocation, &error);
+        }
+        info!("{} set {} to {:?}", msg.sender.login, name, text);
+        let replaced = ctx.custom_commands.lock().unwrap().add(&name, text);
+        let reply = if replaced {
+            format!("Updated {}", name)
+        } else {
+            format!("Added {}", name)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// Why `text` can't be a custom command's text, if it can't.
+fn length_error(text: &str) -> Option<String> {
+    (text.chars().count() > MAX_MESSAGE_LENGTH)
+        .then(|| format!("Commands can be at most {} characters", MAX_MESSAGE_LENGTH))
+}
+
+/// `!editcmd !name <text>`
+struct EditCommand;
+
+#[async_trait]
+impl CommandHandler for EditCommand {
+    fn description(&self) -> &'static str {
+        "Changes the text of a custom command"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<String>("name"), Param::text("text")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let name = command_name(&invocation.args.value::<String>(0));
+        let text: String = invocation.args.value(1);
+        if let Some(error) = length_error(&text) {
+            return ctx.reply(invocation, &error);
+        }
+        let reply = if ctx
+            .custom_commands
+            .lock()
+            .unwrap()
+            .edit(&name, text.clone())
+        {
+            info!("{} changed {} to {:?}", msg.sender.login, name, text);
+            format!("Updated {}", name)
+        } else {
+            format!("There is no command {}, add it with !addcmd", name)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!delcmd !name`
+struct RemoveCommand;
+
+#[async_trait]
+impl CommandHandler for RemoveCommand {
+    fn description(&self) -> &'static str {
+        "Removes a custom command"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<String>("name")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let name = command_name(&invocation.args.value::<String>(0));
+        let reply = if ctx.custom_commands.lock().unwrap().remove(&name) {
+            info!("{} removed {}", msg.sender.login, name);
+            format!("Removed {}", name)
+        } else {
+            format!("There is no command {}", name)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// What `!lurk` answers with, `{}` being the lurker's name.
+const LURK_REPLIES: &[&str] = &[
+    "{} fades into the background. Enjoy the lurk!",
+    "{} is now lurking. We'll keep the chair warm",
+    "{} slips into the shadows, thanks for hanging out!",
+    "{} has entered lurk mode. Don't forget to hydrate",
+];
+
+/// `!lurk`
+struct Lurk;
+
+#[async_trait]
+impl CommandHandler for Lurk {
+    fn description(&self) -> &'static str {
+        "Lets everyone know you're lurking"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let lurked = ctx
+            .lurkers
+            .lock()
+            .unwrap()
+            .lurk(&msg.channel_login, &msg.sender.login);
+        let reply = if lurked {
+            LURK_REPLIES
+                .choose(&mut rand::thread_rng())
+                .unwrap()
+                .replace("{}", &msg.sender.name)
+        } else {
+            "You're already lurking".to_owned()
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!unlurk`
+struct Unlurk;
+
+#[async_trait]
+impl CommandHandler for Unlurk {
+    fn description(&self) -> &'static str {
+        "Lets everyone know you're back"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let unlurked = ctx
+            .lurkers
+            .lock()
+            .unwrap()
+            .unlurk(&msg.channel_login, &msg.sender.login);
+        let reply = if unlurked {
+            format!("Welcome back, {}!", msg.sender.name)
+        } else {
+            "You weren't lurking".to_owned()
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{
+        drain, test_broadcaster_msg, test_config, test_context, test_context_with, test_msg,
+    };
+
+    #[tokio::test]
+    async fn adding_custom_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!addcmd Repo  https://github.com/silen-z/ferris-bot ",
+            "!addcmd !repo",
+            "!repo",
+            "!editcmd !Repo https://github.com/rust-lang/rust",
+            "!repo",
+            "!delcmd !REPO",
+            "!editcmd !repo text",
+            "!delcmd !repo",
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Added !repo",
+                "@broadcaster: Usage: !addcmd <name> <text>",
+                "https://github.com/silen-z/ferris-bot",
+                "@broadcaster: Updated !repo",
+                "https://github.com/rust-lang/rust",
+                "@broadcaster: Removed !repo",
+                "@broadcaster: There is no command !repo, add it with !addcmd",
+                "@broadcaster: There is no command !repo",
+            ]
+        );
+
+        registry
+            .dispatch(test_msg("!addcmd !repo text"), &ctx)
+            .await;
+        assert!(ctx.custom_commands.lock().unwrap().get("!repo").is_none());
+    }
+
+    #[tokio::test]
+    async fn builtin_responses() {
+        let mut config = test_config();
+        config.commands.responses_path = None;
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        registry.dispatch(test_msg("!discord"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: yOu shOULd Buy AMC sTOnKS",
+                "https://discord.gg/UyrsFX7N"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lurking() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!lurk"), &ctx).await;
+        let reply = drain(&mut sent).join("\n");
+        assert!(reply.starts_with("@login: name "));
+        assert_eq!(ctx.lurkers.lock().unwrap().count("channel_login"), 1);
+
+        for text in ["!lurk", "!unlurk", "!unlurk"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: You're already lurking",
+                "@login: Welcome back, name!",
+                "@login: You weren't lurking",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatching_triggers() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        // the sample config's triggers
+        for text in [
+            "yet another Segfault",
+            "segfault again",
+            "!stonk segfault",
+            "python > rust",
+            "python is rust",
+        ] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        registry
+            .dispatch(test_broadcaster_msg("SEGFAULT"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "Have you tried rewriting it in Rust?",
+                "@login: yOu shOULd Buy AMC sTOnKS",
+                "@login: This must be Lord",
+                "@login: This must be Lord",
+                "Have you tried rewriting it in Rust?",
+            ]
+        );
+    }
+}
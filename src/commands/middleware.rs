@@ -0,0 +1,326 @@
+//! The checks every command goes through before it's handled, in order:
+//! logging, whether it's disabled, permissions, role cooldowns, command
+//! cooldowns and the audit log. Each is a
+//! `Middleware`, so handlers don't have to repeat them.
+
+use super::{command_name, configured, required_permission, Invocation};
+use crate::audit::Entry;
+use crate::cooldowns::{Cooldown, Cooldowns};
+use crate::disabled_commands::DisabledCommands;
+use crate::permissions::Permission;
+use crate::Context;
+use chrono::Utc;
+use log::debug;
+use std::sync::{Arc, Mutex};
+
+/// A step between parsing a command and handling it.
+pub trait Middleware: Send + Sync {
+    /// Whether the command may go on to the next step and then its handler.
+    /// `permission` is the one the handler needs unless configured otherwise.
+    fn allow(&self, invocation: &Invocation, permission: Permission, ctx: &Context) -> bool;
+
+    /// Called once every step allowed the command, so nothing is counted for
+    /// commands a later step stops.
+    fn passed(&self, _invocation: &Invocation, _permission: Permission, _ctx: &Context) {}
+
+    /// Called after the command's handler ran.
+    fn handled(&self, _invocation: &Invocation, _permission: Permission, _ctx: &Context) {}
+}
+
+/// The built-in steps, with `disabled` shared with `!disable` and `!enable`.
+pub fn builtin(disabled: &Arc<Mutex<DisabledCommands>>) -> Vec<Box<dyn Middleware>> {
+    vec![
+        Box::new(Logging),
+        Box::new(Disabled {
+            disabled: disabled.clone(),
+        }),
+        Box::new(Permissions),
+        Box::new(RoleCooldowns::default()),
+        Box::new(CooldownCheck::default()),
+        Box::new(Audit),
+    ]
+}
+
+/// Logs every command used.
+struct Logging;
+
+impl Middleware for Logging {
+    fn allow(&self, invocation: &Invocation, _permission: Permission, _ctx: &Context) -> bool {
+        debug!(
+            "{} used {} in {}",
+            invocation.msg.sender.login, invocation.command, invocation.msg.channel_login
+        );
+        true
+    }
+}
+
+/// Stops commands turned off with `!disable` or in the channel's config.
+struct Disabled {
+    disabled: Arc<Mutex<DisabledCommands>>,
+}
+
+impl Middleware for Disabled {
+    fn allow(&self, invocation: &Invocation, _permission: Permission, ctx: &Context) -> bool {
+        let disabled_here = ctx
+            .config
+            .commands
+            .channels
+            .get(&invocation.msg.channel_login)
+            .is_some_and(|config| {
+                config
+                    .disabled
+                    .iter()
+                    .any(|command| command_name(command) == invocation.command)
+            });
+        let disabled = self.disabled.lock().unwrap();
+        let channel = &invocation.msg.channel_login;
+        if disabled_here || disabled.contains(channel, &invocation.command) {
+            debug!("{} is disabled", invocation.command);
+            return false;
+        }
+        true
+    }
+}
+
+/// Stops commands their caller may not use.
+struct Permissions;
+
+impl Middleware for Permissions {
+    fn allow(&self, invocation: &Invocation, permission: Permission, ctx: &Context) -> bool {
+        let required = required_permission(&invocation.command, permission, &ctx.config);
+        if ctx.permission(&invocation.msg) < required {
+            debug!(
+                "{} may not use {}",
+                invocation.msg.sender.login, invocation.msg.message_text
+            );
+            return false;
+        }
+        true
+    }
+}
+
+/// Stops any command when the caller's role used them too recently, counts
+/// this use once every step allowed it.
+#[derive(Default)]
+struct RoleCooldowns {
+    // keyed by channel and role
+    cooldowns: Mutex<Cooldowns<(String, Permission)>>,
+}
+
+impl RoleCooldowns {
+    /// The cooldown of the caller's role in the channel, `None` if they're not
+    /// held back.
+    fn bucket<'a>(
+        invocation: &Invocation,
+        ctx: &'a Context,
+    ) -> Option<((String, Permission), &'a Cooldown)> {
+        let permission = ctx.permission(&invocation.msg);
+        if permission == Permission::Broadcaster {
+            return None;
+        }
+        let (&role, cooldown) = ctx
+            .config
+            .commands
+            .role_cooldowns
+            .range(..=permission)
+            .next_back()?;
+        Some(((invocation.msg.channel_login.clone(), role), cooldown))
+    }
+}
+
+impl Middleware for RoleCooldowns {
+    fn allow(&self, invocation: &Invocation, _permission: Permission, ctx: &Context) -> bool {
+        let (key, cooldown) = match RoleCooldowns::bucket(invocation, ctx) {
+            Some(bucket) => bucket,
+            None => return true,
+        };
+        let allowed = self.cooldowns.lock().unwrap().ready(
+            &key,
+            &invocation.msg.sender.login,
+            cooldown,
+            Utc::now(),
+        );
+        if !allowed {
+            debug!(
+                "{} is on cooldown for {:?}",
+                invocation.msg.sender.login, key.1
+            );
+        }
+        allowed
+    }
+
+    fn passed(&self, invocation: &Invocation, _permission: Permission, ctx: &Context) {
+        if let Some((key, _)) = RoleCooldowns::bucket(invocation, ctx) {
+            (self.cooldowns.lock().unwrap()).record(&key, &invocation.msg.sender.login, Utc::now());
+        }
+    }
+}
+
+/// Stops commands used too recently, counts this use once every step allowed
+/// it. Moderators are never held back.
+#[derive(Default)]
+struct CooldownCheck {
+    // keyed by channel and command
+    cooldowns: Mutex<Cooldowns<(String, String)>>,
+}
+
+impl CooldownCheck {
+    /// The cooldown of the command in its channel, `None` if it has none or
+    /// the caller isn't held back.
+    fn cooldown<'a>(
+        invocation: &Invocation,
+        ctx: &'a Context,
+    ) -> Option<((String, String), &'a Cooldown)> {
+        if ctx.permission(&invocation.msg) >= Permission::Moderator {
+            return None;
+        }
+        let commands = &ctx.config.commands;
+        let channel = &invocation.msg.channel_login;
+        let cooldown = commands
+            .channels
+            .get(channel)
+            .and_then(|config| configured(&config.cooldowns, &invocation.command))
+            .or_else(|| configured(&commands.cooldowns, &invocation.command))?;
+        // channels don't hold each other back
+        Some(((channel.clone(), invocation.command.clone()), cooldown))
+    }
+}
+
+impl Middleware for CooldownCheck {
+    fn allow(&self, invocation: &Invocation, _permission: Permission, ctx: &Context) -> bool {
+        let (key, cooldown) = match CooldownCheck::cooldown(invocation, ctx) {
+            Some(cooldown) => cooldown,
+            None => return true,
+        };
+        let allowed = self.cooldowns.lock().unwrap().ready(
+            &key,
+            &invocation.msg.sender.login,
+            cooldown,
+            Utc::now(),
+        );
+        if !allowed {
+            debug!("{} is on cooldown", invocation.msg.message_text);
+        }
+        allowed
+    }
+
+    fn passed(&self, invocation: &Invocation, _permission: Permission, ctx: &Context) {
+        if let Some((key, _)) = CooldownCheck::cooldown(invocation, ctx) {
+            (self.cooldowns.lock().unwrap()).record(&key, &invocation.msg.sender.login, Utc::now());
+        }
+    }
+}
+
+/// Adds commands that need a moderator or more to the audit log, once they
+/// ran.
+struct Audit;
+
+impl Middleware for Audit {
+    fn allow(&self, _invocation: &Invocation, _permission: Permission, _ctx: &Context) -> bool {
+        true
+    }
+
+    fn handled(&self, invocation: &Invocation, permission: Permission, ctx: &Context) {
+        let msg = &invocation.msg;
+        if required_permission(&invocation.command, permission, &ctx.config)
+            >= Permission::Moderator
+        {
+            ctx.audit.lock().unwrap().record(Entry {
+                time: Utc::now(),
+                channel: msg.channel_login.clone(),
+                user: msg.sender.login.clone(),
+                command: msg.message_text.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::Registry;
+    use crate::cooldowns::Cooldown;
+    use crate::permissions::Permission;
+    use crate::tests::{drain, test_broadcaster_msg, test_config, test_context_with, test_msg};
+
+    #[tokio::test]
+    async fn checks_run_in_order() {
+        let mut config = test_config();
+        config.commands.cooldowns.insert(
+            "!stonk".to_owned(),
+            Cooldown {
+                global: Some(60),
+                user: None,
+            },
+        );
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        // turned down before the cooldown check, so it doesn't count
+        let disabled = &registry.disabled;
+        disabled.lock().unwrap().disable("channel_login", "!stonk");
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        disabled.lock().unwrap().enable("channel_login", "!stonk");
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        assert_eq!(drain(&mut sent), ["@login: yOu shOULd Buy AMC sTOnKS"]);
+    }
+
+    #[tokio::test]
+    async fn role_cooldowns() {
+        let mut config = test_config();
+        let cooldown = |user| Cooldown {
+            global: None,
+            user: Some(user),
+        };
+        config
+            .commands
+            .role_cooldowns
+            .insert(Permission::Everyone, cooldown(60));
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        // any command counts, for every user on their own
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        registry.dispatch(test_msg("!queue"), &ctx).await;
+        let mut alice = test_msg("!stonk");
+        alice.sender.login = "alice".to_owned();
+        registry.dispatch(alice, &ctx).await;
+        // the broadcaster isn't limited
+        registry
+            .dispatch(test_broadcaster_msg("!stonk"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!stonk"), &ctx)
+            .await;
+        assert_eq!(drain(&mut sent).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn stopped_commands_dont_count() {
+        let mut config = test_config();
+        config.commands.role_cooldowns.insert(
+            Permission::Everyone,
+            Cooldown {
+                global: None,
+                user: Some(60),
+            },
+        );
+        config.commands.cooldowns.insert(
+            "!stonk".to_owned(),
+            Cooldown {
+                global: Some(60),
+                user: None,
+            },
+        );
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        let mut alice = test_msg("!stonk");
+        alice.sender.login = "alice".to_owned();
+        registry.dispatch(alice, &ctx).await;
+        // stopped by the command's cooldown, so their role's isn't used up
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        registry.dispatch(test_msg("!queue"), &ctx).await;
+        assert_eq!(drain(&mut sent).len(), 2);
+    }
+}
@@ -0,0 +1,740 @@
+//! Controlling the bot itself: muting it, turning commands off and seeing
+//! which ones are used, and by whom. Also timeouts and bans through the bot,
+//! chat modes and the chat filters, and posting the messages AutoMod held
+//! to Discord.
+
+use super::args::{Param, UserName};
+use super::filters::{BannedPhraseFilter, LinkFilter, Permits, SpamFilter};
+use super::{
+    chat_modes, toggles, twitch_user, BotModule, CommandHandler, Invocation, Registry, UNAVAILABLE,
+};
+use crate::config::FerrisBotConfig;
+use crate::discord_commands;
+use crate::eventsub::Event;
+use crate::permissions::Permission;
+use crate::{format_duration, Context};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use log::{error, info};
+use serenity::model::id::ChannelId;
+use serenity::model::interactions::message_component::ButtonStyle;
+use std::num::NonZeroUsize;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use twitch_irc::message::PrivmsgMessage;
+
+/// How many commands `!topcommands` lists.
+const TOP_COMMANDS: usize = 5;
+
+/// How many entries `!audit` shows unless asked for more.
+const AUDIT_ENTRIES: usize = 5;
+
+pub struct Module {
+    links: Option<Arc<LinkFilter>>,
+    spam: Option<SpamFilter>,
+    banned_phrases: Option<BannedPhraseFilter>,
+    // shared with `!permit`
+    permits: Arc<Mutex<Permits>>,
+}
+
+impl Module {
+    /// Panics if a filter in `config` is invalid.
+    pub fn new(config: &FerrisBotConfig) -> Module {
+        let links = config.moderation.links.as_ref().map(|links| {
+            let filter = LinkFilter::new(links)
+                .unwrap_or_else(|e| panic!("Invalid link filter pattern: {}", e));
+            Arc::new(filter)
+        });
+        Module {
+            links,
+            spam: config.moderation.spam.as_ref().map(SpamFilter::new),
+            banned_phrases: config
+                .moderation
+                .banned_phrases
+                .as_ref()
+                .map(BannedPhraseFilter::new),
+            permits: Arc::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "moderation"
+    }
+
+    fn register(&self, registry: &mut Registry, config: &FerrisBotConfig) {
+        if let Some(links) = &self.links {
+            registry.register(
+                "!permit",
+                Permit {
+                    duration: links.permit,
+                    permits: self.permits.clone(),
+                },
+            );
+        }
+        if self.banned_phrases.is_some() {
+            registry.register("!addbanned", AddBanned);
+            registry.register("!delbanned", DelBanned);
+        }
+        registry.register("!topcommands", TopCommands);
+        registry.register("!chatstats", ChatStats);
+        registry.register("!audit", Audit);
+        registry.register("!mute", Mute);
+        registry.register("!unmute", Unmute);
+        registry.register("!timeout", Timeout);
+        registry.register("!ban", Ban);
+        toggles::register(registry, config);
+        chat_modes::register(registry);
+    }
+
+    /// Removes messages caught by a filter, they aren't handled any further.
+    async fn on_message(&self, msg: &PrivmsgMessage, ctx: &Context) -> bool {
+        if let Some(banned_phrases) = &self.banned_phrases {
+            if banned_phrases.catches(msg, ctx) {
+                banned_phrases.punish(msg, ctx).await;
+                return true;
+            }
+        }
+        if let Some(links) = &self.links {
+            let caught = links.catches(msg, ctx)
+                && !self.permits.lock().unwrap().allows(
+                    &msg.channel_login,
+                    &msg.sender.login,
+                    Utc::now(),
+                );
+            if caught {
+                links.punish(msg, ctx).await;
+                return true;
+            }
+        }
+        if let Some(spam) = &self.spam {
+            if let Some(warning) = spam.check(msg, ctx) {
+                spam.punish(msg, warning, ctx).await;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn removes_messages(&self) -> bool {
+        true
+    }
+
+    async fn on_event(&self, event: &Event, _channel_login: &str, ctx: &Context) {
+        if let Event::AutoModHold {
+            message_id,
+            user,
+            text,
+            category,
+        } = event
+        {
+            held_by_automod(message_id, user, text, category, ctx).await;
+        }
+    }
+}
+
+/// Posts a message by `user` AutoMod held for `category` to Discord, with
+/// buttons for moderators to approve or deny it.
+async fn held_by_automod(message_id: &str, user: &str, text: &str, category: &str, ctx: &Context) {
+    let config = match &ctx.config.moderation.automod {
+        Some(config) => config,
+        None => return,
+    };
+    let text = format!(
+        "AutoMod held a message by {} for {}: {}",
+        user, category, text
+    );
+    let sent = ChannelId(config.discord_channel_id)
+        .send_message(&ctx.discord_http, |message| {
+            message
+                .content(text)
+                // chatters could ping everyone otherwise
+                .allowed_mentions(|mentions| mentions.empty_parse())
+                .components(|components| {
+                    components.create_action_row(|row| {
+                        row.create_button(|button| {
+                            button
+                                .style(ButtonStyle::Success)
+                                .label("Approve")
+                                .custom_id(discord_commands::automod_button_id(message_id, true))
+                        })
+                        .create_button(|button| {
+                            button
+                                .style(ButtonStyle::Danger)
+                                .label("Deny")
+                                .custom_id(discord_commands::automod_button_id(message_id, false))
+                        })
+                    })
+                })
+        })
+        .await;
+    if let Err(e) = sent {
+        error!("Failed to post a held message in Discord: {}", e);
+    }
+}
+
+/// `!permit <user>`, lets a user post links for a while.
+struct Permit {
+    duration: Duration,
+    permits: Arc<Mutex<Permits>>,
+}
+
+#[async_trait]
+impl CommandHandler for Permit {
+    fn description(&self) -> &'static str {
+        "Lets a user post links for a while"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<UserName>("user")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(user) = invocation.args.value(0);
+        let until = Utc::now() + self.duration;
+        self.permits
+            .lock()
+            .unwrap()
+            .grant(&msg.channel_login, &user, until);
+        ctx.say(
+            msg.channel_login.clone(),
+            format!(
+                "{} may post links for the next {}",
+                user,
+                format_duration(self.duration)
+            ),
+        );
+    }
+}
+
+/// `!addbanned <phrase>`, removes messages with the phrase from now on.
+struct AddBanned;
+
+#[async_trait]
+impl CommandHandler for AddBanned {
+    fn description(&self) -> &'static str {
+        "Bans a phrase from chat"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::text("phrase")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let phrase: String = invocation.args.value(0);
+        let reply = if ctx.banned_phrases.lock().unwrap().add(&phrase) {
+            "Phrase banned"
+        } else {
+            "That phrase is already banned"
+        };
+        ctx.reply(invocation, reply);
+    }
+}
+
+/// `!delbanned <phrase>`
+struct DelBanned;
+
+#[async_trait]
+impl CommandHandler for DelBanned {
+    fn description(&self) -> &'static str {
+        "Allows a banned phrase again"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::text("phrase")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let phrase: String = invocation.args.value(0);
+        let reply = if ctx.banned_phrases.lock().unwrap().remove(&phrase) {
+            "Phrase allowed again"
+        } else {
+            "That phrase isn't banned"
+        };
+        ctx.reply(invocation, reply);
+    }
+}
+
+/// `!topcommands`, the most used commands since the bot started.
+struct TopCommands;
+
+#[async_trait]
+impl CommandHandler for TopCommands {
+    fn description(&self) -> &'static str {
+        "Lists the most used commands"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let summary = ctx.usage.lock().unwrap().summary(TOP_COMMANDS);
+        ctx.reply(invocation, &summary);
+    }
+}
+
+/// `!chatstats`, how lively chat was lately.
+struct ChatStats;
+
+#[async_trait]
+impl CommandHandler for ChatStats {
+    fn description(&self) -> &'static str {
+        "Shows how active chat was lately"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let summary = ctx
+            .chat_stats
+            .lock()
+            .unwrap()
+            .summary(&msg.channel_login, Utc::now());
+        let lurking = ctx.lurkers.lock().unwrap().count(&msg.channel_login);
+        ctx.reply(invocation, &format!("{}. {} lurking", summary, lurking));
+    }
+}
+
+/// `!audit [count]`, the latest uses of moderator commands in the channel.
+struct Audit;
+
+#[async_trait]
+impl CommandHandler for Audit {
+    fn description(&self) -> &'static str {
+        "Lists who used moderator commands lately"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<NonZeroUsize>("count")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let count = invocation
+            .args
+            .get(0)
+            .map_or(AUDIT_ENTRIES, NonZeroUsize::get);
+        let entries: Vec<String> = ctx
+            .audit
+            .lock()
+            .unwrap()
+            .latest(&msg.channel_login, count)
+            .into_iter()
+            .map(|entry| {
+                format!(
+                    "[{}] {}: {}",
+                    entry.time.format("%Y-%m-%d %H:%M UTC"),
+                    entry.user,
+                    entry.command
+                )
+            })
+            .collect();
+        if entries.is_empty() {
+            return ctx.reply(invocation, "No moderator commands were used yet");
+        }
+        ctx.reply(invocation, &entries.join(", "));
+    }
+}
+
+/// `!mute`, suppresses all chat output in the channel while commands keep
+/// working.
+struct Mute;
+
+#[async_trait]
+impl CommandHandler for Mute {
+    fn description(&self) -> &'static str {
+        "Stops the bot from talking"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Broadcaster
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        ctx.muted[&msg.channel_login].store(true, Ordering::Relaxed);
+        info!(
+            "Muted in {} by {}, chat output is suppressed",
+            msg.channel_login, msg.sender.login
+        );
+    }
+}
+
+/// `!unmute`
+struct Unmute;
+
+#[async_trait]
+impl CommandHandler for Unmute {
+    fn description(&self) -> &'static str {
+        "Lets the bot talk again"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Broadcaster
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        ctx.muted[&msg.channel_login].store(false, Ordering::Relaxed);
+        info!("Unmuted in {} by {}", msg.channel_login, msg.sender.login);
+        ctx.reply(invocation, "I'm back!");
+    }
+}
+
+/// `!timeout <user> <duration> [reason]`
+struct Timeout;
+
+#[async_trait]
+impl CommandHandler for Timeout {
+    fn description(&self) -> &'static str {
+        "Times a user out"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::required::<UserName>("user"),
+            Param::required::<Duration>("duration"),
+            Param::optional_text("reason"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let duration: Duration = invocation.args.value(1);
+        // what Twitch allows
+        if duration < Duration::seconds(1) || duration > Duration::weeks(2) {
+            return ctx.reply(invocation, "Timeouts last between 1 second and 2 weeks");
+        }
+        ban(invocation, Some(duration), ctx).await;
+    }
+}
+
+/// `!ban <user> [reason]`
+struct Ban;
+
+#[async_trait]
+impl CommandHandler for Ban {
+    fn description(&self) -> &'static str {
+        "Bans a user"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::required::<UserName>("user"),
+            Param::optional_text("reason"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        ban(invocation, None, ctx).await;
+    }
+}
+
+/// Bans the user given to `invocation`, for `duration` if given, and tells
+/// Discord if enabled. The reason is the invocation's last argument.
+async fn ban(invocation: &Invocation, duration: Option<Duration>, ctx: &Context) {
+    let msg = &invocation.msg;
+    let UserName(login) = invocation.args.value(0);
+    let reason: String = invocation
+        .args
+        .get(if duration.is_some() { 2 } else { 1 })
+        .unwrap_or_default();
+    let user = match twitch_user(&login, invocation, ctx).await {
+        Some(user) => user,
+        None => return,
+    };
+    let banned = ctx
+        .helix
+        .ban(&msg.channel_id, &user.id, duration, &reason)
+        .await;
+    if let Err(e) = banned {
+        error!("Failed to ban {}: {}", login, e);
+        return ctx.reply(invocation, UNAVAILABLE);
+    }
+    let action = match duration {
+        Some(duration) => format!("timed out {} for {}", login, format_duration(duration)),
+        None => format!("banned {}", login),
+    };
+    ctx.reply(invocation, &format!("You {}", action));
+    if ctx.config.commands.moderation_to_discord {
+        let mut announcement = format!("{} {} in {}", msg.sender.name, action, msg.channel_login);
+        if !reason.is_empty() {
+            announcement.push_str(&format!(": {}", reason));
+        }
+        let discord_channel = ChannelId(ctx.config.discord.channel_id);
+        if let Err(e) = discord_channel.say(&ctx.discord_http, announcement).await {
+            error!("Failed to announce a ban in Discord: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BannedPhrasesConfig, LinkFilterConfig};
+    use crate::tests::{
+        drain, test_broadcaster_msg, test_config, test_context, test_context_with, test_msg,
+    };
+    use twitch_queue_bot::queue_manager::DEFAULT_QUEUE;
+
+    #[tokio::test]
+    async fn listing_top_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!join",
+            "!queue",
+            "!stonk",
+            "!queue",
+            "!unknown",
+            "!leave x y",
+        ] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        drain(&mut sent);
+        registry
+            .dispatch(test_broadcaster_msg("!topcommands"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            ["@broadcaster: 6 command(s) used by 2 user(s), most used: \
+              !queue (2), !join (1), !leave (1), !stonk (1), !topcommands (1)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn muted_joins_still_queue() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_broadcaster_msg("!mute"), &ctx).await;
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        assert_eq!(
+            ctx.queue_manager("channel_login")
+                .lock()
+                .unwrap()
+                .queue(DEFAULT_QUEUE)
+                .unwrap(),
+            ["login"]
+        );
+        assert!(drain(&mut sent).is_empty());
+
+        registry.dispatch(test_msg("!leave"), &ctx).await;
+        assert!(ctx
+            .queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .queue(DEFAULT_QUEUE)
+            .unwrap()
+            .is_empty());
+        assert!(drain(&mut sent).is_empty());
+
+        registry
+            .dispatch(test_broadcaster_msg("!unmute"), &ctx)
+            .await;
+        assert_eq!(drain(&mut sent), ["@broadcaster: I'm back!"]);
+    }
+
+    #[tokio::test]
+    async fn muting_per_channel() {
+        let mut config = test_config();
+        config.twitch.extra_channels = vec!["other_channel".to_owned()];
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        let mut mute = test_broadcaster_msg("!mute");
+        mute.channel_login = "other_channel".to_owned();
+        registry.dispatch(mute, &ctx).await;
+        assert!(ctx.is_muted("other_channel"));
+        assert!(!ctx.is_muted("channel_login"));
+
+        let mut other = test_msg("!stonk");
+        other.channel_login = "other_channel".to_owned();
+        registry.dispatch(other, &ctx).await;
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        assert_eq!(drain(&mut sent), ["@login: yOu shOULd Buy AMC sTOnKS"]);
+    }
+
+    #[tokio::test]
+    async fn only_broadcaster_can_mute() {
+        let (ctx, _sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!mute"), &ctx).await;
+        assert!(!ctx.is_muted("channel_login"));
+    }
+
+    #[tokio::test]
+    async fn auditing_moderator_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in ["!join", "!next", "!disable !dave"] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        // not allowed, so not audited
+        registry.dispatch(test_msg("!clear"), &ctx).await;
+        drain(&mut sent);
+        registry
+            .dispatch(test_broadcaster_msg("!audit 3"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!audit 3"), &ctx)
+            .await;
+        let replies = drain(&mut sent);
+        let entries = |reply: &str| -> Vec<String> {
+            reply
+                .trim_start_matches("@broadcaster: ")
+                .split(", ")
+                .map(|entry| entry.split("] ").nth(1).unwrap().to_owned())
+                .collect()
+        };
+        assert_eq!(
+            entries(&replies[0]),
+            ["broadcaster: !next", "broadcaster: !disable !dave"]
+        );
+        // it's a moderator command too, recorded once it ran
+        assert_eq!(
+            entries(&replies[1]),
+            [
+                "broadcaster: !next",
+                "broadcaster: !disable !dave",
+                "broadcaster: !audit 3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn timeouts_and_bans() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!ban spammer"), &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+
+        registry
+            .dispatch(test_broadcaster_msg("!timeout spammer 400h"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!timeout spammer"), &ctx)
+            .await;
+        // the test context has no token, so Helix can't be asked
+        registry
+            .dispatch(test_broadcaster_msg("!ban spammer links"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Timeouts last between 1 second and 2 weeks",
+                "@broadcaster: Usage: !timeout <user> <duration> [reason]",
+                &format!("@broadcaster: {}", UNAVAILABLE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn filtering_links() {
+        let mut config = test_config();
+        config.moderation.links = Some(LinkFilterConfig {
+            patterns: Vec::new(),
+            exempt: Permission::Vip,
+            permit_seconds: 60,
+            timeout: None,
+        });
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        // not handled as a command either
+        registry
+            .dispatch(test_msg("!join https://example.com"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!permit @Login"), &ctx)
+            .await;
+        registry
+            .dispatch(test_msg("!join https://example.com"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: Please ask a moderator for a !permit before posting links",
+                "login may post links for the next 1m 0s",
+                "@login: Join requested. You are next in the queue"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn banning_phrases() {
+        let mut config = test_config();
+        config.moderation.banned_phrases = Some(BannedPhrasesConfig {
+            path: None,
+            exempt: Permission::Moderator,
+            timeout: None,
+        });
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!addbanned scam"), &ctx).await;
+        registry
+            .dispatch(test_broadcaster_msg("!addbanned Buy Followers"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!addbanned buy followers"), &ctx)
+            .await;
+        // moderators may still say it
+        registry
+            .dispatch(test_broadcaster_msg("!delbanned scam"), &ctx)
+            .await;
+        assert!(
+            !registry
+                .dispatch(test_msg("!join to BUY FOLLOWERS"), &ctx)
+                .await
+        );
+        registry
+            .dispatch(test_broadcaster_msg("!delbanned buy followers"), &ctx)
+            .await;
+        assert!(registry.dispatch(test_msg("buy followers"), &ctx).await);
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Phrase banned",
+                "@broadcaster: That phrase is already banned",
+                "@broadcaster: That phrase isn't banned",
+                "@login: That message had a banned phrase in it",
+                "@broadcaster: Phrase allowed again"
+            ]
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! `!poll` and `!vote`, sharing the poll that's currently running, and
+//! `!prediction`. Polls can be run by the bot or by Twitch.
+
+use super::args::Arg;
+use super::{BotModule, CommandHandler, Invocation, Registry, UNAVAILABLE};
+use crate::config::FerrisBotConfig;
+use crate::permissions::Permission;
+use crate::poll::{Poll, VoteError};
+use crate::twitch_polls::{prediction_results, Kind, Running};
+use crate::{format_duration, Context};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use log::error;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+/// Twitch's limits on polls: how many choices, how long they run and how long
+/// the question and each choice may be.
+const POLL_CHOICES: RangeInclusive<usize> = 2..=5;
+const POLL_SECONDS: RangeInclusive<i64> = 15..=1800;
+const POLL_QUESTION_LENGTH: usize = 60;
+
+/// Twitch's limits on predictions, like the ones on polls. The duration is
+/// how long viewers can predict.
+const PREDICTION_OUTCOMES: RangeInclusive<usize> = 2..=10;
+const PREDICTION_SECONDS: RangeInclusive<i64> = 30..=1800;
+const PREDICTION_QUESTION_LENGTH: usize = 45;
+
+const CHOICE_LENGTH: usize = 25;
+
+pub struct Module;
+
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "polls"
+    }
+
+    fn register(&self, registry: &mut Registry, _config: &FerrisBotConfig) {
+        let current = Arc::new(Mutex::new(None));
+        registry.register(
+            "!poll",
+            PollCommand {
+                current: current.clone(),
+            },
+        );
+        registry.register("!vote", Vote { current });
+        registry.register("!prediction", PredictionCommand);
+    }
+}
+
+/// `!poll start "Question" option1 option2 ...` or `!poll end`, or
+/// `!poll "Question" option1|option2 <duration>` for a poll run by Twitch.
+struct PollCommand {
+    current: Arc<Mutex<Option<Poll>>>,
+}
+
+#[async_trait]
+impl CommandHandler for PollCommand {
+    fn description(&self) -> &'static str {
+        "Starts or ends a poll"
+    }
+
+    fn usage(&self, command: &str) -> String {
+        format!(
+            "Usage: {0} start \"Question\" option1 option2 ..., {0} end \
+             or {0} \"Question\" option1|option2 <duration>",
+            command
+        )
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        match invocation.words().as_slice() {
+            ["start", question, options @ ..] if options.len() >= 2 => {
+                let mut poll = self.current.lock().unwrap();
+                if poll.is_some() {
+                    return ctx.reply(
+                        invocation,
+                        "A poll is already running, close it with !poll end",
+                    );
+                }
+
+                let choices: Vec<String> = options
+                    .iter()
+                    .enumerate()
+                    .map(|(i, option)| format!("{}) {}", i + 1, option))
+                    .collect();
+                ctx.say(
+                    msg.channel_login.clone(),
+                    format!(
+                        "Poll: {} {} - vote with !vote <number>",
+                        question,
+                        choices.join(" ")
+                    ),
+                );
+                *poll = Some(Poll::new(
+                    question.to_string(),
+                    options.iter().map(|option| option.to_string()).collect(),
+                    ctx.config.poll.revote,
+                ));
+            }
+            ["end"] => {
+                let poll = self.current.lock().unwrap().take();
+                match poll {
+                    Some(poll) => ctx.say(msg.channel_login.clone(), poll.summary()),
+                    None => ctx.reply(invocation, "There is no poll running"),
+                }
+            }
+            [question, choices, duration] if choices.contains('|') => {
+                let duration = Duration::parse(duration);
+                start_twitch_poll(question, choices, duration, invocation, ctx).await
+            }
+            _ => ctx.reply(invocation, &self.usage(&invocation.command)),
+        }
+    }
+}
+
+/// Starts a poll run by Twitch, its results are announced once it ends.
+async fn start_twitch_poll(
+    question: &str,
+    choices: &str,
+    duration: Option<Duration>,
+    invocation: &Invocation,
+    ctx: &Context,
+) {
+    let msg = &invocation.msg;
+    let choices = match split_choices(question, choices, POLL_QUESTION_LENGTH, POLL_CHOICES) {
+        Ok(choices) => choices,
+        Err(error) => return ctx.reply(invocation, &error),
+    };
+    let duration = match duration {
+        Some(duration) if POLL_SECONDS.contains(&duration.num_seconds()) => duration,
+        _ => return ctx.reply(invocation, "Polls last between 15 seconds and 30 minutes"),
+    };
+    let poll = ctx
+        .helix
+        .create_poll(&msg.channel_id, question, &choices, duration)
+        .await;
+    let poll = match poll {
+        Ok(poll) => poll,
+        Err(e) => {
+            error!("Failed to start a poll in {}: {}", msg.channel_login, e);
+            return ctx.reply(invocation, UNAVAILABLE);
+        }
+    };
+    ctx.twitch_polls.lock().unwrap().add(Running {
+        kind: Kind::Poll,
+        id: poll.id,
+        channel_login: msg.channel_login.clone(),
+        broadcaster_id: msg.channel_id.clone(),
+        check_at: Utc::now() + duration,
+    });
+    ctx.reply(
+        invocation,
+        &format!("Poll started, it ends in {}", format_duration(duration)),
+    );
+}
+
+/// The choices separated by `|`, or why they or the question aren't allowed.
+fn split_choices<'a>(
+    question: &str,
+    choices: &'a str,
+    question_length: usize,
+    count: RangeInclusive<usize>,
+) -> Result<Vec<&'a str>, String> {
+    let choices: Vec<&str> = choices
+        .split('|')
+        .map(str::trim)
+        .filter(|choice| !choice.is_empty())
+        .collect();
+    if !count.contains(&choices.len()) {
+        return Err(format!(
+            "There need to be between {} and {} choices",
+            count.start(),
+            count.end()
+        ));
+    }
+    if question.chars().count() > question_length {
+        return Err(format!(
+            "The question can't be longer than {} characters",
+            question_length
+        ));
+    }
+    if choices
+        .iter()
+        .any(|choice| choice.chars().count() > CHOICE_LENGTH)
+    {
+        return Err(format!(
+            "Choices can't be longer than {} characters",
+            CHOICE_LENGTH
+        ));
+    }
+    Ok(choices)
+}
+
+/// `!prediction "Question" outcome1|outcome2 <duration>`, then
+/// `!prediction win <number>` or `!prediction cancel`. Predictions are run by
+/// Twitch, how they turned out is announced once they're resolved.
+struct PredictionCommand;
+
+#[async_trait]
+impl CommandHandler for PredictionCommand {
+    fn description(&self) -> &'static str {
+        "Starts or resolves a prediction"
+    }
+
+    fn usage(&self, command: &str) -> String {
+        format!(
+            "Usage: {0} \"Question\" outcome1|outcome2 <duration>, {0} win <number> \
+             or {0} cancel",
+            command
+        )
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        match invocation.words().as_slice() {
+            ["win", number] => match number.parse() {
+                Ok(number) => end_prediction(Some(number), invocation, ctx).await,
+                Err(_) => ctx.reply(invocation, &self.usage(&invocation.command)),
+            },
+            ["cancel"] => end_prediction(None, invocation, ctx).await,
+            [question, outcomes, window] if outcomes.contains('|') => {
+                let window = Duration::parse(window);
+                start_prediction(question, outcomes, window, invocation, ctx).await
+            }
+            _ => ctx.reply(invocation, &self.usage(&invocation.command)),
+        }
+    }
+}
+
+async fn start_prediction(
+    question: &str,
+    outcomes: &str,
+    window: Option<Duration>,
+    invocation: &Invocation,
+    ctx: &Context,
+) {
+    let msg = &invocation.msg;
+    let outcomes = split_choices(
+        question,
+        outcomes,
+        PREDICTION_QUESTION_LENGTH,
+        PREDICTION_OUTCOMES,
+    );
+    let outcomes = match outcomes {
+        Ok(outcomes) => outcomes,
+        Err(error) => return ctx.reply(invocation, &error),
+    };
+    let window = match window {
+        Some(window) if PREDICTION_SECONDS.contains(&window.num_seconds()) => window,
+        _ => {
+            return ctx.reply(
+                invocation,
+                "Predictions are open between 30 seconds and 30 minutes",
+            )
+        }
+    };
+    let prediction = ctx
+        .helix
+        .create_prediction(&msg.channel_id, question, &outcomes, window)
+        .await;
+    let prediction = match prediction {
+        Ok(prediction) => prediction,
+        Err(e) => {
+            error!(
+                "Failed to start a prediction in {}: {}",
+                msg.channel_login, e
+            );
+            return ctx.reply(invocation, UNAVAILABLE);
+        }
+    };
+    ctx.twitch_polls.lock().unwrap().add(Running {
+        kind: Kind::Prediction,
+        id: prediction.id,
+        channel_login: msg.channel_login.clone(),
+        broadcaster_id: msg.channel_id.clone(),
+        check_at: Utc::now() + window,
+    });
+    ctx.reply(
+        invocation,
+        &format!(
+            "Prediction started, predict within {}",
+            format_duration(window)
+        ),
+    );
+}
+
+/// Resolves the running prediction with the outcome `winner`, 1-based as
+/// typed in chat, or cancels it if there's none.
+async fn end_prediction(winner: Option<usize>, invocation: &Invocation, ctx: &Context) {
+    let msg = &invocation.msg;
+    let running = ctx
+        .twitch_polls
+        .lock()
+        .unwrap()
+        .take_prediction(&msg.channel_login);
+    let running = match running {
+        Some(running) => running,
+        None => return ctx.reply(invocation, "There is no prediction running"),
+    };
+    let ended = resolve(&running, winner, ctx).await;
+    match ended {
+        Ok(results) => ctx.say(msg.channel_login.clone(), results),
+        Err(error) => {
+            ctx.reply(invocation, &error);
+            // it's still running
+            ctx.twitch_polls.lock().unwrap().add(running);
+        }
+    }
+}
+
+/// Ends the `running` prediction, returns how it turned out or what to reply
+/// instead.
+async fn resolve(
+    running: &Running,
+    winner: Option<usize>,
+    ctx: &Context,
+) -> Result<String, String> {
+    let (broadcaster_id, id) = (&running.broadcaster_id, &running.id);
+    let unavailable = |e| {
+        error!("Failed to end the prediction {}: {}", id, e);
+        UNAVAILABLE.to_owned()
+    };
+    let winning_outcome_id = match winner {
+        Some(number) => {
+            let prediction = ctx.helix.prediction(broadcaster_id, id).await;
+            let outcomes = match prediction.map_err(unavailable)? {
+                Some(prediction) => prediction.outcomes,
+                None => return Err("There is no prediction running".to_owned()),
+            };
+            match number.checked_sub(1).and_then(|index| outcomes.get(index)) {
+                Some(outcome) => Some(outcome.id.clone()),
+                None => return Err(format!("There is no outcome {}", number)),
+            }
+        }
+        None => None,
+    };
+    let prediction = ctx
+        .helix
+        .end_prediction(broadcaster_id, id, winning_outcome_id.as_deref())
+        .await
+        .map_err(unavailable)?;
+    prediction_results(&prediction).ok_or_else(|| UNAVAILABLE.to_owned())
+}
+
+/// `!vote <number>`, ignored while no poll is running.
+struct Vote {
+    current: Arc<Mutex<Option<Poll>>>,
+}
+
+#[async_trait]
+impl CommandHandler for Vote {
+    fn description(&self) -> &'static str {
+        "Votes in the running poll"
+    }
+
+    fn usage(&self, command: &str) -> String {
+        format!("Usage: {} <number>", command)
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let option = match invocation.words().as_slice() {
+            [option] => match option.parse() {
+                Ok(option) => option,
+                Err(_) => return,
+            },
+            _ => return,
+        };
+        let result = match self.current.lock().unwrap().as_mut() {
+            Some(poll) => poll.vote(msg.sender.login.clone(), option),
+            None => return,
+        };
+        match result {
+            Ok(()) => {}
+            Err(VoteError::NoSuchOption(option)) => {
+                ctx.reply(invocation, &format!("There is no option {}", option))
+            }
+            Err(VoteError::AlreadyVoted) => ctx.reply(invocation, "You have already voted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drain, test_broadcaster_msg, test_context, test_msg};
+
+    #[tokio::test]
+    async fn running_a_poll() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!vote 1",
+            r#"!poll start "Only one option?" yes"#,
+            r#"!poll start "Best editor?" vim "vs code" emacs"#,
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        for text in ["!vote 2", "!vote 4", "!vote two"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        registry
+            .dispatch(test_broadcaster_msg("!poll end"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!poll end"), &ctx)
+            .await;
+
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Usage: !poll start \"Question\" option1 option2 ..., !poll end \
+                 or !poll \"Question\" option1|option2 <duration>",
+                "Poll: Best editor? 1) vim 2) vs code 3) emacs - vote with !vote <number>",
+                "@login: There is no option 4",
+                "Poll \"Best editor?\" closed. Winner: vs code with 1 vote(s). \
+                 Results: 1) vim: 0, 2) vs code: 1, 3) emacs: 0",
+                "@broadcaster: There is no poll running",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn twitch_polls_and_predictions() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry
+            .dispatch(test_msg(r#"!poll "Best editor?" vim|emacs 2m"#), &ctx)
+            .await;
+        for text in [
+            r#"!poll "Best editor?" vim| 2m"#,
+            r#"!poll "Best editor?" vim|emacs 10"#,
+            r#"!poll "Best editor?" "vim|emacs|a very long choice that goes on" 2m"#,
+            r#"!prediction "Will it compile?" yes|no 10"#,
+            "!prediction win 1",
+            "!prediction win first",
+            // the test context has no token, so Helix can't be asked
+            r#"!poll "Best editor?" vim|emacs 2m"#,
+            r#"!prediction "Will it compile?" yes|no 2m"#,
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: There need to be between 2 and 5 choices",
+                "@broadcaster: Polls last between 15 seconds and 30 minutes",
+                "@broadcaster: Choices can't be longer than 25 characters",
+                "@broadcaster: Predictions are open between 30 seconds and 30 minutes",
+                "@broadcaster: There is no prediction running",
+                "@broadcaster: Usage: !prediction \"Question\" outcome1|outcome2 <duration>, \
+                 !prediction win <number> or !prediction cancel",
+                &format!("@broadcaster: {}", UNAVAILABLE),
+                &format!("@broadcaster: {}", UNAVAILABLE),
+            ]
+        );
+        assert!(ctx.twitch_polls.lock().unwrap().due(Utc::now()).is_empty());
+    }
+
+    #[test]
+    fn splitting_choices() {
+        assert_eq!(
+            split_choices("Best?", " vim | emacs |", 60, POLL_CHOICES),
+            Ok(vec!["vim", "emacs"])
+        );
+        assert_eq!(
+            split_choices(&"?".repeat(46), "a|b", 45, PREDICTION_OUTCOMES),
+            Err("The question can't be longer than 45 characters".to_owned())
+        );
+    }
+}
@@ -0,0 +1,1346 @@
+//! Joining, picking from and managing the queues.
+
+use super::args::{Param, QueueName, Toggle, UserName};
+use super::{BotModule, CommandHandler, Invocation, Registry, ReplyTarget};
+use crate::config::FerrisBotConfig;
+use crate::permissions::Permission;
+use crate::{
+    announce_group, announce_pick, format_duration, queue_error_reply, queue_label, queue_name,
+    stats_summary, user_type, Context,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::error;
+use serenity::model::id::ChannelId;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::{fs, io};
+use twitch_irc::message::PrivmsgMessage;
+use twitch_queue_bot::queue_manager::{
+    ExportedEntry, QueueError, QueueManager, QueueStats, Undone, DEFAULT_QUEUE,
+};
+
+/// How many users `!queue` lists per page.
+const QUEUE_PAGE_SIZE: usize = 10;
+
+/// Notes given with `!join` are cut off after this many characters.
+const MAX_NOTE_LENGTH: usize = 200;
+
+pub struct Module;
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "queue"
+    }
+
+    fn register(&self, registry: &mut Registry, config: &FerrisBotConfig) {
+        registry.register("!join", Join);
+        registry.register("!leave", Leave);
+        registry.register("!next", Next);
+        registry.register("!pickrandom", PickRandom);
+        registry.register("!position", Position);
+        registry.register("!openqueue", OpenQueue);
+        registry.register("!closequeue", CloseQueue);
+        registry.register("!subqueue", SubQueue);
+        registry.register("!blockqueue", BlockQueue);
+        registry.register("!unblockqueue", UnblockQueue);
+        registry.register("!undoqueue", UndoQueue);
+        registry.register("!queue", ShowQueue);
+        registry.register("!remove", Remove);
+        registry.register("!shuffle", Shuffle);
+        registry.register("!swap", Swap);
+        registry.register("!move", Move);
+        registry.register("!queuestats", Stats);
+        registry.register("!current", Current);
+        registry.register("!endstream", EndStream);
+        registry.register("!exportqueue", ExportQueue);
+        // only picks that need confirming can be confirmed
+        if config.queue.confirm_timeout.is_some() {
+            registry.register("!here", Here);
+        }
+    }
+
+    /// Rewards that ask for text show up as chat messages tagged with the
+    /// reward, they're never handled as commands. They're fulfilled when
+    /// EventSub reports the redemption, see `[redemptions]`.
+    async fn on_message(&self, msg: &PrivmsgMessage, _ctx: &Context) -> bool {
+        msg.source.tags.0.contains_key("custom-reward-id")
+    }
+}
+
+/// `!join [queue] [note]`
+struct Join;
+
+#[async_trait]
+impl CommandHandler for Join {
+    fn description(&self) -> &'static str {
+        "Joins a queue, optionally with a note"
+    }
+
+    fn usage(&self, command: &str) -> String {
+        format!("Usage: {} [queue] [note]", command)
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let reply = {
+            let mut queue_manager = ctx.queue_manager(&msg.channel_login).lock().unwrap();
+            let (queue, note) = join_args(&queue_manager, &invocation.rest);
+            let result = queue_manager.join(
+                &queue,
+                msg.sender.login.clone(),
+                user_type(ctx.permission(msg)),
+                note,
+            );
+            match result {
+                Ok(()) => format!(
+                    "Join requested. {}",
+                    position_reply(&queue_manager, &queue, &msg.sender.login)
+                ),
+                // blocked users are ignored without a word
+                Err(QueueError::Blocked) => return,
+                Err(e) => queue_error_reply(&queue, e),
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!leave [queue]`
+struct Leave;
+
+#[async_trait]
+impl CommandHandler for Leave {
+    fn description(&self) -> &'static str {
+        "Leaves a queue"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let result = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .leave(&queue, &msg.sender.login);
+        match result {
+            Ok(()) => ctx.reply(invocation, &format!("You left {}", queue_label(&queue))),
+            Err(e) => ctx.reply(invocation, &queue_error_reply(&queue, e)),
+        }
+    }
+}
+
+/// `!next [queue] [count]`, picks a group when `count` is more than one.
+struct Next;
+
+#[async_trait]
+impl CommandHandler for Next {
+    fn description(&self) -> &'static str {
+        "Picks the next user, or a group of them"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::optional::<QueueName>("queue"),
+            Param::optional::<NonZeroUsize>("count"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let count = invocation.args.get(1).map_or(1, NonZeroUsize::get);
+        match count {
+            1 => {
+                let next = ctx
+                    .queue_manager(&msg.channel_login)
+                    .lock()
+                    .unwrap()
+                    .next(&queue);
+                match next {
+                    Ok(pick) => announce_pick(&pick, msg.channel_login.clone(), ctx),
+                    Err(e) => ctx.reply(invocation, &queue_error_reply(&queue, e)),
+                }
+            }
+            count => {
+                let group = ctx
+                    .queue_manager(&msg.channel_login)
+                    .lock()
+                    .unwrap()
+                    .next_group(&queue, count);
+                match group {
+                    Ok(picks) => announce_group(&picks, msg.channel_login.clone(), ctx),
+                    Err(e) => ctx.reply(invocation, &queue_error_reply(&queue, e)),
+                }
+            }
+        }
+    }
+}
+
+/// `!pickrandom [queue]`
+struct PickRandom;
+
+#[async_trait]
+impl CommandHandler for PickRandom {
+    fn description(&self) -> &'static str {
+        "Picks a random user"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let pick = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .pick_random(&queue, ctx.config.queue.random_weighting);
+        match pick {
+            Ok(pick) => announce_pick(&pick, msg.channel_login.clone(), ctx),
+            Err(e) => ctx.reply(invocation, &queue_error_reply(&queue, e)),
+        }
+    }
+}
+
+/// `!position [queue]`
+struct Position;
+
+#[async_trait]
+impl CommandHandler for Position {
+    fn description(&self) -> &'static str {
+        "Shows where you are in a queue"
+    }
+
+    fn reply_target(&self) -> Option<ReplyTarget> {
+        Some(ReplyTarget::Thread)
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let reply = {
+            let queue_manager = ctx.queue_manager(&msg.channel_login).lock().unwrap();
+            match queue_manager.position(&queue, &msg.sender.login) {
+                Some(_) => position_reply(&queue_manager, &queue, &msg.sender.login),
+                None => queue_error_reply(&queue, QueueError::NotInQueue),
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!openqueue [queue]`, creates the queue if it doesn't exist yet.
+struct OpenQueue;
+
+#[async_trait]
+impl CommandHandler for OpenQueue {
+    fn description(&self) -> &'static str {
+        "Opens a queue, creating it if needed"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        ctx.queue_manager(&invocation.msg.channel_login)
+            .lock()
+            .unwrap()
+            .open(&queue);
+        let join = match queue.as_str() {
+            DEFAULT_QUEUE => "!join".to_owned(),
+            _ => format!("!join {}", queue),
+        };
+        ctx.say(
+            invocation.msg.channel_login.clone(),
+            format!(
+                "{} is now open, type {} to get in",
+                queue_label(&queue),
+                join
+            ),
+        );
+    }
+}
+
+/// `!closequeue [queue]`
+struct CloseQueue;
+
+#[async_trait]
+impl CommandHandler for CloseQueue {
+    fn description(&self) -> &'static str {
+        "Closes a queue"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let result = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .close(&queue);
+        match result {
+            Ok(()) => ctx.say(
+                msg.channel_login.clone(),
+                format!("{} is now closed", queue_label(&queue)),
+            ),
+            Err(e) => ctx.reply(invocation, &queue_error_reply(&queue, e)),
+        }
+    }
+}
+
+/// `!subqueue on|off [queue]`
+struct SubQueue;
+
+#[async_trait]
+impl CommandHandler for SubQueue {
+    fn description(&self) -> &'static str {
+        "Limits a queue to subscribers"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::required::<Toggle>("on|off"),
+            Param::optional::<QueueName>("queue"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let Toggle(enabled) = invocation.args.value(0);
+        let QueueName(queue) = invocation.args.get(1).unwrap_or_default();
+        let result = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .set_sub_only(&queue, enabled);
+        match result {
+            Ok(()) if enabled => ctx.say(
+                msg.channel_login.clone(),
+                format!("{} is now sub-only", queue_label(&queue)),
+            ),
+            Ok(()) => ctx.say(
+                msg.channel_login.clone(),
+                format!("{} is open to everyone again", queue_label(&queue)),
+            ),
+            Err(e) => ctx.reply(invocation, &queue_error_reply(&queue, e)),
+        }
+    }
+}
+
+/// `!blockqueue <user>`
+struct BlockQueue;
+
+#[async_trait]
+impl CommandHandler for BlockQueue {
+    fn description(&self) -> &'static str {
+        "Keeps a user from joining"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<UserName>("user")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(user) = invocation.args.value(0);
+        let reply = if ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .block(&user)
+        {
+            format!("{} can no longer join the queue", user)
+        } else {
+            format!("{} is already blocked", user)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!unblockqueue <user>`
+struct UnblockQueue;
+
+#[async_trait]
+impl CommandHandler for UnblockQueue {
+    fn description(&self) -> &'static str {
+        "Lets a blocked user join again"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<UserName>("user")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(user) = invocation.args.value(0);
+        let reply = if ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .unblock(&user)
+        {
+            format!("{} can join the queue again", user)
+        } else {
+            format!("{} is not blocked", user)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!undoqueue`, reverts the last removal, pick or reordering.
+struct UndoQueue;
+
+#[async_trait]
+impl CommandHandler for UndoQueue {
+    fn description(&self) -> &'static str {
+        "Reverts the last removal, pick or reordering"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let reply = match ctx
+            .queue_manager(&invocation.msg.channel_login)
+            .lock()
+            .unwrap()
+            .undo()
+        {
+            Ok(Undone::Removed { queue, names }) | Ok(Undone::Picked { queue, names })
+                if !names.is_empty() =>
+            {
+                format!("Put {} back in {}", names.join(", "), queue_label(&queue))
+            }
+            Ok(Undone::Removed { queue, .. }) | Ok(Undone::Picked { queue, .. }) => {
+                format!("Everyone is already back in {}", queue_label(&queue))
+            }
+            Ok(Undone::Reordered { queue }) => {
+                format!("Restored the previous order of {}", queue_label(&queue))
+            }
+            Err(QueueError::NothingToUndo) => "There is nothing to undo".to_owned(),
+            Err(e) => queue_error_reply(DEFAULT_QUEUE, e),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!queue [queue] [page]`
+struct ShowQueue;
+
+#[async_trait]
+impl CommandHandler for ShowQueue {
+    fn description(&self) -> &'static str {
+        "Lists who is waiting"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::optional::<QueueName>("queue"),
+            Param::optional::<usize>("page"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let page = invocation.args.get(1).unwrap_or(1);
+        let reply = match ctx
+            .queue_manager(&invocation.msg.channel_login)
+            .lock()
+            .unwrap()
+            .queue(&queue)
+        {
+            Ok(users) => queue_page(&queue, &users, page),
+            Err(e) => queue_error_reply(&queue, e),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!remove <user> [queue]`
+struct Remove;
+
+#[async_trait]
+impl CommandHandler for Remove {
+    fn description(&self) -> &'static str {
+        "Removes a user from a queue"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::required::<UserName>("user"),
+            Param::optional::<QueueName>("queue"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(user) = invocation.args.value(0);
+        let QueueName(queue) = invocation.args.get(1).unwrap_or_default();
+        let result = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .remove(&queue, &user);
+        let reply = match result {
+            Ok(()) => format!("Removed {} from {}", user, queue_label(&queue)),
+            Err(QueueError::NotInQueue) => format!("{} is not in {}", user, queue_label(&queue)),
+            Err(e) => queue_error_reply(&queue, e),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!shuffle [queue]`
+struct Shuffle;
+
+#[async_trait]
+impl CommandHandler for Shuffle {
+    fn description(&self) -> &'static str {
+        "Shuffles a queue"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let reply = {
+            let mut queue_manager = ctx
+                .queue_manager(&invocation.msg.channel_login)
+                .lock()
+                .unwrap();
+            match queue_manager.shuffle(&queue) {
+                Ok(()) => format!(
+                    "Shuffled {}: {}",
+                    queue_label(&queue),
+                    queue_manager.queue(&queue).unwrap().join(", ")
+                ),
+                Err(e) => queue_error_reply(&queue, e),
+            }
+        };
+        ctx.say(invocation.msg.channel_login.clone(), reply);
+    }
+}
+
+/// `!swap <user> <user> [queue]`
+struct Swap;
+
+#[async_trait]
+impl CommandHandler for Swap {
+    fn description(&self) -> &'static str {
+        "Swaps two users in a queue"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::required::<UserName>("user"),
+            Param::required::<UserName>("user"),
+            Param::optional::<QueueName>("queue"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(a) = invocation.args.value(0);
+        let UserName(b) = invocation.args.value(1);
+        let QueueName(queue) = invocation.args.get(2).unwrap_or_default();
+        let result = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .swap(&queue, &a, &b);
+        let reply = match result {
+            Ok(()) => format!("Swapped {} and {} in {}", a, b, queue_label(&queue)),
+            Err(QueueError::NotInQueue) => {
+                format!("Both {} and {} need to be in {}", a, b, queue_label(&queue))
+            }
+            Err(e) => queue_error_reply(&queue, e),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!move <user> <position> [queue]`, positions start at 1.
+struct Move;
+
+#[async_trait]
+impl CommandHandler for Move {
+    fn description(&self) -> &'static str {
+        "Moves a user to a position, starting at 1"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![
+            Param::required::<UserName>("user"),
+            Param::required::<NonZeroUsize>("position"),
+            Param::optional::<QueueName>("queue"),
+        ]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(user) = invocation.args.value(0);
+        let position = invocation.args.value::<NonZeroUsize>(1).get();
+        let QueueName(queue) = invocation.args.get(2).unwrap_or_default();
+        let reply = {
+            let mut queue_manager = ctx.queue_manager(&msg.channel_login).lock().unwrap();
+            match queue_manager.move_to(&queue, &user, position - 1) {
+                Ok(()) => format!("Moved {} to #{} in {}", user, position, queue_label(&queue)),
+                Err(QueueError::NotInQueue) => {
+                    format!("{} is not in {}", user, queue_label(&queue))
+                }
+                Err(QueueError::InvalidPosition) => format!(
+                    "Position must be between 1 and {}",
+                    queue_manager.queue(&queue).unwrap().len()
+                ),
+                Err(e) => queue_error_reply(&queue, e),
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!queuestats`
+struct Stats;
+
+#[async_trait]
+impl CommandHandler for Stats {
+    fn description(&self) -> &'static str {
+        "Shows this stream's queue stats"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let summary = {
+            let queue_manager = ctx.queue_manager(&invocation.msg.channel_login);
+            stats_summary(queue_manager.lock().unwrap().stats())
+        };
+        ctx.reply(invocation, &summary);
+    }
+}
+
+/// `!current [queue]`
+struct Current;
+
+#[async_trait]
+impl CommandHandler for Current {
+    fn description(&self) -> &'static str {
+        "Shows who was picked last and for how long"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let reply = match ctx
+            .queue_manager(&invocation.msg.channel_login)
+            .lock()
+            .unwrap()
+            .current(&queue)
+        {
+            Ok(Some((user, elapsed))) => format!(
+                "{} is up from {}, going for {}",
+                user,
+                queue_label(&queue),
+                format_duration(elapsed)
+            ),
+            Ok(None) => format!("Nobody has been picked from {} yet", queue_label(&queue)),
+            Err(e) => queue_error_reply(&queue, e),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!endstream`, clears the queues and posts the stream's stats.
+struct EndStream;
+
+#[async_trait]
+impl CommandHandler for EndStream {
+    fn description(&self) -> &'static str {
+        "Clears the queues and posts the stream's stats"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let stats = ctx
+            .queue_manager(&invocation.msg.channel_login)
+            .lock()
+            .unwrap()
+            .end_stream();
+        if let Some(path) = &ctx.config.queue.stats_log {
+            if let Err(e) = append_stats(path, &stats) {
+                error!("Failed to write queue stats to {}: {}", path, e);
+            }
+        }
+
+        let summary = stats_summary(&stats);
+        ctx.say(
+            invocation.msg.channel_login.clone(),
+            format!(
+                "Thanks for watching! The queue has been cleared. {}",
+                summary
+            ),
+        );
+        // Discord is about the main channel only
+        if invocation.msg.channel_login == ctx.config.twitch.channel_name {
+            let chat = ctx
+                .chat_stats
+                .lock()
+                .unwrap()
+                .summary(&invocation.msg.channel_login, Utc::now());
+            let _ = ChannelId(ctx.config.discord.channel_id)
+                .say(
+                    &ctx.discord_http,
+                    format!("Stream ended. {} Chat: {}", summary, chat),
+                )
+                .await;
+        }
+        // lurking is for one stream
+        ctx.lurkers
+            .lock()
+            .unwrap()
+            .forget(&invocation.msg.channel_login);
+        ctx.welcome_again(&invocation.msg.channel_login);
+    }
+}
+
+/// `!here`, confirms a pick.
+struct Here;
+
+#[async_trait]
+impl CommandHandler for Here {
+    fn description(&self) -> &'static str {
+        "Confirms you're there after being picked"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        if ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .confirm(&msg.sender.login)
+        {
+            ctx.reply(invocation, "Great, you're up!");
+        }
+    }
+}
+
+/// `!exportqueue [queue]`, uploads the queue to Discord as CSV and JSON.
+struct ExportQueue;
+
+#[async_trait]
+impl CommandHandler for ExportQueue {
+    fn description(&self) -> &'static str {
+        "Uploads a queue to Discord"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<QueueName>("queue")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let QueueName(queue) = invocation.args.get(0).unwrap_or_default();
+        let files = {
+            let queue_manager = ctx.queue_manager(&msg.channel_login).lock().unwrap();
+            queue_manager.export(&queue).map(|entries| {
+                (
+                    export_csv(&entries),
+                    serde_json::to_string_pretty(&entries).unwrap(),
+                )
+            })
+        };
+        let (csv, json) = match files {
+            Ok(files) => files,
+            Err(e) => return ctx.reply(invocation, &queue_error_reply(&queue, e)),
+        };
+        let csv_name = format!("{}.csv", queue);
+        let json_name = format!("{}.json", queue);
+        let sent = ChannelId(ctx.config.discord.channel_id)
+            .send_files(
+                &ctx.discord_http,
+                vec![
+                    (csv.as_bytes(), csv_name.as_str()),
+                    (json.as_bytes(), json_name.as_str()),
+                ],
+                |m| m.content(format!("Export of {}", queue_label(&queue))),
+            )
+            .await;
+        match sent {
+            Ok(_) => ctx.reply(invocation, "Exported the queue to Discord"),
+            Err(e) => {
+                error!("Failed to export queue to Discord: {}", e);
+                ctx.reply(invocation, "Sorry, exporting the queue failed");
+            }
+        }
+    }
+}
+
+/// Splits `!join` arguments into the queue to join and the note. The first
+/// word only names a queue if such a queue exists, otherwise it's part of the
+/// note for the default queue.
+fn join_args(queue_manager: &QueueManager, args: &str) -> (String, Option<String>) {
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let first = first.to_lowercase();
+    let (queue, note) = if !first.is_empty() && queue_manager.queue(&first).is_ok() {
+        (first, rest.trim())
+    } else {
+        (DEFAULT_QUEUE.to_owned(), args)
+    };
+    let note = (!note.is_empty()).then(|| note.chars().take(MAX_NOTE_LENGTH).collect());
+    (queue, note)
+}
+
+/// "You are #4 in the queue, ~20m" style description of where `user` is waiting.
+fn position_reply(queue_manager: &QueueManager, queue: &str, user: &str) -> String {
+    let position = match queue_manager.position(queue, user) {
+        Some(0) => format!("You are next in {}", queue_label(queue)),
+        Some(ahead) => format!("You are #{} in {}", ahead + 1, queue_label(queue)),
+        None => return format!("You are not in {}", queue_label(queue)),
+    };
+    match queue_manager.estimated_wait(queue, user) {
+        Some(wait) => format!("{}, ~{}", position, format_duration(wait)),
+        None => position,
+    }
+}
+
+/// One page of `!queue` output, `page` starts at 1.
+fn queue_page(queue: &str, users: &[&str], page: usize) -> String {
+    if users.is_empty() {
+        return queue_error_reply(queue, QueueError::QueueEmpty);
+    }
+    let pages = users.len().div_ceil(QUEUE_PAGE_SIZE);
+    if page == 0 || page > pages {
+        return format!("There are only {} page(s) in {}", pages, queue_label(queue));
+    }
+    if pages == 1 {
+        return format!("Current {}: {}", queue_name(queue), users.join(", "));
+    }
+
+    let start = (page - 1) * QUEUE_PAGE_SIZE;
+    let end = (start + QUEUE_PAGE_SIZE).min(users.len());
+    let mut reply = format!(
+        "Current {} (showing {}-{} of {}): {}",
+        queue_name(queue),
+        start + 1,
+        end,
+        users.len(),
+        users[start..end].join(", ")
+    );
+    if page < pages {
+        let command = match queue {
+            DEFAULT_QUEUE => format!("!queue {}", page + 1),
+            _ => format!("!queue {} {}", queue, page + 1),
+        };
+        reply.push_str(&format!(" - use {} for more", command));
+    }
+    reply
+}
+
+/// Formats exported queue entries as CSV with a header row.
+fn export_csv(entries: &[ExportedEntry]) -> String {
+    fn field(value: &str) -> String {
+        if value.contains(&[',', '"', '\n', '\r'][..]) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_owned()
+        }
+    }
+
+    let mut csv = "position,name,user_type,joined_at,note\n".to_owned();
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{},{:?},{},{}\n",
+            entry.position,
+            field(entry.name),
+            entry.user_type,
+            entry.joined_at.to_rfc3339(),
+            field(entry.note.unwrap_or_default())
+        ));
+    }
+    csv
+}
+
+/// Appends `stats` as a line of JSON to the file at `path`.
+fn append_stats(path: &str, stats: &QueueStats) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(stats)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{
+        drain, test_broadcaster_msg, test_config, test_context, test_context_with, test_msg,
+    };
+    use twitch_queue_bot::queue_manager::UserType;
+
+    /// The users waiting in `queue`.
+    fn waiting(ctx: &Context, queue: &str) -> Vec<String> {
+        let queue_manager = ctx.queue_manager("channel_login").lock().unwrap();
+        queue_manager
+            .queue(queue)
+            .unwrap()
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn join(ctx: &Context, queue: &str, users: &[&str]) {
+        let mut queue_manager = ctx.queue_manager("channel_login").lock().unwrap();
+        for user in users {
+            queue_manager
+                .join(queue, user.to_string(), UserType::Default, None)
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn joining_with_notes() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        ctx.queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .open("review");
+
+        registry
+            .dispatch(test_msg("!join Review  help with lifetimes "), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            ["@login: Join requested. You are next in the review queue"]
+        );
+        assert_eq!(
+            ctx.queue_manager("channel_login")
+                .lock()
+                .unwrap()
+                .entries("review")
+                .unwrap(),
+            [("login", Some("help with lifetimes"))]
+        );
+
+        // aliases are cut off the arguments just like the command would be
+        registry.dispatch(test_msg("!j lifetimes"), &ctx).await;
+        assert_eq!(
+            ctx.queue_manager("channel_login")
+                .lock()
+                .unwrap()
+                .entries(DEFAULT_QUEUE)
+                .unwrap(),
+            [("login", Some("lifetimes"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn queues_per_channel() {
+        let mut config = test_config();
+        config.twitch.extra_channels = vec!["other_channel".to_owned()];
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        let mut other = test_msg("!join");
+        other.channel_login = "other_channel".to_owned();
+        registry.dispatch(other, &ctx).await;
+        registry.dispatch(test_msg("!queue"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: Join requested. You are next in the queue",
+                "@login: Nobody is waiting in the queue"
+            ]
+        );
+        assert!(waiting(&ctx, DEFAULT_QUEUE).is_empty());
+        assert_eq!(
+            ctx.queue_manager("other_channel")
+                .lock()
+                .unwrap()
+                .queue(DEFAULT_QUEUE)
+                .unwrap(),
+            ["login"]
+        );
+    }
+
+    #[tokio::test]
+    async fn managing_users() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        ctx.queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .open("review");
+        join(&ctx, DEFAULT_QUEUE, &["alice", "bob", "ferris"]);
+        join(&ctx, "review", &["ferris"]);
+
+        for text in [
+            "!remove @Ferris",
+            "!remove ferris review",
+            "!swap @Alice bob",
+            "!move alice 1",
+            "!move alice 0",
+            "!remove",
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Removed ferris from the queue",
+                "@broadcaster: Removed ferris from the review queue",
+                "@broadcaster: Swapped alice and bob in the queue",
+                "@broadcaster: Moved alice to #1 in the queue",
+                "@broadcaster: Usage: !move <user> <position> [queue]",
+                "@broadcaster: Usage: !remove <user> [queue]",
+            ]
+        );
+        assert_eq!(waiting(&ctx, DEFAULT_QUEUE), ["alice", "bob"]);
+        assert!(waiting(&ctx, "review").is_empty());
+    }
+
+    #[tokio::test]
+    async fn picking_groups() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        join(&ctx, DEFAULT_QUEUE, &["alice", "bob", "carol"]);
+
+        registry
+            .dispatch(test_broadcaster_msg("!next 0"), &ctx)
+            .await;
+        registry
+            .dispatch(test_broadcaster_msg("!next 2"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Usage: !next [queue] [count]",
+                "@alice, @bob: You're up next together!"
+            ]
+        );
+        assert_eq!(waiting(&ctx, DEFAULT_QUEUE), ["carol"]);
+    }
+
+    #[tokio::test]
+    async fn picked_users_are_whispered() {
+        let mut config = crate::tests::test_config();
+        config.queue.whisper_on_pick = true;
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        sent.try_recv().unwrap();
+        registry.dispatch(test_broadcaster_msg("!next"), &ctx).await;
+
+        let whisper = sent.try_recv().unwrap();
+        assert_eq!(whisper.whisper_to.as_deref(), Some("login"));
+        let announcement = sent.try_recv().unwrap();
+        assert_eq!(announcement.whisper_to, None);
+        assert_eq!(
+            announcement.text,
+            "@login: You're up next! Type !here within 2m 0s to keep your spot."
+        );
+    }
+
+    #[tokio::test]
+    async fn confirming_picks() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        join(&ctx, DEFAULT_QUEUE, &["login"]);
+        ctx.queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .next(DEFAULT_QUEUE)
+            .unwrap();
+
+        registry.dispatch(test_msg("!here"), &ctx).await;
+        assert_eq!(drain(&mut sent), ["@login: Great, you're up!"]);
+
+        // without a timeout there's nothing to confirm
+        let mut config = crate::tests::test_config();
+        config.queue.confirm_timeout = None;
+        assert!(!Registry::builtin(&config).handlers.contains_key("!here"));
+    }
+
+    #[tokio::test]
+    async fn sub_only_queues() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry
+            .dispatch(test_broadcaster_msg("!subqueue ON"), &ctx)
+            .await;
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        registry
+            .dispatch(test_broadcaster_msg("!subqueue maybe"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "the queue is now sub-only",
+                "@login: Sorry, the queue is for subscribers only right now",
+                "@broadcaster: Usage: !subqueue <on|off> [queue]",
+            ]
+        );
+        assert!(ctx
+            .queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .is_sub_only(DEFAULT_QUEUE)
+            .unwrap());
+
+        ctx.queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .open("review");
+        registry
+            .dispatch(test_broadcaster_msg("!subqueue off review"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            ["the review queue is open to everyone again"]
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_users_are_ignored() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry
+            .dispatch(test_broadcaster_msg("!blockqueue @Login"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            ["@broadcaster: login can no longer join the queue"]
+        );
+
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+        assert!(waiting(&ctx, DEFAULT_QUEUE).is_empty());
+    }
+
+    #[tokio::test]
+    async fn undoing_a_pick() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        join(&ctx, DEFAULT_QUEUE, &["alice", "bob"]);
+
+        registry.dispatch(test_broadcaster_msg("!next"), &ctx).await;
+        sent.try_recv().unwrap();
+        registry
+            .dispatch(test_broadcaster_msg("!undoqueue"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            ["@broadcaster: Put alice back in the queue"]
+        );
+        assert_eq!(waiting(&ctx, DEFAULT_QUEUE), ["alice", "bob"]);
+
+        registry
+            .dispatch(test_broadcaster_msg("!undoqueue"), &ctx)
+            .await;
+        assert_eq!(drain(&mut sent), ["@broadcaster: There is nothing to undo"]);
+    }
+
+    #[tokio::test]
+    async fn queue_management_needs_moderators() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!openqueue review"), &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+        assert!(ctx
+            .queue_manager("channel_login")
+            .lock()
+            .unwrap()
+            .queue("review")
+            .is_err());
+
+        registry
+            .dispatch(test_broadcaster_msg("!openqueue Review"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            ["the review queue is now open, type !join review to get in"]
+        );
+    }
+
+    #[tokio::test]
+    async fn showing_queue_pages() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+        let users: Vec<String> = (1..=12).map(|i| format!("user{}", i)).collect();
+        let users: Vec<&str> = users.iter().map(String::as_str).collect();
+        join(&ctx, DEFAULT_QUEUE, &users);
+
+        registry.dispatch(test_msg("!Q 2"), &ctx).await;
+        registry.dispatch(test_msg("!queue review"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: Current queue (showing 11-12 of 12): user11, user12",
+                "@login: There is no queue named review",
+            ]
+        );
+    }
+
+    #[test]
+    fn paginating_queues() {
+        let users: Vec<String> = (1..=23).map(|i| format!("user{}", i)).collect();
+        let users: Vec<&str> = users.iter().map(String::as_str).collect();
+
+        assert_eq!(
+            queue_page(DEFAULT_QUEUE, &users[..2], 1),
+            "Current queue: user1, user2"
+        );
+        assert_eq!(
+            queue_page(DEFAULT_QUEUE, &users, 1),
+            "Current queue (showing 1-10 of 23): user1, user2, user3, user4, user5, \
+             user6, user7, user8, user9, user10 - use !queue 2 for more"
+        );
+        assert_eq!(
+            queue_page("review", &users, 3),
+            "Current review queue (showing 21-23 of 23): user21, user22, user23"
+        );
+        assert!(queue_page("review", &users, 2).ends_with("use !queue review 3 for more"));
+        assert_eq!(
+            queue_page(DEFAULT_QUEUE, &users, 4),
+            "There are only 3 page(s) in the queue"
+        );
+        assert_eq!(
+            queue_page(DEFAULT_QUEUE, &[], 1),
+            "Nobody is waiting in the queue"
+        );
+    }
+
+    #[test]
+    fn splitting_join_args() {
+        let mut queue_manager = QueueManager::new();
+        queue_manager.open("review");
+
+        assert_eq!(join_args(&queue_manager, ""), (DEFAULT_QUEUE.into(), None));
+        assert_eq!(join_args(&queue_manager, "Review"), ("review".into(), None));
+        assert_eq!(
+            join_args(&queue_manager, "review my PR please"),
+            ("review".into(), Some("my PR please".into()))
+        );
+        assert_eq!(
+            join_args(&queue_manager, "I want help with lifetimes"),
+            (
+                DEFAULT_QUEUE.into(),
+                Some("I want help with lifetimes".into())
+            )
+        );
+
+        let long = "a".repeat(MAX_NOTE_LENGTH + 10);
+        let (_, note) = join_args(&queue_manager, &long);
+        assert_eq!(note.unwrap().len(), MAX_NOTE_LENGTH);
+    }
+
+    #[test]
+    fn exporting_csv() {
+        let mut queue_manager = QueueManager::new();
+        queue_manager
+            .join(DEFAULT_QUEUE, "first".to_owned(), UserType::Vip, None)
+            .unwrap();
+        queue_manager
+            .join(
+                DEFAULT_QUEUE,
+                "second".to_owned(),
+                UserType::Default,
+                Some(r#"help with "lifetimes", please"#.to_owned()),
+            )
+            .unwrap();
+
+        let entries = queue_manager.export(DEFAULT_QUEUE).unwrap();
+        let csv = export_csv(&entries);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "position,name,user_type,joined_at,note");
+        assert_eq!(
+            lines[1],
+            format!("1,first,Vip,{},", entries[0].joined_at.to_rfc3339())
+        );
+        assert_eq!(
+            lines[2],
+            format!(
+                r#"2,second,Default,{},"help with ""lifetimes"", please""#,
+                entries[1].joined_at.to_rfc3339()
+            )
+        );
+    }
+}
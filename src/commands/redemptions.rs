@@ -0,0 +1,153 @@
+//! Channel point rewards, which can bump the redeemer in a queue, count and
+//! say something in chat, see `[redemptions]`.
+
+use super::{BotModule, Registry};
+use crate::config::FerrisBotConfig;
+use crate::eventsub::Event;
+use crate::templates::{self, Variables};
+use crate::{queue_error_reply, Context};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+
+pub struct Module;
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "redemptions"
+    }
+
+    fn register(&self, _registry: &mut Registry, _config: &FerrisBotConfig) {}
+
+    async fn on_event(&self, event: &Event, _channel_login: &str, ctx: &Context) {
+        if let Event::Redemption {
+            user,
+            login,
+            reward_id,
+            input,
+        } = event
+        {
+            redeem(reward_id, user, login, input, ctx);
+        }
+    }
+}
+
+/// Does what's configured for the reward with `reward_id`, redeemed by `user`
+/// in the main channel.
+fn redeem(reward_id: &str, user: &str, login: &str, input: &str, ctx: &Context) {
+    let channel = &ctx.config.twitch.channel_name;
+    let redemption = match ctx.config.redemptions.get(reward_id) {
+        Some(redemption) => redemption,
+        None => return debug!("Nothing to do for reward {}", reward_id),
+    };
+    let mut count = 0;
+    if let Some(counter) = &redemption.counter {
+        match ctx.counters.lock().unwrap().increment(counter) {
+            Some(value) => count = value,
+            None => warn!(
+                "Reward {} counts {}, which doesn't exist",
+                reward_id, counter
+            ),
+        }
+    }
+    if let Some(queue) = &redemption.bump {
+        let places = redemption.places.unwrap_or(usize::MAX);
+        let bumped = ctx
+            .queue_manager(channel)
+            .lock()
+            .unwrap()
+            .bump(queue, login, places);
+        // nothing to announce if they weren't moved
+        if let Err(e) = bumped {
+            return info!(
+                "Reward {} didn't bump {}: {}",
+                reward_id,
+                login,
+                queue_error_reply(queue, e)
+            );
+        }
+    }
+    if let Some(template) = &redemption.say {
+        let variables = Variables {
+            user,
+            channel,
+            args: input,
+            count,
+            months: None,
+        };
+        let text = templates::render(template, &variables, &mut rand::thread_rng());
+        ctx.say(channel.clone(), text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RedemptionConfig;
+    use crate::tests::{drain, test_config, test_context_with};
+    use twitch_queue_bot::queue_manager::{UserType, DEFAULT_QUEUE};
+
+    #[test]
+    fn redeeming_rewards() {
+        let mut config = test_config();
+        config.redemptions.insert(
+            "skip".to_owned(),
+            RedemptionConfig {
+                bump: Some(DEFAULT_QUEUE.to_owned()),
+                say: Some("$(user) skipped the line: $(args)".to_owned()),
+                ..RedemptionConfig::default()
+            },
+        );
+        config.redemptions.insert(
+            "boost".to_owned(),
+            RedemptionConfig {
+                bump: Some(DEFAULT_QUEUE.to_owned()),
+                places: Some(1),
+                say: Some("Boosted $(user)".to_owned()),
+                ..RedemptionConfig::default()
+            },
+        );
+        config.redemptions.insert(
+            "hydrate".to_owned(),
+            RedemptionConfig {
+                counter: Some("hydrate".to_owned()),
+                say: Some("Hydrated $(count) times".to_owned()),
+                ..RedemptionConfig::default()
+            },
+        );
+        let (ctx, mut sent) = test_context_with(config);
+        let channel = ctx.config.twitch.channel_name.clone();
+        {
+            let mut queue_manager = ctx.queue_manager(&channel).lock().unwrap();
+            for user in ["alice", "bob", "dave"] {
+                queue_manager
+                    .join(DEFAULT_QUEUE, user.to_owned(), UserType::Default, None)
+                    .unwrap();
+            }
+        }
+        ctx.counters.lock().unwrap().add("hydrate");
+
+        redeem("skip", "Bob", "bob", "sorry", &ctx);
+        redeem("skip", "Carol", "carol", "", &ctx);
+        redeem("boost", "Dave", "dave", "", &ctx);
+        redeem("hydrate", "Carol", "carol", "", &ctx);
+        redeem("unknown", "Carol", "carol", "", &ctx);
+        assert_eq!(
+            ctx.queue_manager(&channel)
+                .lock()
+                .unwrap()
+                .queue(DEFAULT_QUEUE)
+                .unwrap(),
+            ["bob", "dave", "alice"]
+        );
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "Bob skipped the line: sorry",
+                // Carol isn't waiting, so there's nothing to say
+                "Boosted Dave",
+                "Hydrated 1 times"
+            ]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Commands written as Rhai scripts, so they can be added and changed without
+//! recompiling the bot. Scripts can't touch files or the network and are
+//! stopped when they run for too long.
+//!
+//! A script sees `args` (the words after the command), `sender`, `user`,
+//! `channel`, `queue_length` and `counters`, and whatever it evaluates to is
+//! posted in chat unless it's `()`.
+
+use super::{BotModule, CommandHandler, Invocation, Registry};
+use crate::config::FerrisBotConfig;
+use crate::Context;
+use async_trait::async_trait;
+use log::{debug, warn};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use std::fs;
+use std::time::{Duration, Instant};
+use twitch_queue_bot::queue_manager::DEFAULT_QUEUE;
+
+/// How long a script may run unless configured otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct Module;
+
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "scripts"
+    }
+
+    /// Panics if a script can't be read or doesn't compile.
+    fn register(&self, registry: &mut Registry, config: &FerrisBotConfig) {
+        let timeout = config
+            .scripts
+            .timeout_ms
+            .map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+        for (command, path) in &config.scripts.commands {
+            let source = fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Failed to read script {}: {}", path, e));
+            let ast = engine(timeout)
+                .compile(&source)
+                .unwrap_or_else(|e| panic!("Invalid script {}: {}", path, e));
+            registry.register(command, Script { ast, timeout });
+        }
+    }
+}
+
+/// A sandboxed engine that gives up after `timeout`.
+fn engine(timeout: Duration) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(100_000);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(1_000);
+    engine.set_max_map_size(1_000);
+    engine.on_print(|text| debug!("Script printed: {}", text));
+    engine.on_debug(|text, _, _| debug!("Script debug: {}", text));
+    let started = Instant::now();
+    engine.on_progress(move |_| (started.elapsed() > timeout).then_some(Dynamic::UNIT));
+    engine
+}
+
+/// A command running a script.
+struct Script {
+    ast: AST,
+    timeout: Duration,
+}
+
+impl Script {
+    fn run(&self, invocation: &Invocation, ctx: &Context) -> Result<Dynamic, Box<EvalAltResult>> {
+        let msg = &invocation.msg;
+        let args: Array = invocation
+            .words()
+            .into_iter()
+            .map(|word| Dynamic::from(word.to_owned()))
+            .collect();
+        let queue_length = ctx
+            .queue_manager(&msg.channel_login)
+            .lock()
+            .unwrap()
+            .queue(DEFAULT_QUEUE)
+            .map_or(0, |queue| queue.len());
+        let counters: Map = ctx
+            .counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, value)| (name.into(), Dynamic::from(value as i64)))
+            .collect();
+
+        let mut scope = Scope::new();
+        scope.push_constant("args", args);
+        scope.push_constant("sender", msg.sender.login.clone());
+        scope.push_constant("user", msg.sender.name.clone());
+        scope.push_constant("channel", msg.channel_login.clone());
+        scope.push_constant("queue_length", queue_length as i64);
+        scope.push_constant("counters", counters);
+        engine(self.timeout).eval_ast_with_scope(&mut scope, &self.ast)
+    }
+}
+
+#[async_trait]
+impl CommandHandler for Script {
+    fn description(&self) -> &'static str {
+        "Runs a script"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        match self.run(invocation, ctx) {
+            Ok(output) if output.is_unit() => {}
+            Ok(output) => ctx.say(invocation.msg.channel_login.clone(), output.to_string()),
+            Err(e) => warn!("Script for {} failed: {}", invocation.command, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drain, test_config, test_context_with, test_msg};
+    use std::path::PathBuf;
+
+    fn script(name: &str, source: &str) -> String {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("{}-{}.rhai", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path.display().to_string()
+    }
+
+    #[tokio::test]
+    async fn running_scripts() {
+        let mut config = test_config();
+        let scripts = [
+            (
+                "!greet",
+                script(
+                    "greet",
+                    r#"`Hi ${user}, ${args.len()} args, ${queue_length} waiting`"#,
+                ),
+            ),
+            ("!quiet", script("quiet", "let x = 1;")),
+            ("!forever", script("forever", "loop { }")),
+            ("!broken", script("broken", "undefined_function()")),
+        ];
+        config.scripts.timeout_ms = Some(10);
+        for (command, path) in &scripts {
+            config
+                .scripts
+                .commands
+                .insert(command.to_string(), path.clone());
+        }
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!join"), &ctx).await;
+        drain(&mut sent);
+        for text in ["!greet a b", "!quiet", "!forever", "!broken"] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        assert_eq!(drain(&mut sent), ["Hi name, 2 args, 1 waiting"]);
+
+        for (_, path) in scripts {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Posting code snippets to Discord.
+
+use super::args::Param;
+use super::{BotModule, CommandHandler, Invocation, Registry};
+use crate::config::FerrisBotConfig;
+use crate::Context;
+use async_trait::async_trait;
+use log::debug;
+use serenity::model::id::ChannelId;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// `!code` and `!nothing`.
+pub struct Module;
+
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "snippets"
+    }
+
+    fn register(&self, registry: &mut Registry, _config: &FerrisBotConfig) {
+        registry.register("!nothing", Nothing);
+        registry.register("!code", Snippet);
+    }
+}
+
+/// `!nothing`
+struct Nothing;
+
+#[async_trait]
+impl CommandHandler for Nothing {
+    fn description(&self) -> &'static str {
+        "Does nothing"
+    }
+
+    async fn handle(&self, _invocation: &Invocation, ctx: &Context) {
+        debug!("nothing received");
+        let _ = ChannelId(ctx.config.discord.channel_id)
+            .say(&ctx.discord_http, "This does nothing")
+            .await;
+    }
+}
+
+/// `!code <snippet>`, posts the snippet to Discord, formatted if it's valid Rust.
+struct Snippet;
+
+#[async_trait]
+impl CommandHandler for Snippet {
+    fn description(&self) -> &'static str {
+        "Posts a code snippet to Discord"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::text("code")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let snippet: String = invocation.args.value(0);
+        let formatted = format_snippet(&snippet).unwrap_or(snippet);
+        let code_block = format!("```rs\n{}\n```", formatted);
+
+        let _ = ChannelId(ctx.config.discord.channel_id)
+            .say(&ctx.discord_http, code_block)
+            .await;
+    }
+}
+
+fn format_snippet(snippet: &str) -> Result<String, io::Error> {
+    let mut rustfmt = Command::new("rustfmt")
+        .args(["--config", "newline_style=Unix"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let input = rustfmt.stdin.as_mut().unwrap();
+    input.write_all(snippet.as_bytes())?;
+
+    let output = rustfmt.wait_with_output()?;
+
+    if output.status.success() {
+        String::from_utf8(output.stdout).map_err(io::Error::other)
+    } else {
+        Err(io::Error::other(String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_snippets() {
+        // converting to Option here because std::io::Error doesn't impl PartialEq
+        assert_eq!(
+            format_snippet(r#"fn main() { println!("hello world"); }"#)
+                .as_deref()
+                .ok(),
+            Some("fn main() {\n    println!(\"hello world\");\n}\n")
+        );
+
+        assert!(format_snippet(r#"totally not rust code"#).is_err());
+    }
+}
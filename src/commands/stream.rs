@@ -0,0 +1,707 @@
+//! Commands about the stream itself, like its title and how long it has been
+//! live, looked up with Helix. Also announcing it in Discord once it goes live.
+
+use super::args::{Param, UserName};
+use super::{twitch_user, BotModule, CommandHandler, Invocation, Registry, UNAVAILABLE};
+use crate::config::FerrisBotConfig;
+use crate::eventsub::Event;
+use crate::helix::ChannelInfo;
+use crate::permissions::Permission;
+use crate::templates::{self, Variables};
+use crate::{format_duration, Context};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use log::{error, info};
+use serde_json::json;
+use serenity::model::id::ChannelId;
+
+/// How many users `!topcheers` shows.
+const TOP_CHEERS: usize = 5;
+
+/// How many users `!topwatchers` shows.
+const TOP_WATCHERS: usize = 5;
+
+/// Twitch rejects longer marker descriptions.
+const MAX_MARKER_DESCRIPTION: usize = 140;
+
+pub struct Module;
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "stream"
+    }
+
+    fn register(&self, registry: &mut Registry, _config: &FerrisBotConfig) {
+        registry.register("!title", Title);
+        registry.register("!game", Game);
+        registry.register("!settitle", SetTitle);
+        registry.register("!setgame", SetGame);
+        registry.register("!uptime", Uptime);
+        registry.register("!followage", FollowAge);
+        registry.register("!so", Shoutout);
+        registry.register("!topcheers", TopCheers);
+        registry.register("!watchtime", Watchtime);
+        registry.register("!topwatchers", TopWatchers);
+        registry.register("!clip", Clip);
+        registry.register("!marker", Marker);
+    }
+
+    async fn on_event(&self, event: &Event, _channel_login: &str, ctx: &Context) {
+        if let Event::Online {
+            stream_id,
+            user,
+            login,
+            user_id,
+        } = event
+        {
+            went_live(stream_id, user, login, user_id, ctx).await;
+        }
+    }
+}
+
+/// Announces the stream with id `stream_id` of `user` in Discord, unless it
+/// already was, and starts welcoming chatters again.
+async fn went_live(stream_id: &str, user: &str, login: &str, user_id: &str, ctx: &Context) {
+    let config = match &ctx.config.live {
+        Some(config) => config,
+        None => return,
+    };
+    if !ctx.last_stream.lock().unwrap().is_new(stream_id) {
+        return info!("Stream {} was already announced", stream_id);
+    }
+    ctx.welcome_again(login);
+    ctx.lurkers.lock().unwrap().forget(login);
+
+    // still worth announcing without the details
+    let info = match ctx.helix.channel(user_id).await {
+        Ok(info) => info,
+        Err(e) => {
+            error!("Failed to look up the channel of {}: {}", login, e);
+            None
+        }
+    };
+    let variables = Variables {
+        user,
+        channel: login,
+        args: "",
+        count: 0,
+        months: None,
+    };
+    let text = templates::render(&config.message, &variables, &mut rand::thread_rng());
+    let url = format!("https://twitch.tv/{}", login);
+    let discord_channel = ChannelId(
+        config
+            .discord_channel_id
+            .unwrap_or(ctx.config.discord.channel_id),
+    );
+    let sent = discord_channel
+        .send_message(&ctx.discord_http, |message| {
+            message.content(format!("{} {}", text, url)).embed(|embed| {
+                embed.url(&url);
+                match &info {
+                    Some(info) => {
+                        embed.title(&info.title);
+                        if !info.game_name.is_empty() {
+                            embed.field("Category", &info.game_name, true);
+                        }
+                    }
+                    None => {
+                        embed.title(&url);
+                    }
+                }
+                embed
+            })
+        })
+        .await;
+    match sent {
+        // tried again if the stream comes up again, e.g. after a restart
+        Err(e) => error!("Failed to announce the stream in Discord: {}", e),
+        Ok(_) => ctx.last_stream.lock().unwrap().record(stream_id),
+    }
+}
+
+/// The settings of the channel `invocation` was used in, `None` after
+/// replying why there aren't any.
+async fn channel_info(invocation: &Invocation, ctx: &Context) -> Option<ChannelInfo> {
+    let msg = &invocation.msg;
+    match ctx.helix.channel(&msg.channel_id).await {
+        Ok(Some(info)) => Some(info),
+        Ok(None) => {
+            error!("Twitch doesn't know the channel {}", msg.channel_login);
+            ctx.reply(invocation, UNAVAILABLE);
+            None
+        }
+        Err(e) => {
+            error!("Failed to look up {}: {}", msg.channel_login, e);
+            ctx.reply(invocation, UNAVAILABLE);
+            None
+        }
+    }
+}
+
+/// `!title`
+struct Title;
+
+#[async_trait]
+impl CommandHandler for Title {
+    fn description(&self) -> &'static str {
+        "Shows the stream title"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        if let Some(info) = channel_info(invocation, ctx).await {
+            ctx.reply(invocation, &format!("The title is: {}", info.title));
+        }
+    }
+}
+
+/// `!game`
+struct Game;
+
+#[async_trait]
+impl CommandHandler for Game {
+    fn description(&self) -> &'static str {
+        "Shows the stream category"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        if let Some(info) = channel_info(invocation, ctx).await {
+            let reply = match info.game_name.as_str() {
+                "" => "The stream has no category".to_owned(),
+                game => format!("Streaming in {}", game),
+            };
+            ctx.reply(invocation, &reply);
+        }
+    }
+}
+
+/// `!settitle <text>`
+struct SetTitle;
+
+#[async_trait]
+impl CommandHandler for SetTitle {
+    fn description(&self) -> &'static str {
+        "Changes the stream title"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::text("title")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let title: String = invocation.args.value(0);
+        let changes = json!({ "title": title });
+        let reply = match ctx.helix.update_channel(&msg.channel_id, &changes).await {
+            Ok(()) => format!("The title is now: {}", title),
+            Err(e) => {
+                error!("Failed to change the title of {}: {}", msg.channel_login, e);
+                UNAVAILABLE.to_owned()
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!setgame <name>`
+struct SetGame;
+
+#[async_trait]
+impl CommandHandler for SetGame {
+    fn description(&self) -> &'static str {
+        "Changes the stream category"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::text("name")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let name: String = invocation.args.value(0);
+        let game = match ctx.helix.game(&name).await {
+            Ok(Some(game)) => game,
+            Ok(None) => return ctx.reply(invocation, &format!("There is no category {}", name)),
+            Err(e) => {
+                error!("Failed to look up the category {}: {}", name, e);
+                return ctx.reply(invocation, UNAVAILABLE);
+            }
+        };
+        let changes = json!({ "game_id": game.id });
+        let reply = match ctx.helix.update_channel(&msg.channel_id, &changes).await {
+            Ok(()) => format!("Now streaming in {}", game.name),
+            Err(e) => {
+                error!(
+                    "Failed to change the category of {}: {}",
+                    msg.channel_login, e
+                );
+                UNAVAILABLE.to_owned()
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!uptime`
+struct Uptime;
+
+#[async_trait]
+impl CommandHandler for Uptime {
+    fn description(&self) -> &'static str {
+        "Shows how long the stream has been live"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let reply = match ctx.helix.stream(&msg.channel_id).await {
+            Ok(Some(stream)) => format!(
+                "Live for {}",
+                format_duration(Utc::now() - stream.started_at)
+            ),
+            Ok(None) => format!("{} is offline", msg.channel_login),
+            Err(e) => {
+                error!(
+                    "Failed to look up the stream of {}: {}",
+                    msg.channel_login, e
+                );
+                UNAVAILABLE.to_owned()
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!followage [user]`, of whoever used it unless a user is given.
+struct FollowAge;
+
+#[async_trait]
+impl CommandHandler for FollowAge {
+    fn description(&self) -> &'static str {
+        "Shows how long someone has been following"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<UserName>("user")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let (name, user_id) = match invocation.args.get(0) {
+            None => (msg.sender.name.clone(), msg.sender.id.clone()),
+            Some(UserName(login)) => match twitch_user(&login, invocation, ctx).await {
+                Some(user) => (login, user.id),
+                None => return,
+            },
+        };
+        let reply = match ctx.helix.followed_at(&msg.channel_id, &user_id).await {
+            Ok(Some(since)) => format!(
+                "{} has been following for {}",
+                name,
+                long_duration(Utc::now() - since)
+            ),
+            Ok(None) => format!("{} isn't following {}", name, msg.channel_login),
+            Err(e) => {
+                error!("Failed to look up the follow of {}: {}", name, e);
+                UNAVAILABLE.to_owned()
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!so <user>`, recommends another streamer's channel.
+struct Shoutout;
+
+#[async_trait]
+impl CommandHandler for Shoutout {
+    fn description(&self) -> &'static str {
+        "Gives another streamer a shoutout"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<UserName>("user")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let UserName(login) = invocation.args.value(0);
+        let user = match twitch_user(&login, invocation, ctx).await {
+            Some(user) => user,
+            None => return,
+        };
+        // still worth a shoutout without the category
+        let game = match ctx.helix.channel(&user.id).await {
+            Ok(info) => info.map(|info| info.game_name).unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to look up the channel of {}: {}", login, e);
+                String::new()
+            }
+        };
+        ctx.say(
+            msg.channel_login.clone(),
+            shoutout_text(&user.display_name, &user.login, &game),
+        );
+        if ctx.config.commands.native_shoutouts {
+            if let Err(e) = ctx.helix.shoutout(&msg.channel_id, &user.id).await {
+                error!("Failed to send a Twitch shoutout to {}: {}", login, e);
+            }
+        }
+    }
+}
+
+fn shoutout_text(name: &str, login: &str, game: &str) -> String {
+    let last_seen = match game {
+        "" => String::new(),
+        game => format!(" They were last seen streaming {}.", game),
+    };
+    format!(
+        "Go check out {} at https://twitch.tv/{} !{}",
+        name, login, last_seen
+    )
+}
+
+/// `!topcheers`
+struct TopCheers;
+
+#[async_trait]
+impl CommandHandler for TopCheers {
+    fn description(&self) -> &'static str {
+        "Shows who cheered the most bits"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let cheers = ctx.cheers.lock().unwrap();
+        let top: Vec<String> = cheers
+            .top(&msg.channel_login, TOP_CHEERS)
+            .into_iter()
+            .map(|(user, bits)| format!("{} ({})", user, bits))
+            .collect();
+        let reply = match top.as_slice() {
+            [] => "No one has cheered yet".to_owned(),
+            top => format!("Top cheers: {}", top.join(", ")),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!watchtime [user]`
+struct Watchtime;
+
+#[async_trait]
+impl CommandHandler for Watchtime {
+    fn description(&self) -> &'static str {
+        "Shows how long you or another user have been watching"
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional::<UserName>("user")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let (name, login) = match invocation.args.get(0) {
+            None => (msg.sender.name.clone(), msg.sender.login.clone()),
+            Some(UserName(login)) => (login.clone(), login),
+        };
+        let watched = ctx.watchtime.lock().unwrap().of(&msg.channel_login, &login);
+        let reply = match watched {
+            Some(watched) => format!("{} has watched for {}", name, format_duration(watched)),
+            None => format!("{} hasn't watched yet", name),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!topwatchers`
+struct TopWatchers;
+
+#[async_trait]
+impl CommandHandler for TopWatchers {
+    fn description(&self) -> &'static str {
+        "Shows who watched the longest"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let watchtime = ctx.watchtime.lock().unwrap();
+        let top: Vec<String> = watchtime
+            .top(&msg.channel_login, TOP_WATCHERS)
+            .into_iter()
+            .map(|(user, watched)| format!("{} ({})", user, format_duration(watched)))
+            .collect();
+        let reply = match top.as_slice() {
+            [] => "No one has watched yet".to_owned(),
+            top => format!("Top watchers: {}", top.join(", ")),
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!clip`, clips the last seconds of the stream.
+struct Clip;
+
+#[async_trait]
+impl CommandHandler for Clip {
+    fn description(&self) -> &'static str {
+        "Clips what just happened on stream"
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        // Twitch only says the request failed if the stream is offline
+        match ctx.helix.stream(&msg.channel_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return ctx.reply(invocation, "There's nothing to clip while offline"),
+            Err(e) => {
+                error!(
+                    "Failed to look up the stream of {}: {}",
+                    msg.channel_login, e
+                );
+                return ctx.reply(invocation, UNAVAILABLE);
+            }
+        }
+        let clip = match ctx.helix.create_clip(&msg.channel_id).await {
+            Ok(clip) => clip,
+            Err(e) => {
+                error!("Failed to clip {}: {}", msg.channel_login, e);
+                return ctx.reply(invocation, UNAVAILABLE);
+            }
+        };
+        ctx.reply(invocation, &format!("Clipped it! {}", clip.url()));
+        if msg.channel_login == ctx.config.twitch.channel_name {
+            let discord_channel = ChannelId(ctx.config.discord.channel_id);
+            let announcement = format!("{} clipped the stream: {}", msg.sender.name, clip.url());
+            if let Err(e) = discord_channel.say(&ctx.discord_http, announcement).await {
+                error!("Failed to post a clip in Discord: {}", e);
+            }
+        }
+    }
+}
+
+/// `!marker [description]`, marks the moment for editors to find later.
+struct Marker;
+
+#[async_trait]
+impl CommandHandler for Marker {
+    fn description(&self) -> &'static str {
+        "Marks this moment in the stream"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::optional_text("description")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let msg = &invocation.msg;
+        let description: String = invocation.args.get(0).unwrap_or_default();
+        if description.chars().count() > MAX_MARKER_DESCRIPTION {
+            let reply = format!(
+                "Marker descriptions can't be longer than {} characters",
+                MAX_MARKER_DESCRIPTION
+            );
+            return ctx.reply(invocation, &reply);
+        }
+        let reply = match ctx.helix.create_marker(&msg.channel_id, &description).await {
+            Ok(marker) => format!(
+                "Marker added at {}",
+                format_duration(Duration::seconds(marker.position_seconds))
+            ),
+            Err(e) => {
+                // also when the stream is offline
+                error!("Failed to add a marker in {}: {}", msg.channel_login, e);
+                UNAVAILABLE.to_owned()
+            }
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// E.g. "2 years, 3 months and 5 days", counting months as 30 days. Shorter
+/// than a day it's "4h 20m".
+fn long_duration(duration: Duration) -> String {
+    let days = duration.num_days();
+    if days < 1 {
+        return format_duration(duration);
+    }
+    let parts: Vec<String> = [
+        (days / 365, "year"),
+        (days % 365 / 30, "month"),
+        (days % 365 % 30, "day"),
+    ]
+    .iter()
+    .filter(|&&(count, _)| count > 0)
+    .map(|&(count, unit)| match count {
+        1 => format!("1 {}", unit),
+        count => format!("{} {}s", count, unit),
+    })
+    .collect();
+    match parts.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => unreachable!("there's at least a day"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{drain, test_broadcaster_msg, test_context, test_msg};
+
+    #[tokio::test]
+    async fn changing_needs_moderators() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry
+            .dispatch(test_msg("!settitle Rewriting it in Rust"), &ctx)
+            .await;
+        registry.dispatch(test_msg("!setgame Chess"), &ctx).await;
+        registry.dispatch(test_msg("!so ferris"), &ctx).await;
+        registry.dispatch(test_msg("!marker"), &ctx).await;
+        assert!(drain(&mut sent).is_empty());
+    }
+
+    #[test]
+    fn shoutout_texts() {
+        assert_eq!(
+            shoutout_text("Ferris", "ferris", "Science & Technology"),
+            "Go check out Ferris at https://twitch.tv/ferris ! \
+             They were last seen streaming Science & Technology."
+        );
+        assert_eq!(
+            shoutout_text("Ferris", "ferris", ""),
+            "Go check out Ferris at https://twitch.tv/ferris !"
+        );
+    }
+
+    #[test]
+    fn describing_long_durations() {
+        assert_eq!(long_duration(Duration::minutes(90)), "1h 30m");
+        assert_eq!(long_duration(Duration::days(1)), "1 day");
+        assert_eq!(long_duration(Duration::days(45)), "1 month and 15 days");
+        assert_eq!(
+            long_duration(Duration::days(2 * 365 + 90 + 1)),
+            "2 years, 3 months and 1 day"
+        );
+        assert_eq!(
+            long_duration(Duration::days(365 + 60)),
+            "1 year and 2 months"
+        );
+    }
+
+    #[tokio::test]
+    async fn top_cheers() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!topcheers"), &ctx).await;
+        {
+            let mut cheers = ctx.cheers.lock().unwrap();
+            cheers.add("channel_login", "alice", 100);
+            cheers.add("channel_login", "bob", 500);
+            cheers.add("other", "carol", 1000);
+        }
+        registry.dispatch(test_msg("!topcheers"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: No one has cheered yet",
+                "@login: Top cheers: bob (500), alice (100)"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn watchtime() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!watchtime"), &ctx).await;
+        registry.dispatch(test_msg("!topwatchers"), &ctx).await;
+        {
+            let now = Utc::now();
+            let mut watchtime = ctx.watchtime.lock().unwrap();
+            watchtime.seen("channel_login", "1", "login", now);
+            watchtime.credit("channel_login", Duration::minutes(5), now);
+            watchtime.seen("channel_login", "1", "bob", now);
+            watchtime.credit("channel_login", Duration::hours(2), now);
+        }
+        for text in [
+            "!watchtime",
+            "!watchtime @Bob",
+            "!watchtime carol",
+            "!topwatchers",
+        ] {
+            registry.dispatch(test_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@login: name hasn't watched yet",
+                "@login: No one has watched yet",
+                "@login: name has watched for 2h 5m",
+                "@login: bob has watched for 2h 0m",
+                "@login: carol hasn't watched yet",
+                "@login: Top watchers: login (2h 5m), bob (2h 0m)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn adding_markers() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        let too_long = format!("!marker {}", "a".repeat(141));
+        registry
+            .dispatch(test_broadcaster_msg(&too_long), &ctx)
+            .await;
+        // the test context has no token, so Helix can't be asked
+        registry
+            .dispatch(test_broadcaster_msg("!marker great play"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Marker descriptions can't be longer than 140 characters",
+                &format!("@broadcaster: {}", UNAVAILABLE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn twitch_unavailable() {
+        // the test context has no token, so Helix can't be asked
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        registry.dispatch(test_msg("!title"), &ctx).await;
+        registry.dispatch(test_msg("!uptime"), &ctx).await;
+        registry.dispatch(test_msg("!clip"), &ctx).await;
+        registry
+            .dispatch(test_msg("!followage @Ferris"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            vec![format!("@login: {}", UNAVAILABLE); 4]
+        );
+    }
+}
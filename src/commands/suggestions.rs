@@ -0,0 +1,69 @@
+//! "Did you mean" suggestions for commands that don't exist, e.g. `!queue`
+//! for `!queeu`.
+
+/// The name closest to `typed`, if one is only a typo or two away. Short
+/// commands need to match more closely, anything under 3 characters isn't
+/// matched at all.
+pub fn closest<'a>(typed: &str, names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = typed.trim_start_matches('!').chars().count() / 3;
+    names
+        .into_iter()
+        .map(|name| (distance(typed, name), name))
+        .filter(|&(distance, _)| distance > 0 && distance <= max_distance)
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Edits needed to turn `a` into `b`: inserting, removing or changing a
+/// character, or swapping two neighbouring ones.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between a[..i] and b[..j]
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in distances[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let change = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + change);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = best;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measuring_distance() {
+        assert_eq!(distance("!queue", "!queue"), 0);
+        assert_eq!(distance("!queeu", "!queue"), 1);
+        assert_eq!(distance("!jion", "!join"), 1);
+        assert_eq!(distance("!nxt", "!next"), 1);
+        assert_eq!(distance("!dave", "!zoya"), 4);
+        assert_eq!(distance("żółć", "zółć"), 1);
+    }
+
+    #[test]
+    fn suggesting_commands() {
+        let names = ["!queue", "!join", "!leave", "!next", "!stonk"];
+        assert_eq!(closest("!queeu", names), Some("!queue"));
+        assert_eq!(closest("!jion", names), Some("!join"));
+        assert_eq!(closest("!leaev", names), Some("!leave"));
+        assert_eq!(closest("!hello", names), None);
+        // too short to guess
+        assert_eq!(closest("!nx", names), None);
+    }
+}
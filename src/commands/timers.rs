@@ -0,0 +1,25 @@
+//! Counting chat activity for the timers, which post on their own.
+
+use super::{BotModule, Registry};
+use crate::config::FerrisBotConfig;
+use crate::Context;
+use async_trait::async_trait;
+use twitch_irc::message::PrivmsgMessage;
+
+pub struct Module;
+
+#[async_trait]
+impl BotModule for Module {
+    fn name(&self) -> &'static str {
+        "timers"
+    }
+
+    fn register(&self, _registry: &mut Registry, _config: &FerrisBotConfig) {}
+
+    async fn on_message(&self, msg: &PrivmsgMessage, ctx: &Context) -> bool {
+        if let Some(timers) = ctx.timers.get(&msg.channel_login) {
+            timers.lock().unwrap().record_message();
+        }
+        false
+    }
+}
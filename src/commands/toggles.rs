@@ -0,0 +1,183 @@
+//! `!disable <command>` and `!enable <command>`, turning commands off and on
+//! without restarting the bot.
+
+use super::args::Param;
+use super::{command_name, resolve_alias, CommandHandler, Invocation, Registry};
+use crate::config::FerrisBotConfig;
+use crate::disabled_commands::DisabledCommands;
+use crate::permissions::Permission;
+use crate::Context;
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+pub fn register(registry: &mut Registry, config: &FerrisBotConfig) {
+    let disabled = match &config.commands.disabled_path {
+        Some(path) => DisabledCommands::persistent(path.into()).unwrap(),
+        None => DisabledCommands::default(),
+    };
+    // the middleware checking for disabled commands shares the same list
+    *registry.disabled.lock().unwrap() = disabled;
+    registry.register(
+        "!disable",
+        Disable {
+            disabled: registry.disabled.clone(),
+        },
+    );
+    registry.register(
+        "!enable",
+        Enable {
+            disabled: registry.disabled.clone(),
+        },
+    );
+}
+
+/// The command `!disable` and `!enable` are about, with aliases resolved.
+fn target(invocation: &Invocation, ctx: &Context) -> String {
+    let command = command_name(&invocation.args.value::<String>(0));
+    resolve_alias(&ctx.config.commands.aliases, &command)
+}
+
+/// `!disable <command>`
+struct Disable {
+    disabled: Arc<Mutex<DisabledCommands>>,
+}
+
+#[async_trait]
+impl CommandHandler for Disable {
+    fn description(&self) -> &'static str {
+        "Turns a command off until it's enabled again"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<String>("command")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let command = target(invocation, ctx);
+        // there'd be no way back
+        if command == "!enable" {
+            return ctx.reply(invocation, "!enable can't be disabled");
+        }
+        let mut disabled = self.disabled.lock().unwrap();
+        let reply = if disabled.disable(&invocation.msg.channel_login, &command) {
+            format!(
+                "Disabled {}, use !enable {} to turn it back on",
+                command, command
+            )
+        } else {
+            format!("{} is already disabled", command)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+/// `!enable <command>`
+struct Enable {
+    disabled: Arc<Mutex<DisabledCommands>>,
+}
+
+#[async_trait]
+impl CommandHandler for Enable {
+    fn description(&self) -> &'static str {
+        "Turns a disabled command back on"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Moderator
+    }
+
+    fn params(&self) -> Vec<Param> {
+        vec![Param::required::<String>("command")]
+    }
+
+    async fn handle(&self, invocation: &Invocation, ctx: &Context) {
+        let command = target(invocation, ctx);
+        let mut disabled = self.disabled.lock().unwrap();
+        let reply = if disabled.enable(&invocation.msg.channel_login, &command) {
+            format!("Enabled {}", command)
+        } else {
+            format!("{} isn't disabled", command)
+        };
+        ctx.reply(invocation, &reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{
+        drain, test_broadcaster_msg, test_config, test_context, test_context_with, test_msg,
+    };
+    use twitch_irc::message::PrivmsgMessage;
+
+    #[tokio::test]
+    async fn disabling_commands() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in [
+            "!disable stonk",
+            "!disable !Q",
+            "!disable !stonk",
+            "!disable enable",
+        ] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        registry.dispatch(test_msg("!enable !stonk"), &ctx).await;
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        // disabled for moderators as well
+        registry
+            .dispatch(test_broadcaster_msg("!queue"), &ctx)
+            .await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Disabled !stonk, use !enable !stonk to turn it back on",
+                "@broadcaster: Disabled !queue, use !enable !queue to turn it back on",
+                "@broadcaster: !stonk is already disabled",
+                "@broadcaster: !enable can't be disabled",
+            ]
+        );
+
+        for text in ["!enable !stonk", "!enable !stonk", "!stonk"] {
+            registry.dispatch(test_broadcaster_msg(text), &ctx).await;
+        }
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Enabled !stonk",
+                "@broadcaster: !stonk isn't disabled",
+                "@broadcaster: yOu shOULd Buy AMC sTOnKS",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn disabling_per_channel() {
+        let mut config = test_config();
+        config.twitch.extra_channels = vec!["other_channel".to_owned()];
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+        let in_other = |mut msg: PrivmsgMessage| {
+            msg.channel_login = "other_channel".to_owned();
+            msg
+        };
+
+        registry
+            .dispatch(in_other(test_broadcaster_msg("!disable stonk")), &ctx)
+            .await;
+        registry.dispatch(in_other(test_msg("!stonk")), &ctx).await;
+        registry.dispatch(test_msg("!stonk"), &ctx).await;
+        assert_eq!(
+            drain(&mut sent),
+            [
+                "@broadcaster: Disabled !stonk, use !enable !stonk to turn it back on",
+                "@login: yOu shOULd Buy AMC sTOnKS",
+            ]
+        );
+    }
+}
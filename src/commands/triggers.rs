@@ -0,0 +1,101 @@
+//! Canned replies to chat messages that aren't commands, triggered by a
+//! keyword or a regular expression, e.g. anyone typing "segfault".
+
+use crate::config::TriggerConfig;
+use crate::cooldowns::Cooldown;
+use regex::Regex;
+
+/// A configured trigger, ready to match messages.
+pub struct Trigger {
+    pattern: Regex,
+    pub text: String,
+    pub reply: bool,
+    pub cooldown: Cooldown,
+}
+
+impl Trigger {
+    /// Keywords match as whole words, ignoring case.
+    pub fn new(config: &TriggerConfig) -> Result<Trigger, String> {
+        let pattern = match (&config.keyword, &config.regex) {
+            (Some(keyword), None) => keyword_pattern(keyword),
+            (None, Some(regex)) => regex.clone(),
+            _ => return Err("a trigger needs either a keyword or a regex".to_owned()),
+        };
+        let pattern = Regex::new(&pattern).map_err(|e| e.to_string())?;
+        Ok(Trigger {
+            pattern,
+            text: config.text.clone(),
+            reply: config.reply,
+            cooldown: config.cooldown,
+        })
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.pattern.is_match(text)
+    }
+
+    /// Identifies the trigger's cooldowns among the commands'.
+    pub fn key(&self) -> &str {
+        self.pattern.as_str()
+    }
+}
+
+/// Matches `keyword` as a whole word, `\b` only works at word characters so
+/// `c++` is delimited by whitespace instead.
+fn keyword_pattern(keyword: &str) -> String {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if is_word(keyword.chars().next()) {
+        r"\b"
+    } else {
+        r"(?:^|\s)"
+    };
+    let end = if is_word(keyword.chars().last()) {
+        r"\b"
+    } else {
+        r"(?:$|\s)"
+    };
+    format!("(?i){}{}{}", start, regex::escape(keyword), end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(keyword: Option<&str>, regex: Option<&str>) -> Result<Trigger, String> {
+        Trigger::new(&TriggerConfig {
+            keyword: keyword.map(str::to_owned),
+            regex: regex.map(str::to_owned),
+            text: "text".to_owned(),
+            reply: false,
+            cooldown: Cooldown::default(),
+        })
+    }
+
+    #[test]
+    fn matching_keywords() {
+        let trigger = trigger(Some("c++"), None).unwrap();
+        assert!(trigger.matches("I love C++"));
+        assert!(trigger.matches("c++ is fine"));
+        assert!(!trigger.matches("c+++"));
+        assert!(!trigger.matches("c"));
+
+        let trigger = self::trigger(Some("segfault"), None).unwrap();
+        assert!(trigger.matches("another SEGFAULT!"));
+        assert!(!trigger.matches("segfaults"));
+    }
+
+    #[test]
+    fn matching_regexes() {
+        let trigger = trigger(None, Some(r"(?i)seg(mentation)? ?fault")).unwrap();
+        assert!(trigger.matches("Segmentation fault (core dumped)"));
+        assert!(trigger.matches("segfault"));
+        assert!(!trigger.matches("default"));
+    }
+
+    #[test]
+    fn invalid_triggers() {
+        assert!(trigger(None, None).is_err());
+        assert!(trigger(Some("a"), Some("b")).is_err());
+        assert!(trigger(None, Some("(")).is_err());
+    }
+}
@@ -0,0 +1,747 @@
+use crate::commands::ReplyTarget;
+use crate::cooldowns::Cooldown;
+use crate::eventsub::Kind;
+use crate::permissions::Permission;
+use crate::poll::RevotePolicy;
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use toml::value::{Table, Value};
+use twitch_queue_bot::queue_manager::{PriorityWeights, RandomWeighting, StrategyKind};
+
+#[derive(Deserialize)]
+pub struct FerrisBotConfig {
+    pub twitch: TwitchConfig,
+    pub discord: DiscordConfig,
+    #[serde(default)]
+    pub poll: PollConfig,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    #[serde(default)]
+    pub modules: ModulesConfig,
+    /// Messages posted at intervals, keyed by a name for the timer.
+    #[serde(default)]
+    pub timers: HashMap<String, TimerConfig>,
+    #[serde(default)]
+    pub scripts: ScriptsConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    /// What the bot does when a channel point reward is redeemed in the main
+    /// channel, keyed by the reward's id.
+    #[serde(default)]
+    pub redemptions: HashMap<String, RedemptionConfig>,
+    /// Chat filters, off unless configured.
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    /// Welcomes people chatting for the first time, off unless configured.
+    pub welcome: Option<WelcomeConfig>,
+    /// Announces in Discord when the main channel goes live, off unless
+    /// configured.
+    pub live: Option<LiveConfig>,
+    /// Writes chat to daily files, off unless configured.
+    pub chat_log: Option<ChatLogConfig>,
+}
+
+impl FerrisBotConfig {
+    /// The events there's something to do for.
+    pub fn event_kinds(&self) -> Vec<Kind> {
+        let mut kinds = self.events.kinds();
+        if !self.redemptions.is_empty() {
+            kinds.push(Kind::Redemption);
+        }
+        if self.live.is_some() {
+            kinds.push(Kind::Online);
+        }
+        if self.moderation.automod.is_some() {
+            kinds.push(Kind::AutoMod);
+        }
+        kinds
+    }
+}
+
+/// Which parts of the bot run, every one unless turned off.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModulesConfig {
+    pub queue: bool,
+    /// `!code`
+    pub snippets: bool,
+    /// Custom and response commands, counters and triggers.
+    pub memes: bool,
+    /// `!mute`, `!disable` and the like, the chat filters and `[moderation.automod]`.
+    pub moderation: bool,
+    pub polls: bool,
+    /// Messages posted at intervals, see `[timers]`.
+    pub timers: bool,
+    /// Commands written as scripts, see `[scripts]`.
+    pub scripts: bool,
+    /// `!title`, `!game` and the like, about the stream itself, and the
+    /// announcement in `[live]`.
+    pub stream: bool,
+    /// Thanks for follows, subscriptions, raids and cheers, see `[events]`.
+    pub events: bool,
+    /// Channel point rewards, see `[redemptions]`.
+    pub redemptions: bool,
+}
+
+impl Default for ModulesConfig {
+    fn default() -> ModulesConfig {
+        ModulesConfig {
+            queue: true,
+            snippets: true,
+            memes: true,
+            moderation: true,
+            polls: true,
+            timers: true,
+            scripts: true,
+            stream: true,
+            events: true,
+            redemptions: true,
+        }
+    }
+}
+
+impl ModulesConfig {
+    pub fn is_enabled(&self, module: &str) -> bool {
+        match module {
+            "queue" => self.queue,
+            "snippets" => self.snippets,
+            "memes" => self.memes,
+            "moderation" => self.moderation,
+            "polls" => self.polls,
+            "timers" => self.timers,
+            "scripts" => self.scripts,
+            "stream" => self.stream,
+            "events" => self.events,
+            "redemptions" => self.redemptions,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TwitchConfig {
+    pub token_filepath: String,
+    pub login_name: String,
+    /// The main channel, the one Discord and the queue stats are about.
+    pub channel_name: String,
+    /// More channels to join, each with queues of its own.
+    #[serde(default)]
+    pub extra_channels: Vec<String>,
+    pub client_id: String,
+    pub secret: String,
+    /// Chat messages the bot may send every 30 seconds, 20 unless set. Verified
+    /// bots and moderators may send 100.
+    pub message_limit: Option<usize>,
+}
+
+impl TwitchConfig {
+    /// Every channel to join, the main one first.
+    pub fn channels(&self) -> Vec<&str> {
+        let mut channels = vec![self.channel_name.as_str()];
+        for channel in &self.extra_channels {
+            if !channels.contains(&channel.as_str()) {
+                channels.push(channel);
+            }
+        }
+        channels
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DiscordConfig {
+    pub auth_token: String,
+    pub channel_id: u64,
+    /// Channel with a pinned message that always shows the current queues.
+    pub mirror_channel_id: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct PollConfig {
+    #[serde(default)]
+    pub revote: RevotePolicy,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CommandsConfig {
+    /// What commands start with, `!` if unset.
+    #[serde(default, deserialize_with = "prefix")]
+    pub prefix: Option<String>,
+    /// File the commands added with `!addcmd` are saved to, they're lost on
+    /// restart if unset.
+    pub persist_path: Option<String>,
+    /// File with simple response commands like `!stonk`, reloaded when it
+    /// changes. The ones in `responses.toml` are built in if unset.
+    pub responses_path: Option<String>,
+    /// Directory the texts of response commands are read from, instead of
+    /// next to the responses file.
+    pub assets_path: Option<String>,
+    /// File the counters added with `!addcounter` are saved to, they're lost
+    /// on restart if unset.
+    pub counters_path: Option<String>,
+    /// File the commands turned off with `!disable` in every channel are saved to.
+    pub disabled_path: Option<String>,
+    /// File every use of a moderator command is added to.
+    pub audit_path: Option<String>,
+    /// File the bits everyone cheered are saved to, for `!topcheers`.
+    pub cheers_path: Option<String>,
+    /// File how long everyone watched is saved to, for `!watchtime`.
+    pub watchtime_path: Option<String>,
+    /// Don't suggest a similar command when an unknown one is used.
+    #[serde(default)]
+    pub silence_suggestions: bool,
+    /// Have `!so` send a Twitch shoutout as well, which needs the
+    /// `moderator:manage:shoutouts` scope.
+    #[serde(default)]
+    pub native_shoutouts: bool,
+    /// Post timeouts and bans done with `!timeout` and `!ban` in the Discord
+    /// channel.
+    #[serde(default)]
+    pub moderation_to_discord: bool,
+    /// Seconds between looking up the moderators and VIPs of the joined
+    /// channels, which needs the `moderation:read` and `channel:read:vips`
+    /// scopes. Roles are only known from chat badges if unset.
+    pub role_refresh_interval: Option<u64>,
+    /// Seconds between logging which commands were used the most.
+    pub usage_log_interval: Option<u64>,
+    /// Seconds back `!chatstats` looks, an hour if unset.
+    pub chat_stats_window: Option<i64>,
+    /// Extra triggers mapped to the command they stand for, e.g. `!q` to `!queue`.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// How often each command may be used, keyed by command, e.g. `!dave`.
+    /// Moderators aren't limited.
+    #[serde(default)]
+    pub cooldowns: HashMap<String, Cooldown>,
+    /// How often any command may be used, keyed by the lowest role it applies
+    /// to, so chat can't go through one command after another. The
+    /// broadcaster isn't limited.
+    #[serde(default, deserialize_with = "by_role")]
+    pub role_cooldowns: BTreeMap<Permission, Cooldown>,
+    /// Who may use a command, overriding its default, keyed by command.
+    #[serde(default)]
+    pub permissions: HashMap<String, Permission>,
+    /// Where replies go unless the command has a default of its own or one
+    /// in `reply_targets`, also for replies to messages that aren't commands.
+    #[serde(default)]
+    pub reply_target: ReplyTarget,
+    /// Where the replies to a command go, overriding its default, keyed by
+    /// command.
+    #[serde(default)]
+    pub reply_targets: HashMap<String, ReplyTarget>,
+    /// Commands that run other commands in order, keyed by command, e.g.
+    /// `!dnd` to `["!closequeue", "!brb"]`. Steps like `discord: text` post
+    /// the text in Discord.
+    #[serde(default)]
+    pub chains: HashMap<String, Vec<String>>,
+    /// Replies to messages that aren't commands.
+    #[serde(default)]
+    pub triggers: Vec<TriggerConfig>,
+    /// Settings for single channels, keyed by channel login.
+    #[serde(default)]
+    pub channels: HashMap<String, ChannelCommandsConfig>,
+}
+
+impl CommandsConfig {
+    /// What commands start with in `channel`.
+    pub fn prefix(&self, channel: &str) -> &str {
+        self.channels
+            .get(channel)
+            .and_then(|channel| channel.prefix.as_ref())
+            .or(self.prefix.as_ref())
+            .map_or("!", String::as_str)
+    }
+}
+
+/// Commands in a single channel, on top of the ones for every channel.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelCommandsConfig {
+    /// Overrides `prefix`.
+    #[serde(default, deserialize_with = "prefix")]
+    pub prefix: Option<String>,
+    /// Commands turned off in this channel only.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Override the cooldowns of the same commands.
+    #[serde(default)]
+    pub cooldowns: HashMap<String, Cooldown>,
+    /// Commands answering with a fixed text in this channel only, keyed by
+    /// command. They take precedence over the responses file.
+    #[serde(default)]
+    pub responses: HashMap<String, String>,
+}
+
+/// Commands written as Rhai scripts, only run with the `scripting` feature.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptsConfig {
+    /// Milliseconds a script may run before it's stopped, 100 if unset.
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub timeout_ms: Option<u64>,
+    /// Script files keyed by the command running them, e.g. `!roll`.
+    #[serde(default)]
+    pub commands: HashMap<String, String>,
+}
+
+/// Messages posted when someone follows the main channel, or subscribes to,
+/// raids or cheers in any joined channel. `$(user)` is who did it, in
+/// subscription messages `$(months)` is how many months they've been
+/// subscribed and in raid messages `$(count)` is the number of raiders.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EventsConfig {
+    pub follow: Option<String>,
+    pub subscribe: Option<String>,
+    /// For gifted subscriptions, `$(user)` is who got it. `subscribe` is used
+    /// if unset.
+    pub gift_subscribe: Option<String>,
+    /// For subscriptions gifted to the community, `$(user)` is who gifted them
+    /// and `$(count)` how many. The single gifts they're made of aren't
+    /// thanked on their own.
+    pub community_gift: Option<String>,
+    pub raid: Option<String>,
+    /// For cheers of at least `min_cheer` bits, `$(count)` is the number of bits.
+    pub cheer: Option<String>,
+    #[serde(default)]
+    pub min_cheer: u64,
+    /// Post the ones in the main channel in the Discord channel as well.
+    #[serde(default)]
+    pub discord: bool,
+    /// Hype trains in the main channel.
+    pub hype_train: Option<HypeTrainConfig>,
+}
+
+impl EventsConfig {
+    /// The events there's a message for.
+    pub fn kinds(&self) -> Vec<Kind> {
+        let mut kinds = Vec::new();
+        if self.follow.is_some() {
+            kinds.push(Kind::Follow);
+        }
+        if self.hype_train.is_some() {
+            kinds.push(Kind::HypeTrain);
+        }
+        kinds
+    }
+}
+
+/// Messages hyping a hype train along, `$(count)` is its level.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HypeTrainConfig {
+    pub begin: Option<String>,
+    /// When it reaches a new level.
+    pub level: Option<String>,
+    /// Override `level` for single levels, keyed by the level, e.g. `"5"`.
+    #[serde(default)]
+    pub levels: HashMap<String, String>,
+    /// When it's over.
+    pub end: Option<String>,
+}
+
+/// Welcomes everyone the first time they chat in a channel.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WelcomeConfig {
+    /// `$(user)` is who's chatting for the first time.
+    pub message: String,
+    /// File everyone who chatted is added to, so only people who never
+    /// chatted before are welcomed. If unset, everyone is welcomed the first
+    /// time they chat in a stream, which ends with `!endstream` or, in the
+    /// main channel, when it goes live if `live` is set up.
+    pub path: Option<String>,
+    /// Tell them about `!help` as well.
+    #[serde(default)]
+    pub help: bool,
+    /// Invite to the Discord server to point them to.
+    pub discord: Option<String>,
+}
+
+/// Announces the stream in Discord when it goes live, with its title and
+/// category.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LiveConfig {
+    /// `$(user)` is the streamer.
+    #[serde(default = "default_live_message")]
+    pub message: String,
+    /// The Discord channel to announce it in, the one under `[discord]` if
+    /// unset.
+    pub discord_channel_id: Option<u64>,
+    /// File the last announced stream is saved to, so restarting the bot while
+    /// live doesn't announce it again.
+    pub path: Option<String>,
+}
+
+/// Writes every chat message, timeout, ban and deleted message in the joined
+/// channels to a file per channel and day.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatLogConfig {
+    /// Directory the files are written to.
+    pub dir: String,
+    /// Days after which logs are deleted, they're kept forever if unset.
+    pub retention_days: Option<i64>,
+}
+
+fn default_live_message() -> String {
+    "$(user) is live!".to_owned()
+}
+
+/// What's done for a redeemed channel point reward, in this order.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RedemptionConfig {
+    /// Counts one more on this counter, e.g. `deaths`.
+    pub counter: Option<String>,
+    /// Moves whoever redeemed it up in this queue if they're in it, nothing
+    /// is posted if they aren't.
+    pub bump: Option<String>,
+    /// How many places `bump` moves them up, to the front if unset.
+    pub places: Option<usize>,
+    /// Posted in chat. `$(user)` is who redeemed it, `$(args)` what they typed
+    /// and `$(count)` the value of `counter`.
+    pub say: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimerConfig {
+    /// Seconds between posts.
+    pub interval: i64,
+    pub text: String,
+    /// Chat messages needed since the last post, e.g. 1 to stay quiet while
+    /// nobody is chatting.
+    #[serde(default)]
+    pub min_messages: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ModerationConfig {
+    pub links: Option<LinkFilterConfig>,
+    pub spam: Option<SpamFilterConfig>,
+    pub banned_phrases: Option<BannedPhrasesConfig>,
+    pub automod: Option<AutoModConfig>,
+}
+
+/// Posts messages AutoMod held in the main channel to Discord, with buttons to
+/// approve or deny them.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AutoModConfig {
+    /// Where to post them.
+    pub discord_channel_id: u64,
+    /// Discord roles that may approve or deny them, by id. Without any, only
+    /// members who can manage messages may.
+    #[serde(default)]
+    pub discord_role_ids: Vec<u64>,
+}
+
+/// Removes messages with links, unless a moderator gave a `!permit`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkFilterConfig {
+    /// Regexes links are recognized by, common web addresses if empty.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// The lowest role that may post links anyway.
+    #[serde(default = "default_exempt")]
+    pub exempt: Permission,
+    /// Seconds a `!permit` lasts.
+    #[serde(default = "default_permit_seconds")]
+    pub permit_seconds: i64,
+    /// Seconds the sender is timed out for, the message is only deleted if unset.
+    pub timeout: Option<i64>,
+}
+
+/// Removes messages shouting, repeating characters or flooding emotes, every
+/// rule is off unless its threshold is set.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpamFilterConfig {
+    /// Percentage of capital letters a message may have, emotes aside.
+    pub max_caps_percent: Option<u32>,
+    /// Messages with fewer letters than this may be all caps.
+    #[serde(default = "default_caps_min_letters")]
+    pub caps_min_letters: usize,
+    /// How many times in a row a message may repeat a character.
+    pub max_repeated: Option<usize>,
+    pub max_emotes: Option<usize>,
+    /// The lowest role the rules don't apply to.
+    #[serde(default = "default_exempt")]
+    pub exempt: Permission,
+    /// Seconds the sender is timed out for, the message is only deleted if unset.
+    pub timeout: Option<i64>,
+}
+
+/// Removes messages with a phrase moderators banned with `!addbanned`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BannedPhrasesConfig {
+    /// File the phrases are saved to, reloaded when it changes. They're lost
+    /// on restart if unset.
+    pub path: Option<String>,
+    /// The lowest role that may use them anyway.
+    #[serde(default = "default_banned_phrases_exempt")]
+    pub exempt: Permission,
+    /// Seconds the sender is timed out for, the message is only deleted if unset.
+    pub timeout: Option<i64>,
+}
+
+fn default_caps_min_letters() -> usize {
+    10
+}
+
+fn default_exempt() -> Permission {
+    Permission::Vip
+}
+
+fn default_banned_phrases_exempt() -> Permission {
+    Permission::Moderator
+}
+
+fn default_permit_seconds() -> i64 {
+    60
+}
+
+/// A reply to any message with a keyword or matching a regex, not both.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+    /// Matched as a whole word, ignoring case.
+    pub keyword: Option<String>,
+    pub regex: Option<String>,
+    pub text: String,
+    /// Address the reply to whoever triggered it.
+    #[serde(default)]
+    pub reply: bool,
+    #[serde(default)]
+    pub cooldown: Cooldown,
+}
+
+#[derive(Deserialize, Default)]
+pub struct QueueConfig {
+    /// File the queue is saved to after every change and restored from on
+    /// startup. The queues of extra channels go next to it, e.g.
+    /// `queue.other_channel.json`.
+    pub persist_path: Option<String>,
+    /// Also announce the user picked by `!next` in the Discord channel.
+    #[serde(default)]
+    pub notify_discord: bool,
+    /// Also whisper the user picked by `!next`, needs the `user:manage:whispers` scope.
+    #[serde(default)]
+    pub whisper_on_pick: bool,
+    #[serde(default)]
+    pub priority: PriorityWeights,
+    /// Halve priority weights for every earlier join in the same stream.
+    #[serde(default)]
+    pub priority_decay: bool,
+    /// `!join` is refused once the queue holds this many users.
+    pub max_queue_size: Option<usize>,
+    /// Seconds a user picked by `!next` has to wait before they can `!join` again.
+    pub rejoin_cooldown: Option<i64>,
+    /// Seconds a picked user has to confirm with `!here` before they're skipped.
+    pub confirm_timeout: Option<i64>,
+    /// Seconds between announcements of users who moved up in the queue,
+    /// nothing is announced if unset.
+    pub position_updates: Option<i64>,
+    /// Put users skipped for not confirming back at the end of the queue.
+    #[serde(default)]
+    pub requeue_unresponsive: bool,
+    /// How many times a user can be picked per stream, reset by `!endstream`.
+    pub max_picks_per_stream: Option<usize>,
+    /// Post the queue stats to the Discord channel when the bot shuts down.
+    #[serde(default)]
+    pub stats_to_discord: bool,
+    /// File the queue stats of every stream are appended to on `!endstream`.
+    pub stats_log: Option<String>,
+    /// How the queue is ordered and who `!next` picks.
+    #[serde(default)]
+    pub strategy: StrategyKind,
+    /// How `!pickrandom`, and `!next` with the random strategy, favour some
+    /// users over others.
+    #[serde(default)]
+    pub random_weighting: RandomWeighting,
+}
+
+/// A table keyed by role, e.g. `moderator`, toml can't read enum keys itself.
+fn by_role<'de, D, T>(deserializer: D) -> Result<BTreeMap<Permission, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    HashMap::<String, T>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(role, value)| Ok((Permission::deserialize(role.into_deserializer())?, value)))
+        .collect()
+}
+
+fn prefix<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let prefix = String::deserialize(deserializer)?;
+    if prefix.trim().is_empty() {
+        return Err(serde::de::Error::custom("prefix can't be empty"));
+    }
+    Ok(Some(prefix))
+}
+
+/// Reads all config files in order, merging every file over the previous ones.
+pub fn load(paths: &[String]) -> Result<FerrisBotConfig, Box<dyn Error>> {
+    let layers = paths
+        .iter()
+        .map(|path| fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(from_layers(&layers)?)
+}
+
+fn from_layers<S: AsRef<str>>(layers: &[S]) -> Result<FerrisBotConfig, toml::de::Error> {
+    let mut merged = Value::Table(Table::new());
+    for layer in layers {
+        merge(&mut merged, toml::from_str(layer.as_ref())?);
+    }
+    merged.try_into()
+}
+
+/// Tables are merged key by key, any other value in `overlay` replaces the one in `base`.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+        [twitch]
+        login_name = 'ferris_bot'
+        channel_name = 'stuck_overflow'
+        client_id = 'shared_client'
+        secret = 'placeholder'
+        token_filepath = '.twitchauthtoken.json'
+
+        [discord]
+        auth_token = 'placeholder'
+        channel_id = 1
+    "#;
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let overlay = r#"
+            [twitch]
+            secret = 'real_secret'
+
+            [poll]
+            revote = 'first_wins'
+        "#;
+
+        let config = from_layers(&[BASE, overlay]).unwrap();
+        assert_eq!(config.twitch.secret, "real_secret");
+        assert_eq!(config.twitch.client_id, "shared_client");
+        assert_eq!(config.twitch.login_name, "ferris_bot");
+        assert_eq!(config.discord.channel_id, 1);
+        assert_eq!(config.poll.revote, RevotePolicy::FirstWins);
+    }
+
+    #[test]
+    fn joined_channels() {
+        let overlay = r#"
+            [twitch]
+            extra_channels = ['other', 'stuck_overflow', 'other']
+        "#;
+
+        let config = from_layers(&[BASE, overlay]).unwrap();
+        assert_eq!(config.twitch.channels(), ["stuck_overflow", "other"]);
+    }
+
+    #[test]
+    fn optional_sections() {
+        let config = from_layers(&[BASE]).unwrap();
+        assert!(config.moderation.links.is_none());
+        assert!(config.welcome.is_none());
+        assert!(config.chat_log.is_none());
+        assert!(config.event_kinds().is_empty());
+
+        // every section loads with only its required keys, and the ones that
+        // need events subscribe to them
+        let sections = [
+            ("[moderation.links]", None),
+            ("[moderation.spam]", None),
+            ("[moderation.banned_phrases]", None),
+            ("[welcome]\nmessage = 'Welcome $(user)!'", None),
+            ("[chat_log]\ndir = 'logs'", None),
+            ("[live]", Some(Kind::Online)),
+            (
+                "[moderation.automod]\ndiscord_channel_id = 3",
+                Some(Kind::AutoMod),
+            ),
+            ("[events.hype_train]", Some(Kind::HypeTrain)),
+        ];
+        for (overlay, kind) in sections {
+            let config =
+                from_layers(&[BASE, overlay]).unwrap_or_else(|e| panic!("{}: {}", overlay, e));
+            if let Some(kind) = kind {
+                assert!(config.event_kinds().contains(&kind), "{}", overlay);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_prefixes() {
+        let config = from_layers(&[BASE, "[commands]\nprefix = '?'"]).unwrap();
+        assert_eq!(config.commands.prefix.as_deref(), Some("?"));
+
+        assert!(from_layers(&[BASE, "[commands]\nprefix = ''"]).is_err());
+        let overlay = r#"
+            [commands.channels.other]
+            prefix = ' '
+        "#;
+        assert!(from_layers(&[BASE, overlay]).is_err());
+    }
+
+    #[test]
+    fn role_cooldowns() {
+        let overlay = r#"
+            [commands.role_cooldowns]
+            everyone = { user = 10 }
+            moderator = { global = 2 }
+        "#;
+
+        let config = from_layers(&[BASE, overlay]).unwrap();
+        let cooldowns = &config.commands.role_cooldowns;
+        assert_eq!(cooldowns[&Permission::Everyone].user, Some(10));
+        assert_eq!(cooldowns[&Permission::Moderator].global, Some(2));
+
+        let unknown = r#"
+            [commands.role_cooldowns]
+            admin = { user = 10 }
+        "#;
+        assert!(from_layers(&[BASE, unknown]).is_err());
+    }
+
+    #[test]
+    fn single_layer() {
+        let config = from_layers(&[BASE]).unwrap();
+        assert_eq!(config.twitch.secret, "placeholder");
+        assert_eq!(config.poll.revote, RevotePolicy::LastWins);
+    }
+}
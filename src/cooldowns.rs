@@ -0,0 +1,128 @@
+//! Rate limiting commands so they can't be spammed.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// How often a command may be used, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(default)]
+pub struct Cooldown {
+    /// Between any two uses in the channel.
+    pub global: Option<i64>,
+    /// Between two uses by the same user.
+    pub user: Option<i64>,
+}
+
+/// When commands were last used, overall and by every user. Commands are
+/// told apart by a `K`, their name unless set otherwise.
+pub struct Cooldowns<K = String> {
+    last_used: HashMap<K, DateTime<Utc>>,
+    last_used_by: HashMap<(K, String), DateTime<Utc>>,
+}
+
+impl<K> Default for Cooldowns<K> {
+    fn default() -> Cooldowns<K> {
+        Cooldowns {
+            last_used: HashMap::new(),
+            last_used_by: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Cooldowns<K> {
+    /// Records a use of `command` by `user` unless it's still on `cooldown`,
+    /// returns whether it may be used.
+    pub fn try_use<Q>(
+        &mut self,
+        command: &Q,
+        user: &str,
+        cooldown: &Cooldown,
+        now: DateTime<Utc>,
+    ) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if !self.ready(command, user, cooldown, now) {
+            return false;
+        }
+        self.record(command, user, now);
+        true
+    }
+
+    /// Whether `user` may use `command` at `now`, without counting it as a use.
+    pub fn ready<Q>(&self, command: &Q, user: &str, cooldown: &Cooldown, now: DateTime<Utc>) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let cooling = |last: Option<&DateTime<Utc>>, seconds: Option<i64>| match (last, seconds) {
+            (Some(&last), Some(seconds)) => now - last < Duration::seconds(seconds),
+            _ => false,
+        };
+        let key = (command.to_owned(), user.to_owned());
+        !cooling(self.last_used.get(command), cooldown.global)
+            && !cooling(self.last_used_by.get(&key), cooldown.user)
+    }
+
+    /// Counts a use of `command` by `user` at `now`, see `ready`.
+    pub fn record<Q>(&mut self, command: &Q, user: &str, now: DateTime<Utc>)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.last_used.insert(command.to_owned(), now);
+        self.last_used_by
+            .insert((command.to_owned(), user.to_owned()), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_cooldown() {
+        let mut cooldowns = Cooldowns::default();
+        let cooldown = Cooldown {
+            global: Some(30),
+            user: None,
+        };
+        let now = Utc::now();
+        assert!(cooldowns.try_use("!dave", "alice", &cooldown, now));
+        assert!(!cooldowns.try_use("!dave", "bob", &cooldown, now + Duration::seconds(10)));
+        // other commands aren't affected
+        assert!(cooldowns.try_use("!zoya", "bob", &cooldown, now));
+        assert!(cooldowns.try_use("!dave", "bob", &cooldown, now + Duration::seconds(30)));
+    }
+
+    #[test]
+    fn user_cooldown() {
+        let mut cooldowns = Cooldowns::default();
+        let cooldown = Cooldown {
+            global: None,
+            user: Some(60),
+        };
+        let now = Utc::now();
+        assert!(cooldowns.try_use("!dave", "alice", &cooldown, now));
+        assert!(cooldowns.try_use("!dave", "bob", &cooldown, now));
+        assert!(!cooldowns.try_use("!dave", "alice", &cooldown, now + Duration::seconds(59)));
+        assert!(cooldowns.try_use("!dave", "alice", &cooldown, now + Duration::seconds(60)));
+    }
+
+    #[test]
+    fn refused_uses_dont_restart_the_cooldown() {
+        let mut cooldowns = Cooldowns::default();
+        let cooldown = Cooldown {
+            global: Some(30),
+            user: None,
+        };
+        let now = Utc::now();
+        assert!(cooldowns.try_use("!dave", "alice", &cooldown, now));
+        assert!(!cooldowns.try_use("!dave", "alice", &cooldown, now + Duration::seconds(20)));
+        assert!(cooldowns.try_use("!dave", "alice", &cooldown, now + Duration::seconds(30)));
+    }
+}
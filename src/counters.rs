@@ -0,0 +1,91 @@
+//! Counters moderators add with `!addcounter`, e.g. `!deaths`.
+
+use crate::commands::command_name;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use twitch_queue_bot::json_store::JsonStore;
+
+/// Counter names (without the `!`) mapped to their values.
+#[derive(Default)]
+pub struct Counters {
+    counters: BTreeMap<String, u64>,
+    // saved to after every change, if set
+    store: Option<JsonStore<BTreeMap<String, u64>>>,
+}
+
+impl Counters {
+    /// Restores the counters saved at `path`, if there are any, and saves
+    /// every change back to it.
+    pub fn persistent(path: PathBuf) -> io::Result<Counters> {
+        let store = JsonStore::new(path);
+        Ok(Counters {
+            counters: store.load_or_default()?,
+            store: Some(store),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.counters.get(&normalize(name)).copied()
+    }
+
+    /// Every counter and its value, sorted by name.
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.counters
+            .iter()
+            .map(|(name, &value)| (name.as_str(), value))
+    }
+
+    /// Adds a counter starting at 0, returns whether there wasn't one already.
+    pub fn add(&mut self, name: &str) -> bool {
+        let name = normalize(name);
+        if self.counters.contains_key(&name) {
+            return false;
+        }
+        self.counters.insert(name, 0);
+        self.save();
+        true
+    }
+
+    /// Counts one more, returns the new value or `None` if there's no such counter.
+    pub fn increment(&mut self, name: &str) -> Option<u64> {
+        let value = self.counters.get_mut(&normalize(name))?;
+        *value += 1;
+        let value = *value;
+        self.save();
+        Some(value)
+    }
+
+    fn save(&self) {
+        if let Some(store) = &self.store {
+            store.save(&self.counters);
+        }
+    }
+}
+
+/// `!Deaths` and `deaths` both name the `deaths` counter, which is kept
+/// without the `!`.
+fn normalize(name: &str) -> String {
+    command_name(name).split_off(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting() {
+        let mut counters = Counters::default();
+        assert_eq!(counters.increment("deaths"), None);
+        assert!(counters.add("Deaths"));
+        assert!(!counters.add("!deaths"));
+        assert_eq!(counters.get("deaths"), Some(0));
+
+        assert_eq!(counters.increment("!DEATHS"), Some(1));
+        assert_eq!(counters.increment("deaths"), Some(2));
+        // adding it again doesn't reset it
+        assert!(!counters.add("deaths"));
+        assert_eq!(counters.get("deaths"), Some(2));
+    }
+}
@@ -0,0 +1,94 @@
+//! Text commands moderators add from chat with `!addcmd`.
+
+use crate::commands::command_name;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use twitch_queue_bot::json_store::JsonStore;
+
+/// Command names (including the `!`) mapped to the text they reply with.
+#[derive(Default)]
+pub struct CustomCommands {
+    commands: BTreeMap<String, String>,
+    // saved to after every change, if set
+    store: Option<JsonStore<BTreeMap<String, String>>>,
+}
+
+impl CustomCommands {
+    /// Restores the commands saved at `path`, if there are any, and saves
+    /// every change back to it.
+    pub fn persistent(path: PathBuf) -> io::Result<CustomCommands> {
+        let store = JsonStore::new(path);
+        Ok(CustomCommands {
+            commands: store.load_or_default()?,
+            store: Some(store),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.commands.get(&command_name(name)).map(String::as_str)
+    }
+
+    /// The names of every command, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.commands.keys().map(String::as_str)
+    }
+
+    /// Adds or replaces a command, returns whether it replaced one.
+    pub fn add(&mut self, name: &str, text: String) -> bool {
+        let replaced = self.commands.insert(command_name(name), text).is_some();
+        self.save();
+        replaced
+    }
+
+    /// Changes the text of an existing command, returns whether there was one.
+    pub fn edit(&mut self, name: &str, text: String) -> bool {
+        let command = match self.commands.get_mut(&command_name(name)) {
+            Some(command) => command,
+            None => return false,
+        };
+        *command = text;
+        self.save();
+        true
+    }
+
+    /// Returns whether there was such a command.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let removed = self.commands.remove(&command_name(name)).is_some();
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    fn save(&self) {
+        if let Some(store) = &self.store {
+            store.save(&self.commands);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adding_and_removing() {
+        let mut commands = CustomCommands::default();
+        assert!(!commands.add("!repo", "https://github.com/silen-z/ferris-bot".to_owned()));
+        assert_eq!(
+            commands.get("!REPO"),
+            Some("https://github.com/silen-z/ferris-bot")
+        );
+        assert!(commands.add("repo", "moved".to_owned()));
+        assert_eq!(commands.get("!repo"), Some("moved"));
+        assert!(commands.edit("!Repo", "moved again".to_owned()));
+        assert_eq!(commands.get("!repo"), Some("moved again"));
+        assert!(!commands.edit("!nope", "text".to_owned()));
+        assert_eq!(commands.get("!nope"), None);
+
+        assert!(commands.remove("!repo"));
+        assert!(!commands.remove("!repo"));
+        assert_eq!(commands.get("!repo"), None);
+    }
+}
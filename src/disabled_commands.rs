@@ -0,0 +1,88 @@
+//! Commands moderators turned off with `!disable`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+use twitch_queue_bot::json_store::JsonStore;
+
+/// Names (including the `!`) of the commands that are turned off, per channel.
+#[derive(Default)]
+pub struct DisabledCommands {
+    // keyed by channel login
+    commands: BTreeMap<String, BTreeSet<String>>,
+    // saved to after every change, if set
+    store: Option<JsonStore<BTreeMap<String, BTreeSet<String>>>>,
+}
+
+impl DisabledCommands {
+    /// Restores the commands saved at `path`, if there are any, and saves
+    /// every change back to it.
+    pub fn persistent(path: PathBuf) -> io::Result<DisabledCommands> {
+        let store = JsonStore::new(path);
+        Ok(DisabledCommands {
+            commands: store.load_or_default()?,
+            store: Some(store),
+        })
+    }
+
+    pub fn contains(&self, channel: &str, command: &str) -> bool {
+        (self.commands.get(channel)).is_some_and(|commands| commands.contains(command))
+    }
+
+    /// Returns whether the command was enabled in `channel` before.
+    pub fn disable(&mut self, channel: &str, command: &str) -> bool {
+        let disabled = self
+            .commands
+            .entry(channel.to_owned())
+            .or_default()
+            .insert(command.to_owned());
+        if disabled {
+            self.save();
+        }
+        disabled
+    }
+
+    /// Returns whether the command was disabled in `channel` before.
+    pub fn enable(&mut self, channel: &str, command: &str) -> bool {
+        let commands = match self.commands.get_mut(channel) {
+            Some(commands) => commands,
+            None => return false,
+        };
+        let enabled = commands.remove(command);
+        if commands.is_empty() {
+            self.commands.remove(channel);
+        }
+        if enabled {
+            self.save();
+        }
+        enabled
+    }
+
+    fn save(&self) {
+        if let Some(store) = &self.store {
+            store.save(&self.commands);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabling() {
+        let mut disabled = DisabledCommands::default();
+        assert!(disabled.disable("channel", "!dave"));
+        assert!(!disabled.disable("channel", "!dave"));
+        assert!(disabled.disable("channel", "!zoya"));
+        assert!(disabled.enable("channel", "!zoya"));
+        assert!(!disabled.enable("channel", "!zoya"));
+        assert!(disabled.contains("channel", "!dave"));
+        assert!(!disabled.contains("channel", "!zoya"));
+
+        // every channel has its own
+        assert!(!disabled.contains("other", "!dave"));
+        assert!(!disabled.enable("other", "!dave"));
+        assert!(disabled.disable("other", "!dave"));
+    }
+}
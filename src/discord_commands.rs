@@ -8,6 +8,8 @@
 //! git = "https://github.com/serenity-rs/serenity.git"
 //! features = ["framework", "standard_framework"]
 //! ```
+use crate::config::AutoModConfig;
+use crate::helix::Helix;
 use log::{debug, error, info};
 use serenity::prelude::*;
 use serenity::{
@@ -24,7 +26,10 @@ use serenity::{
     model::{
         channel::{Channel, Message},
         gateway::Ready,
-        id::{ChannelId, UserId},
+        id::{ChannelId, MessageId, RoleId, UserId},
+        interactions::{
+            Interaction, InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+        },
         permissions::Permissions,
     },
     utils::{content_safe, ContentSafeOptions},
@@ -50,13 +55,122 @@ impl TypeMapKey for CommandCounter {
     type Value = HashMap<String, u64>;
 }
 
-struct Handler;
+struct Handler {
+    helix: Arc<Helix>,
+    // where held messages are posted and who may resolve them, if enabled
+    automod: Option<AutoModConfig>,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, _: Context, ready: Ready) {
         info!("{} is connected to Discord!", ready.user.name);
     }
+
+    /// Approves or denies a message AutoMod held when one of its buttons is
+    /// clicked, see `automod_button_id`, if whoever clicked it may.
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let component = match interaction {
+            Interaction::MessageComponent(component) => component,
+            _ => return,
+        };
+        let (message_id, allow) = match parse_automod_button(&component.data.custom_id) {
+            Some(button) => button,
+            None => return,
+        };
+        let allowed = self.automod.as_ref().is_some_and(|automod| {
+            component.channel_id == ChannelId(automod.discord_channel_id)
+                && component.member.as_ref().is_some_and(|member| {
+                    may_resolve(&member.roles, member.permissions, &automod.discord_role_ids)
+                })
+        });
+        if !allowed {
+            info!(
+                "{} isn't allowed to resolve held message {}",
+                component.user.name, message_id
+            );
+            let refused = component
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|data| {
+                            data.content("Only moderators can approve or deny held messages")
+                                .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                        })
+                })
+                .await;
+            if let Err(e) = refused {
+                error!("Failed to respond to a button: {}", e);
+            }
+            return;
+        }
+        let responded = match self.helix.resolve_held_message(message_id, allow).await {
+            Ok(()) => {
+                let outcome = if allow { "Approved" } else { "Denied" };
+                let content = format!(
+                    "{}\n{} by {}",
+                    component.message.content, outcome, component.user.name
+                );
+                // without the buttons, it can't be resolved twice
+                component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|data| {
+                                data.content(content).components(|components| components)
+                            })
+                    })
+                    .await
+            }
+            Err(e) => {
+                error!("Failed to resolve held message {}: {}", message_id, e);
+                component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|data| {
+                                data.content("Couldn't reach Twitch, try again later")
+                                    .flags(
+                                        InteractionApplicationCommandCallbackDataFlags::EPHEMERAL,
+                                    )
+                            })
+                    })
+                    .await
+            }
+        };
+        if let Err(e) = responded {
+            error!("Failed to respond to a button: {}", e);
+        }
+    }
+}
+
+/// The custom id of the button that approves the message with id
+/// `message_id` AutoMod held if `allow`, or denies it.
+pub fn automod_button_id(message_id: &str, allow: bool) -> String {
+    let action = if allow { "allow" } else { "deny" };
+    format!("automod:{}:{}", action, message_id)
+}
+
+/// Whether a member with `roles` and `permissions` may approve or deny held
+/// messages, by having one of the `allowed` roles or, without any, by being
+/// able to manage messages.
+fn may_resolve(roles: &[RoleId], permissions: Option<Permissions>, allowed: &[u64]) -> bool {
+    if allowed.is_empty() {
+        return permissions.is_some_and(|permissions| permissions.manage_messages());
+    }
+    roles.iter().any(|role| allowed.contains(&role.0))
+}
+
+/// The held message and whether to approve it, of a button made with
+/// `automod_button_id`.
+fn parse_automod_button(custom_id: &str) -> Option<(&str, bool)> {
+    let rest = custom_id.strip_prefix("automod:")?;
+    let (action, message_id) = rest.split_once(':')?;
+    match action {
+        "allow" => Some((message_id, true)),
+        "deny" => Some((message_id, false)),
+        _ => None,
+    }
 }
 
 #[group]
@@ -233,7 +347,9 @@ fn _dispatch_error_no_macro<'fut>(
     .boxed()
 }
 
-pub async fn init_discord_bot(http: &Http, token: &str) {
+pub async fn init_discord_bot(token: &str, helix: Arc<Helix>, automod: Option<AutoModConfig>) {
+    let http = Http::new_with_token(token);
+
     // We will fetch your bot's owners and id
     let (owners, bot_id) = match http.get_current_application_info().await {
         Ok(info) => {
@@ -316,8 +432,8 @@ pub async fn init_discord_bot(http: &Http, token: &str) {
         .group(&MATH_GROUP)
         .group(&OWNER_GROUP);
 
-    let mut client = Client::builder(&token)
-        .event_handler(Handler)
+    let mut client = Client::builder(token)
+        .event_handler(Handler { helix, automod })
         .framework(framework)
         .await
         .expect("Err creating client");
@@ -333,6 +449,55 @@ pub async fn init_discord_bot(http: &Http, token: &str) {
     }
 }
 
+/// A pinned message in a Discord channel that gets edited to always show the
+/// current state of the queues.
+pub struct QueueMirror {
+    channel: ChannelId,
+    message: Option<MessageId>,
+}
+
+impl QueueMirror {
+    pub fn new(channel: ChannelId) -> QueueMirror {
+        QueueMirror {
+            channel,
+            message: None,
+        }
+    }
+
+    pub async fn update(&mut self, http: &Http, content: &str) -> serenity::Result<()> {
+        if self.message.is_none() {
+            self.message = self.find_pinned(http).await?;
+        }
+
+        if let Some(message) = self.message {
+            match self
+                .channel
+                .edit_message(http, message, |m| m.content(content))
+                .await
+            {
+                Ok(_) => return Ok(()),
+                // most likely deleted or unpinned by someone, post a new one
+                Err(why) => debug!("Failed to edit queue mirror message: {:?}", why),
+            }
+        }
+
+        let message = self.channel.say(http, content).await?;
+        self.channel.pin(http, message.id).await?;
+        self.message = Some(message.id);
+        Ok(())
+    }
+
+    /// Picks up the message pinned by a previous run of the bot.
+    async fn find_pinned(&self, http: &Http) -> serenity::Result<Option<MessageId>> {
+        let bot_id = http.get_current_user().await?.id;
+        let pins = self.channel.pins(http).await?;
+        Ok(pins
+            .into_iter()
+            .find(|message| message.author.id == bot_id)
+            .map(|message| message.id))
+    }
+}
+
 // Commands can be created via the attribute `#[command]` macro.
 #[command]
 // Options are passed via subsequent attributes.
@@ -433,7 +598,7 @@ async fn about_role(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     if let Some(guild) = msg.guild(&ctx.cache).await {
         // `role_by_name()` allows us to attempt attaining a reference to a role
         // via its name.
-        if let Some(role) = guild.role_by_name(&potential_role_name) {
+        if let Some(role) = guild.role_by_name(potential_role_name) {
             if let Err(why) = msg
                 .channel_id
                 .say(&ctx.http, &format!("Role-ID: {}", role.id))
@@ -597,7 +762,7 @@ async fn am_i_admin(ctx: &Context, msg: &Message, _args: Args) -> CommandResult
             if role
                 .to_role_cached(&ctx.cache)
                 .await
-                .map_or(false, |r| r.has_permission(Permissions::ADMINISTRATOR))
+                .is_some_and(|r| r.has_permission(Permissions::ADMINISTRATOR))
             {
                 msg.channel_id.say(&ctx.http, "Yes, you are.").await?;
 
@@ -612,11 +777,13 @@ async fn am_i_admin(ctx: &Context, msg: &Message, _args: Args) -> CommandResult
 }
 
 #[command]
+// `GuildChannel::slow_mode_rate` only gets renamed with serenity 0.11.
+#[allow(deprecated)]
 async fn slow_mode(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let say_content = if let Ok(slow_mode_rate_seconds) = args.single::<u64>() {
         if let Err(why) = msg
             .channel_id
-            .edit(&ctx.http, |c| c.slow_mode_rate(slow_mode_rate_seconds))
+            .edit(&ctx.http, |c| c.rate_limit_per_user(slow_mode_rate_seconds))
             .await
         {
             error!("Error setting channel's slow mode rate: {:?}", why);
@@ -665,3 +832,31 @@ async fn sub(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn automod_buttons() {
+        let allow = automod_button_id("bad-message-id", true);
+        assert_eq!(parse_automod_button(&allow), Some(("bad-message-id", true)));
+        let deny = automod_button_id("bad-message-id", false);
+        assert_eq!(parse_automod_button(&deny), Some(("bad-message-id", false)));
+        assert_eq!(parse_automod_button("automod:maybe:1"), None);
+        assert_eq!(parse_automod_button("queue"), None);
+    }
+
+    #[test]
+    fn resolving_held_messages() {
+        let roles = [RoleId(1), RoleId(2)];
+        assert!(may_resolve(&roles, None, &[2, 3]));
+        assert!(!may_resolve(&roles, None, &[3]));
+        assert!(!may_resolve(&[], Some(Permissions::MANAGE_MESSAGES), &[3]));
+
+        // without roles set up, moderators may
+        assert!(may_resolve(&[], Some(Permissions::MANAGE_MESSAGES), &[]));
+        assert!(!may_resolve(&roles, Some(Permissions::SEND_MESSAGES), &[]));
+        assert!(!may_resolve(&roles, None, &[]));
+    }
+}
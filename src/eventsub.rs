@@ -0,0 +1,477 @@
+//! Follows, channel point redemptions, hype trains, the stream going live and
+//! messages held by AutoMod in the main channel, received through Twitch's
+//! EventSub websocket. Subscriptions and raids are seen in chat instead, in
+//! every joined channel.
+//!
+//! Follows and held messages need the bot to be a moderator in the channel,
+//! the latter with the `moderator:manage:automod` scope. Channel point
+//! redemptions and hype trains need its token to have the
+//! `channel:read:redemptions` and `channel:read:hype_train` scopes, which only
+//! the broadcaster can grant.
+
+use crate::helix::{Helix, HelixError};
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const WEBSOCKET_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+
+/// How long to wait before connecting again after losing the connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// How long to wait for the welcome message, after that Twitch says how long
+/// to wait for the next message.
+const WELCOME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened in the channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Follow {
+        user: String,
+    },
+    /// A subscription or resubscription, for gifts `user` is who got it.
+    Subscribe {
+        user: String,
+        gift: bool,
+        months: u64,
+    },
+    /// `count` subscriptions gifted to the community by `user` at once.
+    CommunityGift {
+        user: String,
+        count: u64,
+    },
+    Raid {
+        user: String,
+        viewers: u64,
+    },
+    Cheer {
+        user: String,
+        bits: u64,
+    },
+    /// A channel point reward was redeemed, `input` is what the user typed if
+    /// the reward asks for it.
+    Redemption {
+        user: String,
+        login: String,
+        reward_id: String,
+        input: String,
+    },
+    /// A hype train started, reached a new level or ended at `level`.
+    HypeTrain {
+        stage: HypeTrainStage,
+        level: u64,
+    },
+    /// The stream went live, `stream_id` is different for every stream.
+    Online {
+        stream_id: String,
+        user: String,
+        login: String,
+        user_id: String,
+    },
+    /// AutoMod held back a chat message until a moderator approves or denies
+    /// it, `category` is why, e.g. `swearing`.
+    AutoModHold {
+        message_id: String,
+        user: String,
+        text: String,
+        category: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HypeTrainStage {
+    Begin,
+    /// Sent for every contribution, not only when the level changes.
+    Progress,
+    End,
+}
+
+/// The kinds of events there are, to choose which ones to subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Follow,
+    Redemption,
+    HypeTrain,
+    Online,
+    AutoMod,
+}
+
+impl Kind {
+    /// The EventSub subscription types and their versions.
+    fn subscriptions(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Kind::Follow => &[("channel.follow", "2")],
+            Kind::Redemption => &[("channel.channel_points_custom_reward_redemption.add", "1")],
+            Kind::HypeTrain => &[
+                ("channel.hype_train.begin", "2"),
+                ("channel.hype_train.progress", "2"),
+                ("channel.hype_train.end", "2"),
+            ],
+            Kind::Online => &[("stream.online", "1")],
+            Kind::AutoMod => &[("automod.message.hold", "1")],
+        }
+    }
+
+    /// Which channel the events are about, and who's asking for them.
+    fn condition(self, broadcaster_id: &str, bot_id: &str) -> Value {
+        match self {
+            Kind::Follow | Kind::AutoMod => json!({
+                "broadcaster_user_id": broadcaster_id,
+                "moderator_user_id": bot_id,
+            }),
+            Kind::Redemption | Kind::HypeTrain | Kind::Online => {
+                json!({ "broadcaster_user_id": broadcaster_id })
+            }
+        }
+    }
+}
+
+/// A message from the websocket that matters to the bot.
+#[derive(Debug, PartialEq)]
+enum Received {
+    /// The connection is ready for subscriptions in this session, and a
+    /// message comes at least every `keepalive` while it's alive.
+    Welcome {
+        session_id: String,
+        keepalive: Duration,
+    },
+    /// Twitch wants the bot to connect to this URL, subscriptions carry over.
+    Reconnect(String),
+    Event(Event),
+    /// Keepalives and the like.
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    metadata: Metadata,
+    #[serde(default)]
+    payload: Value,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    message_type: String,
+    subscription_type: Option<String>,
+}
+
+fn parse(text: &str) -> Result<Received, HelixError> {
+    let message: Message = serde_json::from_str(text)?;
+    let payload = &message.payload;
+    let string = |value: &Value| -> Result<String, HelixError> {
+        value
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| format!("Unexpected EventSub message: {}", text).into())
+    };
+    let received = match message.metadata.message_type.as_str() {
+        "session_welcome" => Received::Welcome {
+            session_id: string(&payload["session"]["id"])?,
+            keepalive: Duration::from_secs(
+                payload["session"]["keepalive_timeout_seconds"]
+                    .as_u64()
+                    .unwrap_or(WELCOME_TIMEOUT.as_secs()),
+            ),
+        },
+        "session_reconnect" => Received::Reconnect(string(&payload["session"]["reconnect_url"])?),
+        "revocation" => {
+            warn!("EventSub subscription revoked: {}", payload["subscription"]);
+            Received::Other
+        }
+        "notification" => {
+            let event = &payload["event"];
+            match message.metadata.subscription_type.as_deref() {
+                Some("channel.follow") => Received::Event(Event::Follow {
+                    user: string(&event["user_name"])?,
+                }),
+                Some("channel.channel_points_custom_reward_redemption.add") => {
+                    Received::Event(Event::Redemption {
+                        user: string(&event["user_name"])?,
+                        login: string(&event["user_login"])?,
+                        reward_id: string(&event["reward"]["id"])?,
+                        input: event["user_input"].as_str().unwrap_or("").to_owned(),
+                    })
+                }
+                Some(hype_train) if hype_train.starts_with("channel.hype_train.") => {
+                    let stage = match hype_train {
+                        "channel.hype_train.begin" => HypeTrainStage::Begin,
+                        "channel.hype_train.progress" => HypeTrainStage::Progress,
+                        _ => HypeTrainStage::End,
+                    };
+                    let level = event["level"].as_u64();
+                    Received::Event(Event::HypeTrain {
+                        stage,
+                        level: level
+                            .ok_or_else(|| format!("Unexpected EventSub message: {}", text))?,
+                    })
+                }
+                Some("stream.online") => Received::Event(Event::Online {
+                    stream_id: string(&event["id"])?,
+                    user: string(&event["broadcaster_user_name"])?,
+                    login: string(&event["broadcaster_user_login"])?,
+                    user_id: string(&event["broadcaster_user_id"])?,
+                }),
+                Some("automod.message.hold") => Received::Event(Event::AutoModHold {
+                    message_id: string(&event["message_id"])?,
+                    user: string(&event["user_name"])?,
+                    text: string(&event["message"]["text"])?,
+                    category: string(&event["category"])?,
+                }),
+                _ => Received::Other,
+            }
+        }
+        _ => Received::Other,
+    };
+    Ok(received)
+}
+
+/// Sends the `kinds` of events in `channel_login` to `events` for as long as
+/// the bot runs, connecting again whenever the connection is lost.
+pub async fn run(
+    helix: Arc<Helix>,
+    channel_login: String,
+    kinds: Vec<Kind>,
+    events: mpsc::UnboundedSender<Event>,
+) {
+    let mut url = WEBSOCKET_URL.to_owned();
+    let mut subscribed = false;
+    loop {
+        match session(&url, subscribed, &helix, &channel_login, &kinds, &events).await {
+            Ok(Some(reconnect_url)) => {
+                url = reconnect_url;
+                subscribed = true;
+                continue;
+            }
+            Ok(None) => warn!("EventSub connection closed"),
+            Err(e) => error!("EventSub connection failed: {}", e),
+        }
+        url = WEBSOCKET_URL.to_owned();
+        subscribed = false;
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Handles one connection until it's closed or goes quiet for longer than
+/// Twitch said it would, returns the URL to connect to next if Twitch asked to.
+async fn session(
+    url: &str,
+    subscribed: bool,
+    helix: &Helix,
+    channel_login: &str,
+    kinds: &[Kind],
+    events: &mpsc::UnboundedSender<Event>,
+) -> Result<Option<String>, HelixError> {
+    use async_tungstenite::tungstenite::Message as WsMessage;
+
+    let (mut socket, _) = async_tungstenite::tokio::connect_async(url).await?;
+    let mut timeout = WELCOME_TIMEOUT;
+    loop {
+        let message = match tokio::time::timeout(timeout, socket.next()).await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(_) => return Err(format!("No EventSub message for {:?}", timeout).into()),
+        };
+        let text = match message? {
+            WsMessage::Text(text) => text,
+            WsMessage::Close(_) => break,
+            _ => continue,
+        };
+        match parse(&text)? {
+            Received::Welcome {
+                session_id,
+                keepalive,
+            } => {
+                // with some leeway for the message to arrive
+                timeout = keepalive + Duration::from_secs(5);
+                if !subscribed {
+                    subscribe(helix, &session_id, channel_login, kinds).await?;
+                    info!("Listening to EventSub in {}", channel_login);
+                }
+            }
+            Received::Reconnect(url) => return Ok(Some(url)),
+            Received::Event(event) => {
+                let _ = events.send(event);
+            }
+            Received::Other => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Subscribes to the `kinds` of events in `channel_login` for `session_id`.
+async fn subscribe(
+    helix: &Helix,
+    session_id: &str,
+    channel_login: &str,
+    kinds: &[Kind],
+) -> Result<(), HelixError> {
+    let bot_login = helix.login().await?;
+    let users = helix.users(&[channel_login, &bot_login]).await?;
+    let id = |login: &str| -> Result<&str, HelixError> {
+        users
+            .iter()
+            .find(|user| user.login == login)
+            .map(|user| user.id.as_str())
+            .ok_or_else(|| format!("No Twitch user {}", login).into())
+    };
+    let broadcaster_id = id(channel_login)?;
+    let bot_id = id(&bot_login)?;
+
+    for &kind in kinds {
+        for &(subscription_type, version) in kind.subscriptions() {
+            let subscription = json!({
+                "type": subscription_type,
+                "version": version,
+                "condition": kind.condition(broadcaster_id, bot_id),
+                "transport": { "method": "websocket", "session_id": session_id },
+            });
+            // the others still work without it, e.g. if a scope is missing
+            if let Err(e) = helix.post("eventsub/subscriptions", &subscription).await {
+                error!("Failed to subscribe to {}: {}", subscription_type, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(subscription_type: &str, event: Value) -> String {
+        json!({
+            "metadata": {
+                "message_id": "befa7b53-d79d-478f-86b9-120f112b044e",
+                "message_type": "notification",
+                "message_timestamp": "2022-11-16T10:11:12.464757833Z",
+                "subscription_type": subscription_type,
+                "subscription_version": "1",
+            },
+            "payload": { "event": event },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn parsing_messages() {
+        let welcome = r#"{
+            "metadata": { "message_type": "session_welcome" },
+            "payload": {
+                "session": { "id": "AQoQexAWVYKSTIu4ec_2VAxyuhAB", "keepalive_timeout_seconds": 30 }
+            }
+        }"#;
+        assert_eq!(
+            parse(welcome).unwrap(),
+            Received::Welcome {
+                session_id: "AQoQexAWVYKSTIu4ec_2VAxyuhAB".to_owned(),
+                keepalive: Duration::from_secs(30)
+            }
+        );
+        let keepalive = r#"{ "metadata": { "message_type": "session_keepalive" }, "payload": {} }"#;
+        assert_eq!(parse(keepalive).unwrap(), Received::Other);
+
+        let follow = notification("channel.follow", json!({ "user_name": "Cool_User" }));
+        assert_eq!(
+            parse(&follow).unwrap(),
+            Received::Event(Event::Follow {
+                user: "Cool_User".to_owned()
+            })
+        );
+
+        let redemption = notification(
+            "channel.channel_points_custom_reward_redemption.add",
+            json!({
+                "user_login": "cool_user",
+                "user_name": "Cool_User",
+                "user_input": "pogchamp",
+                "status": "unfulfilled",
+                "reward": {
+                    "id": "92af127c-7326-4483-a52b-b0da0be61c01",
+                    "title": "Hydrate",
+                    "cost": 100,
+                    "prompt": "Make the streamer drink"
+                }
+            }),
+        );
+        assert_eq!(
+            parse(&redemption).unwrap(),
+            Received::Event(Event::Redemption {
+                user: "Cool_User".to_owned(),
+                login: "cool_user".to_owned(),
+                reward_id: "92af127c-7326-4483-a52b-b0da0be61c01".to_owned(),
+                input: "pogchamp".to_owned()
+            })
+        );
+
+        let progress = notification(
+            "channel.hype_train.progress",
+            json!({
+                "id": "1b0AsbInCHZW2SQFQkCzqN07Ib2",
+                "broadcaster_user_id": "1337",
+                "level": 2,
+                "total": 700,
+                "progress": 200,
+                "goal": 1000
+            }),
+        );
+        assert_eq!(
+            parse(&progress).unwrap(),
+            Received::Event(Event::HypeTrain {
+                stage: HypeTrainStage::Progress,
+                level: 2
+            })
+        );
+
+        let online = notification(
+            "stream.online",
+            json!({
+                "id": "9001",
+                "broadcaster_user_id": "1337",
+                "broadcaster_user_login": "cool_user",
+                "broadcaster_user_name": "Cool_User",
+                "type": "live",
+                "started_at": "2020-10-11T10:11:12.123Z"
+            }),
+        );
+        assert_eq!(
+            parse(&online).unwrap(),
+            Received::Event(Event::Online {
+                stream_id: "9001".to_owned(),
+                user: "Cool_User".to_owned(),
+                login: "cool_user".to_owned(),
+                user_id: "1337".to_owned()
+            })
+        );
+
+        let held = notification(
+            "automod.message.hold",
+            json!({
+                "broadcaster_user_id": "1337",
+                "user_id": "9001",
+                "user_login": "cool_user",
+                "user_name": "Cool_User",
+                "message_id": "bad-message-id",
+                "message": { "text": "you're a stinky", "fragments": [] },
+                "category": "insults",
+                "level": 4,
+                "held_at": "2022-12-02T15:00:00.00Z"
+            }),
+        );
+        assert_eq!(
+            parse(&held).unwrap(),
+            Received::Event(Event::AutoModHold {
+                message_id: "bad-message-id".to_owned(),
+                user: "Cool_User".to_owned(),
+                text: "you're a stinky".to_owned(),
+                category: "insults".to_owned()
+            })
+        );
+
+        assert!(parse("not json").is_err());
+        assert!(parse(&notification("channel.follow", json!({}))).is_err());
+    }
+}
@@ -0,0 +1,658 @@
+//! A client for Twitch's Helix API, authenticated with the same token the bot
+//! uses for chat.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::Arc;
+use twitch_irc::login::{CredentialsPair, LoginCredentials};
+
+const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+pub type HelixError = Box<dyn Error + Send + Sync>;
+
+/// Credentials that can be handed to the chat client and Helix alike, so a
+/// token is only ever refreshed in one place.
+#[derive(Debug)]
+pub struct SharedCredentials<C>(Arc<C>);
+
+impl<C> SharedCredentials<C> {
+    pub fn new(credentials: C) -> SharedCredentials<C> {
+        SharedCredentials(Arc::new(credentials))
+    }
+}
+
+impl<C> Clone for SharedCredentials<C> {
+    fn clone(&self) -> Self {
+        SharedCredentials(self.0.clone())
+    }
+}
+
+#[async_trait]
+impl<C: LoginCredentials> LoginCredentials for SharedCredentials<C> {
+    type Error = C::Error;
+
+    async fn get_credentials(&self) -> Result<CredentialsPair, C::Error> {
+        self.0.get_credentials().await
+    }
+}
+
+/// `LoginCredentials` without the error type, so `Helix` doesn't need to know it.
+#[async_trait]
+trait Credentials: Send + Sync {
+    async fn get(&self) -> Result<CredentialsPair, HelixError>;
+}
+
+#[async_trait]
+impl<C: LoginCredentials> Credentials for C {
+    async fn get(&self) -> Result<CredentialsPair, HelixError> {
+        self.get_credentials()
+            .await
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+/// A Twitch user.
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+}
+
+/// The settings of a channel.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelInfo {
+    pub title: String,
+    /// The category, e.g. `Science & Technology`.
+    pub game_name: String,
+}
+
+/// A stream that's live.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Stream {
+    pub started_at: DateTime<Utc>,
+}
+
+/// The chat modes of a channel. The durations are only set while their mode
+/// is on.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ChatSettings {
+    pub slow_mode: bool,
+    /// Seconds chatters wait between messages.
+    pub slow_mode_wait_time: Option<u64>,
+    pub follower_mode: bool,
+    /// Minutes users have to follow before chatting.
+    pub follower_mode_duration: Option<u64>,
+    pub emote_mode: bool,
+}
+
+/// A user with a role in a channel, like its moderators.
+#[derive(Deserialize)]
+struct Member {
+    user_login: String,
+}
+
+#[derive(Deserialize)]
+struct Follow {
+    followed_at: DateTime<Utc>,
+}
+
+/// A category streams can be in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Game {
+    pub id: String,
+    pub name: String,
+}
+
+/// A clip that's being created, it can take a few seconds until it can be
+/// watched.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Clip {
+    pub id: String,
+}
+
+impl Clip {
+    pub fn url(&self) -> String {
+        format!("https://clips.twitch.tv/{}", self.id)
+    }
+}
+
+/// A marker in a stream, for finding a moment again in the video.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Marker {
+    /// How far into the stream it is.
+    pub position_seconds: i64,
+}
+
+/// A poll run by Twitch.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Poll {
+    pub id: String,
+    pub title: String,
+    pub choices: Vec<PollChoice>,
+    /// `ACTIVE` until it ends.
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PollChoice {
+    pub title: String,
+    #[serde(default)]
+    pub votes: u64,
+}
+
+/// A prediction run by Twitch, viewers bet channel points on its outcomes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Prediction {
+    pub id: String,
+    pub title: String,
+    pub winning_outcome_id: Option<String>,
+    pub outcomes: Vec<Outcome>,
+    /// `ACTIVE` while viewers can predict, `LOCKED` until it's resolved, then
+    /// `RESOLVED` or `CANCELED`.
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Outcome {
+    pub id: String,
+    pub title: String,
+    /// How many users predicted it.
+    #[serde(default)]
+    pub users: u64,
+    /// How many channel points were bet on it.
+    #[serde(default)]
+    pub channel_points: u64,
+}
+
+/// Every Helix response wraps what was asked for in `data`.
+#[derive(Deserialize)]
+struct Data<T> {
+    data: Vec<T>,
+}
+
+pub struct Helix {
+    http: reqwest::Client,
+    client_id: String,
+    credentials: Box<dyn Credentials>,
+}
+
+impl Helix {
+    pub fn new<C: LoginCredentials>(client_id: String, credentials: C) -> Helix {
+        Helix {
+            http: reqwest::Client::new(),
+            client_id,
+            credentials: Box::new(credentials),
+        }
+    }
+
+    /// The login of the bot's own account.
+    pub async fn login(&self) -> Result<String, HelixError> {
+        Ok(self.credentials.get().await?.login)
+    }
+
+    /// The user id of the bot's own account.
+    async fn bot_id(&self) -> Result<String, HelixError> {
+        let login = self.login().await?;
+        let bot = self.users(&[&login]).await?.into_iter().next();
+        Ok(bot.ok_or("The bot's own user doesn't exist")?.id)
+    }
+
+    /// The users with these logins, leaving out the ones that don't exist.
+    pub async fn users(&self, logins: &[&str]) -> Result<Vec<User>, HelixError> {
+        let query: Vec<(&str, &str)> = logins.iter().map(|&login| ("login", login)).collect();
+        self.get("users", &query).await
+    }
+
+    /// The settings of the channel of the user with id `broadcaster_id`.
+    pub async fn channel(&self, broadcaster_id: &str) -> Result<Option<ChannelInfo>, HelixError> {
+        let channels = self
+            .get("channels", &[("broadcaster_id", broadcaster_id)])
+            .await?;
+        Ok(channels.into_iter().next())
+    }
+
+    /// The stream of the user with id `user_id`, `None` if they're offline.
+    pub async fn stream(&self, user_id: &str) -> Result<Option<Stream>, HelixError> {
+        let streams = self.get("streams", &[("user_id", user_id)]).await?;
+        Ok(streams.into_iter().next())
+    }
+
+    /// The logins of the moderators of the channel of `broadcaster_id`, at
+    /// most 100. Needs the `moderation:read` scope of the broadcaster.
+    pub async fn moderators(&self, broadcaster_id: &str) -> Result<Vec<String>, HelixError> {
+        let query = [("broadcaster_id", broadcaster_id), ("first", "100")];
+        let moderators: Vec<Member> = self.get("moderation/moderators", &query).await?;
+        Ok(moderators.into_iter().map(|m| m.user_login).collect())
+    }
+
+    /// The logins of the VIPs of the channel of `broadcaster_id`, at most 100.
+    /// Needs the `channel:read:vips` scope of the broadcaster.
+    pub async fn vips(&self, broadcaster_id: &str) -> Result<Vec<String>, HelixError> {
+        let query = [("broadcaster_id", broadcaster_id), ("first", "100")];
+        let vips: Vec<Member> = self.get("channels/vips", &query).await?;
+        Ok(vips.into_iter().map(|m| m.user_login).collect())
+    }
+
+    /// Since when the user with id `user_id` follows the channel of
+    /// `broadcaster_id`, `None` if they don't. Needs the bot to be a moderator
+    /// there, with the `moderator:read:followers` scope.
+    pub async fn followed_at(
+        &self,
+        broadcaster_id: &str,
+        user_id: &str,
+    ) -> Result<Option<DateTime<Utc>>, HelixError> {
+        let query = [("broadcaster_id", broadcaster_id), ("user_id", user_id)];
+        let follows: Vec<Follow> = self.get("channels/followers", &query).await?;
+        Ok(follows.into_iter().next().map(|follow| follow.followed_at))
+    }
+
+    /// Gives the channel of `to_broadcaster_id` a Twitch shoutout in the one of
+    /// `from_broadcaster_id`. Needs the bot to be a moderator there, with the
+    /// `moderator:manage:shoutouts` scope.
+    pub async fn shoutout(
+        &self,
+        from_broadcaster_id: &str,
+        to_broadcaster_id: &str,
+    ) -> Result<(), HelixError> {
+        let bot_id = self.bot_id().await?;
+        let request = self
+            .http
+            .post(format!("{}/chat/shoutouts", HELIX_URL))
+            .query(&[
+                ("from_broadcaster_id", from_broadcaster_id),
+                ("to_broadcaster_id", to_broadcaster_id),
+                ("moderator_id", &bot_id),
+            ]);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Bans the user with id `user_id` from the channel of `broadcaster_id`,
+    /// for `duration` if given. Needs the bot to be a moderator there, with the
+    /// `moderator:manage:banned_users` scope.
+    pub async fn ban(
+        &self,
+        broadcaster_id: &str,
+        user_id: &str,
+        duration: Option<Duration>,
+        reason: &str,
+    ) -> Result<(), HelixError> {
+        let bot_id = self.bot_id().await?;
+        let mut ban = json!({ "user_id": user_id, "reason": reason });
+        if let Some(duration) = duration {
+            ban["duration"] = duration.num_seconds().into();
+        }
+        let request = self
+            .http
+            .post(format!("{}/moderation/bans", HELIX_URL))
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &bot_id),
+            ])
+            .json(&json!({ "data": ban }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Lets the message with id `message_id` AutoMod held into chat if `allow`,
+    /// drops it otherwise. Needs the bot to be a moderator in its channel, with
+    /// the `moderator:manage:automod` scope.
+    pub async fn resolve_held_message(
+        &self,
+        message_id: &str,
+        allow: bool,
+    ) -> Result<(), HelixError> {
+        let bot_id = self.bot_id().await?;
+        let action = if allow { "ALLOW" } else { "DENY" };
+        let body = json!({ "user_id": bot_id, "msg_id": message_id, "action": action });
+        self.post("moderation/automod/message", &body).await
+    }
+
+    /// Deletes the chat message with id `message_id` in the channel of
+    /// `broadcaster_id`. Needs the bot to be a moderator there, with the
+    /// `moderator:manage:chat_messages` scope.
+    pub async fn delete_message(
+        &self,
+        broadcaster_id: &str,
+        message_id: &str,
+    ) -> Result<(), HelixError> {
+        let bot_id = self.bot_id().await?;
+        let request = self
+            .http
+            .delete(format!("{}/moderation/chat", HELIX_URL))
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &bot_id),
+                ("message_id", message_id),
+            ]);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Sends a whisper from the bot to the user `to_login`. Needs the
+    /// `user:manage:whispers` scope, and the bot's account a verified phone
+    /// number.
+    pub async fn whisper(&self, to_login: &str, text: &str) -> Result<(), HelixError> {
+        let bot_login = self.login().await?;
+        let users = self.users(&[&bot_login, to_login]).await?;
+        let id = |login: &str| -> Result<&str, HelixError> {
+            users
+                .iter()
+                .find(|user| user.login.eq_ignore_ascii_case(login))
+                .map(|user| user.id.as_str())
+                .ok_or_else(|| format!("No Twitch user {}", login).into())
+        };
+        let request = self
+            .http
+            .post(format!("{}/whispers", HELIX_URL))
+            .query(&[
+                ("from_user_id", id(&bot_login)?),
+                ("to_user_id", id(to_login)?),
+            ])
+            .json(&json!({ "message": text }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Clips the last seconds of the live stream of `broadcaster_id`. Needs
+    /// the `clips:edit` scope.
+    pub async fn create_clip(&self, broadcaster_id: &str) -> Result<Clip, HelixError> {
+        let request = self
+            .http
+            .post(format!("{}/clips", HELIX_URL))
+            .query(&[("broadcaster_id", broadcaster_id)]);
+        self.send_for_one(request).await
+    }
+
+    /// Marks the current moment in the live stream of `user_id`. Needs the
+    /// `channel:manage:broadcast` scope.
+    pub async fn create_marker(
+        &self,
+        user_id: &str,
+        description: &str,
+    ) -> Result<Marker, HelixError> {
+        let request = self
+            .http
+            .post(format!("{}/streams/markers", HELIX_URL))
+            .json(&json!({ "user_id": user_id, "description": description }));
+        self.send_for_one(request).await
+    }
+
+    /// Starts a poll in the channel of `broadcaster_id` that runs for
+    /// `duration`. Needs the `channel:manage:polls` scope from the broadcaster.
+    pub async fn create_poll(
+        &self,
+        broadcaster_id: &str,
+        title: &str,
+        choices: &[&str],
+        duration: Duration,
+    ) -> Result<Poll, HelixError> {
+        let choices: Vec<Value> = choices
+            .iter()
+            .map(|choice| json!({ "title": choice }))
+            .collect();
+        let request = self.http.post(format!("{}/polls", HELIX_URL)).json(&json!({
+            "broadcaster_id": broadcaster_id,
+            "title": title,
+            "choices": choices,
+            "duration": duration.num_seconds(),
+        }));
+        self.send_for_one(request).await
+    }
+
+    /// The poll with id `id` in the channel of `broadcaster_id`.
+    pub async fn poll(&self, broadcaster_id: &str, id: &str) -> Result<Option<Poll>, HelixError> {
+        let query = [("broadcaster_id", broadcaster_id), ("id", id)];
+        let polls = self.get("polls", &query).await?;
+        Ok(polls.into_iter().next())
+    }
+
+    /// Starts a prediction in the channel of `broadcaster_id` that viewers can
+    /// predict for `window`. Needs the `channel:manage:predictions` scope
+    /// from the broadcaster.
+    pub async fn create_prediction(
+        &self,
+        broadcaster_id: &str,
+        title: &str,
+        outcomes: &[&str],
+        window: Duration,
+    ) -> Result<Prediction, HelixError> {
+        let outcomes: Vec<Value> = outcomes
+            .iter()
+            .map(|outcome| json!({ "title": outcome }))
+            .collect();
+        let request = self
+            .http
+            .post(format!("{}/predictions", HELIX_URL))
+            .json(&json!({
+                "broadcaster_id": broadcaster_id,
+                "title": title,
+                "outcomes": outcomes,
+                "prediction_window": window.num_seconds(),
+            }));
+        self.send_for_one(request).await
+    }
+
+    /// The prediction with id `id` in the channel of `broadcaster_id`.
+    pub async fn prediction(
+        &self,
+        broadcaster_id: &str,
+        id: &str,
+    ) -> Result<Option<Prediction>, HelixError> {
+        let query = [("broadcaster_id", broadcaster_id), ("id", id)];
+        let predictions = self.get("predictions", &query).await?;
+        Ok(predictions.into_iter().next())
+    }
+
+    /// Resolves the prediction with id `id` with the outcome
+    /// `winning_outcome_id`, or cancels it and refunds the channel points if
+    /// there's none.
+    pub async fn end_prediction(
+        &self,
+        broadcaster_id: &str,
+        id: &str,
+        winning_outcome_id: Option<&str>,
+    ) -> Result<Prediction, HelixError> {
+        let mut end = json!({ "broadcaster_id": broadcaster_id, "id": id });
+        match winning_outcome_id {
+            Some(outcome_id) => {
+                end["status"] = "RESOLVED".into();
+                end["winning_outcome_id"] = outcome_id.into();
+            }
+            None => end["status"] = "CANCELED".into(),
+        }
+        let request = self
+            .http
+            .patch(format!("{}/predictions", HELIX_URL))
+            .json(&end);
+        self.send_for_one(request).await
+    }
+
+    /// Changes the settings in `changes`, e.g. `{ "title": "..." }`. Needs the
+    /// `channel:manage:broadcast` scope from the broadcaster.
+    pub async fn update_channel(
+        &self,
+        broadcaster_id: &str,
+        changes: &Value,
+    ) -> Result<(), HelixError> {
+        let request = self
+            .http
+            .patch(format!("{}/channels", HELIX_URL))
+            .query(&[("broadcaster_id", broadcaster_id)])
+            .json(changes);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// The chat modes of the channel of `broadcaster_id`. Needs the bot to be a
+    /// moderator there, with the `moderator:manage:chat_settings` scope.
+    pub async fn chat_settings(&self, broadcaster_id: &str) -> Result<ChatSettings, HelixError> {
+        let bot_id = self.bot_id().await?;
+        let request = self
+            .http
+            .get(format!("{}/chat/settings", HELIX_URL))
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &bot_id),
+            ]);
+        self.send_for_one(request).await
+    }
+
+    /// Changes the chat modes in `changes`, e.g. `{ "emote_mode": true }`,
+    /// returning all of them. Needs the bot to be a moderator there, with the
+    /// `moderator:manage:chat_settings` scope.
+    pub async fn update_chat_settings(
+        &self,
+        broadcaster_id: &str,
+        changes: &Value,
+    ) -> Result<ChatSettings, HelixError> {
+        let bot_id = self.bot_id().await?;
+        let request = self
+            .http
+            .patch(format!("{}/chat/settings", HELIX_URL))
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &bot_id),
+            ])
+            .json(changes);
+        self.send_for_one(request).await
+    }
+
+    /// The category named exactly `name`, ignoring case.
+    pub async fn game(&self, name: &str) -> Result<Option<Game>, HelixError> {
+        let games = self.get("games", &[("name", name)]).await?;
+        Ok(games.into_iter().next())
+    }
+
+    /// A `GET` of `path`, e.g. `streams`, returning the items in `data`.
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<T>, HelixError> {
+        let request = self
+            .http
+            .get(format!("{}/{}", HELIX_URL, path))
+            .query(query);
+        let response: Data<T> = self.send(request).await?.json().await?;
+        Ok(response.data)
+    }
+
+    /// A `POST` of `body` to `path`, e.g. `eventsub/subscriptions`.
+    pub async fn post(&self, path: &str, body: &Value) -> Result<(), HelixError> {
+        let request = self.http.post(format!("{}/{}", HELIX_URL, path)).json(body);
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Sends `request`, returning the one item in `data` it creates or changes.
+    async fn send_for_one<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, HelixError> {
+        let response: Data<T> = self.send(request).await?.json().await?;
+        let item = response.data.into_iter().next();
+        Ok(item.ok_or("Twitch responded without data")?)
+    }
+
+    /// Sends `request` with the bot's token, failing on error statuses.
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HelixError> {
+        let credentials = self.credentials.get().await?;
+        let token = credentials.token.ok_or("Helix needs a token")?;
+        let response = request
+            .bearer_auth(token)
+            .header("Client-Id", &self.client_id)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_responses() {
+        let response = r#"{
+            "data": [{
+                "id": "141981764",
+                "login": "twitchdev",
+                "display_name": "TwitchDev",
+                "type": "",
+                "broadcaster_type": "partner"
+            }]
+        }"#;
+        let users: Data<User> = serde_json::from_str(response).unwrap();
+        assert_eq!(users.data[0].id, "141981764");
+        assert_eq!(users.data[0].login, "twitchdev");
+        assert_eq!(users.data[0].display_name, "TwitchDev");
+
+        let response = r#"{
+            "data": [{
+                "broadcaster_id": "141981764",
+                "broadcaster_login": "twitchdev",
+                "broadcaster_name": "TwitchDev",
+                "broadcaster_language": "en",
+                "game_id": "509670",
+                "game_name": "Science & Technology",
+                "title": "TwitchDev Monthly Update // May 6, 2021",
+                "delay": 0
+            }]
+        }"#;
+        let channels: Data<ChannelInfo> = serde_json::from_str(response).unwrap();
+        assert_eq!(channels.data[0].game_name, "Science & Technology");
+
+        let offline: Data<Stream> = serde_json::from_str(r#"{ "data": [] }"#).unwrap();
+        assert!(offline.data.is_empty());
+        let response = r#"{
+            "data": [{
+                "id": "40952121085",
+                "user_id": "101051819",
+                "user_login": "afro",
+                "type": "live",
+                "title": "Jacob: Digital Den Laptops & Tablets",
+                "viewer_count": 1490,
+                "started_at": "2021-03-10T03:18:11Z"
+            }]
+        }"#;
+        let streams: Data<Stream> = serde_json::from_str(response).unwrap();
+        assert_eq!(
+            streams.data[0].started_at.to_rfc3339(),
+            "2021-03-10T03:18:11+00:00"
+        );
+
+        let response = r#"{
+            "data": [{
+                "id": "FiveWordsForClipSlug",
+                "edit_url": "http://clips.twitch.tv/FiveWordsForClipSlug/edit"
+            }]
+        }"#;
+        let clips: Data<Clip> = serde_json::from_str(response).unwrap();
+        let markers: Data<Marker> = serde_json::from_str(
+            r#"{
+                "data": [{
+                    "id": "123",
+                    "created_at": "2018-08-20T20:10:03Z",
+                    "description": "hello, this is a marker!",
+                    "position_seconds": 244
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(markers.data[0].position_seconds, 244);
+        assert_eq!(
+            clips.data[0].url(),
+            "https://clips.twitch.tv/FiveWordsForClipSlug"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! State kept as JSON in a file between runs.
+
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// A file holding a `T` as JSON, written again after every change.
+pub struct JsonStore<T> {
+    path: PathBuf,
+    _saved: PhantomData<fn(&T)>,
+}
+
+impl<T> JsonStore<T> {
+    pub fn new(path: PathBuf) -> JsonStore<T> {
+        JsonStore {
+            path,
+            _saved: PhantomData,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The saved JSON, `None` if nothing was saved yet.
+    pub fn read(&self) -> io::Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(saved) => Ok(Some(saved)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> JsonStore<T> {
+    /// The saved value, `None` if nothing was saved yet.
+    pub fn load(&self) -> io::Result<Option<T>> {
+        match self.read()? {
+            Some(saved) => Ok(Some(serde_json::from_str(&saved)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The saved value, or the default if nothing was saved yet.
+    pub fn load_or_default(&self) -> io::Result<T>
+    where
+        T: Default,
+    {
+        Ok(self.load()?.unwrap_or_default())
+    }
+}
+
+impl<T: Serialize> JsonStore<T> {
+    /// Replaces the saved value.
+    pub fn write(&self, value: &T) -> io::Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(value)?)
+    }
+
+    /// Replaces the saved value, logging failures since the value is still
+    /// kept in memory and saved again with the next change.
+    pub fn save(&self, value: &T) {
+        if let Err(e) = self.write(value) {
+            error!("Failed to save {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn saving_and_loading() {
+        let path = std::env::temp_dir().join(format!("json-store-{}.json", std::process::id()));
+        let store: JsonStore<BTreeMap<String, u64>> = JsonStore::new(path.clone());
+        assert!(store.load().unwrap().is_none());
+        assert!(store.load_or_default().unwrap().is_empty());
+
+        let mut value = BTreeMap::new();
+        value.insert("bonks".to_owned(), 3);
+        store.save(&value);
+        assert_eq!(store.load().unwrap(), Some(value));
+
+        fs::write(&path, "{").unwrap();
+        assert!(store.load().is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
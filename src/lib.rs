@@ -0,0 +1,5 @@
+//! The queue logic of the bot, independent of Twitch and Discord so it can be
+//! tested on its own and reused by other frontends.
+
+pub mod json_store;
+pub mod queue_manager;
@@ -0,0 +1,66 @@
+//! Which stream was last announced in Discord, so it's announced once even if
+//! the bot is restarted while live.
+
+use log::error;
+use std::path::PathBuf;
+use std::{fs, io};
+
+#[derive(Default)]
+pub struct LastStream {
+    id: Option<String>,
+    // saved to after every new stream, if set
+    path: Option<PathBuf>,
+}
+
+impl LastStream {
+    /// Restores the stream saved at `path`, if there is one, and saves every
+    /// new one to it.
+    pub fn persistent(path: PathBuf) -> io::Result<LastStream> {
+        let id = match fs::read_to_string(&path) {
+            Ok(saved) => Some(saved.trim().to_owned()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(LastStream {
+            id,
+            path: Some(path),
+        })
+    }
+
+    /// Whether the stream with id `id` is a different one than the last one
+    /// recorded.
+    pub fn is_new(&self, id: &str) -> bool {
+        self.id.as_deref() != Some(id)
+    }
+
+    /// Remembers the stream with id `id` as announced.
+    pub fn record(&mut self, id: &str) {
+        self.id = Some(id.to_owned());
+        if let Some(path) = &self.path {
+            if let Err(e) = fs::write(path, id) {
+                error!("Failed to save the stream to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_streams() {
+        let path = std::env::temp_dir().join(format!("last-stream-{}", std::process::id()));
+        let mut last = LastStream::persistent(path.clone()).unwrap();
+        assert!(last.is_new("1"));
+        // until it was announced
+        assert!(last.is_new("1"));
+        last.record("1");
+        assert!(!last.is_new("1"));
+
+        let restored = LastStream::persistent(path.clone()).unwrap();
+        assert!(!restored.is_new("1"));
+        assert!(restored.is_new("2"));
+        fs::remove_file(path).unwrap();
+    }
+}
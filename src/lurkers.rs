@@ -0,0 +1,60 @@
+//! Who said they're lurking with `!lurk`, until `!unlurk` or the end of the
+//! stream.
+
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Default)]
+pub struct Lurkers {
+    // keyed by channel login
+    lurking: HashMap<String, BTreeSet<String>>,
+}
+
+impl Lurkers {
+    /// Returns whether `user` wasn't lurking in `channel` already.
+    pub fn lurk(&mut self, channel: &str, user: &str) -> bool {
+        self.lurking
+            .entry(channel.to_owned())
+            .or_default()
+            .insert(user.to_owned())
+    }
+
+    /// Returns whether `user` was lurking in `channel`.
+    pub fn unlurk(&mut self, channel: &str, user: &str) -> bool {
+        self.lurking
+            .get_mut(channel)
+            .is_some_and(|lurking| lurking.remove(user))
+    }
+
+    pub fn count(&self, channel: &str) -> usize {
+        self.lurking.get(channel).map_or(0, BTreeSet::len)
+    }
+
+    /// Forgets everyone lurking in `channel`, when its stream starts or ends.
+    pub fn forget(&mut self, channel: &str) {
+        self.lurking.remove(channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lurking() {
+        let mut lurkers = Lurkers::default();
+        assert!(lurkers.lurk("channel", "alice"));
+        assert!(!lurkers.lurk("channel", "alice"));
+        assert!(lurkers.lurk("channel", "bob"));
+        assert!(lurkers.lurk("other", "alice"));
+        assert_eq!(lurkers.count("channel"), 2);
+
+        assert!(lurkers.unlurk("channel", "alice"));
+        assert!(!lurkers.unlurk("channel", "alice"));
+        assert!(!lurkers.unlurk("none", "alice"));
+        assert_eq!(lurkers.count("channel"), 1);
+
+        lurkers.forget("channel");
+        assert_eq!(lurkers.count("channel"), 0);
+        assert_eq!(lurkers.count("other"), 1);
+    }
+}
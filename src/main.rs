@@ -1,23 +1,79 @@
+mod audit;
+mod backoff;
+mod banned_phrases;
+mod chat_log;
+mod chat_stats;
+mod chatters;
+mod cheers;
+mod commands;
+mod config;
+mod cooldowns;
+mod counters;
+mod custom_commands;
+mod disabled_commands;
 mod discord_commands;
-mod queue_manager;
+mod eventsub;
+mod helix;
+mod live;
+mod lurkers;
+mod permissions;
+mod poll;
+mod rate_limit;
+mod responses;
+mod roles;
+mod templates;
+mod timers;
+mod twitch_polls;
+mod usage;
+mod watchtime;
 
 use async_trait::async_trait;
+use audit::AuditLog;
+use backoff::Backoff;
+use banned_phrases::BannedPhrases;
+use chat_log::ChatLog;
+use chat_stats::ChatStats;
+use chatters::Chatters;
+use cheers::Cheers;
 use chrono::{DateTime, Duration, Utc};
-use log::{debug, trace, LevelFilter};
-use queue_manager::QueueManager;
+use commands::{Invocation, Registry, ReplyTarget};
+use config::{FerrisBotConfig, WelcomeConfig};
+use counters::Counters;
+use custom_commands::CustomCommands;
+use discord_commands::QueueMirror;
+use eventsub::Event;
+use helix::{Helix, SharedCredentials};
+use live::LastStream;
+use log::{debug, error, info, trace, warn, LevelFilter};
+use lurkers::Lurkers;
+use permissions::Permission;
+use rate_limit::RateLimiter;
+use responses::Responses;
+use roles::Roles;
 use serde::{Deserialize, Serialize};
 use serenity::http::Http;
 use serenity::model::id::ChannelId;
 use simple_logger::SimpleLogger;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::{fs, io, str};
 use structopt::StructOpt;
+use templates::Variables;
+use timers::Timers;
+use tokio::sync::{broadcast, mpsc, watch};
 use twitch_irc::login::{RefreshingLoginCredentials, TokenStorage, UserAccessToken};
-use twitch_irc::message::{PrivmsgMessage, ServerMessage};
+use twitch_irc::message::{PrivmsgMessage, ServerMessage, WhisperMessage};
 use twitch_irc::{ClientConfig, TCPTransport, TwitchIRCClient};
+use twitch_polls::TwitchPolls;
+use twitch_queue_bot::queue_manager::{
+    self, Pick, PositionChange, PositionUpdates, QueueError, QueueEvent, QueueManager, QueueStats,
+    UserType,
+};
+use usage::CommandUsage;
+use watchtime::Watchtime;
 
 #[derive(Debug)]
 struct CustomTokenStorage {
@@ -46,27 +102,6 @@ impl TokenStorage for CustomTokenStorage {
     }
 }
 
-#[derive(Deserialize)]
-struct FerrisBotConfig {
-    twitch: TwitchConfig,
-    discord: DiscordConfig,
-}
-
-#[derive(Deserialize)]
-struct TwitchConfig {
-    token_filepath: String,
-    login_name: String,
-    channel_name: String,
-    client_id: String,
-    secret: String,
-}
-
-#[derive(Deserialize)]
-struct DiscordConfig {
-    auth_token: String,
-    channel_id: u64,
-}
-
 #[derive(Deserialize)]
 struct FirstToken {
     access_token: String,
@@ -81,9 +116,9 @@ struct Cli {
     #[structopt(short, long, case_insensitive = true, default_value = "INFO")]
     log_level: LevelFilter,
 
-    /// Twitch credential files.
-    #[structopt(short, long, default_value = "ferrisbot.toml")]
-    config_file: String,
+    /// Config files, can be repeated. Later files are merged over earlier ones.
+    #[structopt(short, long, default_value = "ferrisbot.toml", number_of_values = 1)]
+    config_file: Vec<String>,
 
     /// Generates the curl command to obtain the first token and exits.
     #[structopt(short, long)]
@@ -118,11 +153,10 @@ pub async fn main() {
         .init()
         .unwrap();
 
-    let config = fs::read_to_string(args.config_file).unwrap();
-    let config: FerrisBotConfig = toml::from_str(&config).unwrap();
+    let config = config::load(&args.config_file).unwrap();
 
     if args.show_auth_url {
-        println!("https://id.twitch.tv/oauth2/authorize?client_id={}&redirect_uri=http://localhost&response_type=code&scope=chat:read%20chat:edit", config.twitch.client_id);
+        println!("https://id.twitch.tv/oauth2/authorize?client_id={}&redirect_uri=http://localhost&response_type=code&scope=chat:read%20chat:edit%20whispers:read%20user:manage:whispers%20moderator:read:followers%20channel:manage:broadcast%20channel:read:redemptions%20moderator:manage:shoutouts%20moderator:manage:banned_users%20moderator:manage:chat_messages%20clips:edit%20channel:manage:polls%20channel:manage:predictions%20channel:read:hype_train%20moderation:read%20channel:read:vips%20moderator:manage:chat_settings%20moderator:manage:automod", config.twitch.client_id);
         std::process::exit(0);
     }
 
@@ -158,232 +192,1676 @@ pub async fn main() {
         storage.update_token(&user_access_token).await.unwrap();
     }
 
-    // Discord credentials.
-    let discord_http = Http::new_with_token(&config.discord.auth_token);
-    discord_commands::init_discord_bot(&discord_http, &config.discord.auth_token).await;
-
-    let irc_config = ClientConfig::new_simple(RefreshingLoginCredentials::new(
+    let credentials = SharedCredentials::new(RefreshingLoginCredentials::new(
         config.twitch.login_name.clone(),
         config.twitch.client_id.clone(),
         config.twitch.secret.clone(),
         storage,
     ));
+    let helix = Arc::new(Helix::new(
+        config.twitch.client_id.clone(),
+        credentials.clone(),
+    ));
 
-    let (mut incoming_messages, twitch_client) =
-        TwitchIRCClient::<TCPTransport, _>::new(irc_config);
+    // Discord credentials.
+    let discord_http = Http::new_with_token(&config.discord.auth_token);
+    let discord_token = config.discord.auth_token.clone();
+    let discord_helix = helix.clone();
+    let automod = config.moderation.automod.clone();
+    tokio::spawn(async move {
+        discord_commands::init_discord_bot(&discord_token, discord_helix, automod).await
+    });
+    let channels: Vec<String> = config
+        .twitch
+        .channels()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+    let (mut incoming_messages, twitch_client) = connect(&credentials, &channels);
+    // replaced whenever the bot reconnects
+    let (twitch_clients, current_client) = watch::channel(twitch_client);
 
-    let context = Context {
-        queue_manager: Arc::new(Mutex::new(QueueManager::new())),
-        twitch_client,
-        discord_http,
+    // All chat output goes through this channel so it can be suppressed while
+    // muted, and paced so Twitch doesn't drop messages.
+    let (outgoing, mut outgoing_messages) = mpsc::unbounded_channel::<OutgoingMessage>();
+    let mut limiter = RateLimiter::new(
+        config
+            .twitch
+            .message_limit
+            .unwrap_or(rate_limit::DEFAULT_LIMIT),
+        rate_limit::WINDOW,
+    );
+    let whisper_helix = helix.clone();
+    tokio::spawn(async move {
+        let mut pending = VecDeque::new();
+        loop {
+            if pending.is_empty() {
+                match outgoing_messages.recv().await {
+                    Some(message) => pending.push_back(message),
+                    None => break,
+                }
+            }
+            let wait = limiter.wait_time(std::time::Instant::now());
+            if !wait.is_zero() {
+                debug!("Rate limited, waiting {:?} to send", wait);
+                tokio::time::sleep(wait).await;
+            }
+            // whatever piled up in the meantime goes out in as few messages as possible
+            while let Ok(message) = outgoing_messages.try_recv() {
+                pending.push_back(message);
+            }
+            prioritize(&mut pending);
+            pending = coalesce(pending, MAX_MESSAGE_LENGTH);
+
+            let message = pending.pop_front().unwrap();
+            limiter.record(std::time::Instant::now());
+            match message.whisper_to {
+                Some(user) => {
+                    if let Err(e) = whisper_helix.whisper(&user, &message.text).await {
+                        error!("Failed to whisper {}: {}", user, e);
+                    }
+                }
+                None => {
+                    let twitch_client = current_client.borrow().clone();
+                    let sent = twitch_client
+                        .say_in_response(message.channel_login, message.text, message.reply_to)
+                        .await;
+                    if let Err(e) = sent {
+                        error!("Failed to send chat message: {}", e);
+                    }
+                }
+            }
+        }
+    });
+
+    let mut queue_managers = HashMap::new();
+    for channel in config.twitch.channels() {
+        let main_channel = channel == config.twitch.channel_name;
+        let restored = match &config.queue.persist_path {
+            Some(path) if main_channel => QueueManager::persistent(path.into()),
+            Some(path) => QueueManager::persistent(channel_file(path, channel)),
+            None => Ok(QueueManager::new()),
+        };
+        // starting empty would overwrite the saved queue
+        let mut queue_manager = match restored {
+            Ok(queue_manager) => queue_manager,
+            Err(e) => {
+                error!("Failed to restore the queue of {}: {}", channel, e);
+                std::process::exit(1);
+            }
+        };
+        queue_manager.set_priority_weights(config.queue.priority.clone());
+        queue_manager.set_priority_decay(config.queue.priority_decay);
+        queue_manager.set_strategy(config.queue.strategy.build(config.queue.random_weighting));
+        queue_manager.set_max_size(config.queue.max_queue_size);
+        queue_manager.set_rejoin_cooldown(config.queue.rejoin_cooldown.map(Duration::seconds));
+        queue_manager.set_pick_limit(config.queue.max_picks_per_stream);
+
+        let queue_events = queue_manager.subscribe();
+        let queue_manager = Arc::new(Mutex::new(queue_manager));
+        // Discord only shows the main channel's queues
+        if main_channel {
+            tokio::spawn(forward_queue_events(
+                queue_events,
+                queue_manager.clone(),
+                Http::new_with_token(&config.discord.auth_token),
+                config.discord.mirror_channel_id.map(ChannelId),
+                config
+                    .queue
+                    .notify_discord
+                    .then_some(ChannelId(config.discord.channel_id)),
+            ));
+        }
+        queue_managers.insert(channel.to_owned(), queue_manager);
+    }
+
+    let custom_commands = match &config.commands.persist_path {
+        Some(path) => CustomCommands::persistent(path.into()).unwrap(),
+        None => CustomCommands::default(),
+    };
+    let responses = match &config.commands.responses_path {
+        Some(path) => {
+            let assets = config.commands.assets_path.as_ref().map(Into::into);
+            Responses::load(path.into(), assets).unwrap()
+        }
+        None => Responses::builtin(),
+    };
+    let counters = match &config.commands.counters_path {
+        Some(path) => Counters::persistent(path.into()).unwrap(),
+        None => Counters::default(),
+    };
+    let audit = match &config.commands.audit_path {
+        Some(path) => AuditLog::persistent(path.into()).unwrap(),
+        None => AuditLog::default(),
+    };
+    let cheers = match &config.commands.cheers_path {
+        Some(path) => Cheers::persistent(path.into()).unwrap(),
+        None => Cheers::default(),
+    };
+    let banned_phrases = match config
+        .moderation
+        .banned_phrases
+        .as_ref()
+        .and_then(|banned| banned.path.as_ref())
+    {
+        Some(path) => BannedPhrases::persistent(path.into()).unwrap(),
+        None => BannedPhrases::default(),
+    };
+    let chatters = match config
+        .welcome
+        .as_ref()
+        .and_then(|welcome| welcome.path.as_ref())
+    {
+        Some(path) => Chatters::persistent(path.into()).unwrap(),
+        None => Chatters::default(),
+    };
+    let last_stream = match config.live.as_ref().and_then(|live| live.path.as_ref()) {
+        Some(path) => LastStream::persistent(path.into()).unwrap(),
+        None => LastStream::default(),
     };
 
-    // join a channel
-    context
-        .twitch_client
-        .join(config.twitch.channel_name.to_owned());
+    let watchtime_presence = Duration::seconds(WATCHTIME_PRESENCE);
+    let watchtime = match &config.commands.watchtime_path {
+        Some(path) => Watchtime::persistent(watchtime_presence, path.into()).unwrap(),
+        None => Watchtime::new(watchtime_presence),
+    };
+
+    let chat_log = config.chat_log.as_ref().map(|chat_log| {
+        let retention = chat_log.retention_days.map(Duration::days);
+        ChatLog::new(chat_log.dir.clone().into(), retention)
+    });
+
+    let chat_stats_window = Duration::seconds(
+        config
+            .commands
+            .chat_stats_window
+            .unwrap_or(DEFAULT_CHAT_STATS_WINDOW),
+    );
+
+    let stats_channel = config
+        .queue
+        .stats_to_discord
+        .then_some(ChannelId(config.discord.channel_id));
+    let registry = Arc::new(Registry::builtin(&config));
+
+    let timers = config
+        .twitch
+        .channels()
+        .into_iter()
+        .map(|channel| {
+            let timers = Timers::new(&config.timers, Utc::now());
+            (channel.to_owned(), Mutex::new(timers))
+        })
+        .collect();
+    let muted = config
+        .twitch
+        .channels()
+        .into_iter()
+        .map(|channel| (channel.to_owned(), AtomicBool::new(false)))
+        .collect();
+    let context = Arc::new(Context {
+        queue_managers,
+        custom_commands: Arc::new(Mutex::new(custom_commands)),
+        responses: Mutex::new(responses),
+        counters: Mutex::new(counters),
+        usage: Mutex::new(CommandUsage::default()),
+        audit: Mutex::new(audit),
+        cheers: Mutex::new(cheers),
+        banned_phrases: Mutex::new(banned_phrases),
+        chatters: Mutex::new(chatters),
+        twitch_polls: Mutex::new(TwitchPolls::default()),
+        last_stream: Mutex::new(last_stream),
+        community_gifts: Mutex::new(VecDeque::new()),
+        chat_stats: Mutex::new(ChatStats::new(chat_stats_window, Utc::now())),
+        lurkers: Mutex::new(Lurkers::default()),
+        watchtime: Mutex::new(watchtime),
+        roles: Mutex::new(Roles::default()),
+        chat_log,
+        timers,
+        outgoing,
+        muted,
+        discord_http,
+        helix,
+        config: Arc::new(config),
+    });
 
-    context
-        .twitch_client
-        .say(
-            config.twitch.channel_name.to_owned(),
+    let config = context.config.clone();
+    for channel in config.twitch.channels() {
+        context.say(
+            channel.to_owned(),
             "Hello! I am the Stuck-Bot, How may I unstick you?".to_owned(),
-        )
-        .await
-        .unwrap();
+        );
+    }
+
+    if let (true, Some(timeout)) = (config.modules.queue, config.queue.confirm_timeout) {
+        tokio::spawn(skip_unresponsive(
+            Duration::seconds(timeout),
+            context.clone(),
+        ));
+    }
+
+    if config.modules.timers && !config.timers.is_empty() {
+        tokio::spawn(post_timers(context.clone()));
+    }
+
+    if config.modules.polls {
+        tokio::spawn(announce_twitch_polls(context.clone()));
+    }
+
+    if config.modules.stream {
+        tokio::spawn(track_watchtime(context.clone()));
+    }
 
+    if (config.chat_log.as_ref()).is_some_and(|chat_log| chat_log.retention_days.is_some()) {
+        tokio::spawn(prune_chat_logs(context.clone()));
+    }
+
+    if config.modules.memes && config.commands.responses_path.is_some() {
+        tokio::spawn(reload_when_changed(
+            "response commands",
+            context.clone(),
+            |ctx| ctx.responses.lock().unwrap().reload_if_changed(),
+        ));
+    }
+
+    let banned_phrases_saved = (config.moderation.banned_phrases.as_ref())
+        .is_some_and(|banned_phrases| banned_phrases.path.is_some());
+    if config.modules.moderation && banned_phrases_saved {
+        tokio::spawn(reload_when_changed(
+            "banned phrases",
+            context.clone(),
+            |ctx| ctx.banned_phrases.lock().unwrap().reload_if_changed(),
+        ));
+    }
+
+    if let Some(interval) = config.commands.role_refresh_interval {
+        tokio::spawn(refresh_roles(
+            std::time::Duration::from_secs(interval),
+            context.clone(),
+        ));
+    }
+
+    if let Some(interval) = config.commands.usage_log_interval {
+        tokio::spawn(log_usage(
+            std::time::Duration::from_secs(interval),
+            context.clone(),
+        ));
+    }
+
+    if let (true, Some(interval)) = (config.modules.queue, config.queue.position_updates) {
+        for channel in config.twitch.channels() {
+            tokio::spawn(announce_positions(
+                PositionUpdates::new(Duration::seconds(interval)),
+                channel.to_owned(),
+                context.clone(),
+            ));
+        }
+    }
+
+    let event_kinds = config.event_kinds();
+    if !event_kinds.is_empty() {
+        let (events, received) = mpsc::unbounded_channel();
+        tokio::spawn(eventsub::run(
+            context.helix.clone(),
+            config.twitch.channel_name.clone(),
+            event_kinds,
+            events,
+        ));
+        tokio::spawn(react_to_events(received, registry.clone(), context.clone()));
+    }
+
+    let handler_context = context.clone();
+    let main_channel = config.twitch.channel_name.clone();
     let join_handle = tokio::spawn(async move {
-        while let Some(message) = incoming_messages.recv().await {
-            trace!("{:?}", message);
-            match message {
-                ServerMessage::Privmsg(msg) => {
-                    if let Some(cmd) = TwitchCommand::parse_msg(&msg) {
-                        cmd.handle(msg, &config, &context).await;
+        // of the main channel, looked up with the first whisper
+        let mut main_channel_id = None;
+        let mut backoff = Backoff::new(RECONNECT_DELAY, MAX_RECONNECT_DELAY);
+        loop {
+            // Twitch pings every few minutes, silence means the connection is gone
+            while let Ok(Some(message)) =
+                tokio::time::timeout(SILENCE_TIMEOUT, incoming_messages.recv()).await
+            {
+                backoff.reset();
+                trace!("{:?}", message);
+                match message {
+                    ServerMessage::Privmsg(msg) => {
+                        log_chat(
+                            &msg.channel_login,
+                            msg.server_timestamp,
+                            chat_log::message_line(&msg),
+                            &handler_context,
+                        );
+                        if let Some(bits) = msg.bits {
+                            cheered(&msg, bits, &registry, &handler_context).await;
+                        }
+                        handler_context.roles.lock().unwrap().saw(
+                            &msg.channel_login,
+                            &msg.sender.login,
+                            Permission::of(&msg),
+                        );
+                        record_chat_activity(&msg, &handler_context);
+                        // removed messages aren't worth a welcome
+                        if registry.dispatch(msg.clone(), &handler_context).await {
+                            welcome(&msg, &handler_context);
+                        }
+                    }
+                    ServerMessage::Whisper(whisper) => {
+                        if main_channel_id.is_none() {
+                            main_channel_id = user_id(&main_channel, &handler_context).await;
+                        }
+                        if let Some(channel_id) = &main_channel_id {
+                            let msg = whispered_command(whisper, &main_channel, channel_id);
+                            registry.dispatch_command(msg, &handler_context).await
+                        }
                     }
+                    ServerMessage::UserNotice(notice) => {
+                        let community_gifts = &handler_context.community_gifts;
+                        let event =
+                            commands::chat_event(&notice, &mut community_gifts.lock().unwrap());
+                        if let Some(event) = event {
+                            registry
+                                .react(&event, &notice.channel_login, &handler_context)
+                                .await;
+                        }
+                    }
+                    ServerMessage::ClearChat(clear) => log_chat(
+                        &clear.channel_login,
+                        clear.server_timestamp,
+                        chat_log::clear_chat_line(&clear),
+                        &handler_context,
+                    ),
+                    ServerMessage::ClearMsg(clear) => log_chat(
+                        &clear.channel_login,
+                        clear.server_timestamp,
+                        chat_log::clear_msg_line(&clear),
+                        &handler_context,
+                    ),
+                    _ => continue,
                 }
-                _ => continue,
             }
+            let delay = backoff.next_delay();
+            warn!(
+                "Lost the connection to Twitch chat, reconnecting in {:?}",
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            // without greeting the channels again, they were greeted on startup
+            let (incoming, twitch_client) = connect(&credentials, &channels);
+            incoming_messages = incoming;
+            let _ = twitch_clients.send(twitch_client);
         }
     });
 
     // keep the tokio executor alive.
     // If you return instead of waiting the background task will exit.
-    join_handle.await.unwrap();
+    tokio::select! {
+        result = join_handle => result.unwrap(),
+        _ = tokio::signal::ctrl_c() => info!("Shutting down"),
+    }
+
+    if let Some(channel) = stats_channel {
+        let queue_manager = context.queue_manager(&config.twitch.channel_name);
+        let summary = stats_summary(queue_manager.lock().unwrap().stats());
+        if let Err(e) = channel.say(&context.discord_http, summary).await {
+            error!("Failed to post queue stats to Discord: {}", e);
+        }
+    }
+}
+
+/// How long to wait before reconnecting to chat the first time, doubling
+/// with every failed attempt up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Chat is considered disconnected after hearing nothing for this long.
+const SILENCE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+type TwitchClient = TwitchIRCClient<
+    TCPTransport,
+    SharedCredentials<RefreshingLoginCredentials<CustomTokenStorage>>,
+>;
+
+/// Connects to chat and joins `channels`.
+fn connect(
+    credentials: &SharedCredentials<RefreshingLoginCredentials<CustomTokenStorage>>,
+    channels: &[String],
+) -> (mpsc::UnboundedReceiver<ServerMessage>, TwitchClient) {
+    let irc_config = ClientConfig::new_simple(credentials.clone());
+    let (incoming_messages, twitch_client) = TwitchClient::new(irc_config);
+    for channel in channels {
+        twitch_client.join(channel.clone());
+    }
+    (incoming_messages, twitch_client)
 }
 
 struct Context {
-    twitch_client: TwitchIRCClient<TCPTransport, RefreshingLoginCredentials<CustomTokenStorage>>,
-    queue_manager: Arc<Mutex<QueueManager>>,
+    // keyed by channel login, every joined channel has queues of its own
+    queue_managers: HashMap<String, Arc<Mutex<QueueManager>>>,
+    custom_commands: Arc<Mutex<CustomCommands>>,
+    responses: Mutex<Responses>,
+    counters: Mutex<Counters>,
+    usage: Mutex<CommandUsage>,
+    audit: Mutex<AuditLog>,
+    cheers: Mutex<Cheers>,
+    banned_phrases: Mutex<BannedPhrases>,
+    chatters: Mutex<Chatters>,
+    twitch_polls: Mutex<TwitchPolls>,
+    last_stream: Mutex<LastStream>,
+    // origin ids of the latest community gifts, see `chat_event`
+    community_gifts: Mutex<VecDeque<String>>,
+    chat_stats: Mutex<ChatStats>,
+    lurkers: Mutex<Lurkers>,
+    watchtime: Mutex<Watchtime>,
+    roles: Mutex<Roles>,
+    chat_log: Option<ChatLog>,
+    // keyed by channel login
+    timers: HashMap<String, Mutex<Timers>>,
+    outgoing: mpsc::UnboundedSender<OutgoingMessage>,
+    // keyed by channel login
+    muted: HashMap<String, AtomicBool>,
     discord_http: Http,
+    helix: Arc<Helix>,
+    config: Arc<FerrisBotConfig>,
 }
 
-#[derive(Debug, PartialEq)]
-enum TwitchCommand {
-    Join,
-    Queue,
-    ReplyWith(&'static str),
-    Broadcast(&'static str),
-    Nothing,
-    DiscordSnippet(String),
-}
-
-impl TwitchCommand {
-    async fn handle(self, msg: PrivmsgMessage, config: &FerrisBotConfig, ctx: &Context) {
-        match self {
-            TwitchCommand::Join => {
-                ctx.twitch_client
-                    .say(
-                        msg.channel_login,
-                        format!("@{}: Join requested", &msg.sender.login),
-                    )
-                    .await
-                    .unwrap();
-
-                ctx.queue_manager
-                    .lock()
-                    .unwrap()
-                    .join(msg.sender.login, queue_manager::UserType::Default)
-                    .unwrap();
-            }
+/// Seconds back `!chatstats` looks unless configured.
+const DEFAULT_CHAT_STATS_WINDOW: i64 = 3600;
+
+/// Seconds until a poll or prediction that hasn't ended when expected is
+/// looked at again, predictions stay open until they're resolved.
+const TWITCH_POLL_RECHECK: i64 = 30;
+
+/// Seconds between crediting everyone watching a live stream with watchtime.
+const WATCHTIME_INTERVAL: i64 = 60;
+
+/// Seconds after their last message chatters still count as watching.
+const WATCHTIME_PRESENCE: i64 = 600;
+
+/// Twitch rejects chat messages longer than this many characters.
+const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// How urgent a chat message is, when the bot is rate limited more urgent
+/// messages skip ahead of the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    /// E.g. response commands and timers.
+    Low,
+    Normal,
+    /// E.g. queue picks and replies to moderators.
+    High,
+}
+
+#[derive(Debug, Clone)]
+struct OutgoingMessage {
+    channel_login: String,
+    // sent as a whisper to this user instead of to the channel
+    whisper_to: Option<String>,
+    // id of the chat message this is a reply to
+    reply_to: Option<String>,
+    priority: Priority,
+    text: String,
+}
+
+impl OutgoingMessage {
+    fn to(channel_login: String, text: String) -> OutgoingMessage {
+        OutgoingMessage {
+            channel_login,
+            whisper_to: None,
+            reply_to: None,
+            priority: Priority::Normal,
+            text,
+        }
+    }
+}
+
+impl Context {
+    /// Welcomes everyone in `channel_login` again the next time they chat, as
+    /// a new stream starts. Unless every chatter is saved, then only people
+    /// who never chatted before are welcomed.
+    fn welcome_again(&self, channel_login: &str) {
+        let welcome = self.config.welcome.as_ref();
+        if welcome.is_some_and(|welcome| welcome.path.is_none()) {
+            self.chatters.lock().unwrap().forget(channel_login);
+        }
+    }
+
+    /// The queues of `channel_login`, which has to be one of the joined channels.
+    fn queue_manager(&self, channel_login: &str) -> &Arc<Mutex<QueueManager>> {
+        self.queue_managers
+            .get(channel_login)
+            .unwrap_or_else(|| panic!("{} isn't a joined channel", channel_login))
+    }
+
+    /// Whether the bot was muted in `channel_login` with `!mute`.
+    fn is_muted(&self, channel_login: &str) -> bool {
+        (self.muted.get(channel_login)).is_some_and(|muted| muted.load(Ordering::Relaxed))
+    }
+
+    /// Sends a chat message, unless the bot is muted there. Messages that are too
+    /// long for Twitch are split up.
+    fn say(&self, channel_login: String, text: String) {
+        self.send(OutgoingMessage::to(channel_login, text));
+    }
+
+    /// Sends a chat message that may skip ahead of others or fall behind them.
+    fn say_with_priority(&self, channel_login: String, text: String, priority: Priority) {
+        self.send(OutgoingMessage {
+            priority,
+            ..OutgoingMessage::to(channel_login, text)
+        });
+    }
+
+    /// Sends a private message to `user`, going through the channel's connection.
+    fn whisper(&self, channel_login: String, user: &str, text: String) {
+        self.send(OutgoingMessage {
+            whisper_to: Some(user.to_owned()),
+            ..OutgoingMessage::to(channel_login, text)
+        });
+    }
+
+    fn send(&self, message: OutgoingMessage) {
+        if self.is_muted(&message.channel_login) {
+            debug!(
+                "Muted in {}, not sending: {}",
+                message.channel_login, message.text
+            );
+            return;
+        }
+        for text in split_message(&message.text, MAX_MESSAGE_LENGTH) {
+            let _ = self.outgoing.send(OutgoingMessage {
+                text,
+                ..message.clone()
+            });
+        }
+    }
+
+    /// The role of the sender of `msg` in its channel. The one they're known
+    /// to have is used, whispers carry no channel badges.
+    fn permission(&self, msg: &PrivmsgMessage) -> Permission {
+        let known = (self.roles.lock().unwrap()).of(&msg.channel_login, &msg.sender.login);
+        known.unwrap_or_else(|| Permission::of(msg))
+    }
+
+    /// Sends a message addressed to whoever used a command, to wherever
+    /// replies to it go.
+    fn reply(&self, invocation: &Invocation, text: &str) {
+        self.reply_to(&invocation.msg, invocation.reply_target, text);
+    }
+
+    /// Sends a message addressed to the sender of `msg`, to `target` or else
+    /// where replies go by default. Replies to moderators go out first,
+    /// replies to whispers are always whispered.
+    fn reply_to(&self, msg: &PrivmsgMessage, target: Option<ReplyTarget>, text: &str) {
+        let target = if is_whisper(msg) {
+            Some(ReplyTarget::Whisper)
+        } else {
+            target
+        };
+        let mut message = OutgoingMessage::to(msg.channel_login.clone(), text.to_owned());
+        if self.permission(msg) >= Permission::Moderator {
+            message.priority = Priority::High;
+        }
+        match target.unwrap_or(self.config.commands.reply_target) {
+            ReplyTarget::Chat => message.text = format!("@{}: {}", msg.sender.login, text),
+            ReplyTarget::Whisper => message.whisper_to = Some(msg.sender.login.clone()),
+            ReplyTarget::Thread => message.reply_to = Some(msg.message_id.clone()),
+        }
+        self.send(message);
+    }
+}
+
+/// Where the data of an extra channel is saved, e.g. `queue.other.json` next
+/// to `queue.json`.
+fn channel_file(path: &str, channel_login: &str) -> PathBuf {
+    let path = Path::new(path);
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(channel_login);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Discord rejects messages longer than this many characters.
+const DISCORD_MESSAGE_LENGTH: usize = 2000;
+
+/// Contents of the Discord queue mirror message, listing every queue.
+fn mirror_text(queue_manager: &QueueManager) -> String {
+    let mut lines = Vec::new();
+    for queue in queue_manager.queue_names() {
+        let mut title = format!("**{}**", queue_name(queue));
+        if queue_manager.is_closed(queue).unwrap() {
+            title.push_str(" (closed)");
+        }
+        if queue_manager.is_sub_only(queue).unwrap() {
+            title.push_str(" (sub-only)");
+        }
+        lines.push(title);
+        if let Some((user, elapsed)) = queue_manager.current(queue).unwrap() {
+            lines.push(format!("Up now: {} ({})", user, format_duration(elapsed)));
+        }
+        let entries = queue_manager.entries(queue).unwrap();
+        if entries.is_empty() {
+            lines.push("Nobody is waiting".to_owned());
+        }
+        for (i, (user, note)) in entries.into_iter().enumerate() {
+            lines.push(match note {
+                Some(note) => format!("{}. {} - {}", i + 1, user, note),
+                None => format!("{}. {}", i + 1, user),
+            });
+        }
+        lines.push(String::new());
+    }
 
-            TwitchCommand::Queue => {
-                let reply = {
-                    let queue_manager = ctx.queue_manager.lock().unwrap();
-                    queue_manager.queue().join(", ")
+    let mut text = String::new();
+    for line in lines {
+        // leave room for the marker below
+        if text.len() + line.len() + 1 > DISCORD_MESSAGE_LENGTH - 10 {
+            text.push_str("...");
+            break;
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.trim_end().to_owned()
+}
+
+/// Tells chat, and the user with a whisper if enabled, who was picked.
+fn announce_pick(pick: &Pick, channel_login: String, ctx: &Context) {
+    if ctx.config.queue.whisper_on_pick {
+        ctx.whisper(
+            channel_login.clone(),
+            &pick.name,
+            format!("It's your turn in {}'s queue!", channel_login),
+        );
+    }
+    let confirm = match ctx.config.queue.confirm_timeout {
+        Some(timeout) => format!(
+            " Type !here within {} to keep your spot.",
+            format_duration(Duration::seconds(timeout))
+        ),
+        None => String::new(),
+    };
+    ctx.say_with_priority(
+        channel_login,
+        format!(
+            "@{}: You're up next!{}{}",
+            pick.name,
+            note_suffix(pick),
+            confirm
+        ),
+        Priority::High,
+    );
+}
+
+/// Tells chat, and every user with a whisper if enabled, who was picked
+/// together.
+fn announce_group(picks: &[Pick], channel_login: String, ctx: &Context) {
+    if ctx.config.queue.whisper_on_pick {
+        for pick in picks {
+            ctx.whisper(
+                channel_login.clone(),
+                &pick.name,
+                format!("It's your turn in {}'s queue!", channel_login),
+            );
+        }
+    }
+    let mentions: Vec<String> = picks
+        .iter()
+        .map(|pick| format!("@{}{}", pick.name, note_suffix(pick)))
+        .collect();
+    ctx.say_with_priority(
+        channel_login,
+        format!("{}: You're up next together!", mentions.join(", ")),
+        Priority::High,
+    );
+}
+
+/// Checks every few seconds for picked users who didn't `!here` in time and
+/// moves on to the next person.
+async fn skip_unresponsive(timeout: Duration, ctx: Arc<Context>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        for (channel, queue_manager) in &ctx.queue_managers {
+            let skipped = queue_manager
+                .lock()
+                .unwrap()
+                .skip_unresponsive(timeout, ctx.config.queue.requeue_unresponsive);
+            for skipped in skipped {
+                let requeued = if skipped.requeued {
+                    format!(", moved to the back of {}", queue_label(&skipped.queue))
+                } else {
+                    String::new()
                 };
-                ctx.twitch_client
-                    .say(
-                        msg.channel_login,
-                        format!("@{}: Current queue: {}", msg.sender.login, reply),
-                    )
-                    .await
-                    .unwrap();
+                ctx.say(
+                    channel.clone(),
+                    format!("@{} didn't respond in time{}", skipped.name, requeued),
+                );
+                if let Some(next) = skipped.next {
+                    announce_pick(&next, channel.clone(), &ctx);
+                }
             }
+        }
+    }
+}
 
-            TwitchCommand::ReplyWith(reply) => {
-                ctx.twitch_client
-                    .say(
-                        msg.channel_login,
-                        format!("@{}: {}", msg.sender.login, reply),
-                    )
-                    .await
-                    .unwrap();
+/// Checks every few seconds whether the file with `what` was edited and loads
+/// it again with `reload`, which returns whether it did.
+async fn reload_when_changed(
+    what: &'static str,
+    ctx: Arc<Context>,
+    reload: impl Fn(&Context) -> io::Result<bool>,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        match reload(&ctx) {
+            Ok(true) => info!("Reloaded the {}", what),
+            Ok(false) => {}
+            Err(e) => error!("Failed to reload the {}: {}", what, e),
+        }
+    }
+}
+
+/// Checks every few seconds for polls and predictions run by Twitch that
+/// should have ended and announces how they turned out.
+async fn announce_twitch_polls(ctx: Arc<Context>) {
+    use twitch_polls::{poll_results, prediction_results, Kind};
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        let due = ctx.twitch_polls.lock().unwrap().due(Utc::now());
+        for mut running in due {
+            let (broadcaster_id, id) = (&running.broadcaster_id, &running.id);
+            let results = match running.kind {
+                Kind::Poll => (ctx.helix.poll(broadcaster_id, id).await)
+                    .map(|poll| poll.map(|poll| poll_results(&poll))),
+                Kind::Prediction => (ctx.helix.prediction(broadcaster_id, id).await)
+                    .map(|prediction| prediction.map(|prediction| prediction_results(&prediction))),
+            };
+            match results {
+                Ok(Some(Some(results))) => ctx.say(running.channel_login, results),
+                Ok(None) => warn!(
+                    "The {:?} {} in {} no longer exists",
+                    running.kind, running.id, running.channel_login
+                ),
+                // still running, or worth another try
+                Ok(Some(None)) | Err(_) => {
+                    if let Err(e) = results {
+                        error!("Failed to look up the {:?} {}: {}", running.kind, id, e);
+                    }
+                    running.check_at = Utc::now() + Duration::seconds(TWITCH_POLL_RECHECK);
+                    ctx.twitch_polls.lock().unwrap().add(running);
+                }
+            }
+        }
+    }
+}
+
+/// Credits everyone who chatted lately in a channel that's live with
+/// watchtime every minute.
+async fn track_watchtime(ctx: Arc<Context>) {
+    let elapsed = Duration::seconds(WATCHTIME_INTERVAL);
+    let mut interval = tokio::time::interval(elapsed.to_std().unwrap());
+    loop {
+        interval.tick().await;
+        let watched = ctx.watchtime.lock().unwrap().watched(Utc::now());
+        for (channel, channel_id) in watched {
+            match ctx.helix.stream(&channel_id).await {
+                Ok(Some(_)) => {
+                    let mut watchtime = ctx.watchtime.lock().unwrap();
+                    watchtime.credit(&channel, elapsed, Utc::now());
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to look up the stream of {}: {}", channel, e),
             }
+        }
+    }
+}
 
-            TwitchCommand::Broadcast(message) => {
-                ctx.twitch_client
-                    .say(msg.channel_login, message.to_owned())
-                    .await
-                    .unwrap();
+/// Looks up the moderators and VIPs of every joined channel every `period`,
+/// so their roles are known before they chat.
+async fn refresh_roles(period: std::time::Duration, ctx: Arc<Context>) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let logins = ctx.config.twitch.channels();
+        let channels = match ctx.helix.users(&logins).await {
+            Ok(channels) => channels,
+            Err(e) => {
+                error!("Failed to look up the joined channels: {}", e);
+                continue;
+            }
+        };
+        for channel in channels {
+            let moderators = ctx.helix.moderators(&channel.id).await;
+            let vips = ctx.helix.vips(&channel.id).await;
+            match (moderators, vips) {
+                (Ok(moderators), Ok(vips)) => {
+                    let mut roles = ctx.roles.lock().unwrap();
+                    roles.refresh(&channel.login, &moderators, &vips);
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Failed to look up the roles in {}: {}", channel.login, e)
+                }
             }
+        }
+    }
+}
 
-            TwitchCommand::Nothing => {
-                debug!("nothing received");
-                let _ = ChannelId(config.discord.channel_id)
-                    .say(&ctx.discord_http, "This does nothing")
-                    .await;
+/// Deletes chat logs past their retention every hour.
+async fn prune_chat_logs(ctx: Arc<Context>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        if let Some(chat_log) = &ctx.chat_log {
+            if let Err(e) = chat_log.prune(Utc::now().date_naive()) {
+                error!("Failed to delete old chat logs: {}", e);
             }
+        }
+    }
+}
 
-            TwitchCommand::DiscordSnippet(snippet) => {
-                let formatted = format_snippet(&snippet).unwrap_or(snippet);
-                let code_block = format!("```rs\n{}\n```", formatted);
+/// Checks every few seconds for timers that are due and posts them.
+async fn post_timers(ctx: Arc<Context>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        for (channel, timers) in &ctx.timers {
+            let due = timers.lock().unwrap().due(Utc::now());
+            for text in due {
+                ctx.say_with_priority(channel.clone(), text, Priority::Low);
+            }
+        }
+    }
+}
 
-                let _ = ChannelId(config.discord.channel_id)
-                    .say(&ctx.discord_http, code_block)
-                    .await;
+/// Reacts to the events EventSub sends about the main channel.
+async fn react_to_events(
+    mut events: mpsc::UnboundedReceiver<Event>,
+    registry: Arc<Registry>,
+    ctx: Arc<Context>,
+) {
+    while let Some(event) = events.recv().await {
+        registry
+            .react(&event, &ctx.config.twitch.channel_name, &ctx)
+            .await;
+    }
+}
+
+/// The id of the Twitch user `login`, logs why if it can't be looked up.
+async fn user_id(login: &str, ctx: &Context) -> Option<String> {
+    match ctx.helix.users(&[login]).await {
+        Ok(users) => match users.into_iter().next() {
+            Some(user) => Some(user.id),
+            None => {
+                error!("No Twitch user {}", login);
+                None
             }
+        },
+        Err(e) => {
+            error!("Failed to look up {}: {}", login, e);
+            None
         }
     }
+}
+
+/// A whisper to the bot as if it was sent in `channel_login`, so commands can
+/// be used privately. Whispers carry no channel badges, the sender's role is
+/// the one they're known to have there, see `Context::permission`.
+fn whispered_command(
+    whisper: WhisperMessage,
+    channel_login: &str,
+    channel_id: &str,
+) -> PrivmsgMessage {
+    let message_id = match whisper.source.tags.0.get("message-id") {
+        Some(Some(id)) => id.clone(),
+        _ => String::new(),
+    };
+    PrivmsgMessage {
+        channel_login: channel_login.to_owned(),
+        channel_id: channel_id.to_owned(),
+        message_text: whisper.message_text,
+        is_action: false,
+        sender: whisper.sender,
+        badge_info: Vec::new(),
+        badges: whisper.badges,
+        bits: None,
+        name_color: whisper.name_color,
+        emotes: whisper.emotes,
+        message_id,
+        server_timestamp: Utc::now(),
+        source: whisper.source,
+    }
+}
+
+/// Whether `msg` was whispered to the bot, see `whispered_command`.
+fn is_whisper(msg: &PrivmsgMessage) -> bool {
+    msg.source.command == "WHISPER"
+}
+
+/// Counts the `bits` cheered with `msg` towards `!topcheers` and thanks for them.
+async fn cheered(msg: &PrivmsgMessage, bits: u64, registry: &Registry, ctx: &Context) {
+    ctx.cheers
+        .lock()
+        .unwrap()
+        .add(&msg.channel_login, &msg.sender.login, bits);
+    let event = Event::Cheer {
+        user: msg.sender.name.clone(),
+        bits,
+    };
+    registry.react(&event, &msg.channel_login, ctx).await;
+}
+
+/// Adds `line` to the chat log of `channel`, if chat is logged.
+fn log_chat(channel: &str, at: DateTime<Utc>, line: String, ctx: &Context) {
+    if let Some(chat_log) = &ctx.chat_log {
+        chat_log.write(channel, at, line);
+    }
+}
+
+/// Counts `msg` towards `!chatstats` and its sender as watching.
+fn record_chat_activity(msg: &PrivmsgMessage, ctx: &Context) {
+    let command = commands::invoked_command(msg, ctx);
+    ctx.chat_stats.lock().unwrap().record(
+        &msg.channel_login,
+        &msg.sender.login,
+        command.as_deref(),
+        Utc::now(),
+    );
+    ctx.watchtime.lock().unwrap().seen(
+        &msg.channel_login,
+        &msg.channel_id,
+        &msg.sender.login,
+        Utc::now(),
+    );
+}
+
+/// Welcomes the sender of `msg` if it's the first time they chat, when enabled.
+fn welcome(msg: &PrivmsgMessage, ctx: &Context) {
+    let config = match &ctx.config.welcome {
+        Some(config) => config,
+        None => return,
+    };
+    let first_time = ctx
+        .chatters
+        .lock()
+        .unwrap()
+        .first_time(&msg.channel_login, &msg.sender.login);
+    if first_time {
+        let prefix = ctx.config.commands.prefix(&msg.channel_login);
+        ctx.say(msg.channel_login.clone(), welcome_text(msg, config, prefix));
+    }
+}
+
+fn welcome_text(msg: &PrivmsgMessage, config: &WelcomeConfig, prefix: &str) -> String {
+    let variables = Variables {
+        user: &msg.sender.name,
+        channel: &msg.channel_login,
+        args: "",
+        count: 0,
+        months: None,
+    };
+    let mut text = templates::render(&config.message, &variables, &mut rand::thread_rng());
+    if config.help {
+        text.push_str(&format!(" Type {}help to see what I can do.", prefix));
+    }
+    if let Some(discord) = &config.discord {
+        text.push_str(&format!(" Join us on Discord: {}", discord));
+    }
+    text
+}
 
-    fn parse_msg(msg: &PrivmsgMessage) -> Option<TwitchCommand> {
-        if !msg.message_text.starts_with('!') {
-            return None;
+/// Logs which commands were used the most every `period`.
+async fn log_usage(period: std::time::Duration, ctx: Arc<Context>) {
+    let mut interval = tokio::time::interval(period);
+    // the first tick completes immediately, before anything was used
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let summary = ctx.usage.lock().unwrap().summary(10);
+        info!("Command usage: {}", summary);
+    }
+}
+
+/// Tells users who moved up in the queues of `channel_login` where they are
+/// now, throttled by `updates` so a mass removal results in a single message.
+async fn announce_positions(
+    mut updates: PositionUpdates,
+    channel_login: String,
+    ctx: Arc<Context>,
+) {
+    let mut events = {
+        let queue_manager = ctx.queue_manager(&channel_login).lock().unwrap();
+        for queue in queue_manager.queue_names() {
+            updates.update(queue, &queue_manager.queue(queue).unwrap());
+        }
+        queue_manager.subscribe()
+    };
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(QueueEvent::Cleared) => updates.reset(),
+                Ok(QueueEvent::Joined { queue, .. })
+                | Ok(QueueEvent::Left { queue, .. })
+                | Ok(QueueEvent::Picked { queue, .. })
+                | Ok(QueueEvent::PickedGroup { queue, .. })
+                | Ok(QueueEvent::Changed { queue }) => {
+                    let queue_manager = ctx.queue_manager(&channel_login).lock().unwrap();
+                    if let Ok(order) = queue_manager.queue(&queue) {
+                        updates.update(&queue, &order);
+                    }
+                }
+                // missed some changes, the next event catches up
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = interval.tick() => {
+                for (queue, changes) in updates.due(Utc::now()).unwrap_or_default() {
+                    ctx.say(
+                        channel_login.clone(),
+                        position_changes_text(&queue, &changes),
+                    );
+                }
+            }
         }
+    }
+}
 
-        let parts: Vec<&str> = msg.message_text.split_whitespace().collect();
-        let (cmd, args) = parts.split_first()?;
+/// Most users mentioned in a single position update, the rest are counted.
+const MAX_POSITION_MENTIONS: usize = 10;
 
-        match (cmd.to_lowercase().as_str(), args) {
-            ("!join", _) => Some(TwitchCommand::Join),
-            ("!queue", _) => Some(TwitchCommand::Queue),
-            ("!pythonsucks", _) => Some(TwitchCommand::ReplyWith("This must be Lord")),
-            ("!stonk", _) => Some(TwitchCommand::ReplyWith("yOu shOULd Buy AMC sTOnKS")),
-            ("!c++", _) => Some(TwitchCommand::ReplyWith("segmentation fault")),
-            ("!dave", _) => Some(TwitchCommand::Broadcast(include_str!("../assets/dave.txt"))),
-            ("!bazylia", _) => Some(TwitchCommand::Broadcast(include_str!(
-                "../assets/bazylia.txt"
-            ))),
-            ("!zoya", _) => Some(TwitchCommand::Broadcast(include_str!("../assets/zoya.txt"))),
-            ("!discord", _) => Some(TwitchCommand::Broadcast("https://discord.gg/UyrsFX7N")),
-            ("!nothing", _) => Some(TwitchCommand::Nothing),
-            ("!code", _) => Some(TwitchCommand::DiscordSnippet(
-                msg.message_text.trim_start_matches(cmd).trim_start().into(),
-            )),
+/// One chat message listing the users who moved up in `queue`.
+fn position_changes_text(queue: &str, changes: &[PositionChange]) -> String {
+    let mut mentions: Vec<String> = changes
+        .iter()
+        .take(MAX_POSITION_MENTIONS)
+        .map(|change| match change.position {
+            0 => format!("@{} is next", change.name),
+            position => format!("@{} is #{}", change.name, position + 1),
+        })
+        .collect();
+    if changes.len() > MAX_POSITION_MENTIONS {
+        mentions.push(format!("{} more", changes.len() - MAX_POSITION_MENTIONS));
+    }
+    format!(
+        "Moving up in {}: {}",
+        queue_label(queue),
+        mentions.join(", ")
+    )
+}
+
+/// Keeps Discord up to date with the queues: the mirror message is refreshed
+/// on every change and picks are announced in `notify_channel`.
+async fn forward_queue_events(
+    mut events: broadcast::Receiver<QueueEvent>,
+    queue_manager: Arc<Mutex<QueueManager>>,
+    http: Http,
+    mirror_channel: Option<ChannelId>,
+    notify_channel: Option<ChannelId>,
+) {
+    let mut mirror = mirror_channel.map(QueueMirror::new);
+    // show the state restored on startup right away
+    let mut event = None;
+    loop {
+        debug!("Queue event: {:?}", event);
+        let announcement = match &event {
+            Some(QueueEvent::Picked { pick, .. }) => {
+                Some(format!("{} is up next!{}", pick.name, note_suffix(pick)))
+            }
+            Some(QueueEvent::PickedGroup { picks, .. }) => {
+                let names: Vec<String> = picks
+                    .iter()
+                    .map(|pick| format!("{}{}", pick.name, note_suffix(pick)))
+                    .collect();
+                Some(format!("{} are up next together!", names.join(", ")))
+            }
             _ => None,
+        };
+        if let (Some(channel), Some(text)) = (notify_channel, announcement) {
+            if let Err(e) = channel.say(&http, text).await {
+                error!("Failed to announce pick in Discord: {}", e);
+            }
         }
+        if let Some(mirror) = &mut mirror {
+            let content = mirror_text(&queue_manager.lock().unwrap());
+            if let Err(e) = mirror.update(&http, &content).await {
+                error!("Failed to update the Discord queue mirror: {}", e);
+            }
+        }
+
+        event = match events.recv().await {
+            Ok(event) => Some(event),
+            // missed some events, the mirror still catches up
+            Err(broadcast::error::RecvError::Lagged(_)) => None,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+    }
+}
+
+/// The note of a picked user, formatted to be appended to the announcement.
+fn note_suffix(pick: &Pick) -> String {
+    match &pick.note {
+        Some(note) => format!(" ({})", note),
+        None => String::new(),
+    }
+}
+
+/// "queue" for the default queue, "<name> queue" for named ones.
+fn queue_name(queue: &str) -> String {
+    match queue {
+        queue_manager::DEFAULT_QUEUE => "queue".to_owned(),
+        _ => format!("{} queue", queue),
+    }
+}
+
+fn queue_label(queue: &str) -> String {
+    format!("the {}", queue_name(queue))
+}
+
+fn stats_summary(stats: &QueueStats) -> String {
+    let mut summary = format!(
+        "Queue stats: {} join(s) from {} user(s), {} picked",
+        stats.joins(),
+        stats.unique_users(),
+        stats.picks()
+    );
+    if let Some(wait) = stats.average_wait() {
+        summary.push_str(&format!(", average wait {}", format_duration(wait)));
+    }
+    if let Some(per_hour) = stats.picks_per_hour() {
+        summary.push_str(&format!(", {:.1} picks per hour", per_hour));
     }
+    summary
 }
 
-fn format_snippet(snippet: &str) -> Result<String, io::Error> {
-    let mut rustfmt = Command::new("rustfmt")
-        .args(&["--config", "newline_style=Unix"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
+/// Moves more urgent messages to the front, keeping the order of the ones that
+/// are just as urgent.
+fn prioritize(messages: &mut VecDeque<OutgoingMessage>) {
+    messages
+        .make_contiguous()
+        .sort_by_key(|message| std::cmp::Reverse(message.priority));
+}
 
-    let input = rustfmt.stdin.as_mut().unwrap();
-    input.write_all(snippet.as_bytes())?;
+/// Joins consecutive chat messages to the same channel and of the same
+/// priority as long as they fit into `max_len` characters. Whispers and
+/// replies are kept as they are.
+fn coalesce(messages: VecDeque<OutgoingMessage>, max_len: usize) -> VecDeque<OutgoingMessage> {
+    let mut coalesced: VecDeque<OutgoingMessage> = VecDeque::with_capacity(messages.len());
+    for message in messages {
+        if let Some(last) = coalesced.back_mut() {
+            let fits = last.text.chars().count() + message.text.chars().count() + 3 <= max_len;
+            if fits
+                && last.whisper_to.is_none()
+                && message.whisper_to.is_none()
+                && last.reply_to.is_none()
+                && message.reply_to.is_none()
+                && last.priority == message.priority
+                && last.channel_login == message.channel_login
+            {
+                last.text.push_str(" | ");
+                last.text.push_str(&message.text);
+                continue;
+            }
+        }
+        coalesced.push_back(message);
+    }
+    coalesced
+}
 
-    let output = rustfmt.wait_with_output()?;
+/// Splits `text` into chunks of at most `max_len` characters, breaking at
+/// whitespace where possible.
+fn split_message(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while let Some((limit, _)) = rest.char_indices().nth(max_len) {
+        let split = if rest[limit..].starts_with(char::is_whitespace) {
+            limit
+        } else {
+            rest[..limit]
+                .rfind(char::is_whitespace)
+                .filter(|&i| !rest[..i].trim().is_empty())
+                .unwrap_or(limit)
+        };
+        chunks.push(rest[..split].trim_end().to_owned());
+        rest = rest[split..].trim_start();
+    }
+    if !rest.is_empty() {
+        chunks.push(rest.to_owned());
+    }
+    chunks
+}
+
+fn queue_error_reply(queue: &str, error: QueueError) -> String {
+    let label = queue_label(queue);
+    match error {
+        QueueError::Cooldown(remaining) => format!(
+            "You were picked recently, you can join again in {}",
+            format_duration(remaining)
+        ),
+        QueueError::PickLimit(picks) => format!(
+            "You've already had your turn {} time(s) this stream, let others have a go!",
+            picks
+        ),
+        QueueError::AlreadyInQueue => format!("You are already in {}", label),
+        QueueError::NotInQueue => format!("You are not in {}", label),
+        QueueError::QueueFull => format!("Sorry, {} is full", label),
+        QueueError::QueueClosed => format!("Sorry, {} is currently closed", label),
+        QueueError::Blocked => format!("You can't join {}", label),
+        QueueError::SubOnly => format!("Sorry, {} is for subscribers only right now", label),
+        QueueError::QueueEmpty => format!("Nobody is waiting in {}", label),
+        QueueError::NoSuchQueue => format!("There is no queue named {}", queue),
+        QueueError::InvalidPosition => format!("That position is not in {}", label),
+        QueueError::NothingToUndo => "There is nothing to undo".to_owned(),
+    }
+}
 
-    if output.status.success() {
-        String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+/// Formats a duration for chat, e.g. "1h 5m" or "4m 20s".
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            String::from_utf8_lossy(&output.stderr),
-        ))
+        format!("{}s", seconds)
+    }
+}
+
+/// The highest queue tier `permission` qualifies for.
+fn user_type(permission: Permission) -> UserType {
+    match permission {
+        Permission::Broadcaster | Permission::Moderator => UserType::Moderator,
+        Permission::Vip => UserType::Vip,
+        Permission::Subscriber => UserType::Subscriber,
+        Permission::Everyone => UserType::Default,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use config::TriggerConfig;
+    use queue_manager::DEFAULT_QUEUE;
+    use std::convert::TryFrom;
+    use twitch_irc::login::StaticLoginCredentials;
+    use twitch_irc::message::IRCMessage;
+
+    #[test]
+    fn splitting_messages() {
+        assert_eq!(split_message("short message", 500), vec!["short message"]);
+        assert_eq!(
+            split_message("one two three four", 9),
+            vec!["one two", "three", "four"]
+        );
+        assert_eq!(split_message("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        // leading whitespace is kept when no split is needed, e.g. for ascii art
+        assert_eq!(split_message("  art", 500), vec!["  art"]);
+        assert_eq!(split_message("żółć żółć", 4), vec!["żółć", "żółć"]);
+        assert!(split_message("", 500).is_empty());
+
+        let long = "word ".repeat(300);
+        let chunks = split_message(&long, MAX_MESSAGE_LENGTH);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.chars().count() <= MAX_MESSAGE_LENGTH));
+    }
+
+    #[test]
+    fn coalescing_messages() {
+        let message = |channel: &str, whisper_to: Option<&str>, text: &str| OutgoingMessage {
+            whisper_to: whisper_to.map(str::to_owned),
+            ..OutgoingMessage::to(channel.to_owned(), text.to_owned())
+        };
+        let mut reply = message("a", None, "re");
+        reply.reply_to = Some("1094e782".to_owned());
+        let coalesced = coalesce(
+            VecDeque::from([
+                message("a", None, "one"),
+                message("a", None, "two"),
+                message("a", Some("ferris"), "psst"),
+                message("a", None, "three"),
+                reply,
+                message("b", None, "four"),
+                message("b", None, "a longer one"),
+            ]),
+            15,
+        );
+        let texts: Vec<&str> = coalesced.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            ["one | two", "psst", "three", "re", "four", "a longer one"]
+        );
+    }
+
+    #[test]
+    fn prioritizing_messages() {
+        let message = |text: &str, priority| OutgoingMessage {
+            priority,
+            ..OutgoingMessage::to("a".to_owned(), text.to_owned())
+        };
+        let mut pending = VecDeque::from([
+            message("meme", Priority::Low),
+            message("hi", Priority::Normal),
+            message("pick", Priority::High),
+            message("another meme", Priority::Low),
+            message("mod reply", Priority::High),
+        ]);
+        prioritize(&mut pending);
+        let coalesced = coalesce(pending, MAX_MESSAGE_LENGTH);
+        let texts: Vec<&str> = coalesced.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["pick | mod reply", "hi", "meme | another meme"]);
+    }
+
+    #[test]
+    fn summarizing_stats() {
+        let mut queue_manager = QueueManager::new();
+        assert_eq!(
+            stats_summary(queue_manager.stats()),
+            "Queue stats: 0 join(s) from 0 user(s), 0 picked"
+        );
+
+        queue_manager
+            .join(DEFAULT_QUEUE, "first".to_owned(), UserType::Default, None)
+            .unwrap();
+        queue_manager.next(DEFAULT_QUEUE).unwrap();
+        assert_eq!(
+            stats_summary(queue_manager.stats()),
+            "Queue stats: 1 join(s) from 1 user(s), 1 picked, average wait 0s"
+        );
+    }
+
+    #[test]
+    fn announcing_position_changes() {
+        let changes: Vec<PositionChange> = (0..12)
+            .map(|position| PositionChange {
+                name: format!("user{}", position),
+                position,
+            })
+            .collect();
+        assert_eq!(
+            position_changes_text("review", &changes[..2]),
+            "Moving up in the review queue: @user0 is next, @user1 is #2"
+        );
+        assert!(position_changes_text(DEFAULT_QUEUE, &changes).ends_with("@user9 is #10, 2 more"));
+    }
 
     #[test]
-    fn parsing_commands() {
-        assert!(TwitchCommand::parse_msg(&test_msg("regular message text")).is_none());
+    fn mirroring_queues() {
+        let mut queue_manager = QueueManager::new();
+        queue_manager.open("review");
+        queue_manager.close("review").unwrap();
+        for (name, note) in &[
+            ("first", None),
+            ("second", Some("lifetimes")),
+            ("third", None),
+        ] {
+            queue_manager
+                .join(
+                    DEFAULT_QUEUE,
+                    name.to_string(),
+                    UserType::Default,
+                    note.map(str::to_owned),
+                )
+                .unwrap();
+        }
+        queue_manager.next(DEFAULT_QUEUE).unwrap();
+
         assert_eq!(
-            TwitchCommand::parse_msg(&test_msg("!join")),
-            Some(TwitchCommand::Join)
+            mirror_text(&queue_manager),
+            "**queue**\nUp now: first (0s)\n1. second - lifetimes\n2. third\n\n\
+             **review queue** (closed)\nNobody is waiting"
         );
 
-        // commands should be case-insensitive with their arguments left untouched
+        for i in 0..300 {
+            queue_manager
+                .join(DEFAULT_QUEUE, format!("user{}", i), UserType::Default, None)
+                .unwrap();
+        }
+        let text = mirror_text(&queue_manager);
+        assert!(text.len() <= DISCORD_MESSAGE_LENGTH);
+        assert!(text.ends_with("..."));
+    }
+
+    #[test]
+    fn naming_channel_files() {
         assert_eq!(
-            TwitchCommand::parse_msg(&test_msg("!sToNk")),
-            Some(TwitchCommand::ReplyWith("yOu shOULd Buy AMC sTOnKS"))
+            channel_file("data/queue.json", "other"),
+            Path::new("data/queue.other.json")
         );
+        assert_eq!(channel_file("queue", "other"), Path::new("queue.other"));
+    }
+
+    #[tokio::test]
+    async fn chat_statistics() {
+        let (ctx, mut sent) = test_context();
+        let registry = Registry::builtin(&ctx.config);
+
+        for text in ["hello", "!join", "!JOIN review"] {
+            record_chat_activity(&test_msg(text), &ctx);
+        }
+        registry
+            .dispatch(test_broadcaster_msg("!chatstats"), &ctx)
+            .await;
+        let reply = drain(&mut sent).join("\n");
+        assert!(reply.starts_with("@broadcaster: 3 messages in the last "));
+        assert!(reply.ends_with("from 1 chatter(s), most used commands: !join (2). 0 lurking"));
+    }
+
+    #[test]
+    fn welcoming_first_chatters() {
+        let mut config = test_config();
+        config.welcome = Some(WelcomeConfig {
+            message: "Welcome $(user)!".to_owned(),
+            path: None,
+            help: true,
+            discord: Some("https://discord.gg/UyrsFX7N".to_owned()),
+        });
+        let (ctx, mut sent) = test_context_with(config);
+
+        welcome(&test_msg("hi"), &ctx);
+        welcome(&test_msg("hello again"), &ctx);
+        // a new stream
+        ctx.welcome_again("channel_login");
+        welcome(&test_msg("hi"), &ctx);
+        let welcome = "Welcome name! Type !help to see what I can do. Join us on Discord: \
+                       https://discord.gg/UyrsFX7N";
+        assert_eq!(drain(&mut sent), [welcome, welcome]);
+    }
+
+    #[tokio::test]
+    async fn commands_in_whispers() {
+        let mut config = test_config();
+        config.commands.triggers.push(TriggerConfig {
+            keyword: Some("hello".to_owned()),
+            regex: None,
+            text: "Hi!".to_owned(),
+            reply: false,
+            cooldown: Default::default(),
+        });
+        let (ctx, mut sent) = test_context_with(config);
+        let registry = Registry::builtin(&ctx.config);
+        let whispered = |text: &str| {
+            let whisper = format!(
+                "@badges=;color=#19E6E6;display-name=Ferris;emotes=;message-id=15;\
+                 thread-id=40286300_553170741;turbo=0;user-id=40286300;user-type= \
+                 :ferris!ferris@ferris.tmi.twitch.tv WHISPER stuckbot :{}",
+                text
+            );
+            let whisper = IRCMessage::parse(&whisper).unwrap();
+            whispered_command(
+                WhisperMessage::try_from(whisper).unwrap(),
+                "channel_login",
+                "1",
+            )
+        };
+        let msg = whispered("!join");
+        assert!(is_whisper(&msg));
+        assert_eq!(msg.channel_id, "1");
+        assert!(!is_whisper(&test_msg("!join")));
+
+        // whispers aren't chat, triggers don't answer them
+        registry.dispatch_command(whispered("hello"), &ctx).await;
+        assert!(sent.try_recv().is_err());
+
+        registry.dispatch_command(msg, &ctx).await;
+        let reply = sent.try_recv().unwrap();
+        assert_eq!(reply.whisper_to.as_deref(), Some("ferris"));
+        assert_eq!(reply.text, "Join requested. You are next in the queue");
         assert_eq!(
-            TwitchCommand::parse_msg(&test_msg("!cOdE fn main() {}")),
-            Some(TwitchCommand::DiscordSnippet("fn main() {}".into()))
+            ctx.queue_manager("channel_login")
+                .lock()
+                .unwrap()
+                .queue(DEFAULT_QUEUE)
+                .unwrap(),
+            ["ferris"]
         );
     }
 
     #[test]
-    fn formatting_snippets() {
-        // converting to Option here because std::io::Error doesn't impl PartialEq
+    fn formatting_durations() {
+        assert_eq!(format_duration(Duration::seconds(42)), "42s");
+        assert_eq!(format_duration(Duration::seconds(260)), "4m 20s");
+        assert_eq!(format_duration(Duration::seconds(3900)), "1h 5m");
+        assert_eq!(format_duration(Duration::seconds(-5)), "0s");
+    }
+
+    #[test]
+    fn user_types_from_roles() {
+        use twitch_irc::message::Badge;
+
+        let (ctx, _sent) = test_context();
+        let user_type = |msg: &PrivmsgMessage| user_type(ctx.permission(msg));
+        assert_eq!(user_type(&test_msg("!join")), UserType::Default);
         assert_eq!(
-            format_snippet(r#"fn main() { println!("hello world"); }"#)
-                .as_deref()
-                .ok(),
-            Some("fn main() {\n    println!(\"hello world\");\n}\n")
+            user_type(&test_broadcaster_msg("!join")),
+            UserType::Moderator
         );
 
-        assert!(format_snippet(r#"totally not rust code"#).is_err());
+        let mut msg = test_msg("!join");
+        msg.badges = vec![
+            Badge {
+                name: "subscriber".to_owned(),
+                version: "12".to_owned(),
+            },
+            Badge {
+                name: "vip".to_owned(),
+                version: "1".to_owned(),
+            },
+        ];
+        assert_eq!(user_type(&msg), UserType::Vip);
+
+        // e.g. whispers, which have no badges
+        ctx.roles
+            .lock()
+            .unwrap()
+            .saw("channel_login", "login", Permission::Subscriber);
+        assert_eq!(user_type(&test_msg("!join")), UserType::Subscriber);
+    }
+
+    pub fn test_context() -> (Context, mpsc::UnboundedReceiver<OutgoingMessage>) {
+        test_context_with(test_config())
+    }
+
+    pub fn test_context_with(
+        config: FerrisBotConfig,
+    ) -> (Context, mpsc::UnboundedReceiver<OutgoingMessage>) {
+        let (outgoing, sent) = mpsc::unbounded_channel();
+        let ctx = Context {
+            queue_managers: config
+                .twitch
+                .channels()
+                .into_iter()
+                .map(|channel| {
+                    (
+                        channel.to_owned(),
+                        Arc::new(Mutex::new(QueueManager::new())),
+                    )
+                })
+                .collect(),
+            custom_commands: Arc::new(Mutex::new(CustomCommands::default())),
+            responses: Mutex::new(match &config.commands.responses_path {
+                Some(path) => {
+                    let assets = config.commands.assets_path.as_ref().map(Into::into);
+                    Responses::load(path.into(), assets).unwrap()
+                }
+                None => Responses::builtin(),
+            }),
+            counters: Mutex::new(Counters::default()),
+            usage: Mutex::new(CommandUsage::default()),
+            audit: Mutex::new(AuditLog::default()),
+            cheers: Mutex::new(Cheers::default()),
+            banned_phrases: Mutex::new(BannedPhrases::default()),
+            chatters: Mutex::new(Chatters::default()),
+            twitch_polls: Mutex::new(TwitchPolls::default()),
+            last_stream: Mutex::new(LastStream::default()),
+            community_gifts: Mutex::new(VecDeque::new()),
+            chat_stats: Mutex::new(ChatStats::new(Duration::hours(1), Utc::now())),
+            lurkers: Mutex::new(Lurkers::default()),
+            watchtime: Mutex::new(Watchtime::new(Duration::seconds(WATCHTIME_PRESENCE))),
+            roles: Mutex::new(Roles::default()),
+            chat_log: None,
+            timers: config
+                .twitch
+                .channels()
+                .into_iter()
+                .map(|channel| (channel.to_owned(), Mutex::new(Timers::default())))
+                .collect(),
+            outgoing,
+            muted: config
+                .twitch
+                .channels()
+                .into_iter()
+                .map(|channel| (channel.to_owned(), AtomicBool::new(false)))
+                .collect(),
+            discord_http: Http::new_with_token(""),
+            helix: Arc::new(Helix::new(
+                String::new(),
+                StaticLoginCredentials::anonymous(),
+            )),
+            config: Arc::new(config),
+        };
+        (ctx, sent)
+    }
+
+    /// The texts of everything sent so far.
+    pub fn drain(sent: &mut mpsc::UnboundedReceiver<OutgoingMessage>) -> Vec<String> {
+        std::iter::from_fn(|| sent.try_recv().ok())
+            .map(|message| message.text)
+            .collect()
+    }
+
+    pub fn test_config() -> FerrisBotConfig {
+        let mut config: FerrisBotConfig =
+            toml::from_str(include_str!("../sample.ferrisbot.toml")).unwrap();
+        // tests shouldn't leave files behind
+        config.commands.disabled_path = None;
+        config.commands.audit_path = None;
+        // the channel of test messages
+        config.twitch.channel_name = "channel_login".to_owned();
+        config
+    }
+
+    pub fn test_broadcaster_msg(message_text: &str) -> PrivmsgMessage {
+        use twitch_irc::message::Badge;
+
+        let mut msg = test_msg(message_text);
+        msg.sender.login = "broadcaster".to_owned();
+        msg.badges.push(Badge {
+            name: "broadcaster".to_owned(),
+            version: "1".to_owned(),
+        });
+        msg
     }
 
-    fn test_msg(message_text: &str) -> PrivmsgMessage {
+    pub fn test_msg(message_text: &str) -> PrivmsgMessage {
         use twitch_irc::message::{IRCMessage, IRCTags, TwitchUserBasics};
 
         PrivmsgMessage {
@@ -0,0 +1,82 @@
+//! Who may use which command, based on the sender's chat badges.
+
+use serde::Deserialize;
+use twitch_irc::message::{Badge, PrivmsgMessage};
+
+/// How trusted a chatter is, every level includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Everyone,
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+}
+
+impl Permission {
+    /// The highest level the sender of `msg` has.
+    pub fn of(msg: &PrivmsgMessage) -> Permission {
+        Permission::from_badges(&msg.badges)
+    }
+
+    pub fn from_badges(badges: &[Badge]) -> Permission {
+        let has_badge = |name: &str| badges.iter().any(|badge| badge.name == name);
+
+        if has_badge("broadcaster") {
+            Permission::Broadcaster
+        } else if has_badge("moderator") {
+            Permission::Moderator
+        } else if has_badge("vip") {
+            Permission::Vip
+        } else if has_badge("subscriber") || has_badge("founder") {
+            Permission::Subscriber
+        } else {
+            Permission::Everyone
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn badges(names: &[&str]) -> Vec<Badge> {
+        names
+            .iter()
+            .map(|name| Badge {
+                name: name.to_string(),
+                version: "1".to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn levels_from_badges() {
+        assert_eq!(Permission::from_badges(&[]), Permission::Everyone);
+        assert_eq!(
+            Permission::from_badges(&badges(&["founder"])),
+            Permission::Subscriber
+        );
+        assert_eq!(
+            Permission::from_badges(&badges(&["subscriber", "vip"])),
+            Permission::Vip
+        );
+        assert_eq!(
+            Permission::from_badges(&badges(&["moderator", "subscriber"])),
+            Permission::Moderator
+        );
+        assert_eq!(
+            Permission::from_badges(&badges(&["broadcaster", "subscriber"])),
+            Permission::Broadcaster
+        );
+    }
+
+    #[test]
+    fn higher_levels_include_lower_ones() {
+        assert!(Permission::Broadcaster > Permission::Moderator);
+        assert!(Permission::Moderator > Permission::Vip);
+        assert!(Permission::Vip > Permission::Subscriber);
+        assert!(Permission::Subscriber > Permission::Everyone);
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// What happens when a user votes more than once in the same poll.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RevotePolicy {
+    /// The first vote is kept, later ones are rejected.
+    FirstWins,
+    /// Every vote replaces the previous one.
+    #[default]
+    LastWins,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VoteError {
+    NoSuchOption(usize),
+    AlreadyVoted,
+}
+
+pub struct Poll {
+    question: String,
+    options: Vec<String>,
+    // login -> index into `options`
+    votes: HashMap<String, usize>,
+    revote: RevotePolicy,
+}
+
+impl Poll {
+    pub fn new(question: String, options: Vec<String>, revote: RevotePolicy) -> Poll {
+        Poll {
+            question,
+            options,
+            votes: HashMap::new(),
+            revote,
+        }
+    }
+
+    /// Records a vote, `option` is 1-based as typed in chat.
+    pub fn vote(&mut self, user: String, option: usize) -> Result<(), VoteError> {
+        if option == 0 || option > self.options.len() {
+            return Err(VoteError::NoSuchOption(option));
+        }
+        if self.revote == RevotePolicy::FirstWins && self.votes.contains_key(&user) {
+            return Err(VoteError::AlreadyVoted);
+        }
+        self.votes.insert(user, option - 1);
+        Ok(())
+    }
+
+    /// Vote counts for every option, in the order the options were given.
+    pub fn tally(&self) -> Vec<(&str, usize)> {
+        let mut counts = vec![0; self.options.len()];
+        for &option in self.votes.values() {
+            counts[option] += 1;
+        }
+        self.options
+            .iter()
+            .map(String::as_str)
+            .zip(counts)
+            .collect()
+    }
+
+    /// Human readable result announcement for chat.
+    pub fn summary(&self) -> String {
+        let tally: Vec<(&str, u64)> = self
+            .tally()
+            .into_iter()
+            .map(|(option, count)| (option, count as u64))
+            .collect();
+        summary(&self.question, &tally)
+    }
+}
+
+/// Announces the results of a poll with the vote counts in `tally`, also used
+/// for the polls run by Twitch.
+pub fn summary(question: &str, tally: &[(&str, u64)]) -> String {
+    let max = tally.iter().map(|&(_, count)| count).max().unwrap_or(0);
+    if max == 0 {
+        return format!("Poll \"{}\" closed, no votes were cast.", question);
+    }
+
+    let winners: Vec<&str> = tally
+        .iter()
+        .filter(|&&(_, count)| count == max)
+        .map(|&(option, _)| option)
+        .collect();
+    let outcome = match winners.as_slice() {
+        [winner] => format!("Winner: {} with {} vote(s)", winner, max),
+        _ => format!(
+            "Tie between {} with {} vote(s) each",
+            winners.join(", "),
+            max
+        ),
+    };
+    let counts: Vec<String> = tally
+        .iter()
+        .enumerate()
+        .map(|(i, (option, count))| format!("{}) {}: {}", i + 1, option, count))
+        .collect();
+
+    format!(
+        "Poll \"{}\" closed. {}. Results: {}",
+        question,
+        outcome,
+        counts.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_poll(revote: RevotePolicy) -> Poll {
+        Poll::new(
+            "Best language?".to_owned(),
+            vec!["rust".to_owned(), "c++".to_owned(), "python".to_owned()],
+            revote,
+        )
+    }
+
+    #[test]
+    fn poll_lifecycle() {
+        let mut poll = test_poll(RevotePolicy::LastWins);
+        assert_eq!(
+            poll.summary(),
+            "Poll \"Best language?\" closed, no votes were cast."
+        );
+
+        assert!(poll.vote("alice".to_owned(), 1).is_ok());
+        assert!(poll.vote("bob".to_owned(), 1).is_ok());
+        assert!(poll.vote("carol".to_owned(), 3).is_ok());
+
+        assert_eq!(poll.tally(), vec![("rust", 2), ("c++", 0), ("python", 1)]);
+        assert_eq!(
+            poll.summary(),
+            "Poll \"Best language?\" closed. Winner: rust with 2 vote(s). \
+             Results: 1) rust: 2, 2) c++: 0, 3) python: 1"
+        );
+    }
+
+    #[test]
+    fn ties_are_announced() {
+        let mut poll = test_poll(RevotePolicy::LastWins);
+        poll.vote("alice".to_owned(), 1).unwrap();
+        poll.vote("bob".to_owned(), 2).unwrap();
+
+        assert!(poll
+            .summary()
+            .contains("Tie between rust, c++ with 1 vote(s) each"));
+    }
+
+    #[test]
+    fn revotes() {
+        let mut last_wins = test_poll(RevotePolicy::LastWins);
+        last_wins.vote("alice".to_owned(), 1).unwrap();
+        assert!(last_wins.vote("alice".to_owned(), 2).is_ok());
+        assert_eq!(
+            last_wins.tally(),
+            vec![("rust", 0), ("c++", 1), ("python", 0)]
+        );
+
+        let mut first_wins = test_poll(RevotePolicy::FirstWins);
+        first_wins.vote("alice".to_owned(), 1).unwrap();
+        assert_eq!(
+            first_wins.vote("alice".to_owned(), 2),
+            Err(VoteError::AlreadyVoted)
+        );
+        assert_eq!(
+            first_wins.tally(),
+            vec![("rust", 1), ("c++", 0), ("python", 0)]
+        );
+    }
+
+    #[test]
+    fn invalid_options() {
+        let mut poll = test_poll(RevotePolicy::LastWins);
+        assert_eq!(
+            poll.vote("alice".to_owned(), 0),
+            Err(VoteError::NoSuchOption(0))
+        );
+        assert_eq!(
+            poll.vote("alice".to_owned(), 4),
+            Err(VoteError::NoSuchOption(4))
+        );
+        assert_eq!(poll.tally(), vec![("rust", 0), ("c++", 0), ("python", 0)]);
+    }
+}
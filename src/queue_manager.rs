@@ -1,29 +1,1171 @@
+//! Viewer queues: joining, picking, priorities and the bookkeeping around them.
+//!
+//! A [`QueueManager`] holds any number of named queues, [`DEFAULT_QUEUE`]
+//! always exists. Every change is announced to subscribers (see
+//! [`QueueManager::subscribe`]) and saved to a [`QueueStorage`] if one is set.
+
+mod entries;
+mod positions;
+mod storage;
+mod strategy;
+
+pub use positions::{PositionChange, PositionUpdates};
+pub use storage::{JsonFile, Memory, QueueStorage};
+pub use strategy::{
+    Candidate, Fifo, Priority, QueueStrategy, Random, RandomWeighting, RoundRobin, StrategyKind,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use entries::Entries;
+use log::error;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+/// Name of the queue used when no queue name is given, it always exists.
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// How many of the most recent sessions the wait time estimate is based on.
+const SESSION_HISTORY: usize = 10;
+
+/// How many mod actions `QueueManager::undo` can go back.
+const UNDO_HISTORY: usize = 10;
+
+/// How many events a slow subscriber can fall behind before it misses some.
+const EVENT_BUFFER: usize = 64;
+
+/// All queues along with the settings that apply to them.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct QueueManager {
-    queue: Vec<String>,
+    queues: BTreeMap<String, Queue>,
+    // when users were last picked, for the rejoin cooldown
+    picked_at: HashMap<String, DateTime<Utc>>,
+    stats: QueueStats,
+    // users who may not join any queue
+    blocked: BTreeSet<String>,
+    #[serde(skip)]
+    weights: PriorityWeights,
+    #[serde(skip)]
+    max_size: Option<usize>,
+    #[serde(skip)]
+    rejoin_cooldown: Option<Duration>,
+    #[serde(skip)]
+    pick_limit: Option<usize>,
+    #[serde(skip)]
+    priority_decay: bool,
+    #[serde(skip)]
+    strategy: Box<dyn QueueStrategy>,
+    // most recent last
+    #[serde(skip)]
+    history: VecDeque<Undo>,
+    #[serde(skip)]
+    storage: Option<Box<dyn QueueStorage>>,
+    #[serde(skip)]
+    events: broadcast::Sender<QueueEvent>,
+}
+
+/// Something that happened to the queues, see `QueueManager::subscribe`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueEvent {
+    Joined {
+        queue: String,
+        name: String,
+    },
+    Left {
+        queue: String,
+        name: String,
+    },
+    Picked {
+        queue: String,
+        pick: Pick,
+    },
+    /// Several users were picked at once, see `QueueManager::next_group`.
+    PickedGroup {
+        queue: String,
+        picks: Vec<Pick>,
+    },
+    /// The queue was opened, closed or reordered.
+    Changed {
+        queue: String,
+    },
+    /// Every queue was emptied at the end of the stream.
+    Cleared,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Queue {
+    entries: Entries,
+    closed: bool,
+    // only subscribers (and VIPs and mods) may join
+    sub_only: bool,
+    // the last user picked, their session lasts until the next pick
+    current: Option<Session>,
+    // lengths of the most recent sessions in seconds
+    session_lengths: VecDeque<i64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Session {
+    name: String,
+    started: DateTime<Utc>,
+    #[serde(default)]
+    user_type: UserType,
+    // whether the user said they're there, see `confirm`
+    #[serde(default)]
+    confirmed: bool,
+    // seconds they waited in the queue, taken back out of the stats if
+    // they're skipped
+    #[serde(default)]
+    waited: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueueEntry {
+    name: String,
+    user_type: UserType,
+    #[serde(default = "Utc::now")]
+    joined_at: DateTime<Utc>,
+    /// What the user wants to do once picked, given when joining.
+    #[serde(default)]
+    note: Option<String>,
+    // priority weight when joining, `None` to go by `user_type`
+    #[serde(default)]
+    weight: Option<u32>,
+}
+
+impl QueueEntry {
+    fn weight(&self, weights: &PriorityWeights) -> u32 {
+        self.weight
+            .unwrap_or_else(|| weights.weight(self.user_type))
+    }
+}
+
+/// A user waiting in a queue, as written out by `!exportqueue`.
+#[derive(Debug, Serialize)]
+pub struct ExportedEntry<'a> {
+    pub position: usize,
+    pub name: &'a str,
+    pub user_type: UserType,
+    pub joined_at: DateTime<Utc>,
+    pub note: Option<&'a str>,
+}
+
+/// A picked user who didn't confirm in time, see `QueueManager::skip_unresponsive`.
+#[derive(Debug, PartialEq)]
+pub struct Skipped {
+    pub queue: String,
+    pub name: String,
+    /// Whether they were put back at the end of the queue.
+    pub requeued: bool,
+    /// Who was picked instead, if anyone was waiting.
+    pub next: Option<Pick>,
+}
+
+/// A user taken off the front of a queue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pick {
+    pub name: String,
+    pub note: Option<String>,
+}
+
+/// Activity across all queues since the stats were last reset.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueStats {
+    since: Option<DateTime<Utc>>,
+    ended: Option<DateTime<Utc>>,
+    joins: usize,
+    // how many times each user joined
+    joined: HashMap<String, usize>,
+    picks: usize,
+    // how many times each user was picked
+    picked: HashMap<String, usize>,
+    // total seconds picked users spent waiting
+    waited: i64,
+}
+
+impl QueueStats {
+    fn record_join(&mut self, name: &str) {
+        self.since.get_or_insert_with(Utc::now);
+        self.joins += 1;
+        *self.joined.entry(name.to_owned()).or_default() += 1;
+    }
+
+    fn record_pick(&mut self, name: &str, waited: Duration) {
+        self.picks += 1;
+        *self.picked.entry(name.to_owned()).or_default() += 1;
+        self.waited += waited.num_seconds();
+    }
+
+    fn unrecord_pick(&mut self, name: &str, waited: Duration) {
+        self.picks = self.picks.saturating_sub(1);
+        if let Some(picks) = self.picked.get_mut(name) {
+            *picks -= 1;
+            if *picks == 0 {
+                self.picked.remove(name);
+            }
+        }
+        self.waited -= waited.num_seconds();
+    }
+
+    /// Successful joins, counting rejoins.
+    pub fn joins(&self) -> usize {
+        self.joins
+    }
+
+    /// Distinct users who joined.
+    pub fn unique_users(&self) -> usize {
+        self.joined.len()
+    }
+
+    /// How many times `name` joined.
+    pub fn joins_of(&self, name: &str) -> usize {
+        self.joined.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn picks(&self) -> usize {
+        self.picks
+    }
+
+    /// How many times `name` was picked.
+    pub fn picks_of(&self, name: &str) -> usize {
+        self.picked.get(name).copied().unwrap_or(0)
+    }
+
+    /// How long picked users waited on average.
+    pub fn average_wait(&self) -> Option<Duration> {
+        if self.picks == 0 {
+            return None;
+        }
+        Some(Duration::seconds(self.waited / self.picks as i64))
+    }
+
+    /// Pick rate since the first join, `None` during the first minute.
+    pub fn picks_per_hour(&self) -> Option<f64> {
+        let elapsed = self.ended.unwrap_or_else(Utc::now) - self.since?;
+        if elapsed < Duration::minutes(1) {
+            return None;
+        }
+        Some(self.picks as f64 / (elapsed.num_seconds() as f64 / 3600.0))
+    }
+}
+
+/// A mod action that can be reverted, see `QueueManager::undo`.
+enum Undo {
+    /// Users were taken out of `queue`, along with the positions they had.
+    Removed {
+        queue: String,
+        entries: Vec<(usize, QueueEntry)>,
+    },
+    /// Users were picked from `queue`, replacing the `previous` session.
+    Picked {
+        queue: String,
+        entries: Vec<(usize, QueueEntry)>,
+        previous: Option<Session>,
+        at: DateTime<Utc>,
+    },
+    /// `queue` was reordered, these are the names in the order before.
+    Reordered { queue: String, order: Vec<String> },
 }
 
+/// What `QueueManager::undo` reverted.
+#[derive(Debug, PartialEq)]
+pub enum Undone {
+    /// These users were put back where they were.
+    Removed { queue: String, names: Vec<String> },
+    /// These users were put back and are no longer picked.
+    Picked { queue: String, names: Vec<String> },
+    /// The queue is back in its previous order.
+    Reordered { queue: String },
+}
+
+/// Why a queue operation was refused.
+#[derive(Debug, PartialEq)]
+pub enum QueueError {
+    /// The user was picked recently and has to wait this long before joining again.
+    Cooldown(Duration),
+    /// The user was already picked this many times during the stream.
+    PickLimit(usize),
+    AlreadyInQueue,
+    NotInQueue,
+    QueueFull,
+    QueueClosed,
+    /// The user is on the blocklist, see `QueueManager::block`.
+    Blocked,
+    /// The queue is in sub-only mode and the user isn't a subscriber.
+    SubOnly,
+    QueueEmpty,
+    NoSuchQueue,
+    /// A position outside of the queue was requested.
+    InvalidPosition,
+    NothingToUndo,
+}
+
+/// The kind of user joining, which decides their priority.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
 pub enum UserType {
+    #[default]
     Default,
     Subscriber,
+    Vip,
+    Moderator,
+}
+
+/// How far each kind of user gets to skip ahead, users are placed in front of
+/// everyone with a lower weight. All zeroes (the default) is a plain FIFO queue.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PriorityWeights {
+    pub default: u32,
+    pub subscriber: u32,
+    pub vip: u32,
+    pub moderator: u32,
+}
+
+impl PriorityWeights {
+    fn weight(&self, user_type: UserType) -> u32 {
+        match user_type {
+            UserType::Default => self.default,
+            UserType::Subscriber => self.subscriber,
+            UserType::Vip => self.vip,
+            UserType::Moderator => self.moderator,
+        }
+    }
+}
+
+impl Default for QueueManager {
+    fn default() -> Self {
+        let mut queues = BTreeMap::new();
+        queues.insert(DEFAULT_QUEUE.to_owned(), Queue::default());
+        QueueManager {
+            queues,
+            picked_at: HashMap::new(),
+            stats: QueueStats::default(),
+            blocked: BTreeSet::new(),
+            weights: PriorityWeights::default(),
+            max_size: None,
+            rejoin_cooldown: None,
+            pick_limit: None,
+            priority_decay: false,
+            strategy: Box::new(Priority),
+            history: VecDeque::new(),
+            storage: None,
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
 }
 
 impl QueueManager {
+    /// An empty, unsaved queue manager with only the default queue.
     pub fn new() -> QueueManager {
-        QueueManager { queue: Vec::new() }
+        QueueManager::default()
+    }
+
+    /// Restores the queues from a JSON file, which is kept up to date from then on.
+    pub fn persistent(path: PathBuf) -> io::Result<QueueManager> {
+        QueueManager::with_storage(Box::new(JsonFile::new(path)))
+    }
+
+    /// Restores the queues from `storage`, which is kept up to date from then on.
+    pub fn with_storage(storage: Box<dyn QueueStorage>) -> io::Result<QueueManager> {
+        let mut queue_manager = storage.load()?.unwrap_or_default();
+        queue_manager
+            .queues
+            .entry(DEFAULT_QUEUE.to_owned())
+            .or_default();
+        queue_manager.storage = Some(storage);
+        Ok(queue_manager)
+    }
+
+    pub fn set_priority_weights(&mut self, weights: PriorityWeights) {
+        self.weights = weights;
+    }
+
+    /// How queues are ordered and who `next` picks, `Priority` by default.
+    pub fn set_strategy(&mut self, strategy: Box<dyn QueueStrategy>) {
+        self.strategy = strategy;
+    }
+
+    /// Halve a user's priority weight for every time they already joined
+    /// during the stream, so tiers only skip ahead on their first join.
+    pub fn set_priority_decay(&mut self, priority_decay: bool) {
+        self.priority_decay = priority_decay;
     }
-    pub fn join(&mut self, name: String, user_type: UserType) -> Result<(), ()> {
-        self.queue.push(name);
+
+    /// Refuse joins once a queue holds this many users.
+    pub fn set_max_size(&mut self, max_size: Option<usize>) {
+        self.max_size = max_size;
+    }
+
+    /// How long a user picked by `next` has to wait before they can join again.
+    pub fn set_rejoin_cooldown(&mut self, cooldown: Option<Duration>) {
+        self.rejoin_cooldown = cooldown;
+    }
+
+    /// How often a user can be picked per stream, counted until `end_stream`.
+    pub fn set_pick_limit(&mut self, pick_limit: Option<usize>) {
+        self.pick_limit = pick_limit;
+    }
+
+    /// Receives every change made to the queues from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<QueueEvent> {
+        self.events.subscribe()
+    }
+
+    /// Opens `queue` for joining, creating it if it doesn't exist yet.
+    pub fn open(&mut self, queue: &str) {
+        self.queues.entry(queue.to_owned()).or_default().closed = false;
+        self.save();
+        self.emit(QueueEvent::Changed {
+            queue: queue.to_owned(),
+        });
+    }
+
+    /// Stops accepting new users, the ones already queued stay.
+    pub fn close(&mut self, queue: &str) -> Result<(), QueueError> {
+        self.get_mut(queue)?.closed = true;
+        self.save();
+        self.emit(QueueEvent::Changed {
+            queue: queue.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Lets only subscribers, VIPs and mods join `queue` while enabled.
+    pub fn set_sub_only(&mut self, queue: &str, sub_only: bool) -> Result<(), QueueError> {
+        self.get_mut(queue)?.sub_only = sub_only;
+        self.save();
+        self.emit(QueueEvent::Changed {
+            queue: queue.to_owned(),
+        });
         Ok(())
     }
-    pub fn queue(&self) -> &[String] {
-        self.queue.as_slice()
+
+    /// Keeps `name` from joining any queue and takes them out of the ones
+    /// they're in, returns whether they weren't blocked already.
+    pub fn block(&mut self, name: &str) -> bool {
+        if !self.blocked.insert(name.to_owned()) {
+            return false;
+        }
+        let left: Vec<String> = self
+            .queues
+            .iter_mut()
+            .filter_map(|(queue_name, queue)| {
+                queue.entries.remove(name).map(|_| queue_name.clone())
+            })
+            .collect();
+        self.save();
+        for queue in left {
+            self.emit(QueueEvent::Left {
+                queue,
+                name: name.to_owned(),
+            });
+        }
+        true
+    }
+
+    /// Lets `name` join again, returns whether they were blocked.
+    pub fn unblock(&mut self, name: &str) -> bool {
+        let unblocked = self.blocked.remove(name);
+        if unblocked {
+            self.save();
+        }
+        unblocked
+    }
+
+    pub fn is_blocked(&self, name: &str) -> bool {
+        self.blocked.contains(name)
+    }
+
+    /// Adds a user to the queue, ahead of everyone with a lower priority weight.
+    pub fn join(
+        &mut self,
+        queue: &str,
+        name: String,
+        user_type: UserType,
+        note: Option<String>,
+    ) -> Result<(), QueueError> {
+        if self.blocked.contains(&name) {
+            return Err(QueueError::Blocked);
+        }
+        if let Some(remaining) = self.cooldown_remaining(&name) {
+            return Err(QueueError::Cooldown(remaining));
+        }
+        let picks = self.stats.picks_of(&name);
+        if matches!(self.pick_limit, Some(limit) if picks >= limit) {
+            return Err(QueueError::PickLimit(picks));
+        }
+        let max_size = self.max_size;
+        let weights = self.weights.clone();
+        let mut weight = weights.weight(user_type);
+        if self.priority_decay {
+            weight = weight
+                .checked_shr(self.stats.joins_of(&name) as u32)
+                .unwrap_or(0);
+        }
+        let joined_at = Utc::now();
+        {
+            let queue = self.queues.get_mut(queue).ok_or(QueueError::NoSuchQueue)?;
+            if queue.closed {
+                return Err(QueueError::QueueClosed);
+            }
+            if queue.sub_only && user_type == UserType::Default {
+                return Err(QueueError::SubOnly);
+            }
+            if queue.entries.contains(&name) {
+                return Err(QueueError::AlreadyInQueue);
+            }
+            if matches!(max_size, Some(max_size) if queue.entries.len() >= max_size) {
+                return Err(QueueError::QueueFull);
+            }
+            let joining = Candidate {
+                name: &name,
+                user_type,
+                weight,
+                joined_at,
+            };
+            let index = self
+                .strategy
+                .insert_at(&queue.candidates(&weights), &joining);
+            queue.entries.insert(
+                index,
+                QueueEntry {
+                    name: name.clone(),
+                    user_type,
+                    joined_at,
+                    note,
+                    weight: Some(weight),
+                },
+            );
+        }
+        self.stats.record_join(&name);
+        self.save();
+        self.emit(QueueEvent::Joined {
+            queue: queue.to_owned(),
+            name,
+        });
+        Ok(())
+    }
+
+    /// Takes a user out of the queue without picking them, can be undone.
+    pub fn remove(&mut self, queue: &str, name: &str) -> Result<(), QueueError> {
+        let (index, entry) = {
+            let queue = self.get_mut(queue)?;
+            let index = queue.position(name).ok_or(QueueError::NotInQueue)?;
+            (index, queue.entries.remove(name).unwrap())
+        };
+        self.remember(Undo::Removed {
+            queue: queue.to_owned(),
+            entries: vec![(index, entry)],
+        });
+        self.save();
+        self.emit(QueueEvent::Left {
+            queue: queue.to_owned(),
+            name: name.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// A user taking themselves out of the queue.
+    pub fn leave(&mut self, queue: &str, name: &str) -> Result<(), QueueError> {
+        {
+            let queue = self.get_mut(queue)?;
+            queue.entries.remove(name).ok_or(QueueError::NotInQueue)?;
+        }
+        self.save();
+        self.emit(QueueEvent::Left {
+            queue: queue.to_owned(),
+            name: name.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Removes and returns the user chosen by the strategy, the one at the
+    /// front of the queue unless configured otherwise.
+    pub fn next(&mut self, queue: &str) -> Result<Pick, QueueError> {
+        let previous = (self.queues.get(queue))
+            .and_then(|queue| queue.current.as_ref())
+            .map(|session| session.user_type);
+        let index = self.strategy_pick(queue, previous)?;
+        self.take(queue, index, true)
+    }
+
+    /// Index of the user the strategy picks in `queue` after a `previous`
+    /// kind of user.
+    fn strategy_pick(&self, queue: &str, previous: Option<UserType>) -> Result<usize, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        if queue.entries.is_empty() {
+            return Err(QueueError::QueueEmpty);
+        }
+        Ok(self
+            .strategy
+            .pick(&queue.candidates(&self.weights), previous))
+    }
+
+    /// Removes up to `count` users at once, in the order `next` would pick
+    /// them, e.g. to fill a multiplayer lobby. The group counts as a single
+    /// turn, which doesn't need to be confirmed with `!here`.
+    pub fn next_group(&mut self, queue: &str, count: usize) -> Result<Vec<Pick>, QueueError> {
+        let now = Utc::now();
+        let group = {
+            let queue = self.queues.get_mut(queue).ok_or(QueueError::NoSuchQueue)?;
+            if queue.entries.is_empty() {
+                return Err(QueueError::QueueEmpty);
+            }
+            let mut previous = queue.current.as_ref().map(|session| session.user_type);
+            let mut group = Vec::new();
+            while group.len() < count && !queue.entries.is_empty() {
+                let index = self
+                    .strategy
+                    .pick(&queue.candidates(&self.weights), previous);
+                let entry = queue.entries.remove_at(index).unwrap();
+                previous = Some(entry.user_type);
+                group.push((index, entry));
+            }
+            let (_, last) = group.last().unwrap();
+            let session = Session {
+                name: last.name.clone(),
+                started: now,
+                user_type: last.user_type,
+                confirmed: true,
+                waited: (now - last.joined_at).num_seconds(),
+            };
+            (group, queue.start_session(session))
+        };
+        let (group, previous) = group;
+        for (_, entry) in &group {
+            self.picked_at.insert(entry.name.clone(), now);
+            self.stats.record_pick(&entry.name, now - entry.joined_at);
+        }
+        let picks: Vec<Pick> = group
+            .iter()
+            .map(|(_, entry)| Pick {
+                name: entry.name.clone(),
+                note: entry.note.clone(),
+            })
+            .collect();
+        self.remember(Undo::Picked {
+            queue: queue.to_owned(),
+            entries: group,
+            previous,
+            at: now,
+        });
+        self.save();
+        self.emit(QueueEvent::PickedGroup {
+            queue: queue.to_owned(),
+            picks: picks.clone(),
+        });
+        Ok(picks)
+    }
+
+    /// Picks a random user instead of the one at the front of the queue.
+    pub fn pick_random(
+        &mut self,
+        queue: &str,
+        weighting: RandomWeighting,
+    ) -> Result<Pick, QueueError> {
+        self.pick_random_with(queue, weighting, &mut rand::thread_rng())
+    }
+
+    fn pick_random_with(
+        &mut self,
+        queue: &str,
+        weighting: RandomWeighting,
+        rng: &mut impl Rng,
+    ) -> Result<Pick, QueueError> {
+        let index = {
+            let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+            if queue.entries.is_empty() {
+                return Err(QueueError::QueueEmpty);
+            }
+            strategy::random_index(&queue.candidates(&self.weights), weighting, rng)
+        };
+        self.take(queue, index, true)
+    }
+
+    /// Takes the user at `index` out of the queue and makes them the current
+    /// one, mod picks are `undoable`.
+    fn take(&mut self, queue: &str, index: usize, undoable: bool) -> Result<Pick, QueueError> {
+        let now = Utc::now();
+        let (next, previous) = {
+            let queue = self.get_mut(queue)?;
+            if index >= queue.entries.len() {
+                return Err(QueueError::QueueEmpty);
+            }
+            let next = queue.entries.remove_at(index).unwrap();
+            let previous = queue.start_session(Session {
+                name: next.name.clone(),
+                started: now,
+                user_type: next.user_type,
+                confirmed: false,
+                waited: (now - next.joined_at).num_seconds(),
+            });
+            (next, previous)
+        };
+        self.picked_at.insert(next.name.clone(), now);
+        self.stats.record_pick(&next.name, now - next.joined_at);
+        let pick = Pick {
+            name: next.name.clone(),
+            note: next.note.clone(),
+        };
+        if undoable {
+            self.remember(Undo::Picked {
+                queue: queue.to_owned(),
+                entries: vec![(index, next)],
+                previous,
+                at: now,
+            });
+        }
+        self.save();
+        self.emit(QueueEvent::Picked {
+            queue: queue.to_owned(),
+            pick: pick.clone(),
+        });
+        Ok(pick)
+    }
+
+    /// Marks `name` as present wherever they're the current user, returns
+    /// whether they were waiting to confirm anywhere.
+    pub fn confirm(&mut self, name: &str) -> bool {
+        let mut confirmed = false;
+        for queue in self.queues.values_mut() {
+            if let Some(session) = &mut queue.current {
+                if session.name == name && !session.confirmed {
+                    session.confirmed = true;
+                    confirmed = true;
+                }
+            }
+        }
+        if confirmed {
+            self.save();
+        }
+        confirmed
+    }
+
+    /// Picks the next user in every queue whose current user hasn't confirmed
+    /// within `timeout`. The skipped users can be put back at the end.
+    pub fn skip_unresponsive(&mut self, timeout: Duration, requeue: bool) -> Vec<Skipped> {
+        let now = Utc::now();
+        let expired: Vec<String> = self
+            .queues
+            .iter()
+            .filter(|(_, queue)| {
+                matches!(&queue.current, Some(session)
+                    if !session.confirmed && now - session.started >= timeout)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut skipped = Vec::new();
+        for queue in expired {
+            let session = self.get_mut(&queue).unwrap().current.take().unwrap();
+            // they never got their turn, so no cooldown and no pick
+            self.picked_at.remove(&session.name);
+            self.stats
+                .unrecord_pick(&session.name, Duration::seconds(session.waited));
+            // picked the way `next` would have after their turn
+            let next = self
+                .strategy_pick(&queue, Some(session.user_type))
+                .and_then(|index| self.take(&queue, index, false))
+                .ok();
+            if requeue {
+                self.get_mut(&queue).unwrap().entries.push(QueueEntry {
+                    name: session.name.clone(),
+                    user_type: session.user_type,
+                    joined_at: now,
+                    note: None,
+                    // at the back means behind everyone joining later too
+                    weight: Some(0),
+                });
+            }
+            self.save();
+            self.emit(QueueEvent::Changed {
+                queue: queue.clone(),
+            });
+            skipped.push(Skipped {
+                queue,
+                name: session.name,
+                requeued: requeue,
+                next,
+            });
+        }
+        skipped
+    }
+
+    /// Lets two users in the queue trade places.
+    pub fn swap(&mut self, queue: &str, a: &str, b: &str) -> Result<(), QueueError> {
+        let order = {
+            let queue = self.get_mut(queue)?;
+            let a = queue.position(a).ok_or(QueueError::NotInQueue)?;
+            let b = queue.position(b).ok_or(QueueError::NotInQueue)?;
+            let order = queue.order();
+            queue.entries.swap_indices(a, b);
+            order
+        };
+        self.remember(Undo::Reordered {
+            queue: queue.to_owned(),
+            order,
+        });
+        self.save();
+        self.emit(QueueEvent::Changed {
+            queue: queue.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Moves a user to `index` (0 being next up), shifting everyone in between.
+    pub fn move_to(&mut self, queue: &str, name: &str, index: usize) -> Result<(), QueueError> {
+        let order = self
+            .queues
+            .get(queue)
+            .ok_or(QueueError::NoSuchQueue)?
+            .order();
+        self.move_entry(queue, name, index)?;
+        self.remember(Undo::Reordered {
+            queue: queue.to_owned(),
+            order,
+        });
+        Ok(())
+    }
+
+    fn move_entry(&mut self, queue: &str, name: &str, index: usize) -> Result<(), QueueError> {
+        {
+            let queue = self.get_mut(queue)?;
+            let from = queue.position(name).ok_or(QueueError::NotInQueue)?;
+            if index >= queue.entries.len() {
+                return Err(QueueError::InvalidPosition);
+            }
+            queue.entries.move_index(from, index);
+        }
+        self.save();
+        self.emit(QueueEvent::Changed {
+            queue: queue.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Moves a user up to `places` spots closer to the front, returning their
+    /// new zero-based position.
+    pub fn bump(&mut self, queue: &str, name: &str, places: usize) -> Result<usize, QueueError> {
+        let from = self
+            .queues
+            .get(queue)
+            .ok_or(QueueError::NoSuchQueue)?
+            .position(name)
+            .ok_or(QueueError::NotInQueue)?;
+        let to = from.saturating_sub(places);
+        self.move_entry(queue, name, to)?;
+        Ok(to)
+    }
+
+    /// Puts the queue in random order, ignoring priorities.
+    pub fn shuffle(&mut self, queue: &str) -> Result<(), QueueError> {
+        let order = {
+            let queue = self.get_mut(queue)?;
+            let order = queue.order();
+            queue.entries.shuffle(&mut rand::thread_rng());
+            order
+        };
+        self.remember(Undo::Reordered {
+            queue: queue.to_owned(),
+            order,
+        });
+        self.save();
+        self.emit(QueueEvent::Changed {
+            queue: queue.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Zero-based index of `name` in the queue, i.e. how many users are ahead of them.
+    pub fn position(&self, queue: &str, name: &str) -> Option<usize> {
+        self.queues.get(queue)?.position(name)
+    }
+
+    /// Rough estimate of how long `name` still has to wait, based on the
+    /// average length of recent sessions. `None` until there is some history.
+    pub fn estimated_wait(&self, queue: &str, name: &str) -> Option<Duration> {
+        let queue = self.queues.get(queue)?;
+        let ahead = queue.position(name)? as i64;
+        let average = Duration::seconds(
+            queue.session_lengths.iter().sum::<i64>() / queue.session_lengths.len().max(1) as i64,
+        );
+        if average.is_zero() {
+            return None;
+        }
+        let current_remaining = match &queue.current {
+            Some(session) => (average - (Utc::now() - session.started)).max(Duration::zero()),
+            None => Duration::zero(),
+        };
+        Some(average * ahead as i32 + current_remaining)
+    }
+
+    /// The user picked last and how long ago that was.
+    pub fn current(&self, queue: &str) -> Result<Option<(&str, Duration)>, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        Ok(queue
+            .current
+            .as_ref()
+            .map(|session| (session.name.as_str(), Utc::now() - session.started)))
+    }
+
+    /// Names of all queues, in alphabetical order.
+    pub fn queue_names(&self) -> impl Iterator<Item = &str> {
+        self.queues.keys().map(String::as_str)
+    }
+
+    pub fn is_closed(&self, queue: &str) -> Result<bool, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        Ok(queue.closed)
+    }
+
+    pub fn is_sub_only(&self, queue: &str) -> Result<bool, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        Ok(queue.sub_only)
+    }
+
+    /// Users in the queue along with the notes they joined with.
+    pub fn entries(&self, queue: &str) -> Result<Vec<(&str, Option<&str>)>, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        Ok(queue
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.note.as_deref()))
+            .collect())
+    }
+
+    /// Everything known about the users in the queue, in queue order.
+    pub fn export(&self, queue: &str) -> Result<Vec<ExportedEntry<'_>>, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        Ok(queue
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| ExportedEntry {
+                position: i + 1,
+                name: &entry.name,
+                user_type: entry.user_type,
+                joined_at: entry.joined_at,
+                note: entry.note.as_deref(),
+            })
+            .collect())
+    }
+
+    /// Users in the queue, next up first.
+    pub fn queue(&self, queue: &str) -> Result<Vec<&str>, QueueError> {
+        let queue = self.queues.get(queue).ok_or(QueueError::NoSuchQueue)?;
+        Ok(queue.entries.names().collect())
+    }
+
+    /// Activity since the stream started, see `end_stream`.
+    pub fn stats(&self) -> &QueueStats {
+        &self.stats
+    }
+
+    /// Empties every queue and starts fresh stats, returning the ones for the
+    /// stream that just ended.
+    pub fn end_stream(&mut self) -> QueueStats {
+        for queue in self.queues.values_mut() {
+            queue.entries.clear();
+            queue.current = None;
+        }
+        self.history.clear();
+        let mut stats = std::mem::take(&mut self.stats);
+        stats.ended = Some(Utc::now());
+        self.save();
+        self.emit(QueueEvent::Cleared);
+        stats
+    }
+
+    /// Reverts the most recent `remove`, pick, `swap`, `move_to` or
+    /// `shuffle` that wasn't undone yet. Users who joined since keep their
+    /// spots.
+    pub fn undo(&mut self) -> Result<Undone, QueueError> {
+        let undo = self.history.pop_back().ok_or(QueueError::NothingToUndo)?;
+        let undone = match undo {
+            Undo::Removed { queue, entries } => {
+                let names = self.get_mut(&queue)?.restore(entries);
+                Undone::Removed { queue, names }
+            }
+            Undo::Picked {
+                queue,
+                entries,
+                previous,
+                at,
+            } => {
+                for (_, entry) in &entries {
+                    self.picked_at.remove(&entry.name);
+                    self.stats.unrecord_pick(&entry.name, at - entry.joined_at);
+                }
+                let names = {
+                    let queue = self.get_mut(&queue)?;
+                    if previous.is_some() {
+                        queue.session_lengths.pop_back();
+                    }
+                    queue.current = previous;
+                    queue.restore(entries)
+                };
+                Undone::Picked { queue, names }
+            }
+            Undo::Reordered { queue, order } => {
+                self.get_mut(&queue)?
+                    .entries
+                    // users who joined since go to the back, in the order they're in now
+                    .sort_by_key(|entry| {
+                        (order.iter())
+                            .position(|name| *name == entry.name)
+                            .unwrap_or(order.len())
+                    });
+                Undone::Reordered { queue }
+            }
+        };
+        let queue = match &undone {
+            Undone::Removed { queue, .. }
+            | Undone::Picked { queue, .. }
+            | Undone::Reordered { queue } => queue.clone(),
+        };
+        self.save();
+        self.emit(QueueEvent::Changed { queue });
+        Ok(undone)
+    }
+
+    fn remember(&mut self, undo: Undo) {
+        self.history.push_back(undo);
+        if self.history.len() > UNDO_HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    fn cooldown_remaining(&mut self, name: &str) -> Option<Duration> {
+        let picked_at = *self.picked_at.get(name)?;
+        let remaining = self.rejoin_cooldown? - (Utc::now() - picked_at);
+        if remaining > Duration::zero() {
+            Some(remaining)
+        } else {
+            self.picked_at.remove(name);
+            None
+        }
+    }
+
+    fn get_mut(&mut self, queue: &str) -> Result<&mut Queue, QueueError> {
+        self.queues.get_mut(queue).ok_or(QueueError::NoSuchQueue)
+    }
+
+    fn emit(&self, event: QueueEvent) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    fn save(&self) {
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.save(self) {
+                error!("Failed to save queue to {}: {}", storage.describe(), e);
+            }
+        }
     }
 }
 
+impl Queue {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries.position(name)
+    }
+
+    /// Ends the current session, if any, and remembers how long it took.
+    /// Returns the ended session.
+    fn start_session(&mut self, session: Session) -> Option<Session> {
+        let started = session.started;
+        let previous = self.current.replace(session)?;
+        self.session_lengths
+            .push_back((started - previous.started).num_seconds());
+        if self.session_lengths.len() > SESSION_HISTORY {
+            self.session_lengths.pop_front();
+        }
+        Some(previous)
+    }
+
+    fn order(&self) -> Vec<String> {
+        self.entries.names().map(str::to_owned).collect()
+    }
+
+    /// Puts taken out users back at their old positions, the ones who
+    /// rejoined in the meantime stay where they are now.
+    fn restore(&mut self, entries: Vec<(usize, QueueEntry)>) -> Vec<String> {
+        let mut names = Vec::new();
+        // in reverse so every position is the one the user had right before
+        // they were taken out
+        for (index, entry) in entries.into_iter().rev() {
+            if self.entries.contains(&entry.name) {
+                continue;
+            }
+            names.push(entry.name.clone());
+            self.entries.insert(index, entry);
+        }
+        names.reverse();
+        names
+    }
+
+    fn candidates(&self, weights: &PriorityWeights) -> Vec<Candidate<'_>> {
+        self.entries
+            .iter()
+            .map(|entry| Candidate {
+                name: &entry.name,
+                user_type: entry.user_type,
+                weight: entry.weight(weights),
+                joined_at: entry.joined_at,
+            })
+            .collect()
+    }
+}
+
+/// The state saved by earlier versions, with a single queue of names or, once
+/// priorities were added, of entries.
+#[derive(Deserialize)]
+struct LegacySave {
+    queue: Vec<LegacyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyEntry {
+    Name(String),
+    Entry(QueueEntry),
+}
+
+/// Restores a state saved as JSON, in the current format or a legacy one.
+fn from_json(saved: &str) -> serde_json::Result<QueueManager> {
+    let value: serde_json::Value = serde_json::from_str(saved)?;
+    if value.get("queues").is_some() || value.get("queue").is_none() {
+        return serde_json::from_value(value);
+    }
+    let legacy: LegacySave = serde_json::from_value(value)?;
+    let mut queue = Queue::default();
+    for entry in legacy.queue {
+        let entry = match entry {
+            LegacyEntry::Name(name) => QueueEntry {
+                name,
+                user_type: UserType::Default,
+                joined_at: Utc::now(),
+                note: None,
+                weight: None,
+            },
+            LegacyEntry::Entry(entry) => entry,
+        };
+        queue.entries.push(entry);
+    }
+    let mut queue_manager = QueueManager::new();
+    queue_manager.queues.insert(DEFAULT_QUEUE.to_owned(), queue);
+    Ok(queue_manager)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::{thread_rng, Rng, distributions::Alphanumeric};
+    use rand::{distributions::Alphanumeric, thread_rng, Rng};
     fn gen_random_user() -> String {
         let rng = thread_rng();
 
@@ -33,6 +1175,10 @@ mod tests {
             .collect()
     }
 
+    fn join(queue_man: &mut QueueManager, name: &str) -> Result<(), QueueError> {
+        queue_man.join(DEFAULT_QUEUE, name.to_owned(), UserType::Default, None)
+    }
+
     #[test]
     fn test_queue() {
         let mut users = vec![];
@@ -40,10 +1186,902 @@ mod tests {
         for _ in 0..3 {
             let random_user = gen_random_user();
             assert!(queue_man
-                .join(random_user.clone(), UserType::Default)
+                .join(DEFAULT_QUEUE, random_user.clone(), UserType::Default, None)
                 .is_ok());
             users.push(random_user);
         }
-        assert_eq!(queue_man.queue(), users.as_slice());
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), users.as_slice());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut queue_man = QueueManager::new();
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        assert_eq!(
+            join(&mut queue_man, "first"),
+            Err(QueueError::AlreadyInQueue)
+        );
+
+        assert!(queue_man.remove(DEFAULT_QUEUE, "first").is_ok());
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["second"]);
+        assert_eq!(
+            queue_man.remove(DEFAULT_QUEUE, "first"),
+            Err(QueueError::NotInQueue)
+        );
+    }
+
+    #[test]
+    fn test_next() {
+        let mut queue_man = QueueManager::new();
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+
+        assert_eq!(
+            queue_man
+                .next(DEFAULT_QUEUE)
+                .map(|pick| pick.name)
+                .as_deref(),
+            Ok("first")
+        );
+        assert_eq!(
+            queue_man
+                .next(DEFAULT_QUEUE)
+                .map(|pick| pick.name)
+                .as_deref(),
+            Ok("second")
+        );
+        assert_eq!(queue_man.next(DEFAULT_QUEUE), Err(QueueError::QueueEmpty));
+    }
+
+    #[test]
+    fn test_join_note() {
+        let mut queue_man = QueueManager::new();
+        queue_man
+            .join(
+                DEFAULT_QUEUE,
+                "first".to_owned(),
+                UserType::Default,
+                Some("help with lifetimes".to_owned()),
+            )
+            .unwrap();
+        join(&mut queue_man, "second").unwrap();
+
+        assert_eq!(
+            queue_man.next(DEFAULT_QUEUE),
+            Ok(Pick {
+                name: "first".to_owned(),
+                note: Some("help with lifetimes".to_owned()),
+            })
+        );
+        assert_eq!(queue_man.next(DEFAULT_QUEUE).unwrap().note, None);
+    }
+
+    #[test]
+    fn test_pick_random() {
+        let mut queue_man = QueueManager::new();
+        for name in &["first", "second", "third"] {
+            join(&mut queue_man, name).unwrap();
+        }
+
+        let mut picked = vec![];
+        for _ in 0..3 {
+            let pick = queue_man
+                .pick_random(DEFAULT_QUEUE, RandomWeighting::Uniform)
+                .unwrap();
+            picked.push(pick.name);
+        }
+        picked.sort();
+        assert_eq!(picked, ["first", "second", "third"]);
+        assert_eq!(
+            queue_man.pick_random(DEFAULT_QUEUE, RandomWeighting::Uniform),
+            Err(QueueError::QueueEmpty)
+        );
+        assert_eq!(queue_man.stats().picks(), 3);
+    }
+
+    #[test]
+    fn test_weighted_pick_random() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut queue_man = QueueManager::new();
+        queue_man.set_priority_weights(PriorityWeights {
+            moderator: 100_000,
+            ..PriorityWeights::default()
+        });
+
+        for _ in 0..20 {
+            join(&mut queue_man, "viewer").unwrap();
+            queue_man
+                .join(DEFAULT_QUEUE, "mod".to_owned(), UserType::Moderator, None)
+                .unwrap();
+            let pick = queue_man
+                .pick_random_with(DEFAULT_QUEUE, RandomWeighting::UserType, &mut rng)
+                .unwrap();
+            assert_eq!(pick.name, "mod");
+            queue_man.end_stream();
+        }
+
+        for _ in 0..20 {
+            join(&mut queue_man, "new").unwrap();
+            join(&mut queue_man, "old").unwrap();
+            queue_man
+                .queues
+                .get_mut(DEFAULT_QUEUE)
+                .unwrap()
+                .entries
+                .get_mut("old")
+                .unwrap()
+                .joined_at = Utc::now() - Duration::days(7);
+            let pick = queue_man
+                .pick_random_with(DEFAULT_QUEUE, RandomWeighting::WaitTime, &mut rng)
+                .unwrap();
+            assert_eq!(pick.name, "old");
+            queue_man.end_stream();
+        }
+    }
+
+    #[test]
+    fn test_position() {
+        let mut queue_man = QueueManager::new();
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+
+        assert_eq!(queue_man.position(DEFAULT_QUEUE, "first"), Some(0));
+        assert_eq!(queue_man.position(DEFAULT_QUEUE, "second"), Some(1));
+        assert_eq!(queue_man.position(DEFAULT_QUEUE, "third"), None);
+
+        queue_man.remove(DEFAULT_QUEUE, "first").unwrap();
+        assert_eq!(queue_man.position(DEFAULT_QUEUE, "second"), Some(0));
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_priority_weights(PriorityWeights {
+            default: 0,
+            subscriber: 1,
+            vip: 1,
+            moderator: 2,
+        });
+
+        let users = [
+            ("viewer", UserType::Default),
+            ("sub", UserType::Subscriber),
+            ("vip", UserType::Vip),
+            ("viewer2", UserType::Default),
+            ("mod", UserType::Moderator),
+        ];
+        for (name, user_type) in users {
+            queue_man
+                .join(DEFAULT_QUEUE, name.to_owned(), user_type, None)
+                .unwrap();
+        }
+
+        // higher tiers go first, same tiers keep their join order
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["mod", "sub", "vip", "viewer", "viewer2"]
+        );
+    }
+
+    #[test]
+    fn test_priority_decay() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_priority_weights(PriorityWeights {
+            subscriber: 2,
+            ..PriorityWeights::default()
+        });
+        queue_man.set_priority_decay(true);
+        let sub = |queue_man: &mut QueueManager| {
+            queue_man
+                .join(DEFAULT_QUEUE, "sub".to_owned(), UserType::Subscriber, None)
+                .unwrap();
+        };
+
+        join(&mut queue_man, "viewer").unwrap();
+        sub(&mut queue_man);
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["sub", "viewer"]);
+
+        // second join at half the weight still beats regular viewers
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        sub(&mut queue_man);
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["sub", "viewer"]);
+
+        // after that they queue up like everyone else
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        join(&mut queue_man, "viewer2").unwrap();
+        sub(&mut queue_man);
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["viewer", "viewer2", "sub"]
+        );
+
+        queue_man.end_stream();
+        join(&mut queue_man, "viewer").unwrap();
+        sub(&mut queue_man);
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["sub", "viewer"]);
+    }
+
+    #[test]
+    fn test_without_priority_is_fifo() {
+        let mut queue_man = QueueManager::new();
+        let users = [
+            ("viewer", UserType::Default),
+            ("mod", UserType::Moderator),
+            ("sub", UserType::Subscriber),
+        ];
+        for (name, user_type) in users {
+            queue_man
+                .join(DEFAULT_QUEUE, name.to_owned(), user_type, None)
+                .unwrap();
+        }
+
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["viewer", "mod", "sub"]
+        );
+    }
+
+    #[test]
+    fn test_round_robin_strategy() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_strategy(StrategyKind::RoundRobin.build(RandomWeighting::Uniform));
+        let users = [
+            ("viewer", UserType::Default),
+            ("viewer2", UserType::Default),
+            ("sub", UserType::Subscriber),
+            ("sub2", UserType::Subscriber),
+            ("mod", UserType::Moderator),
+        ];
+        for (name, user_type) in users {
+            queue_man
+                .join(DEFAULT_QUEUE, name.to_owned(), user_type, None)
+                .unwrap();
+        }
+        // round robin keeps join order in the queue
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["viewer", "viewer2", "sub", "sub2", "mod"]
+        );
+
+        let picks: Vec<String> = (0..5)
+            .map(|_| queue_man.next(DEFAULT_QUEUE).unwrap().name)
+            .collect();
+        assert_eq!(picks, ["mod", "sub", "viewer", "sub2", "viewer2"]);
+    }
+
+    #[test]
+    fn test_next_group() {
+        let mut queue_man = QueueManager::new();
+        let mut events = queue_man.subscribe();
+        assert_eq!(
+            queue_man.next_group(DEFAULT_QUEUE, 4),
+            Err(QueueError::QueueEmpty)
+        );
+        for name in ["first", "second", "third", "fourth", "fifth"] {
+            join(&mut queue_man, name).unwrap();
+        }
+        while events.try_recv().is_ok() {}
+
+        let names =
+            |picks: Vec<Pick>| -> Vec<String> { picks.into_iter().map(|pick| pick.name).collect() };
+        let group = queue_man.next_group(DEFAULT_QUEUE, 3).unwrap();
+        assert_eq!(names(group.clone()), ["first", "second", "third"]);
+        assert_eq!(
+            events.try_recv(),
+            Ok(QueueEvent::PickedGroup {
+                queue: DEFAULT_QUEUE.to_owned(),
+                picks: group,
+            })
+        );
+        assert!(events.try_recv().is_err());
+        assert_eq!(queue_man.stats().picks(), 3);
+        // the group doesn't have to confirm
+        assert!(queue_man
+            .skip_unresponsive(Duration::zero(), false)
+            .is_empty());
+
+        // fewer users than asked for
+        let group = queue_man.next_group(DEFAULT_QUEUE, 3).unwrap();
+        assert_eq!(names(group), ["fourth", "fifth"]);
+        assert!(queue_man.queue(DEFAULT_QUEUE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut queue_man = QueueManager::new();
+        assert_eq!(queue_man.undo(), Err(QueueError::NothingToUndo));
+        for name in ["first", "second", "third", "fourth"] {
+            join(&mut queue_man, name).unwrap();
+        }
+
+        queue_man.remove(DEFAULT_QUEUE, "second").unwrap();
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        queue_man.swap(DEFAULT_QUEUE, "third", "fourth").unwrap();
+        // joins and leaves aren't mod actions, undo skips past them
+        join(&mut queue_man, "fifth").unwrap();
+        queue_man.leave(DEFAULT_QUEUE, "fifth").unwrap();
+        join(&mut queue_man, "sixth").unwrap();
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["fourth", "third", "sixth"]
+        );
+
+        assert_eq!(
+            queue_man.undo(),
+            Ok(Undone::Reordered {
+                queue: DEFAULT_QUEUE.to_owned()
+            })
+        );
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["third", "fourth", "sixth"]
+        );
+
+        assert_eq!(
+            queue_man.undo(),
+            Ok(Undone::Picked {
+                queue: DEFAULT_QUEUE.to_owned(),
+                names: vec!["first".to_owned()]
+            })
+        );
+        assert_eq!(queue_man.current(DEFAULT_QUEUE).unwrap(), None);
+        assert_eq!(queue_man.stats().picks(), 0);
+
+        assert_eq!(
+            queue_man.undo(),
+            Ok(Undone::Removed {
+                queue: DEFAULT_QUEUE.to_owned(),
+                names: vec!["second".to_owned()]
+            })
+        );
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            ["first", "second", "third", "fourth", "sixth"]
+        );
+        assert_eq!(queue_man.undo(), Err(QueueError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_undo_group() {
+        let mut queue_man = QueueManager::new();
+        for name in ["first", "second", "third"] {
+            join(&mut queue_man, name).unwrap();
+        }
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        queue_man.next_group(DEFAULT_QUEUE, 2).unwrap();
+        queue_man.undo().unwrap();
+
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["second", "third"]);
+        assert_eq!(
+            queue_man.current(DEFAULT_QUEUE).unwrap().unwrap().0,
+            "first"
+        );
+    }
+
+    #[test]
+    fn test_max_size() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_max_size(Some(2));
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        assert_eq!(join(&mut queue_man, "third"), Err(QueueError::QueueFull));
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["first", "second"]);
+
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        assert!(join(&mut queue_man, "third").is_ok());
+    }
+
+    #[test]
+    fn test_open_close() {
+        let mut queue_man = QueueManager::new();
+        join(&mut queue_man, "first").unwrap();
+        queue_man.close(DEFAULT_QUEUE).unwrap();
+        assert_eq!(join(&mut queue_man, "second"), Err(QueueError::QueueClosed));
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["first"]);
+
+        queue_man.open(DEFAULT_QUEUE);
+        assert!(join(&mut queue_man, "second").is_ok());
+    }
+
+    #[test]
+    fn test_sub_only() {
+        let mut queue_man = QueueManager::new();
+        assert_eq!(
+            queue_man.set_sub_only("review", true),
+            Err(QueueError::NoSuchQueue)
+        );
+        queue_man.set_sub_only(DEFAULT_QUEUE, true).unwrap();
+        assert!(queue_man.is_sub_only(DEFAULT_QUEUE).unwrap());
+
+        assert_eq!(join(&mut queue_man, "viewer"), Err(QueueError::SubOnly));
+        for (name, user_type) in [
+            ("sub", UserType::Subscriber),
+            ("vip", UserType::Vip),
+            ("mod", UserType::Moderator),
+        ] {
+            assert!(queue_man
+                .join(DEFAULT_QUEUE, name.to_owned(), user_type, None)
+                .is_ok());
+        }
+
+        queue_man.set_sub_only(DEFAULT_QUEUE, false).unwrap();
+        assert!(join(&mut queue_man, "viewer").is_ok());
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let storage = Memory::default();
+        let mut queue_man = QueueManager::with_storage(Box::new(storage.clone())).unwrap();
+        queue_man.open("review");
+        join(&mut queue_man, "troll").unwrap();
+        queue_man
+            .join("review", "troll".to_owned(), UserType::Default, None)
+            .unwrap();
+
+        assert!(queue_man.block("troll"));
+        assert!(!queue_man.block("troll"));
+        assert!(queue_man.queue(DEFAULT_QUEUE).unwrap().is_empty());
+        assert!(queue_man.queue("review").unwrap().is_empty());
+        assert_eq!(join(&mut queue_man, "troll"), Err(QueueError::Blocked));
+
+        let restored = QueueManager::with_storage(Box::new(storage.clone())).unwrap();
+        assert!(restored.is_blocked("troll"));
+
+        assert!(queue_man.unblock("troll"));
+        assert!(!queue_man.unblock("troll"));
+        assert!(join(&mut queue_man, "troll").is_ok());
+    }
+
+    #[test]
+    fn test_named_queues() {
+        let mut queue_man = QueueManager::new();
+        assert_eq!(
+            queue_man.join("review", "first".to_owned(), UserType::Default, None),
+            Err(QueueError::NoSuchQueue)
+        );
+
+        queue_man.open("review");
+        queue_man
+            .join("review", "first".to_owned(), UserType::Default, None)
+            .unwrap();
+        join(&mut queue_man, "second").unwrap();
+        // the same user can wait in several queues at once
+        join(&mut queue_man, "first").unwrap();
+
+        assert_eq!(queue_man.queue("review").unwrap(), ["first"]);
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["second", "first"]);
+
+        assert_eq!(
+            queue_man.next("review").map(|pick| pick.name).as_deref(),
+            Ok("first")
+        );
+        assert_eq!(queue_man.position(DEFAULT_QUEUE, "first"), Some(1));
+    }
+
+    #[test]
+    fn test_rejoin_cooldown() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_rejoin_cooldown(Some(Duration::minutes(10)));
+        join(&mut queue_man, "first").unwrap();
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+
+        assert!(matches!(
+            join(&mut queue_man, "first"),
+            Err(QueueError::Cooldown(remaining)) if remaining > Duration::minutes(9)
+        ));
+        // users who left on their own aren't affected
+        join(&mut queue_man, "second").unwrap();
+        queue_man.remove(DEFAULT_QUEUE, "second").unwrap();
+        assert!(join(&mut queue_man, "second").is_ok());
+
+        queue_man
+            .picked_at
+            .insert("first".to_owned(), Utc::now() - Duration::minutes(11));
+        assert!(join(&mut queue_man, "first").is_ok());
+        assert!(queue_man.picked_at.is_empty());
+    }
+
+    #[test]
+    fn test_pick_limit() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_pick_limit(Some(2));
+        for _ in 0..2 {
+            join(&mut queue_man, "regular").unwrap();
+            queue_man.next(DEFAULT_QUEUE).unwrap();
+        }
+
+        assert_eq!(
+            join(&mut queue_man, "regular"),
+            Err(QueueError::PickLimit(2))
+        );
+        assert!(join(&mut queue_man, "newcomer").is_ok());
+
+        queue_man.end_stream();
+        assert!(join(&mut queue_man, "regular").is_ok());
+    }
+
+    #[test]
+    fn test_estimated_wait() {
+        let mut queue_man = QueueManager::new();
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        join(&mut queue_man, "third").unwrap();
+        assert_eq!(queue_man.estimated_wait(DEFAULT_QUEUE, "second"), None);
+
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        let queue = queue_man.queues.get_mut(DEFAULT_QUEUE).unwrap();
+        queue.session_lengths.extend([240, 360]);
+        queue.current.as_mut().unwrap().started = Utc::now() - Duration::minutes(2);
+
+        // 3 minutes left for "first" plus 5 minutes per user ahead
+        let wait = queue_man.estimated_wait(DEFAULT_QUEUE, "third").unwrap();
+        assert!(wait > Duration::minutes(7) && wait <= Duration::minutes(8));
+        let wait = queue_man.estimated_wait(DEFAULT_QUEUE, "second").unwrap();
+        assert!(wait > Duration::minutes(2) && wait <= Duration::minutes(3));
+        assert_eq!(queue_man.estimated_wait(DEFAULT_QUEUE, "first"), None);
+    }
+
+    #[test]
+    fn test_current() {
+        let mut queue_man = QueueManager::new();
+        assert!(queue_man.current(DEFAULT_QUEUE).unwrap().is_none());
+        assert!(queue_man.current("review").is_err());
+
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        let (name, elapsed) = queue_man.current(DEFAULT_QUEUE).unwrap().unwrap();
+        assert_eq!(name, "first");
+        assert!(elapsed < Duration::seconds(1));
+
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        let (name, _) = queue_man.current(DEFAULT_QUEUE).unwrap().unwrap();
+        assert_eq!(name, "second");
+    }
+
+    #[test]
+    fn test_session_history() {
+        let mut queue_man = QueueManager::new();
+        for i in 0..SESSION_HISTORY + 3 {
+            join(&mut queue_man, &format!("user{}", i)).unwrap();
+            queue_man.next(DEFAULT_QUEUE).unwrap();
+        }
+        let queue = &queue_man.queues[DEFAULT_QUEUE];
+        assert_eq!(queue.session_lengths.len(), SESSION_HISTORY);
+        assert_eq!(queue.current.as_ref().unwrap().name, "user12");
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut queue_man = QueueManager::new();
+        let users: Vec<String> = (0..20).map(|_| gen_random_user()).collect();
+        for user in &users {
+            join(&mut queue_man, user).unwrap();
+        }
+
+        queue_man.shuffle(DEFAULT_QUEUE).unwrap();
+        let mut shuffled = queue_man.queue(DEFAULT_QUEUE).unwrap();
+        assert_ne!(shuffled, users);
+        shuffled.sort_unstable();
+        let mut sorted = users.clone();
+        sorted.sort_unstable();
+        assert_eq!(shuffled, sorted);
+
+        assert_eq!(queue_man.shuffle("lobby"), Err(QueueError::NoSuchQueue));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut queue_man = QueueManager::new();
+        assert_eq!(queue_man.stats().average_wait(), None);
+        assert_eq!(queue_man.stats().picks_per_hour(), None);
+
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        queue_man.remove(DEFAULT_QUEUE, "first").unwrap();
+        join(&mut queue_man, "first").unwrap();
+        queue_man
+            .queues
+            .get_mut(DEFAULT_QUEUE)
+            .unwrap()
+            .entries
+            .get_mut("second")
+            .unwrap()
+            .joined_at = Utc::now() - Duration::minutes(10);
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+
+        let stats = queue_man.stats();
+        assert_eq!(stats.joins(), 3);
+        assert_eq!(stats.unique_users(), 2);
+        assert_eq!(stats.picks(), 2);
+        assert_eq!(stats.average_wait(), Some(Duration::minutes(5)));
+
+        queue_man.stats.since = Some(Utc::now() - Duration::minutes(30));
+        let per_hour = queue_man.stats().picks_per_hour().unwrap();
+        assert!((per_hour - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut queue_man = QueueManager::new();
+        for name in &["first", "second", "third"] {
+            join(&mut queue_man, name).unwrap();
+        }
+
+        assert!(queue_man.swap(DEFAULT_QUEUE, "first", "third").is_ok());
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            vec!["third", "second", "first"]
+        );
+        assert_eq!(
+            queue_man.swap(DEFAULT_QUEUE, "first", "nobody"),
+            Err(QueueError::NotInQueue)
+        );
+        assert_eq!(
+            queue_man.swap("review", "first", "second"),
+            Err(QueueError::NoSuchQueue)
+        );
+    }
+
+    #[test]
+    fn test_move_to() {
+        let mut queue_man = QueueManager::new();
+        for name in &["first", "second", "third", "fourth"] {
+            join(&mut queue_man, name).unwrap();
+        }
+
+        assert!(queue_man.move_to(DEFAULT_QUEUE, "third", 0).is_ok());
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            vec!["third", "first", "second", "fourth"]
+        );
+        assert!(queue_man.move_to(DEFAULT_QUEUE, "third", 3).is_ok());
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            vec!["first", "second", "fourth", "third"]
+        );
+
+        assert_eq!(
+            queue_man.move_to(DEFAULT_QUEUE, "first", 4),
+            Err(QueueError::InvalidPosition)
+        );
+        assert_eq!(
+            queue_man.move_to(DEFAULT_QUEUE, "nobody", 0),
+            Err(QueueError::NotInQueue)
+        );
+    }
+
+    #[test]
+    fn test_skip_unresponsive() {
+        let timeout = Duration::minutes(2);
+        let mut queue_man = QueueManager::new();
+        queue_man.set_rejoin_cooldown(Some(Duration::minutes(30)));
+        for name in &["first", "second", "third"] {
+            join(&mut queue_man, name).unwrap();
+        }
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        assert!(queue_man.skip_unresponsive(timeout, true).is_empty());
+
+        let expire = |queue_man: &mut QueueManager| {
+            let session = queue_man
+                .queues
+                .get_mut(DEFAULT_QUEUE)
+                .unwrap()
+                .current
+                .as_mut()
+                .unwrap();
+            session.started -= Duration::minutes(3);
+        };
+        expire(&mut queue_man);
+        let skipped = queue_man.skip_unresponsive(timeout, true);
+        assert_eq!(queue_man.stats().picks_of("first"), 0);
+        assert_eq!(queue_man.stats().picks(), 1);
+        assert_eq!(
+            skipped,
+            vec![Skipped {
+                queue: DEFAULT_QUEUE.to_owned(),
+                name: "first".to_owned(),
+                requeued: true,
+                next: Some(Pick {
+                    name: "second".to_owned(),
+                    note: None,
+                }),
+            }]
+        );
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["third", "first"]);
+
+        // confirmed users stay
+        assert!(queue_man.confirm("second"));
+        assert!(!queue_man.confirm("second"));
+        expire(&mut queue_man);
+        assert!(queue_man.skip_unresponsive(timeout, true).is_empty());
+
+        // without requeueing they can join again right away
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        expire(&mut queue_man);
+        let skipped = queue_man.skip_unresponsive(timeout, false);
+        assert_eq!(skipped[0].name, "third");
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), Vec::<&str>::new());
+        assert!(join(&mut queue_man, "third").is_ok());
+    }
+
+    #[test]
+    fn test_skip_unresponsive_round_robin() {
+        let mut queue_man = QueueManager::new();
+        queue_man.set_strategy(StrategyKind::RoundRobin.build(RandomWeighting::Uniform));
+        let users = [
+            ("viewer", UserType::Default),
+            ("sub", UserType::Subscriber),
+            ("mod", UserType::Moderator),
+        ];
+        for (name, user_type) in users {
+            queue_man
+                .join(DEFAULT_QUEUE, name.to_owned(), user_type, None)
+                .unwrap();
+        }
+        assert_eq!(queue_man.next(DEFAULT_QUEUE).unwrap().name, "mod");
+        let session = (queue_man.queues.get_mut(DEFAULT_QUEUE).unwrap())
+            .current
+            .as_mut()
+            .unwrap();
+        session.started -= Duration::minutes(3);
+
+        // the sub's turn comes after the mod's, not the viewer at the front
+        let skipped = queue_man.skip_unresponsive(Duration::minutes(2), false);
+        assert_eq!(skipped[0].next.as_ref().unwrap().name, "sub");
+        assert_eq!(queue_man.queue(DEFAULT_QUEUE).unwrap(), ["viewer"]);
+    }
+
+    #[test]
+    fn test_bump() {
+        let mut queue_man = QueueManager::new();
+        for name in &["first", "second", "third", "fourth"] {
+            join(&mut queue_man, name).unwrap();
+        }
+
+        assert_eq!(queue_man.bump(DEFAULT_QUEUE, "fourth", 2), Ok(1));
+        assert_eq!(
+            queue_man.queue(DEFAULT_QUEUE).unwrap(),
+            vec!["first", "fourth", "second", "third"]
+        );
+        assert_eq!(queue_man.bump(DEFAULT_QUEUE, "third", 10), Ok(0));
+        assert_eq!(
+            queue_man.bump(DEFAULT_QUEUE, "nobody", 1),
+            Err(QueueError::NotInQueue)
+        );
+        assert_eq!(
+            queue_man.bump("review", "first", 1),
+            Err(QueueError::NoSuchQueue)
+        );
+    }
+
+    #[test]
+    fn test_end_stream() {
+        let mut queue_man = QueueManager::new();
+        queue_man.open("review");
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        queue_man
+            .join("review", "third".to_owned(), UserType::Default, None)
+            .unwrap();
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+
+        let stats = queue_man.end_stream();
+        assert_eq!(stats.joins(), 3);
+        assert_eq!(stats.picks(), 1);
+        assert!(stats.ended.is_some());
+
+        assert!(queue_man.queue(DEFAULT_QUEUE).unwrap().is_empty());
+        assert!(queue_man.queue("review").unwrap().is_empty());
+        assert!(queue_man.current(DEFAULT_QUEUE).unwrap().is_none());
+        assert_eq!(queue_man.stats().joins(), 0);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut queue_man = QueueManager::new();
+        let mut events = queue_man.subscribe();
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        queue_man.remove(DEFAULT_QUEUE, "second").unwrap();
+        queue_man.next(DEFAULT_QUEUE).unwrap();
+        queue_man.close(DEFAULT_QUEUE).unwrap();
+        // failed changes aren't reported
+        assert!(join(&mut queue_man, "third").is_err());
+        queue_man.end_stream();
+
+        let queue = || DEFAULT_QUEUE.to_owned();
+        let expected = vec![
+            QueueEvent::Joined {
+                queue: queue(),
+                name: "first".to_owned(),
+            },
+            QueueEvent::Joined {
+                queue: queue(),
+                name: "second".to_owned(),
+            },
+            QueueEvent::Left {
+                queue: queue(),
+                name: "second".to_owned(),
+            },
+            QueueEvent::Picked {
+                queue: queue(),
+                pick: Pick {
+                    name: "first".to_owned(),
+                    note: None,
+                },
+            },
+            QueueEvent::Changed { queue: queue() },
+            QueueEvent::Cleared,
+        ];
+        for event in expected {
+            assert_eq!(events.try_recv(), Ok(event));
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_saved_entries_are_a_list() {
+        let saved = r#"{"queues": {"default": {"entries": [
+            {"name": "first", "user_type": "Default"},
+            {"name": "second", "user_type": "Vip", "note": "hi"}
+        ]}}}"#;
+        let queue_man: QueueManager = serde_json::from_str(saved).unwrap();
+        assert_eq!(queue_man.position(DEFAULT_QUEUE, "second"), Some(1));
+
+        let json: serde_json::Value = serde_json::to_value(&queue_man).unwrap();
+        assert_eq!(json["queues"]["default"]["entries"][1]["name"], "second");
+    }
+
+    #[test]
+    fn test_restoring_legacy_saves() {
+        let names = from_json(r#"{"queue": ["first", "second"]}"#).unwrap();
+        assert_eq!(names.queue(DEFAULT_QUEUE).unwrap(), ["first", "second"]);
+
+        let entries = from_json(
+            r#"{"queue": [
+                {"name": "first", "user_type": "Default"},
+                {"name": "second", "user_type": "Vip"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(entries.queue(DEFAULT_QUEUE).unwrap(), ["first", "second"]);
+        assert!(from_json(r#"{"queue": [1]}"#).is_err());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = std::env::temp_dir().join(format!("queue-{}.json", gen_random_user()));
+
+        let mut queue_man = QueueManager::persistent(path.clone()).unwrap();
+        assert!(queue_man.queue(DEFAULT_QUEUE).unwrap().is_empty());
+        join(&mut queue_man, "first").unwrap();
+        join(&mut queue_man, "second").unwrap();
+        queue_man.close(DEFAULT_QUEUE).unwrap();
+        queue_man.open("review");
+
+        let mut restored = QueueManager::persistent(path.clone()).unwrap();
+        assert_eq!(restored.queue(DEFAULT_QUEUE).unwrap(), ["first", "second"]);
+        assert_eq!(join(&mut restored, "third"), Err(QueueError::QueueClosed));
+        assert!(restored.queue("review").unwrap().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let storage = Memory::new();
+        let mut queue_man = QueueManager::with_storage(Box::new(storage.clone())).unwrap();
+        assert!(storage.saved().is_none());
+        join(&mut queue_man, "first").unwrap();
+        assert!(storage.saved().unwrap().contains("first"));
+
+        let restored = QueueManager::with_storage(Box::new(storage)).unwrap();
+        assert_eq!(restored.queue(DEFAULT_QUEUE).unwrap(), ["first"]);
     }
 }
@@ -0,0 +1,232 @@
+//! The users waiting in a queue, in order and indexed by login.
+
+use super::QueueEntry;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+/// Queue entries in order, with an index by login so checking whether a user
+/// is waiting and taking them out by name don't go through the whole queue.
+/// Taking a user out leaves a gap, the gaps are closed once there are more
+/// of them than users or before the order is changed.
+#[derive(Default)]
+pub(super) struct Entries {
+    // in queue order, `None` where a user was taken out
+    slots: Vec<Option<QueueEntry>>,
+    // the slot of every user, keyed by login
+    index: HashMap<String, usize>,
+}
+
+impl Entries {
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// Zero-based position of `name`, counting the users ahead of them.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        let slot = *self.index.get(name)?;
+        if !self.has_gaps() {
+            return Some(slot);
+        }
+        Some(
+            self.slots[..slot]
+                .iter()
+                .filter(|slot| slot.is_some())
+                .count(),
+        )
+    }
+
+    #[cfg(test)]
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut QueueEntry> {
+        let slot = *self.index.get(name)?;
+        self.slots[slot].as_mut()
+    }
+
+    /// The entries in queue order.
+    pub fn iter(&self) -> impl Iterator<Item = &QueueEntry> {
+        self.slots.iter().flatten()
+    }
+
+    /// The logins in queue order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|entry| entry.name.as_str())
+    }
+
+    /// Adds `entry` at the back, returns whether its user wasn't waiting yet.
+    pub fn push(&mut self, entry: QueueEntry) -> bool {
+        if self.contains(&entry.name) {
+            return false;
+        }
+        self.index.insert(entry.name.clone(), self.slots.len());
+        self.slots.push(Some(entry));
+        true
+    }
+
+    /// Puts `entry`, whose user isn't waiting yet, at `position` or at the
+    /// back if the queue is shorter.
+    pub fn insert(&mut self, position: usize, entry: QueueEntry) {
+        self.compact();
+        let position = position.min(self.slots.len());
+        self.slots.insert(position, Some(entry));
+        self.reindex(position);
+    }
+
+    /// Takes `name` out of the queue.
+    pub fn remove(&mut self, name: &str) -> Option<QueueEntry> {
+        let slot = self.index.remove(name)?;
+        let entry = self.slots[slot].take();
+        if self.slots.len() - self.len() > self.len() {
+            self.compact();
+        }
+        entry
+    }
+
+    /// Takes out the user at `position`.
+    pub fn remove_at(&mut self, position: usize) -> Option<QueueEntry> {
+        let name = self.iter().nth(position)?.name.clone();
+        self.remove(&name)
+    }
+
+    /// Lets the users at the positions `a` and `b` trade places.
+    pub fn swap_indices(&mut self, a: usize, b: usize) {
+        self.compact();
+        self.slots.swap(a, b);
+        self.reindex(a.min(b));
+    }
+
+    /// Moves the user at `from` to `to`, shifting everyone in between.
+    pub fn move_index(&mut self, from: usize, to: usize) {
+        self.compact();
+        let entry = self.slots.remove(from);
+        self.slots.insert(to, entry);
+        self.reindex(from.min(to));
+    }
+
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.compact();
+        self.slots.shuffle(rng);
+        self.reindex(0);
+    }
+
+    /// Orders the entries by `key`, keeping the order of equal ones.
+    pub fn sort_by_key<K: Ord>(&mut self, mut key: impl FnMut(&QueueEntry) -> K) {
+        self.compact();
+        self.slots
+            .sort_by_cached_key(|slot| slot.as_ref().map(&mut key));
+        self.reindex(0);
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.index.clear();
+    }
+
+    fn has_gaps(&self) -> bool {
+        self.slots.len() > self.len()
+    }
+
+    /// Closes the gaps left by users taken out.
+    fn compact(&mut self) {
+        if self.has_gaps() {
+            self.slots.retain(Option::is_some);
+            self.reindex(0);
+        }
+    }
+
+    /// Updates the index for every slot from `from` on.
+    fn reindex(&mut self, from: usize) {
+        for (slot, entry) in self.slots.iter().enumerate().skip(from) {
+            if let Some(entry) = entry {
+                self.index.insert(entry.name.clone(), slot);
+            }
+        }
+    }
+}
+
+/// Saved as a plain list, the index is rebuilt on load.
+impl Serialize for Entries {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Entries, D::Error> {
+        let mut entries = Entries::default();
+        for entry in Vec::<QueueEntry>::deserialize(deserializer)? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue_manager::UserType;
+    use chrono::Utc;
+
+    fn entry(name: &str) -> QueueEntry {
+        QueueEntry {
+            name: name.to_owned(),
+            user_type: UserType::Default,
+            joined_at: Utc::now(),
+            note: None,
+            weight: None,
+        }
+    }
+
+    fn names(entries: &Entries) -> Vec<&str> {
+        entries.names().collect()
+    }
+
+    #[test]
+    fn removing_leaves_gaps() {
+        let mut entries = Entries::default();
+        for name in ["a", "b", "c", "d", "e"] {
+            assert!(entries.push(entry(name)));
+        }
+        assert!(!entries.push(entry("c")));
+
+        assert_eq!(entries.remove("b").unwrap().name, "b");
+        assert!(entries.remove("b").is_none());
+        assert_eq!(entries.slots.len(), 5);
+        assert_eq!(entries.position("d"), Some(2));
+        assert_eq!(entries.remove_at(0).unwrap().name, "a");
+        assert_eq!(names(&entries), ["c", "d", "e"]);
+
+        // once most of the slots are gaps they're closed
+        entries.remove("d");
+        assert_eq!(entries.slots.len(), 2);
+        assert_eq!(entries.position("e"), Some(1));
+        assert!(!entries.contains("d"));
+    }
+
+    #[test]
+    fn reordering() {
+        let mut entries = Entries::default();
+        for name in ["a", "b", "c", "d"] {
+            entries.push(entry(name));
+        }
+        entries.remove("b");
+        entries.insert(1, entry("x"));
+        assert_eq!(names(&entries), ["a", "x", "c", "d"]);
+        entries.move_index(3, 0);
+        entries.swap_indices(1, 3);
+        assert_eq!(names(&entries), ["d", "c", "x", "a"]);
+        entries.sort_by_key(|entry| entry.name.clone());
+        assert_eq!(names(&entries), ["a", "c", "d", "x"]);
+        for (position, name) in ["a", "c", "d", "x"].iter().enumerate() {
+            assert_eq!(entries.position(name), Some(position));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Batching up "you moved up" notices so a busy queue doesn't flood chat.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+/// A user who got closer to the front of a queue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionChange {
+    pub name: String,
+    /// Zero-based, like `QueueManager::position`.
+    pub position: usize,
+}
+
+/// Tracks where everyone is in the queues and collects the users who moved
+/// up, handing them out at most once per `interval`.
+pub struct PositionUpdates {
+    interval: Duration,
+    // last seen position of every user, per queue
+    known: HashMap<String, HashMap<String, usize>>,
+    // queue -> user -> current position, for users who moved up since the
+    // last announcement
+    pending: BTreeMap<String, BTreeMap<String, usize>>,
+    last_announced: Option<DateTime<Utc>>,
+}
+
+impl PositionUpdates {
+    pub fn new(interval: Duration) -> PositionUpdates {
+        PositionUpdates {
+            interval,
+            known: HashMap::new(),
+            pending: BTreeMap::new(),
+            last_announced: None,
+        }
+    }
+
+    /// Compares the current `order` of `queue` with the one seen last time.
+    /// The first time a queue is seen nobody counts as moved.
+    pub fn update(&mut self, queue: &str, order: &[&str]) {
+        let positions: HashMap<String, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(position, name)| (name.to_string(), position))
+            .collect();
+        if let Some(known) = self.known.get(queue) {
+            let pending = self.pending.entry(queue.to_owned()).or_default();
+            // users who left don't need to hear about it
+            pending.retain(|name, _| positions.contains_key(name));
+            for (name, &position) in &positions {
+                let moved_up = matches!(known.get(name), Some(&before) if position < before);
+                if moved_up || pending.contains_key(name) {
+                    pending.insert(name.clone(), position);
+                }
+            }
+            if pending.is_empty() {
+                self.pending.remove(queue);
+            }
+        }
+        self.known.insert(queue.to_owned(), positions);
+    }
+
+    /// Forgets everything, e.g. after the queues were cleared.
+    pub fn reset(&mut self) {
+        self.known.clear();
+        self.pending.clear();
+    }
+
+    /// The users who moved up per queue, front first, if there are any and
+    /// the last announcement was at least `interval` ago.
+    pub fn due(&mut self, now: DateTime<Utc>) -> Option<BTreeMap<String, Vec<PositionChange>>> {
+        if self.pending.is_empty()
+            || matches!(self.last_announced, Some(last) if now - last < self.interval)
+        {
+            return None;
+        }
+        self.last_announced = Some(now);
+        let due = std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(queue, users)| {
+                let mut changes: Vec<PositionChange> = users
+                    .into_iter()
+                    .map(|(name, position)| PositionChange { name, position })
+                    .collect();
+                changes.sort_by_key(|change| change.position);
+                (queue, changes)
+            })
+            .collect();
+        Some(due)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(name: &str, position: usize) -> PositionChange {
+        PositionChange {
+            name: name.to_owned(),
+            position,
+        }
+    }
+
+    #[test]
+    fn moving_up_is_announced() {
+        let mut updates = PositionUpdates::new(Duration::seconds(30));
+        let now = Utc::now();
+        updates.update("default", &["alice", "bob", "carol"]);
+        assert_eq!(updates.due(now), None);
+
+        // joining at the back moves nobody up
+        updates.update("default", &["alice", "bob", "carol", "dave"]);
+        assert_eq!(updates.due(now), None);
+
+        updates.update("default", &["bob", "carol", "dave"]);
+        let due = updates.due(now).unwrap();
+        assert_eq!(
+            due["default"],
+            [change("bob", 0), change("carol", 1), change("dave", 2)]
+        );
+    }
+
+    #[test]
+    fn announcements_are_throttled() {
+        let mut updates = PositionUpdates::new(Duration::seconds(30));
+        let now = Utc::now();
+        updates.update("default", &["alice", "bob", "carol", "dave"]);
+        updates.update("default", &["bob", "carol", "dave"]);
+        assert!(updates.due(now).is_some());
+
+        // several removals in a row become a single announcement
+        updates.update("default", &["carol", "dave"]);
+        updates.update("default", &["dave"]);
+        assert_eq!(updates.due(now + Duration::seconds(10)), None);
+        let due = updates.due(now + Duration::seconds(30)).unwrap();
+        assert_eq!(due["default"], [change("dave", 0)]);
+        assert_eq!(updates.due(now + Duration::seconds(60)), None);
+    }
+
+    #[test]
+    fn users_who_left_are_dropped() {
+        let mut updates = PositionUpdates::new(Duration::seconds(30));
+        updates.update("default", &["alice", "bob", "carol"]);
+        updates.update("default", &["bob", "carol"]);
+        updates.update("default", &["carol"]);
+        updates.reset();
+        updates.update("default", &[]);
+        assert_eq!(updates.due(Utc::now()), None);
+
+        updates.update("review", &["alice", "bob"]);
+        updates.update("review", &["bob"]);
+        updates.update("review", &[]);
+        assert_eq!(updates.due(Utc::now()), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Backends a [`QueueManager`] can keep its state in between runs.
+
+use super::{from_json, QueueManager};
+use crate::json_store::JsonStore;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Somewhere to save the queues to after every change and restore them from
+/// on startup.
+pub trait QueueStorage: Send {
+    /// The previously saved state, `None` if nothing was saved yet.
+    fn load(&self) -> io::Result<Option<QueueManager>>;
+
+    /// Replaces the saved state.
+    fn save(&self, queue_manager: &QueueManager) -> io::Result<()>;
+
+    /// Where the state is kept, for error messages.
+    fn describe(&self) -> String;
+}
+
+/// Saves the queues as JSON to a file.
+pub struct JsonFile {
+    store: JsonStore<QueueManager>,
+}
+
+impl JsonFile {
+    pub fn new(path: PathBuf) -> JsonFile {
+        JsonFile {
+            store: JsonStore::new(path),
+        }
+    }
+}
+
+impl QueueStorage for JsonFile {
+    fn load(&self) -> io::Result<Option<QueueManager>> {
+        // read as is rather than through `JsonStore::load` to restore
+        // queues saved in the old formats too
+        match self.store.read()? {
+            Some(saved) => Ok(Some(from_json(&saved)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, queue_manager: &QueueManager) -> io::Result<()> {
+        self.store.write(queue_manager)
+    }
+
+    fn describe(&self) -> String {
+        self.store.path().display().to_string()
+    }
+}
+
+/// Keeps the saved state in memory, clones share it. Meant for tests and for
+/// handing the state to something else in the same process.
+#[derive(Clone, Default)]
+pub struct Memory {
+    saved: Arc<Mutex<Option<String>>>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    /// The last saved state as JSON.
+    pub fn saved(&self) -> Option<String> {
+        self.saved.lock().unwrap().clone()
+    }
+}
+
+impl QueueStorage for Memory {
+    fn load(&self) -> io::Result<Option<QueueManager>> {
+        match &*self.saved.lock().unwrap() {
+            Some(saved) => Ok(Some(from_json(saved)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, queue_manager: &QueueManager) -> io::Result<()> {
+        *self.saved.lock().unwrap() = Some(serde_json::to_string(queue_manager)?);
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_owned()
+    }
+}
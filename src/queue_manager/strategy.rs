@@ -0,0 +1,191 @@
+//! How queues are ordered and who gets picked next.
+
+use super::UserType;
+use chrono::{DateTime, Utc};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::Deserialize;
+
+/// What a strategy gets to know about a user in the queue.
+#[derive(Debug, Clone)]
+pub struct Candidate<'a> {
+    pub name: &'a str,
+    pub user_type: UserType,
+    /// Priority weight when joining, see `PriorityWeights`.
+    pub weight: u32,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// Decides the order of a queue and who `QueueManager::next` picks.
+pub trait QueueStrategy: Send {
+    /// Index in `entries` the joining user is placed at, the end by default.
+    fn insert_at(&self, entries: &[Candidate], _joining: &Candidate) -> usize {
+        entries.len()
+    }
+
+    /// Index of the user to pick from the non-empty `entries`, the front by
+    /// default. `previous` is the kind of user picked last in this queue.
+    fn pick(&self, _entries: &[Candidate], _previous: Option<UserType>) -> usize {
+        0
+    }
+}
+
+/// First come, first served.
+pub struct Fifo;
+
+impl QueueStrategy for Fifo {}
+
+/// Users are placed ahead of everyone with a lower priority weight, FIFO
+/// within the same weight.
+pub struct Priority;
+
+impl QueueStrategy for Priority {
+    fn insert_at(&self, entries: &[Candidate], joining: &Candidate) -> usize {
+        entries
+            .iter()
+            .position(|entry| entry.weight < joining.weight)
+            .unwrap_or(entries.len())
+    }
+}
+
+/// Users wait in join order and a random one is picked.
+pub struct Random(pub RandomWeighting);
+
+impl QueueStrategy for Random {
+    fn pick(&self, entries: &[Candidate], _previous: Option<UserType>) -> usize {
+        random_index(entries, self.0, &mut rand::thread_rng())
+    }
+}
+
+/// Takes turns between mods, VIPs, subs and everyone else, FIFO within each.
+pub struct RoundRobin;
+
+impl RoundRobin {
+    const ORDER: [UserType; 4] = [
+        UserType::Moderator,
+        UserType::Vip,
+        UserType::Subscriber,
+        UserType::Default,
+    ];
+}
+
+impl QueueStrategy for RoundRobin {
+    fn pick(&self, entries: &[Candidate], previous: Option<UserType>) -> usize {
+        let order = RoundRobin::ORDER;
+        let start = previous
+            .and_then(|previous| order.iter().position(|&tier| tier == previous))
+            .map_or(0, |i| i + 1);
+        (0..order.len())
+            .map(|i| order[(start + i) % order.len()])
+            .find_map(|tier| entries.iter().position(|entry| entry.user_type == tier))
+            .unwrap_or(0)
+    }
+}
+
+/// How likely each user is to be chosen by `!pickrandom`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RandomWeighting {
+    /// Everyone has the same chance.
+    #[default]
+    Uniform,
+    /// The chance grows with every second spent waiting.
+    WaitTime,
+    /// The chance grows with the user's priority weight.
+    UserType,
+}
+
+/// Index of a random user in the non-empty `entries`.
+pub(super) fn random_index(
+    entries: &[Candidate],
+    weighting: RandomWeighting,
+    rng: &mut impl Rng,
+) -> usize {
+    let now = Utc::now();
+    // everyone gets a weight of at least 1 so nobody is left out entirely
+    let weights = entries.iter().map(|entry| match weighting {
+        RandomWeighting::Uniform => 1,
+        RandomWeighting::WaitTime => (now - entry.joined_at).num_seconds().max(0) as u64 + 1,
+        RandomWeighting::UserType => entry.weight as u64 + 1,
+    });
+    WeightedIndex::new(weights).unwrap().sample(rng)
+}
+
+/// The built-in strategies, as selected in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    Fifo,
+    #[default]
+    Priority,
+    Random,
+    RoundRobin,
+}
+
+impl StrategyKind {
+    /// `weighting` only applies to the random strategy.
+    pub fn build(self, weighting: RandomWeighting) -> Box<dyn QueueStrategy> {
+        match self {
+            StrategyKind::Fifo => Box::new(Fifo),
+            StrategyKind::Priority => Box::new(Priority),
+            StrategyKind::Random => Box::new(Random(weighting)),
+            StrategyKind::RoundRobin => Box::new(RoundRobin),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(users: &[(&'static str, UserType, u32)]) -> Vec<Candidate<'static>> {
+        users
+            .iter()
+            .map(|&(name, user_type, weight)| Candidate {
+                name,
+                user_type,
+                weight,
+                joined_at: Utc::now(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn insertion() {
+        let entries = candidates(&[
+            ("mod", UserType::Moderator, 2),
+            ("viewer", UserType::Default, 0),
+        ]);
+        let joining = &candidates(&[("sub", UserType::Subscriber, 1)])[0];
+
+        assert_eq!(Fifo.insert_at(&entries, joining), 2);
+        assert_eq!(Priority.insert_at(&entries, joining), 1);
+        assert_eq!(RoundRobin.insert_at(&entries, joining), 2);
+    }
+
+    #[test]
+    fn round_robin() {
+        let entries = candidates(&[
+            ("viewer", UserType::Default, 0),
+            ("sub", UserType::Subscriber, 0),
+            ("viewer2", UserType::Default, 0),
+            ("mod", UserType::Moderator, 0),
+        ]);
+
+        assert_eq!(RoundRobin.pick(&entries, None), 3);
+        assert_eq!(RoundRobin.pick(&entries, Some(UserType::Moderator)), 1);
+        assert_eq!(RoundRobin.pick(&entries, Some(UserType::Subscriber)), 0);
+        assert_eq!(RoundRobin.pick(&entries, Some(UserType::Default)), 3);
+    }
+
+    #[test]
+    fn random_picks_are_in_range() {
+        let entries = candidates(&[
+            ("viewer", UserType::Default, 0),
+            ("sub", UserType::Subscriber, 1),
+        ]);
+        for _ in 0..20 {
+            assert!(Random(RandomWeighting::UserType).pick(&entries, None) < 2);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Pacing of chat messages, Twitch silently drops what's sent over its limit.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Twitch allows this many messages per window for bots that aren't
+/// verified or moderators in the channel.
+pub const DEFAULT_LIMIT: usize = 20;
+pub const WINDOW: Duration = Duration::from_secs(30);
+
+/// Allows `limit` messages in any `window`.
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    // when the messages in the current window were sent, oldest first
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            limit: limit.max(1),
+            window,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// How long to wait before the next message can be sent, zero if it can
+    /// be sent right away.
+    pub fn wait_time(&mut self, now: Instant) -> Duration {
+        while let Some(&oldest) = self.sent.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        match self.sent.front() {
+            Some(&oldest) if self.sent.len() >= self.limit => {
+                self.window - now.duration_since(oldest)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Counts a message sent at `now`.
+    pub fn record(&mut self, now: Instant) {
+        self.sent.push_back(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiting_messages() {
+        let start = Instant::now();
+        let second = |s| start + Duration::from_secs(s);
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(30));
+
+        assert_eq!(limiter.wait_time(start), Duration::ZERO);
+        limiter.record(start);
+        assert_eq!(limiter.wait_time(second(10)), Duration::ZERO);
+        limiter.record(second(10));
+
+        assert_eq!(limiter.wait_time(second(20)), Duration::from_secs(10));
+        // the first message left the window
+        assert_eq!(limiter.wait_time(second(30)), Duration::ZERO);
+        limiter.record(second(30));
+        assert_eq!(limiter.wait_time(second(31)), Duration::from_secs(9));
+    }
+}
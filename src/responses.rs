@@ -0,0 +1,330 @@
+//! Simple response commands defined in a TOML or JSON file, reloaded whenever
+//! the file changes. Without a file the ones in `responses.toml` are built in.
+
+use crate::commands::command_name;
+use log::warn;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fs, io};
+
+/// What the bot answers a command with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    // one is picked at random every time, by weight
+    texts: Vec<(String, u32)>,
+    /// Addressed to whoever used the command instead of the whole chat.
+    pub reply: bool,
+}
+
+impl Response {
+    /// Always answers with `text`.
+    pub fn new(text: String, reply: bool) -> Response {
+        Response {
+            texts: vec![(text, 1)],
+            reply,
+        }
+    }
+
+    /// One of the texts, more likely the higher its weight.
+    pub fn pick(&self, rng: &mut impl Rng) -> &str {
+        self.texts
+            .choose_weighted(rng, |(_, weight)| *weight)
+            .or_else(|_| self.texts.first().ok_or(()))
+            .map_or("", |(text, _)| text)
+    }
+}
+
+/// A command as written in the file, the text is either inline, read from
+/// another file or one of several.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Definition {
+    text: Option<String>,
+    // relative to the assets directory, or else the file defining the command
+    file: Option<PathBuf>,
+    texts: Option<Vec<Choice>>,
+    #[serde(default)]
+    reply: bool,
+}
+
+/// One of several texts, either all plain or all weighted, e.g.
+/// `{ text = 'rare', weight = 1 }` next to `{ text = 'common', weight = 9 }`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Choice {
+    Text(String),
+    Weighted {
+        text: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// The commands shipped in `responses.toml`, with the files they read.
+const BUILTIN: &str = include_str!("../responses.toml");
+const BUILTIN_FILES: &[(&str, &str)] = &[
+    ("assets/bazylia.txt", include_str!("../assets/bazylia.txt")),
+    ("assets/dave.txt", include_str!("../assets/dave.txt")),
+    ("assets/zoya.txt", include_str!("../assets/zoya.txt")),
+];
+
+/// Command names (including the `!`) mapped to their responses.
+#[derive(Default)]
+pub struct Responses {
+    responses: HashMap<String, Response>,
+    path: Option<PathBuf>,
+    assets: Option<PathBuf>,
+    // of the file when it was last loaded
+    modified: Option<SystemTime>,
+}
+
+impl Responses {
+    /// Loads the commands defined at `path`, a `.json` file or TOML otherwise.
+    /// Texts in separate files are read from `assets`, if set, and commands
+    /// whose file can't be read are left out.
+    pub fn load(path: PathBuf, assets: Option<PathBuf>) -> io::Result<Responses> {
+        let modified = fs::metadata(&path)?.modified()?;
+        Ok(Responses {
+            responses: parse(&path, assets.as_deref())?,
+            path: Some(path),
+            assets,
+            modified: Some(modified),
+        })
+    }
+
+    /// The commands shipped with the bot, for when no file is set.
+    pub fn builtin() -> Responses {
+        let definitions = toml::from_str(BUILTIN).expect("responses.toml is invalid");
+        let responses = build(definitions, |file| {
+            BUILTIN_FILES
+                .iter()
+                .find(|(name, _)| Path::new(name) == file)
+                .map(|(_, text)| text.to_string())
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        });
+        Responses {
+            responses: responses.expect("responses.toml is invalid"),
+            ..Responses::default()
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Response> {
+        self.responses.get(&command_name(name))
+    }
+
+    /// The names of every command, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.responses.keys().map(String::as_str)
+    }
+
+    /// Loads the file again if it changed since it was last loaded, returns
+    /// whether it did. The current commands are kept if the file is invalid.
+    pub fn reload_if_changed(&mut self) -> io::Result<bool> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let modified = fs::metadata(path)?.modified()?;
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        // not retried until the file changes again
+        self.modified = Some(modified);
+        self.responses = parse(path, self.assets.as_deref())?;
+        Ok(true)
+    }
+}
+
+fn parse(path: &Path, assets: Option<&Path>) -> io::Result<HashMap<String, Response>> {
+    let contents = fs::read_to_string(path)?;
+    let definitions: HashMap<String, Definition> = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&contents)?
+    } else {
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+    };
+
+    let dir = assets.unwrap_or_else(|| path.parent().unwrap_or_else(|| Path::new("")));
+    build(definitions, |file| fs::read_to_string(dir.join(file)))
+}
+
+/// Turns the definitions into responses, reading the texts kept in separate
+/// files with `read`.
+fn build(
+    definitions: HashMap<String, Definition>,
+    read: impl Fn(&Path) -> io::Result<String>,
+) -> io::Result<HashMap<String, Response>> {
+    let mut responses = HashMap::new();
+    for (name, definition) in definitions {
+        let texts = match (definition.text, definition.file, definition.texts) {
+            (Some(text), None, None) => vec![(text, 1)],
+            (None, Some(file), None) => match read(&file) {
+                Ok(text) => vec![(text, 1)],
+                Err(e) => {
+                    warn!(
+                        "Leaving out {}, {} can't be read: {}",
+                        name,
+                        file.display(),
+                        e
+                    );
+                    continue;
+                }
+            },
+            (None, None, Some(texts)) if !texts.is_empty() => texts
+                .into_iter()
+                .map(|choice| match choice {
+                    Choice::Text(text) => (text, 1),
+                    Choice::Weighted { text, weight } => (text, weight),
+                })
+                .collect(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} needs either a text, a file or texts", name),
+                ))
+            }
+        };
+        let response = Response {
+            texts,
+            reply: definition.reply,
+        };
+        responses.insert(command_name(&name), response);
+    }
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ferris-bot-responses-{}", std::process::id()));
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn loading_the_shipped_responses() {
+        let responses = Responses::load("responses.toml".into(), None).unwrap();
+        assert_eq!(
+            responses.get("!STONK"),
+            Some(&Response::new("yOu shOULd Buy AMC sTOnKS".to_owned(), true))
+        );
+        assert_eq!(
+            responses.get("dave"),
+            Some(&Response::new(
+                include_str!("../assets/dave.txt").to_owned(),
+                false
+            ))
+        );
+        assert!(responses.get("!join").is_none());
+
+        // the same without the file
+        let builtin = Responses::builtin();
+        assert_eq!(builtin.responses, responses.responses);
+        assert!(builtin.get("!bazylia").is_some());
+        assert!(builtin.get("!zoya").is_some());
+    }
+
+    #[test]
+    fn loading_json() {
+        let path = temp_file(
+            "responses.json",
+            r#"{ "!repo": { "text": "https://github.com/silen-z/ferris-bot" } }"#,
+        );
+        let responses = Responses::load(path, None).unwrap();
+        assert_eq!(
+            responses.get("!repo"),
+            Some(&Response::new(
+                "https://github.com/silen-z/ferris-bot".to_owned(),
+                false
+            ))
+        );
+    }
+
+    #[test]
+    fn reading_assets() {
+        let assets = temp_file("assets/ferris.txt", "🦀")
+            .parent()
+            .unwrap()
+            .to_owned();
+        let path = temp_file(
+            "assets.toml",
+            "['!ferris']\nfile = 'ferris.txt'\n['!gone']\nfile = 'gone.txt'\n",
+        );
+        let responses = Responses::load(path, Some(assets)).unwrap();
+        assert_eq!(
+            responses.get("!ferris"),
+            Some(&Response::new("🦀".to_owned(), false))
+        );
+        // missing files only leave out their command
+        assert!(responses.get("!gone").is_none());
+    }
+
+    #[test]
+    fn picking_from_pools() {
+        let path = temp_file(
+            "pools.toml",
+            "['!pick']\ntexts = [{ text = 'a' }, { text = 'b', weight = 3 }, { text = 'never', weight = 0 }]\n\
+             ['!plain']\ntexts = ['a', 'b']\n",
+        );
+        let responses = Responses::load(path, None).unwrap();
+        let response = responses.get("!pick").unwrap();
+        let mut rng = rand::rngs::mock::StepRng::new(0, u64::MAX / 8);
+        let picks: Vec<&str> = (0..8).map(|_| response.pick(&mut rng)).collect();
+        assert!(picks.contains(&"a") && picks.contains(&"b"));
+        assert!(!picks.contains(&"never"));
+        assert!(picks.iter().filter(|&&pick| pick == "b").count() > 4);
+        let plain = responses.get("!plain").unwrap();
+        assert!(["a", "b"].contains(&plain.pick(&mut rng)));
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let path = temp_file("both.toml", "['!a']\ntext = 'a'\nfile = 'a.txt'\n");
+        assert!(Responses::load(path, None).is_err());
+        let path = temp_file("neither.toml", "['!a']\nreply = true\n");
+        assert!(Responses::load(path, None).is_err());
+        let path = temp_file("empty.toml", "['!a']\ntexts = []\n");
+        assert!(Responses::load(path, None).is_err());
+    }
+
+    #[test]
+    fn reloading_changes() {
+        let path = temp_file("reload.toml", "['!a']\ntext = 'first'\n");
+        let mut responses = Responses::load(path.clone(), None).unwrap();
+        assert!(!responses.reload_if_changed().unwrap());
+
+        fs::write(&path, "['!a']\ntext = 'second'\n").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(responses.reload_if_changed().unwrap());
+        assert_eq!(
+            responses.get("!a"),
+            Some(&Response::new("second".to_owned(), false))
+        );
+
+        // broken edits keep the previous commands
+        fs::write(&path, "['!a'\n").unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(20))
+            .unwrap();
+        assert!(responses.reload_if_changed().is_err());
+        assert_eq!(
+            responses.get("!a"),
+            Some(&Response::new("second".to_owned(), false))
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! The roles of users with more than the default role, kept from their last
+//! message's badges and refreshed with the moderators and VIPs Twitch lists,
+//! so roles are known for whispers and for users who haven't chatted lately.
+
+use crate::permissions::Permission;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Roles {
+    // keyed by channel login, then by user login
+    known: HashMap<String, HashMap<String, Permission>>,
+}
+
+impl Roles {
+    /// Notes the role `user` had in a message in `channel`. Users without a
+    /// role aren't kept, so not everyone who chatted is.
+    pub fn saw(&mut self, channel: &str, user: &str, permission: Permission) {
+        let known = self.known.entry(channel.to_owned()).or_default();
+        if permission > Permission::Everyone {
+            known.insert(user.to_owned(), permission);
+        } else {
+            known.remove(user);
+        }
+    }
+
+    /// Replaces the moderators and VIPs of `channel` with the ones Twitch
+    /// lists. Users no longer listed are forgotten until they chat again,
+    /// whether they're subscribed isn't known.
+    pub fn refresh(&mut self, channel: &str, moderators: &[String], vips: &[String]) {
+        let known = self.known.entry(channel.to_owned()).or_default();
+        known.retain(|user, permission| match permission {
+            Permission::Moderator => moderators.contains(user),
+            Permission::Vip => vips.contains(user),
+            _ => true,
+        });
+        for (users, permission) in [(vips, Permission::Vip), (moderators, Permission::Moderator)] {
+            for user in users {
+                let known = known.entry(user.clone()).or_insert(permission);
+                *known = (*known).max(permission);
+            }
+        }
+    }
+
+    /// The role of `user` in `channel`, `None` if they have none or it isn't known.
+    pub fn of(&self, channel: &str, user: &str) -> Option<Permission> {
+        self.known.get(channel)?.get(user).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refreshing_roles() {
+        let mut roles = Roles::default();
+        roles.saw("channel", "owner", Permission::Broadcaster);
+        roles.saw("channel", "alice", Permission::Moderator);
+        roles.saw("channel", "bob", Permission::Subscriber);
+        roles.saw("channel", "carol", Permission::Vip);
+        roles.saw("other", "alice", Permission::Everyone);
+        assert_eq!(roles.of("channel", "alice"), Some(Permission::Moderator));
+        assert_eq!(roles.of("channel", "dave"), None);
+
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|&n| n.to_owned()).collect() };
+        roles.refresh(
+            "channel",
+            &names(&["owner", "bob", "dave"]),
+            &names(&["bob"]),
+        );
+        assert_eq!(roles.of("channel", "owner"), Some(Permission::Broadcaster));
+        assert_eq!(roles.of("channel", "alice"), None);
+        assert_eq!(roles.of("channel", "bob"), Some(Permission::Moderator));
+        assert_eq!(roles.of("channel", "carol"), None);
+        assert_eq!(roles.of("channel", "dave"), Some(Permission::Moderator));
+        assert_eq!(roles.of("other", "alice"), None);
+
+        // losing a role
+        roles.saw("channel", "owner", Permission::Everyone);
+        assert_eq!(roles.of("channel", "owner"), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Variables in command responses, e.g. `$(user) has died $(count) times`.
+//!
+//! Supported are `$(user)`, `$(channel)`, `$(args)`, `$(count)`, `$(months)`
+//! and `$(random <min> <max>)`. Anything else is left as written.
+
+use rand::Rng;
+
+/// What the variables of one response stand for.
+pub struct Variables<'a> {
+    /// Display name of whoever used the command.
+    pub user: &'a str,
+    pub channel: &'a str,
+    /// Everything typed after the command.
+    pub args: &'a str,
+    /// How many times the command has been used, this time included.
+    pub count: u64,
+    /// For subscriptions, how many months the user has been subscribed.
+    pub months: Option<u64>,
+}
+
+/// Replaces the variables in `template`.
+pub fn render(template: &str, variables: &Variables, rng: &mut impl Rng) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("$(") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = match after.find(')') {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        match evaluate(&after[..end], variables, rng) {
+            Some(value) => rendered.push_str(&value),
+            None => rendered.push_str(&rest[start..start + end + 3]),
+        }
+        rest = &after[end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The value of the variable `$(expression)`, `None` if it isn't one.
+fn evaluate(expression: &str, variables: &Variables, rng: &mut impl Rng) -> Option<String> {
+    let words: Vec<&str> = expression.split_whitespace().collect();
+    match words.as_slice() {
+        ["user"] => Some(variables.user.to_owned()),
+        ["channel"] => Some(variables.channel.to_owned()),
+        ["args"] => Some(variables.args.to_owned()),
+        ["count"] => Some(variables.count.to_string()),
+        ["months"] => variables.months.map(|months| months.to_string()),
+        ["random", min, max] => {
+            let min: i64 = min.parse().ok()?;
+            let max: i64 = max.parse().ok()?;
+            (min <= max).then(|| rng.gen_range(min..=max).to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    fn variables() -> Variables<'static> {
+        Variables {
+            user: "Ferris",
+            channel: "rustlang",
+            args: "the borrow checker",
+            count: 3,
+            months: None,
+        }
+    }
+
+    fn render(template: &str) -> String {
+        super::render(template, &variables(), &mut StepRng::new(0, 1))
+    }
+
+    #[test]
+    fn rendering_variables() {
+        assert_eq!(
+            render("$(user) fought $(args) in $(channel) $(count) times"),
+            "Ferris fought the borrow checker in rustlang 3 times"
+        );
+        assert_eq!(render("no variables"), "no variables");
+        assert_eq!(render("$( user )"), "Ferris");
+    }
+
+    #[test]
+    fn rendering_random_numbers() {
+        for _ in 0..100 {
+            let number: i64 = super::render("$(random 1 6)", &variables(), &mut rand::thread_rng())
+                .parse()
+                .unwrap();
+            assert!((1..=6).contains(&number));
+        }
+        assert_eq!(render("$(random 5 5)"), "5");
+        assert_eq!(render("$(random 6 1)"), "$(random 6 1)");
+        assert_eq!(render("$(random one 6)"), "$(random one 6)");
+    }
+
+    #[test]
+    fn leaving_unknown_variables() {
+        assert_eq!(render("$(unknown) $(user)"), "$(unknown) Ferris");
+        assert_eq!(render("costs $(5"), "costs $(5");
+        assert_eq!(render("$(user"), "$(user");
+        assert_eq!(render("$(months)"), "$(months)");
+        assert_eq!(render("$$(user))"), "$Ferris)");
+    }
+}
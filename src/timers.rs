@@ -0,0 +1,115 @@
+//! Messages posted to chat at intervals, like reminders to follow the Discord.
+
+use crate::config::TimerConfig;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+struct Timer {
+    text: String,
+    interval: Duration,
+    min_messages: u64,
+    last_posted: DateTime<Utc>,
+    // chat messages since `last_posted`
+    messages: u64,
+}
+
+/// Every configured timer and when it last posted.
+#[derive(Default)]
+pub struct Timers {
+    timers: Vec<Timer>,
+}
+
+impl Timers {
+    /// Timers first post one interval after `now`.
+    pub fn new(config: &HashMap<String, TimerConfig>, now: DateTime<Utc>) -> Timers {
+        let mut names: Vec<&String> = config.keys().collect();
+        names.sort();
+        let timers = names
+            .into_iter()
+            .map(|name| {
+                let timer = &config[name];
+                Timer {
+                    text: timer.text.clone(),
+                    interval: Duration::seconds(timer.interval),
+                    min_messages: timer.min_messages,
+                    last_posted: now,
+                    messages: 0,
+                }
+            })
+            .collect();
+        Timers { timers }
+    }
+
+    /// Counts a chat message towards every timer's activity.
+    pub fn record_message(&mut self) {
+        for timer in &mut self.timers {
+            timer.messages += 1;
+        }
+    }
+
+    /// The messages to post at `now`, every timer's interval starts over once
+    /// it's posted. Timers waiting for chat activity post as soon as there's
+    /// been enough of it.
+    pub fn due(&mut self, now: DateTime<Utc>) -> Vec<String> {
+        let mut due = Vec::new();
+        for timer in &mut self.timers {
+            if now - timer.last_posted >= timer.interval && timer.messages >= timer.min_messages {
+                due.push(timer.text.clone());
+                timer.last_posted = now;
+                timer.messages = 0;
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(interval: i64, min_messages: u64, text: &str) -> TimerConfig {
+        TimerConfig {
+            interval,
+            text: text.to_owned(),
+            min_messages,
+        }
+    }
+
+    #[test]
+    fn posting_at_intervals() {
+        let now = Utc::now();
+        let minutes = |m| now + Duration::minutes(m);
+        let config = HashMap::from([
+            ("discord".to_owned(), timer(600, 0, "Follow the Discord!")),
+            ("water".to_owned(), timer(1200, 0, "Drink some water")),
+        ]);
+        let mut timers = Timers::new(&config, now);
+
+        assert!(timers.due(minutes(9)).is_empty());
+        assert_eq!(timers.due(minutes(10)), ["Follow the Discord!"]);
+        assert!(timers.due(minutes(15)).is_empty());
+        assert_eq!(
+            timers.due(minutes(20)),
+            ["Follow the Discord!", "Drink some water"]
+        );
+    }
+
+    #[test]
+    fn waiting_for_activity() {
+        let now = Utc::now();
+        let minutes = |m| now + Duration::minutes(m);
+        let config = HashMap::from([("discord".to_owned(), timer(600, 2, "Follow the Discord!"))]);
+        let mut timers = Timers::new(&config, now);
+
+        timers.record_message();
+        assert!(timers.due(minutes(10)).is_empty());
+        timers.record_message();
+        assert_eq!(timers.due(minutes(11)), ["Follow the Discord!"]);
+
+        // activity before the post doesn't count towards the next one
+        assert!(timers.due(minutes(21)).is_empty());
+        timers.record_message();
+        timers.record_message();
+        assert_eq!(timers.due(minutes(22)), ["Follow the Discord!"]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Polls and predictions run by Twitch, started with `!poll` and
+//! `!prediction`, kept until their results are announced in chat.
+
+use crate::helix::{Poll, Prediction};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Poll,
+    Prediction,
+}
+
+/// A poll or prediction whose results weren't announced yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Running {
+    pub kind: Kind,
+    pub id: String,
+    pub channel_login: String,
+    pub broadcaster_id: String,
+    /// When to look whether it has ended.
+    pub check_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct TwitchPolls {
+    running: Vec<Running>,
+}
+
+impl TwitchPolls {
+    pub fn add(&mut self, running: Running) {
+        self.running.push(running);
+    }
+
+    /// Takes out the ones due to be checked at `now`.
+    pub fn due(&mut self, now: DateTime<Utc>) -> Vec<Running> {
+        let (due, later) = self
+            .running
+            .drain(..)
+            .partition(|running| running.check_at <= now);
+        self.running = later;
+        due
+    }
+
+    /// Takes out the prediction started last in `channel`.
+    pub fn take_prediction(&mut self, channel: &str) -> Option<Running> {
+        let index = self.running.iter().rposition(|running| {
+            running.kind == Kind::Prediction && running.channel_login == channel
+        })?;
+        Some(self.running.remove(index))
+    }
+}
+
+/// The announcement of the results of `poll`, `None` while it's running.
+pub fn poll_results(poll: &Poll) -> Option<String> {
+    if poll.status == "ACTIVE" {
+        return None;
+    }
+    let tally: Vec<(&str, u64)> = poll
+        .choices
+        .iter()
+        .map(|choice| (choice.title.as_str(), choice.votes))
+        .collect();
+    Some(crate::poll::summary(&poll.title, &tally))
+}
+
+/// The announcement of how `prediction` turned out, `None` until it's
+/// resolved or canceled.
+pub fn prediction_results(prediction: &Prediction) -> Option<String> {
+    match prediction.status.as_str() {
+        "RESOLVED" => {
+            let winner = prediction
+                .outcomes
+                .iter()
+                .find(|outcome| Some(&outcome.id) == prediction.winning_outcome_id.as_ref())?;
+            let points: u64 = prediction
+                .outcomes
+                .iter()
+                .map(|outcome| outcome.channel_points)
+                .sum();
+            Some(format!(
+                "Prediction \"{}\" resolved. Winner: {}, {} user(s) share {} channel points",
+                prediction.title, winner.title, winner.users, points
+            ))
+        }
+        "CANCELED" => Some(format!(
+            "Prediction \"{}\" was canceled, everyone got their channel points back",
+            prediction.title
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helix::{Outcome, PollChoice};
+    use chrono::Duration;
+
+    fn running(kind: Kind, id: &str, check_at: DateTime<Utc>) -> Running {
+        Running {
+            kind,
+            id: id.to_owned(),
+            channel_login: "channel".to_owned(),
+            broadcaster_id: "1".to_owned(),
+            check_at,
+        }
+    }
+
+    #[test]
+    fn checking_when_due() {
+        let now = Utc::now();
+        let mut polls = TwitchPolls::default();
+        polls.add(running(Kind::Poll, "a", now));
+        polls.add(running(Kind::Prediction, "b", now + Duration::seconds(60)));
+        polls.add(running(Kind::Prediction, "c", now + Duration::seconds(60)));
+
+        let due = polls.due(now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, "a");
+        assert!(polls.due(now).is_empty());
+
+        assert_eq!(polls.take_prediction("channel").unwrap().id, "c");
+        assert!(polls.take_prediction("other").is_none());
+        assert_eq!(polls.due(now + Duration::seconds(60))[0].id, "b");
+    }
+
+    #[test]
+    fn announcing_results() {
+        let mut poll = Poll {
+            id: "1".to_owned(),
+            title: "Best editor?".to_owned(),
+            choices: vec![
+                PollChoice {
+                    title: "vim".to_owned(),
+                    votes: 3,
+                },
+                PollChoice {
+                    title: "emacs".to_owned(),
+                    votes: 1,
+                },
+            ],
+            status: "ACTIVE".to_owned(),
+        };
+        assert_eq!(poll_results(&poll), None);
+        poll.status = "COMPLETED".to_owned();
+        assert_eq!(
+            poll_results(&poll).unwrap(),
+            "Poll \"Best editor?\" closed. Winner: vim with 3 vote(s). \
+             Results: 1) vim: 3, 2) emacs: 1"
+        );
+
+        let outcome = |id: &str, users, channel_points| Outcome {
+            id: id.to_owned(),
+            title: format!("outcome {}", id),
+            users,
+            channel_points,
+        };
+        let mut prediction = Prediction {
+            id: "1".to_owned(),
+            title: "Will it compile?".to_owned(),
+            winning_outcome_id: None,
+            outcomes: vec![outcome("1", 2, 500), outcome("2", 3, 1000)],
+            status: "LOCKED".to_owned(),
+        };
+        assert_eq!(prediction_results(&prediction), None);
+        prediction.status = "RESOLVED".to_owned();
+        prediction.winning_outcome_id = Some("1".to_owned());
+        assert_eq!(
+            prediction_results(&prediction).unwrap(),
+            "Prediction \"Will it compile?\" resolved. Winner: outcome 1, \
+             2 user(s) share 1500 channel points"
+        );
+        prediction.status = "CANCELED".to_owned();
+        assert!(prediction_results(&prediction)
+            .unwrap()
+            .ends_with("was canceled, everyone got their channel points back"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Which commands are used and by how many users.
+
+use std::collections::{HashMap, HashSet};
+
+/// How often every command was used since the bot started.
+#[derive(Default)]
+pub struct CommandUsage {
+    // uses per command
+    counts: HashMap<String, u64>,
+    // everyone who used a command
+    users: HashSet<String>,
+    total: u64,
+}
+
+impl CommandUsage {
+    pub fn record(&mut self, command: &str, user: &str) {
+        *self.counts.entry(command.to_owned()).or_default() += 1;
+        if !self.users.contains(user) {
+            self.users.insert(user.to_owned());
+        }
+        self.total += 1;
+    }
+
+    /// How many times `command` was used.
+    pub fn count(&self, command: &str) -> u64 {
+        self.counts.get(command).copied().unwrap_or_default()
+    }
+
+    /// The `n` most used commands with how often they were used, ties ordered
+    /// by name.
+    pub fn top(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut top: Vec<(&str, u64)> = self
+            .counts
+            .iter()
+            .map(|(command, &count)| (command.as_str(), count))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// "!join (12), !queue (5)" style summary of the `n` most used commands.
+    pub fn summary(&self, n: usize) -> String {
+        let top = self.top(n);
+        if top.is_empty() {
+            return "No commands have been used yet".to_owned();
+        }
+        let top: Vec<String> = top
+            .iter()
+            .map(|(command, count)| format!("{} ({})", command, count))
+            .collect();
+        format!(
+            "{} command(s) used by {} user(s), most used: {}",
+            self.total,
+            self.users.len(),
+            top.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizing_usage() {
+        let mut usage = CommandUsage::default();
+        assert_eq!(usage.summary(3), "No commands have been used yet");
+
+        for (command, user) in [
+            ("!queue", "alice"),
+            ("!join", "alice"),
+            ("!join", "bob"),
+            ("!stonk", "bob"),
+            ("!join", "carol"),
+            ("!queue", "carol"),
+        ] {
+            usage.record(command, user);
+        }
+
+        assert_eq!(usage.count("!join"), 3);
+        assert_eq!(usage.count("!leave"), 0);
+        assert_eq!(usage.top(2), [("!join", 3), ("!queue", 2)]);
+        assert_eq!(
+            usage.summary(5),
+            "6 command(s) used by 3 user(s), most used: !join (3), !queue (2), !stonk (1)"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! How long everyone has been watching, for `!watchtime` and `!topwatchers`.
+//! Chatting counts as watching for a while after each message, and only while
+//! the stream is live.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use twitch_queue_bot::json_store::JsonStore;
+
+/// Watchtime per user, per channel.
+pub struct Watchtime {
+    // seconds, keyed by channel login, then by user login
+    totals: BTreeMap<String, BTreeMap<String, i64>>,
+    // keyed by channel login
+    present: HashMap<String, Presence>,
+    // how long after their last message users still count as watching
+    presence: Duration,
+    // saved to after every credit, if set
+    store: Option<JsonStore<BTreeMap<String, BTreeMap<String, i64>>>>,
+}
+
+struct Presence {
+    channel_id: String,
+    // keyed by user login
+    last_seen: HashMap<String, DateTime<Utc>>,
+}
+
+impl Watchtime {
+    pub fn new(presence: Duration) -> Watchtime {
+        Watchtime {
+            totals: BTreeMap::new(),
+            present: HashMap::new(),
+            presence,
+            store: None,
+        }
+    }
+
+    /// Restores the totals saved at `path`, if there are any, and saves every
+    /// credit back to it.
+    pub fn persistent(presence: Duration, path: PathBuf) -> io::Result<Watchtime> {
+        let store = JsonStore::new(path);
+        Ok(Watchtime {
+            totals: store.load_or_default()?,
+            store: Some(store),
+            ..Watchtime::new(presence)
+        })
+    }
+
+    /// Notes that `user` chatted in the channel `channel` with id `channel_id`.
+    pub fn seen(&mut self, channel: &str, channel_id: &str, user: &str, at: DateTime<Utc>) {
+        let presence = self
+            .present
+            .entry(channel.to_owned())
+            .or_insert_with(|| Presence {
+                channel_id: channel_id.to_owned(),
+                last_seen: HashMap::new(),
+            });
+        presence.last_seen.insert(user.to_owned(), at);
+    }
+
+    /// The logins and ids of the channels someone is watching at `now`.
+    pub fn watched(&mut self, now: DateTime<Utc>) -> Vec<(String, String)> {
+        let presence = self.presence;
+        for watching in self.present.values_mut() {
+            watching.last_seen.retain(|_, &mut at| at > now - presence);
+        }
+        self.present
+            .retain(|_, watching| !watching.last_seen.is_empty());
+        self.present
+            .iter()
+            .map(|(channel, watching)| (channel.clone(), watching.channel_id.clone()))
+            .collect()
+    }
+
+    /// Adds `elapsed` to everyone watching `channel` at `now`.
+    pub fn credit(&mut self, channel: &str, elapsed: Duration, now: DateTime<Utc>) {
+        let watching = match self.present.get(channel) {
+            Some(watching) => watching,
+            None => return,
+        };
+        let totals = self.totals.entry(channel.to_owned()).or_default();
+        for (user, &at) in &watching.last_seen {
+            if at > now - self.presence {
+                *totals.entry(user.clone()).or_default() += elapsed.num_seconds();
+            }
+        }
+        self.save();
+    }
+
+    /// How long `user` has watched `channel`, `None` if they never did.
+    pub fn of(&self, channel: &str, user: &str) -> Option<Duration> {
+        let seconds = self.totals.get(channel)?.get(user)?;
+        Some(Duration::seconds(*seconds))
+    }
+
+    /// The `n` users who watched `channel` the longest with their watchtime,
+    /// ties ordered by name.
+    pub fn top(&self, channel: &str, n: usize) -> Vec<(&str, Duration)> {
+        let mut top: Vec<(&str, i64)> = match self.totals.get(channel) {
+            Some(totals) => totals
+                .iter()
+                .map(|(user, &seconds)| (user.as_str(), seconds))
+                .collect(),
+            None => return Vec::new(),
+        };
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.into_iter()
+            .take(n)
+            .map(|(user, seconds)| (user, Duration::seconds(seconds)))
+            .collect()
+    }
+
+    fn save(&self) {
+        if let Some(store) = &self.store {
+            store.save(&self.totals);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crediting_who_is_present() {
+        let start = Utc::now();
+        let at = |minutes| start + Duration::minutes(minutes);
+        let mut watchtime = Watchtime::new(Duration::minutes(10));
+        assert!(watchtime.watched(at(0)).is_empty());
+
+        watchtime.seen("channel", "1", "alice", at(0));
+        watchtime.seen("channel", "1", "bob", at(5));
+        assert_eq!(
+            watchtime.watched(at(5)),
+            [("channel".to_owned(), "1".to_owned())]
+        );
+        watchtime.credit("channel", Duration::minutes(1), at(5));
+        // alice stopped chatting too long ago
+        watchtime.credit("channel", Duration::minutes(1), at(12));
+        watchtime.credit("other", Duration::minutes(1), at(12));
+
+        assert_eq!(watchtime.of("channel", "alice"), Some(Duration::minutes(1)));
+        assert_eq!(watchtime.of("channel", "bob"), Some(Duration::minutes(2)));
+        assert_eq!(watchtime.of("other", "bob"), None);
+        assert_eq!(
+            watchtime.top("channel", 5),
+            [
+                ("bob", Duration::minutes(2)),
+                ("alice", Duration::minutes(1))
+            ]
+        );
+        assert!(watchtime.watched(at(20)).is_empty());
+    }
+}